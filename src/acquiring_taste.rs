@@ -1,40 +1,142 @@
-use std::{fmt::Write, ops::Not, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Write,
+    ops::Not,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, bail, Context as _};
+use base64::Engine;
 use chrono::Utc;
 use google_sheets4::api::ValueRange;
-use rand::{seq::SliceRandom, thread_rng};
-use reqwest::{redirect::Policy, Url};
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, Rgb};
+use imageproc::drawing::{draw_text_mut, text_size};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use regex::Regex;
+use reqwest::Url;
 use rspotify::{
     model::{Id, PlaylistId, TrackId, UserId},
-    prelude::{BaseClient, OAuthClient, PlayableId},
+    prelude::{OAuthClient, PlayableId},
 };
+use rusqlite::{params, OptionalExtension};
+use rusttype::{Font, Scale};
 use serenity::{
     async_trait,
-    builder::{CreateInteractionResponse, EditInteractionResponse},
+    builder::{
+        CreateCommandOption, CreateEmbed, CreateInteractionResponse, CreateMessage,
+        EditInteractionResponse,
+    },
     client::Context,
-    model::{application::CommandInteraction, Permissions},
+    model::{
+        application::CommandInteraction,
+        guild::Member,
+        id::{ChannelId, GuildId, RoleId},
+        Permissions,
+    },
 };
 use tokio::task::JoinSet;
 
-use crate::forms::Forms;
+use crate::{
+    album::{self, AlbumProvider},
+    forms::Forms,
+    musicbrainz::MusicBrainz,
+    playlist_builder::{
+        add_tracks, dedupe_picks, dry_run_report, pick_isrcs, resolve_pick, resolve_picks,
+        track_id_from_url, write_invalid, AlbumMode, Pick, Progress,
+    },
+    settings::{self, GuildSettings},
+    spotify_cache::SpotifyCache,
+    spotify_market::SpotifyMarket,
+    spotify_retry::with_retry,
+    youtube::Youtube,
+};
 use serenity_command::{BotCommand, CommandResponse};
 use serenity_command_derive::Command;
 use serenity_command_handler::{
-    modules::{AlbumLookup, SpotifyOAuth},
+    db::Db,
+    modules::{AlbumLookup, Spotify, SpotifyOAuth},
     prelude::*,
 };
 
+// Configuration of the server Acquiring the Taste was first run in, used to seed the database
 const FORM_SPREADSHEET: &str = "1Hxm4SiZF7NWLvVIkK2RrnGIwFEZaoC1vR6_zl5e2TcI";
 const USER_ID: &str = "cq21khhkkhy88fo9nsthvqkft";
-// const GUILD_ID: GuildId = GuildId::new(400572085300101120);
-// const HIGH_TASTE: RoleId = RoleId::new(427894012238757908);
+const GUILD_ID: GuildId = GuildId::new(400572085300101120);
+const DEFAULT_PICK_LIMIT: usize = 2;
+/// Path of the font used to draw on playlist covers
+const COVER_FONT_VAR: &str = "ATT_COVER_FONT";
+
+static SPREADSHEET_URL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"/spreadsheets/d/([a-zA-Z0-9_-]+)").unwrap());
+static ROLE_RE: Lazy<Regex> = Lazy::new(|| Regex::new("^(?:<@&)?([0-9]+)>?$").unwrap());
+static SPOTIFY_USER_URL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"open\.spotify\.com/user/([a-zA-Z0-9._-]+)").unwrap());
 
+/// Per-guild configuration of Acquiring the Taste
 #[derive(Clone, Debug)]
-pub struct AcquiringTastePick {
-    pub submitter: String,
-    pub song: String,
-    pub link: String,
+struct AttConfig {
+    /// Spreadsheet the submission form writes to
+    spreadsheet_id: String,
+    /// Spotify user the playlists are created for
+    playlist_owner: String,
+    /// Maximum number of picks per submitter in each edition
+    pick_limit: usize,
+    /// URL of the image the edition number is drawn on for playlist covers
+    cover_template: Option<String>,
+    /// Channel new editions are announced in
+    announce_channel: Option<ChannelId>,
+    /// Whether playlists are mirrored to YouTube
+    youtube_mirror: bool,
+    /// Role submitters need for their picks to be added
+    high_taste_role: Option<RoleId>,
+    /// How album links are handled
+    album_mode: AlbumMode,
+}
+
+impl AttConfig {
+    async fn get(handler: &Handler, guild_id: GuildId) -> anyhow::Result<Self> {
+        let mut config = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT spreadsheet_id, playlist_owner, pick_limit, cover_template, youtube_mirror,
+                     high_taste_role, album_mode
+                     FROM att_config WHERE guild_id = ?1",
+                [guild_id.get()],
+                |row| {
+                    Ok(AttConfig {
+                        spreadsheet_id: row.get(0)?,
+                        playlist_owner: row.get(1)?,
+                        pick_limit: row
+                            .get::<_, Option<usize>>(2)?
+                            .unwrap_or(DEFAULT_PICK_LIMIT),
+                        cover_template: row.get(3)?,
+                        announce_channel: None,
+                        youtube_mirror: row.get::<_, Option<bool>>(4)?.unwrap_or(false),
+                        high_taste_role: row.get::<_, Option<u64>>(5)?.map(RoleId::new),
+                        album_mode: row
+                            .get::<_, Option<String>>(6)?
+                            .as_deref()
+                            .and_then(AlbumMode::parse)
+                            .unwrap_or(AlbumMode::TopTrack),
+                    })
+                },
+            )
+            .optional()?
+            .ok_or_else(|| {
+                anyhow!("Acquiring the Taste is not configured in this server, use /att_configure")
+            })?;
+        config.announce_channel = handler
+            .module::<GuildSettings>()?
+            .channel(guild_id, &settings::ATT_ANNOUNCE_CHANNEL)
+            .await;
+        Ok(config)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -46,11 +148,11 @@ struct Variables {
 }
 
 impl Variables {
-    async fn get(handler: &Handler) -> anyhow::Result<Self> {
+    async fn get(handler: &Handler, config: &AttConfig) -> anyhow::Result<Self> {
         let forms: &Forms = handler.module()?;
         let sheets = forms.sheets_client.spreadsheets();
         let mut var_rows = sheets
-            .values_get(FORM_SPREADSHEET, "Variables!A2:D2")
+            .values_get(&config.spreadsheet_id, "Variables!A2:D2")
             .doit()
             .await?
             .1;
@@ -77,7 +179,7 @@ impl Variables {
         })
     }
 
-    async fn set(self, handler: &Handler) -> anyhow::Result<()> {
+    async fn set(self, handler: &Handler, config: &AttConfig) -> anyhow::Result<()> {
         let forms: &Forms = handler.module()?;
         let sheets = forms.sheets_client.spreadsheets();
         let values = Some(vec![vec![
@@ -90,7 +192,7 @@ impl Variables {
             ..Default::default()
         };
         sheets
-            .values_update(req, FORM_SPREADSHEET, "Variables!A2:C2")
+            .values_update(req, &config.spreadsheet_id, "Variables!A2:C2")
             .value_input_option("USER_ENTERED")
             .doit()
             .await?;
@@ -98,162 +200,149 @@ impl Variables {
     }
 }
 
-async fn pick_from_track_id(
-    spotify: Arc<SpotifyOAuth>,
-    submitter: &str,
-    id: &str,
-) -> anyhow::Result<AcquiringTastePick> {
-    let track = spotify.get_song_from_id(id).await?;
-    let artists = SpotifyOAuth::artists_to_string(&track.artists);
-    let title = &track.name;
-    Ok(AcquiringTastePick {
-        submitter: submitter.to_string(),
-        song: format!("{artists} - {title}"),
-        link: track.id.unwrap().url(),
-    })
-}
-
-async fn pick_from_shortened_link(
-    spotify: Arc<SpotifyOAuth>,
-    submitter: &str,
-    url: &str,
-) -> anyhow::Result<AcquiringTastePick> {
-    let client = reqwest::Client::builder()
-        .redirect(Policy::none())
-        .build()
-        .unwrap();
-    let resp = client
-        .head(url)
-        .send()
+// gets the spotify track IDs of picks from previous editions, with the edition they appeared in
+async fn past_picks(
+    handler: &Handler,
+    config: &AttConfig,
+) -> anyhow::Result<HashMap<String, String>> {
+    let forms: &Forms = handler.module()?;
+    let sheets = forms.sheets_client.spreadsheets();
+    let rows = sheets
+        .values_get(&config.spreadsheet_id, "Picks!A:E")
+        .doit()
         .await
-        .context("Failed to resolve shortened spotify URL")?;
-    let location = resp
-        .headers()
-        .get("location")
-        .and_then(|val| val.to_str().ok())
-        .ok_or_else(|| anyhow!("Not a valid spotify URL"))?;
-    let url = Url::parse(location).context("Spotify shortened URL points to invalid URL")?;
-    if let Some(id) = url.path().strip_prefix("/track/") {
-        pick_from_track_id(spotify, submitter, id).await
-    } else {
-        Err(anyhow!("Not a spotify track URL: {url}"))
-    }
+        .context("failed to get previous picks")?
+        .1;
+    let past = rows
+        .values
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|row| {
+            let edition = format!("edition #{}", row.first()?);
+            let id = track_id_from_url(row.get(4)?)?;
+            Some((id, edition))
+        })
+        .collect();
+    Ok(past)
 }
 
-async fn resolve_pick(
-    spotify: Arc<SpotifyOAuth>,
-    pick: AcquiringTastePick,
-) -> Result<AcquiringTastePick, (AcquiringTastePick, anyhow::Error)> {
-    let url = Url::parse(&pick.link)
-        .context("Not a valid URL")
-        .map_err(|e| (pick.clone(), e))?;
-    let segments = url
-        .path_segments()
-        .into_iter()
-        .flatten()
-        .take(2)
-        .collect::<Vec<_>>();
-    match (url.domain(), segments.as_slice()) {
-        (Some("open.spotify.com"), ["track", id]) => {
-            pick_from_track_id(spotify, &pick.submitter, id).await
-        }
-        (Some("spotify.link"), [_]) => {
-            eprintln!("Found shortened link, resolving it");
-            pick_from_shortened_link(spotify, &pick.submitter, &pick.link).await
+// credits the edition and its submitters, within spotify's 300 characters limit
+fn playlist_description(edition: usize, picks: &[(Pick, TrackId<'static>)]) -> String {
+    const MAX_LEN: usize = 300;
+    let submitters = picks
+        .iter()
+        .map(|(pick, _)| pick.submitter.as_str())
+        .unique();
+    let mut description = format!("Acquiring the Taste #{edition}, picks by ");
+    for (i, submitter) in submitters.enumerate() {
+        let sep = if i == 0 { "" } else { ", " };
+        if description.len() + sep.len() + submitter.len() > MAX_LEN - " and more".len() {
+            description.push_str(" and more");
+            break;
         }
-        _ => return Err((pick, anyhow!("Not a spotify URL"))),
+        description.push_str(sep);
+        description.push_str(submitter);
     }
-    .map_err(|e| (pick, e))
+    description
+}
+
+// draws the edition number on the cover template, returning a base64 encoded jpeg
+fn generate_cover(template: &[u8], font: &[u8], edition: usize) -> anyhow::Result<String> {
+    const SIZE: u32 = 640;
+    let font = Font::try_from_vec(font.to_vec()).ok_or_else(|| anyhow!("invalid cover font"))?;
+    let mut cover = image::load_from_memory(template)
+        .context("invalid cover template")?
+        .resize_to_fill(SIZE, SIZE, FilterType::Lanczos3)
+        .to_rgb8();
+    let text = format!("#{edition}");
+    let scale = Scale::uniform(SIZE as f32 / 4.0);
+    let (width, height) = text_size(scale, &font, &text);
+    let x = (SIZE as i32 - width) / 2;
+    let y = SIZE as i32 - height - SIZE as i32 / 10;
+    draw_text_mut(&mut cover, Rgb([255, 255, 255]), x, y, scale, &font, &text);
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, 85).encode_image(&cover)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(jpeg))
+}
+
+// uploads a cover for a new playlist if the server has a template
+async fn upload_cover(
+    spotify: &SpotifyOAuth,
+    config: &AttConfig,
+    playlist: PlaylistId<'_>,
+    edition: usize,
+) -> anyhow::Result<()> {
+    let Some(template_url) = &config.cover_template else {
+        return Ok(());
+    };
+    let font_path =
+        env::var(COVER_FONT_VAR).with_context(|| format!("{COVER_FONT_VAR} not set"))?;
+    let template = reqwest::get(template_url)
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .context("failed to fetch cover template")?
+        .bytes()
+        .await?;
+    let cover = tokio::task::spawn_blocking(move || {
+        let font = std::fs::read(font_path).context("failed to read cover font")?;
+        generate_cover(&template, &font, edition)
+    })
+    .await??;
+    spotify
+        .client
+        .playlist_upload_cover_image(playlist, &cover)
+        .await
+        .context("failed to upload playlist cover")?;
+    Ok(())
 }
 
-async fn build_playlist<'a, 'b: 'a>(
-    handler: &'a Handler,
-    picks: &'b [AcquiringTastePick],
+async fn build_playlist(
+    handler: &Handler,
+    deferred: Option<(&Context, &CommandInteraction)>,
+    config: &AttConfig,
+    picks: &[(Pick, TrackId<'static>)],
     playlist: Option<PlaylistId<'static>>,
     edition: usize,
-) -> anyhow::Result<(
-    PlaylistId<'static>,
-    Vec<AcquiringTastePick>,
-    Vec<(AcquiringTastePick, String)>,
-)> {
+) -> anyhow::Result<PlaylistId<'static>> {
     let spotify: Arc<SpotifyOAuth> = handler.module_arc()?;
     spotify.client.refresh_token().await?;
-    let user_id: UserId<'static> = UserId::from_id(USER_ID)?;
+    let user_id = UserId::from_id(config.playlist_owner.as_str())?;
     let playlist = match playlist {
         None => {
             let date = Utc::now().date_naive().format("%Y-%m-%d");
-            let resp = spotify
-                .client
-                .user_playlist_create(
-                    user_id,
-                    &format!("I&W Acquiring the Taste #{edition} | {date}"),
+            let name = format!("I&W Acquiring the Taste #{edition} | {date}");
+            let description = playlist_description(edition, picks);
+            let resp = with_retry(deferred, || {
+                spotify.client.user_playlist_create(
+                    user_id.as_ref(),
+                    &name,
                     Some(true),
                     None,
-                    None,
+                    Some(&description),
                 )
-                .await
-                .context("failed to create playlist")?;
+            })
+            .await
+            .context("failed to create playlist")?;
+            if let Err(e) = upload_cover(&spotify, config, resp.id.as_ref(), edition).await {
+                eprintln!("{e:?}");
+            }
             resp.id
         }
         Some(id) => id,
     };
-    let mut invalid = Vec::new();
-    let mut valid = Vec::new();
-    let spotify: Arc<SpotifyOAuth> = handler.module_arc()?;
-    let mut set = JoinSet::new();
-    for pick in picks {
-        set.spawn(resolve_pick(Arc::clone(&spotify), pick.clone()));
-    }
-    let mut picks_resolved = Vec::with_capacity(picks.len());
-    while let Some(res) = set.join_next().await {
-        match res.unwrap() {
-            Ok(pick) => picks_resolved.push(pick),
-            Err((pick, e)) => invalid.push((pick, e.to_string())),
-        }
-    }
-    let items = picks_resolved
-        .iter()
-        .flat_map(|pick| {
-            let Ok(url) = Url::parse(&pick.link) else {
-                invalid.push((pick.clone(), format!("not a url: {}", &pick.link)));
-                return None;
-            };
-            let Some(id) = url.path().strip_prefix("/track/") else {
-                invalid.push((
-                    pick.clone(),
-                    format!("not a spotify track url: <{}>", &pick.link),
-                ));
-                return None;
-            };
-            match TrackId::from_id_or_uri(id) {
-                Ok(id) => {
-                    valid.push(pick.clone());
-                    Some(id.clone_static())
-                }
-                Err(e) => {
-                    invalid.push((pick.clone(), e.to_string()));
-                    None
-                }
-            }
-        })
-        .map(PlayableId::from);
-    let items: Vec<_> = items.collect();
-    spotify
-        .client
-        .playlist_add_items(playlist.as_ref(), items, None)
-        .await
-        .context("failed to add songs to playlist")?;
-    Ok((playlist, valid, invalid))
+    add_tracks(&spotify, deferred, playlist.as_ref(), picks).await?;
+    Ok(playlist)
 }
 
-// gets new submissions from the form and stores them in the database
+// gets new submissions from the form, dropping the picks of submitters over the limit
 async fn get_acquiring_taste_submissions(
     handler: &Handler,
-) -> anyhow::Result<Vec<AcquiringTastePick>> {
+    config: &AttConfig,
+) -> anyhow::Result<(Vec<Pick>, Vec<(Pick, String)>)> {
     let forms: &Forms = handler.module()?;
     let sheets = forms.sheets_client.spreadsheets();
     let rows = sheets
-        .values_get(FORM_SPREADSHEET, "Deduplicated!A:C")
+        .values_get(&config.spreadsheet_id, "Deduplicated!A:C")
         .doit()
         .await
         .context("failed to get submissions")?
@@ -261,35 +350,248 @@ async fn get_acquiring_taste_submissions(
     let Some(values) = rows.values else {
         bail!("No submissions found on this sheet");
     };
-    let picks = values
-        .into_iter()
-        .map(|row| AcquiringTastePick {
+    let mut counts = HashMap::<String, usize>::new();
+    let mut picks = Vec::with_capacity(values.len());
+    let mut over_limit = Vec::new();
+    for row in values {
+        let pick = Pick {
             submitter: row[0].clone(),
             song: row[1].clone(),
             link: row[2].clone(),
-        })
+        };
+        let count = counts.entry(pick.submitter.to_lowercase()).or_default();
+        *count += 1;
+        if *count > config.pick_limit {
+            let reason = format!("over the limit of {} picks per edition", config.pick_limit);
+            over_limit.push((pick, reason));
+        } else {
+            picks.push(pick);
+        }
+    }
+    Ok((picks, over_limit))
+}
+
+// drops the tracks of submitters over the limit once picks are resolved, as an album pick can
+// stand for all of its tracks
+fn limit_tracks(
+    picks: Vec<(Pick, TrackId<'static>)>,
+    pick_limit: usize,
+    invalid: &mut Vec<(Pick, String)>,
+) -> Vec<(Pick, TrackId<'static>)> {
+    let mut counts = HashMap::<String, usize>::new();
+    let mut allowed = Vec::with_capacity(picks.len());
+    for (pick, id) in picks {
+        let count = counts.entry(pick.submitter.to_lowercase()).or_default();
+        *count += 1;
+        if *count > pick_limit {
+            let reason = format!("over the limit of {pick_limit} tracks per edition");
+            invalid.push((pick, reason));
+        } else {
+            allowed.push((pick, id));
+        }
+    }
+    allowed
+}
+
+// shuffles submitters and their picks, then takes one pick from each submitter in turn so the
+// same submitter's songs are spread out
+fn round_robin(picks: Vec<Pick>, rng: &mut impl Rng) -> Vec<Pick> {
+    let total = picks.len();
+    let mut by_submitter: Vec<Vec<Pick>> = picks
+        .into_iter()
+        .into_group_map_by(|pick| pick.submitter.clone())
+        .into_values()
         .collect();
-    Ok(picks)
+    by_submitter.shuffle(rng);
+    by_submitter.iter_mut().for_each(|picks| picks.shuffle(rng));
+    let mut iters: Vec<_> = by_submitter.into_iter().map(Vec::into_iter).collect();
+    let mut ordered = Vec::with_capacity(total);
+    while ordered.len() < total {
+        ordered.extend(iters.iter_mut().filter_map(Iterator::next));
+    }
+    ordered
+}
+
+async fn mirror_pick(
+    handler: &Handler,
+    youtube: &Youtube,
+    playlist_id: &str,
+    pick: &Pick,
+    track_id: &TrackId<'static>,
+) -> anyhow::Result<()> {
+    // searching by title alone finds the wrong video for common titles
+    let spotify = handler.module::<Spotify>()?;
+    let cache = handler.module::<SpotifyCache>()?;
+    let query = match cache.track(&spotify.client, track_id.clone(), None).await {
+        Ok(track) => match track.artists.first() {
+            Some(artist) => format!("{} {}", artist.name, track.name),
+            None => track.name,
+        },
+        // picks are named after their artists and title
+        Err(_) => pick.song.clone(),
+    };
+    let video = youtube.query_album(&query).await?;
+    let url = Url::parse(&video.url)?;
+    let video_id = Youtube::video_id(&url).ok_or_else(|| anyhow!("invalid video url"))?;
+    youtube.add_to_playlist(playlist_id, &video_id).await
+}
+
+// searches each pick on youtube and adds it to the edition's youtube playlist, creating it if
+// needed, returning the playlist url and the picks that could not be mirrored
+async fn mirror_to_youtube(
+    handler: &Handler,
+    guild_id: GuildId,
+    edition: usize,
+    picks: &[(Pick, TrackId<'static>)],
+) -> anyhow::Result<(String, Vec<(Pick, String)>)> {
+    let forms = handler.module::<Forms>()?;
+    let youtube = Youtube::new(
+        &forms.forms_client.client,
+        &forms.forms_client.authenticator,
+    );
+    let existing: Option<String> = handler
+        .db
+        .lock()
+        .await
+        .conn
+        .query_row(
+            "SELECT playlist_id FROM att_youtube_playlists WHERE guild_id = ?1 AND edition = ?2",
+            params![guild_id.get(), edition],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let playlist_id = match existing {
+        Some(id) => id,
+        None => {
+            let date = Utc::now().date_naive().format("%Y-%m-%d");
+            let id = youtube
+                .create_playlist(
+                    &format!("I&W Acquiring the Taste #{edition} | {date}"),
+                    &playlist_description(edition, picks),
+                )
+                .await?;
+            handler.db.lock().await.conn.execute(
+                "INSERT INTO att_youtube_playlists (guild_id, edition, playlist_id)
+                     VALUES (?1, ?2, ?3)",
+                params![guild_id.get(), edition, &id],
+            )?;
+            id
+        }
+    };
+    let mut missing = Vec::new();
+    for (pick, track_id) in picks {
+        if let Err(e) = mirror_pick(handler, &youtube, &playlist_id, pick, track_id).await {
+            missing.push((pick.clone(), e.to_string()));
+        }
+    }
+    Ok((Youtube::playlist_url(&playlist_id), missing))
+}
+
+// finds the guild members who submitted picks by name, searching each distinct name once
+async fn find_submitters(
+    ctx: &Context,
+    guild_id: GuildId,
+    picks: &[(Pick, TrackId<'static>)],
+) -> HashMap<String, Member> {
+    let names: HashSet<String> = picks
+        .iter()
+        .map(|(pick, _)| pick.submitter.to_lowercase())
+        .collect();
+    let mut set = JoinSet::new();
+    for name in names {
+        let http = Arc::clone(&ctx.http);
+        set.spawn(async move {
+            let members = guild_id.search_members(&http, &name, Some(10)).await;
+            (name, members)
+        });
+    }
+    let mut found = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        let (name, members) = res.unwrap();
+        let members = match members {
+            Ok(members) => members,
+            Err(e) => {
+                eprintln!("Error searching for member {name}: {e}");
+                continue;
+            }
+        };
+        let matches = |candidate: Option<&String>| {
+            candidate.is_some_and(|candidate| candidate.to_lowercase() == name)
+        };
+        let member = members.into_iter().find(|member| {
+            matches(Some(&member.user.name))
+                || matches(member.user.global_name.as_ref())
+                || matches(member.nick.as_ref())
+        });
+        if let Some(member) = member {
+            found.insert(name, member);
+        }
+    }
+    found
+}
+
+// posts the new edition's playlist to the server's announcement channel
+async fn announce_edition(
+    ctx: &Context,
+    channel: ChannelId,
+    edition: usize,
+    playlist_url: &str,
+    ntracks: usize,
+    submitters: &[String],
+) -> anyhow::Result<()> {
+    // Leave room for the truncation notice
+    const MAX_LEN: usize = 1000;
+    let mut submitters_list = String::new();
+    for (i, submitter) in submitters.iter().enumerate() {
+        if submitters_list.len() + submitter.len() + 2 > MAX_LEN {
+            _ = write!(&mut submitters_list, " and {} more", submitters.len() - i);
+            break;
+        }
+        if i > 0 {
+            submitters_list.push_str(", ");
+        }
+        submitters_list.push_str(submitter);
+    }
+    let embed = CreateEmbed::new()
+        .title(format!("Acquiring the Taste #{edition} is out!"))
+        .url(playlist_url)
+        .description(playlist_url)
+        .field("Tracks", ntracks.to_string(), true)
+        .field("Submitters", submitters_list, false);
+    channel
+        .send_message(&ctx.http, CreateMessage::new().embed(embed))
+        .await?;
+    Ok(())
 }
 
 async fn build_playlist_from_picks(
     handler: &Handler,
-    _ctx: &Context,
+    ctx: &Context,
+    progress: &mut Progress<'_>,
+    guild_id: GuildId,
     increment_edition: bool,
+    dry_run: bool,
+    shuffle: bool,
 ) -> anyhow::Result<String> {
+    let config = AttConfig::get(handler, guild_id).await?;
+    progress.update("Fetching submissions…").await;
     let Variables {
         last_row: _,
         edition,
         last_playlist,
         current_row,
-    } = Variables::get(handler).await?;
-    let mut picks = get_acquiring_taste_submissions(handler).await?;
+    } = Variables::get(handler, &config).await?;
+    let (mut picks, over_limit) = get_acquiring_taste_submissions(handler, &config).await?;
     if picks.is_empty() {
         return Ok("No new picks to add".to_string());
     }
     {
         let mut rng = thread_rng();
-        picks.shuffle(&mut rng);
+        if shuffle {
+            picks.shuffle(&mut rng);
+        } else {
+            picks = round_robin(picks, &mut rng);
+        }
     }
     let playlist_id = if increment_edition {
         None
@@ -301,14 +603,80 @@ async fn build_playlist_from_picks(
         })
     };
     let edition = edition + if increment_edition { 1 } else { 0 };
-    let (playlist, valid, invalid) = build_playlist(handler, &picks, playlist_id, edition).await?;
+    let market = SpotifyMarket::for_guild(handler, Some(guild_id)).await;
+    let (valid, mut invalid) =
+        resolve_picks(handler, progress, &picks, config.album_mode, market).await?;
+    invalid.extend(over_limit);
+    let valid = limit_tracks(valid, config.pick_limit, &mut invalid);
+    progress.update("Checking past editions…").await;
+    let past = past_picks(handler, &config).await?;
+    let isrcs = pick_isrcs(handler, &valid, &past).await;
+    let valid = dedupe_picks(valid, &past, &isrcs, &mut invalid);
+    let mut members = HashMap::new();
+    let valid = match config.high_taste_role {
+        Some(role) => {
+            let submitters = find_submitters(ctx, guild_id, &valid).await;
+            let mut allowed = Vec::with_capacity(valid.len());
+            for (pick, id) in valid {
+                match submitters.get(&pick.submitter.to_lowercase()) {
+                    None => invalid.push((pick, "not a member of the server".to_string())),
+                    Some(member) if !member.roles.contains(&role) => {
+                        invalid.push((pick, "not high taste".to_string()))
+                    }
+                    Some(member) => {
+                        members.insert(pick.submitter.to_lowercase(), member.user.id);
+                        allowed.push((pick, id));
+                    }
+                }
+            }
+            allowed
+        }
+        None => valid,
+    };
+    if dry_run {
+        return Ok(dry_run_report(&valid, invalid));
+    }
+    let target = match &playlist_id {
+        None => format!("create a new playlist for edition #{edition}"),
+        Some(id) => format!(
+            "add them to the existing playlist for edition #{edition} (<{}>)",
+            id.url()
+        ),
+    };
+    let prompt = format!(
+        "Found {} picks: {} tracks to add, {} invalid picks.\nThis will {target}. Continue?",
+        picks.len(),
+        valid.len(),
+        invalid.len()
+    );
+    if !progress.confirm(&prompt).await? {
+        return Ok("Cancelled, nothing was changed".to_string());
+    }
+    progress
+        .update(&format!("Adding {} tracks to the playlist…", valid.len()))
+        .await;
+    let playlist = build_playlist(
+        handler,
+        progress.deferred(),
+        &config,
+        &valid,
+        playlist_id,
+        edition,
+    )
+    .await?;
     let nvalid = valid.len();
+    let submitters: Vec<String> = valid
+        .iter()
+        .map(|(pick, _)| pick.submitter.clone())
+        .unique()
+        .collect();
     let variables = Variables {
         last_row: current_row,
         edition,
         last_playlist: Some(playlist.to_string()),
         current_row: 0, // not used
     };
+    progress.update("Saving picks to the spreadsheet…").await;
     let sheets = handler.module::<Forms>()?.sheets_client.spreadsheets();
     let playlist_url = playlist.url();
     if increment_edition {
@@ -321,32 +689,24 @@ async fn build_playlist_from_picks(
             ..Default::default()
         };
         sheets
-            .values_append(req, FORM_SPREADSHEET, "Playlists!A:C")
+            .values_append(req, &config.spreadsheet_id, "Playlists!A:C")
             .value_input_option("USER_ENTERED")
             .doit()
             .await
             .context("failed to add playlist to spreadsheet")?;
     }
     let mut picks_values = Vec::with_capacity(picks.len());
-    for pick in valid {
-        // let members = GUILD_ID
-        //     .search_members(&ctx.http, &pick.submitter, Some(1))
-        //     .await?;
-        // if members.is_empty() || !members[0].roles.contains(&HIGH_TASTE) {
-        //     invalid.push((pick, "not high taste".to_string()));
-        //     continue;
-        // }
-        // let user_id = members
-        //     .get(0)
-        //     .map(|member| member.user.id.0.to_string())
-        //     .unwrap_or_default();
-        let user_id = String::new();
+    for (pick, _) in &valid {
+        let user_id = members
+            .get(&pick.submitter.to_lowercase())
+            .map(|user_id| user_id.to_string())
+            .unwrap_or_default();
         let row = vec![
             variables.edition.to_string(),
-            pick.submitter,
+            pick.submitter.clone(),
             user_id,
-            pick.song,
-            pick.link,
+            pick.song.clone(),
+            pick.link.clone(),
         ];
         picks_values.push(row);
     }
@@ -356,14 +716,14 @@ async fn build_playlist_from_picks(
             ..Default::default()
         };
         sheets
-            .values_append(req, FORM_SPREADSHEET, "Picks!A1:E1")
+            .values_append(req, &config.spreadsheet_id, "Picks!A1:E1")
             .value_input_option("USER_ENTERED")
             .doit()
             .await
             .context("failed to save picks to spreadsheet")?;
     }
     variables
-        .set(handler)
+        .set(handler, &config)
         .await
         .context("failed to save variables to spreadsheet")?;
     let mut resp = if last_playlist.is_none() || increment_edition {
@@ -377,23 +737,60 @@ async fn build_playlist_from_picks(
             &playlist_url
         )
     };
-    if !invalid.is_empty() {
-        _ = write!(
-            &mut resp,
-            "\n{} picks were invalid and could not be added:",
-            invalid.len()
-        );
-        invalid.into_iter().for_each(|(pick, reason)| {
-            _ = write!(
-                &mut resp,
-                "\n{}'s pick ({}): {}",
-                pick.submitter, pick.song, reason
-            );
-        })
+    let announce_channel = config.announce_channel.filter(|_| increment_edition);
+    if let Some(channel) = announce_channel {
+        let announced =
+            announce_edition(ctx, channel, edition, &playlist_url, nvalid, &submitters).await;
+        if let Err(e) = announced {
+            _ = write!(&mut resp, "\nCould not announce the new edition: {e}");
+        }
     }
+    if config.youtube_mirror {
+        progress.update("Mirroring the playlist to YouTube…").await;
+        match mirror_to_youtube(handler, guild_id, edition, &valid).await {
+            Ok((url, missing)) => {
+                _ = write!(&mut resp, "\nYouTube mirror: {url}");
+                missing.into_iter().for_each(|(pick, reason)| {
+                    _ = write!(
+                        &mut resp,
+                        "\n{}'s pick ({}) is not on YouTube: {}",
+                        pick.submitter, pick.song, reason
+                    );
+                });
+            }
+            Err(e) => {
+                eprintln!("{e:?}");
+                _ = write!(&mut resp, "\nCould not mirror the playlist to YouTube: {e}");
+            }
+        }
+    }
+    write_invalid(&mut resp, invalid);
     Ok(resp)
 }
 
+/// Builds the playlist of a guild outside of a command, returning the same report as
+/// /build_playlist
+pub async fn build_edition(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: GuildId,
+    reuse: bool,
+    dry_run: bool,
+    shuffle: bool,
+) -> anyhow::Result<String> {
+    let mut progress = Progress::detached(ctx);
+    build_playlist_from_picks(
+        handler,
+        ctx,
+        &mut progress,
+        guild_id,
+        !reuse,
+        dry_run,
+        shuffle,
+    )
+    .await
+}
+
 #[derive(Command)]
 #[cmd(
     name = "build_playlist",
@@ -401,6 +798,10 @@ async fn build_playlist_from_picks(
 )]
 pub struct BuildPlaylist {
     reuse: Option<bool>,
+    #[cmd(desc = "Only report what the playlist would contain, without creating it")]
+    dry_run: Option<bool>,
+    #[cmd(desc = "Shuffle all picks instead of spreading out each submitter's picks")]
+    shuffle: Option<bool>,
 }
 
 #[async_trait]
@@ -420,9 +821,21 @@ impl BotCommand for BuildPlaylist {
                 CreateInteractionResponse::Defer(Default::default()),
             )
             .await?;
-        let res = build_playlist_from_picks(handler, ctx, !self.reuse.unwrap_or(false))
-            .await
-            .context("Error getting new submissions");
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let mut progress = Progress::new(ctx, interaction);
+        let res = build_playlist_from_picks(
+            handler,
+            ctx,
+            &mut progress,
+            guild_id,
+            !self.reuse.unwrap_or(false),
+            self.dry_run.unwrap_or(false),
+            self.shuffle.unwrap_or(false),
+        )
+        .await
+        .context("Error getting new submissions");
         let resp = match res {
             Ok(resp) => resp,
             Err(e) => {
@@ -430,9 +843,445 @@ impl BotCommand for BuildPlaylist {
                 e.to_string()
             }
         };
+        progress.finish(&resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "att_configure",
+    desc = "Configure Acquiring the Taste for this server"
+)]
+pub struct AttConfigure {
+    #[cmd(desc = "ID or URL of the spreadsheet the submission form writes to")]
+    spreadsheet_id: String,
+    #[cmd(desc = "ID or profile URL of the Spotify user creating the playlists")]
+    playlist_owner: String,
+    // optional settings are left unchanged when not given
+    #[cmd(desc = "Maximum number of picks per submitter in each edition (default: 2)")]
+    pick_limit: Option<u64>,
+    #[cmd(desc = "URL of an image to draw the edition number on for playlist covers")]
+    cover_template: Option<String>,
+    #[cmd(desc = "Channel to announce new editions in")]
+    announce_channel: Option<String>,
+    #[cmd(desc = "Mirror the playlists to YouTube")]
+    youtube_mirror: Option<bool>,
+    #[cmd(desc = "Role submitters need for their picks to be added")]
+    high_taste_role: Option<String>,
+    #[cmd(desc = "Add the most popular track (default) or all tracks of submitted albums")]
+    album_mode: Option<String>,
+    #[cmd(desc = "Clear an optional setting, restoring its default")]
+    reset: Option<String>,
+}
+
+/// Optional settings of /att_configure that can be reset
+const RESETTABLE_SETTINGS: &[&str] = &[
+    "pick_limit",
+    "cover_template",
+    "announce_channel",
+    "youtube_mirror",
+    "high_taste_role",
+    "album_mode",
+];
+
+#[async_trait]
+impl BotCommand for AttConfigure {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let spreadsheet_id = SPREADSHEET_URL_RE
+            .captures(&self.spreadsheet_id)
+            .map(|cap| cap[1].to_string())
+            .unwrap_or_else(|| self.spreadsheet_id.trim().to_string());
+        let playlist_owner = SPOTIFY_USER_URL_RE
+            .captures(&self.playlist_owner)
+            .map(|cap| cap[1].to_string())
+            .unwrap_or_else(|| self.playlist_owner.trim().to_string());
+        UserId::from_id(playlist_owner.as_str()).context("Invalid Spotify user")?;
+        let announce_channel = match &self.announce_channel {
+            None => None,
+            Some(channel) => {
+                Some(settings::parse_channel(channel).ok_or_else(|| anyhow!("Invalid channel"))?)
+            }
+        };
+        if let Some(mode) = &self.album_mode {
+            AlbumMode::parse(mode).ok_or_else(|| anyhow!("Invalid album mode"))?;
+        }
+        let high_taste_role = match &self.high_taste_role {
+            None => None,
+            Some(role) => Some(
+                ROLE_RE
+                    .captures(role.trim())
+                    .and_then(|caps| caps[1].parse::<u64>().ok())
+                    .ok_or_else(|| anyhow!("Invalid role"))?,
+            ),
+        };
+        let reset = self.reset.as_deref().map(str::trim);
+        if let Some(reset) = reset {
+            if !RESETTABLE_SETTINGS.contains(&reset) {
+                return CommandResponse::private(format!("Unknown setting {reset}"));
+            }
+            let given = match reset {
+                "pick_limit" => self.pick_limit.is_some(),
+                "cover_template" => self.cover_template.is_some(),
+                "announce_channel" => announce_channel.is_some(),
+                "youtube_mirror" => self.youtube_mirror.is_some(),
+                "high_taste_role" => high_taste_role.is_some(),
+                _ => self.album_mode.is_some(),
+            };
+            if given {
+                return CommandResponse::private(format!("Can't both set and reset {reset}"));
+            }
+        }
+        let db = handler.db.lock().await;
+        db.conn.execute(
+            "INSERT INTO att_config
+                 (guild_id, spreadsheet_id, playlist_owner, pick_limit, cover_template,
+                  youtube_mirror, high_taste_role, album_mode)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT (guild_id) DO UPDATE
+                 SET spreadsheet_id = ?2, playlist_owner = ?3,
+                     pick_limit = COALESCE(?4, pick_limit),
+                     cover_template = COALESCE(?5, cover_template),
+                     youtube_mirror = COALESCE(?6, youtube_mirror),
+                     high_taste_role = COALESCE(?7, high_taste_role),
+                     album_mode = COALESCE(?8, album_mode)",
+            params![
+                guild_id.get(),
+                &spreadsheet_id,
+                &playlist_owner,
+                self.pick_limit,
+                &self.cover_template,
+                self.youtube_mirror,
+                high_taste_role,
+                &self.album_mode
+            ],
+        )?;
+        match reset {
+            // stored with the guild's settings
+            Some("announce_channel") | None => (),
+            // the column name is one of RESETTABLE_SETTINGS
+            Some(column) => {
+                db.conn.execute(
+                    &format!("UPDATE att_config SET {column} = NULL WHERE guild_id = ?1"),
+                    [guild_id.get()],
+                )?;
+            }
+        }
+        drop(db);
+        if reset == Some("announce_channel") {
+            handler
+                .module::<GuildSettings>()?
+                .set(handler, guild_id, &settings::ATT_ANNOUNCE_CHANNEL, None)
+                .await?;
+        }
+        if let Some(channel) = announce_channel {
+            handler
+                .module::<GuildSettings>()?
+                .set(
+                    handler,
+                    guild_id,
+                    &settings::ATT_ANNOUNCE_CHANNEL,
+                    Some(channel.to_string()),
+                )
+                .await?;
+        }
+        let pick_limit = AttConfig::get(handler, guild_id).await?.pick_limit;
+        CommandResponse::private(format!(
+            "Acquiring the Taste will use spreadsheet `{spreadsheet_id}` and create playlists \
+             for Spotify user `{playlist_owner}`, with up to {pick_limit} picks per submitter"
+        ))
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        match opt_name {
+            "album_mode" => opt
+                .add_string_choice("top track", "top")
+                .add_string_choice("all tracks", "all"),
+            "reset" => RESETTABLE_SETTINGS.iter().fold(opt, |opt, setting| {
+                opt.add_string_choice(*setting, *setting)
+            }),
+            _ => opt,
+        }
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "att_remove_track",
+    desc = "Remove a track from the current Acquiring the Taste playlist"
+)]
+pub struct AttRemoveTrack {
+    #[cmd(desc = "Spotify link to the track")]
+    link: String,
+}
+
+#[async_trait]
+impl BotCommand for AttRemoveTrack {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let config = AttConfig::get(handler, guild_id).await?;
+        let Variables {
+            edition,
+            last_playlist,
+            ..
+        } = Variables::get(handler, &config).await?;
+        let playlist = last_playlist
+            .as_deref()
+            .and_then(|p| PlaylistId::from_id_or_uri(p).ok())
+            .ok_or_else(|| anyhow!("There is no current playlist"))?;
+        let spotify: Arc<SpotifyOAuth> = handler.module_arc()?;
+        let pick = Pick {
+            submitter: String::new(),
+            song: String::new(),
+            link: self.link,
+        };
+        let cache: &SpotifyCache = handler.module()?;
+        let market = SpotifyMarket::for_guild(handler, Some(guild_id)).await;
+        let providers = album::track_providers(handler, market)?;
+        let pick = resolve_pick(
+            Arc::clone(&spotify),
+            cache,
+            market,
+            &providers,
+            pick,
+            AlbumMode::TopTrack,
+        )
+        .await
+        .map_err(|(_, e)| e)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No track found"))?;
+        let track_id =
+            track_id_from_url(&pick.link).ok_or_else(|| anyhow!("Not a spotify track URL"))?;
+        let track = TrackId::from_id(track_id.as_str())?;
+
+        spotify.client.refresh_token().await?;
+        spotify
+            .client
+            .playlist_remove_all_occurrences_of_items(playlist, [PlayableId::from(track)], None)
+            .await
+            .context("failed to remove track from playlist")?;
+
+        // mark the pick as removed in the spreadsheet
+        let sheets = handler.module::<Forms>()?.sheets_client.spreadsheets();
+        let rows = sheets
+            .values_get(&config.spreadsheet_id, "Picks!A:E")
+            .doit()
+            .await
+            .context("failed to get picks")?
+            .1
+            .values
+            .unwrap_or_default();
+        let edition = edition.to_string();
+        let row = rows.iter().position(|row| {
+            row.first() == Some(&edition)
+                && row.get(4).and_then(|link| track_id_from_url(link)).as_ref() == Some(&track_id)
+        });
+        let Some(row) = row else {
+            return CommandResponse::private(format!(
+                "Removed {} from the playlist, but could not find it in this edition's picks",
+                pick.song
+            ));
+        };
+        let req = ValueRange {
+            values: Some(vec![vec!["removed".to_string()]]),
+            ..Default::default()
+        };
+        sheets
+            .values_update(req, &config.spreadsheet_id, &format!("Picks!F{}", row + 1))
+            .value_input_option("USER_ENTERED")
+            .doit()
+            .await
+            .context("failed to mark pick as removed")?;
+        CommandResponse::private(format!("Removed {} from the playlist", pick.song))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "att_stats",
+    desc = "Show statistics about an Acquiring the Taste edition"
+)]
+pub struct AttStats {
+    #[cmd(desc = "Edition number, defaults to the latest")]
+    edition: Option<u64>,
+}
+
+// counts occurrences and returns the most frequent first
+fn most_frequent<'a>(items: impl Iterator<Item = &'a str>, n: usize) -> Vec<(&'a str, usize)> {
+    items
+        .counts()
+        .into_iter()
+        .sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)))
+        .take(n)
+        .collect()
+}
+
+fn format_counts(counts: &[(&str, usize)]) -> String {
+    if counts.is_empty() {
+        return "None".to_string();
+    }
+    counts
+        .iter()
+        .map(|(name, count)| format!("{name} ({count})"))
+        .join("\n")
+}
+
+async fn edition_stats(
+    handler: &Handler,
+    deferred: Option<(&Context, &CommandInteraction)>,
+    guild_id: GuildId,
+    edition: Option<u64>,
+) -> anyhow::Result<CreateEmbed> {
+    let config = AttConfig::get(handler, guild_id).await?;
+    let market = SpotifyMarket::for_guild(handler, Some(guild_id)).await;
+    let sheets = handler.module::<Forms>()?.sheets_client.spreadsheets();
+    let rows = sheets
+        .values_get(&config.spreadsheet_id, "Picks!A:F")
+        .doit()
+        .await
+        .context("failed to get picks")?
+        .1
+        .values
+        .unwrap_or_default();
+    // edition, submitter, user id, song, link, removed
+    let rows: Vec<_> = rows
+        .into_iter()
+        .filter(|row| row.len() >= 5 && row.get(5).map_or(true, |removed| removed.is_empty()))
+        .filter_map(|row| Some((row[0].parse::<u64>().ok()?, row)))
+        .collect();
+    let edition = edition
+        .or_else(|| rows.iter().map(|(edition, _)| *edition).max())
+        .ok_or_else(|| anyhow!("No picks found"))?;
+    let picks: Vec<_> = rows
+        .iter()
+        .filter(|(e, _)| *e == edition)
+        .map(|(_, row)| row)
+        .collect();
+    if picks.is_empty() {
+        bail!("No picks found for edition #{edition}");
+    }
+    let edition_submitters = most_frequent(picks.iter().map(|row| row[1].as_str()), 5);
+    let all_time_submitters = most_frequent(rows.iter().map(|(_, row)| row[1].as_str()), 5);
+
+    let spotify: Arc<SpotifyOAuth> = handler.module_arc()?;
+    let track_ids: Vec<_> = picks
+        .iter()
+        .filter_map(|row| track_id_from_url(&row[4]))
+        .filter_map(|id| TrackId::from_id(id).ok())
+        .collect();
+    let mut tracks = Vec::with_capacity(track_ids.len());
+    for chunk in track_ids.chunks(50) {
+        let mut fetched = with_retry(deferred, || {
+            spotify.client.tracks(chunk.iter().cloned(), market)
+        })
+        .await
+        .context("failed to get tracks")?;
+        tracks.append(&mut fetched);
+    }
+    let average_length = if tracks.is_empty() {
+        "Unknown".to_string()
+    } else {
+        let total: i64 = tracks
+            .iter()
+            .map(|track| track.duration.num_seconds())
+            .sum();
+        let average = total / tracks.len() as i64;
+        format!("{}:{:02}", average / 60, average % 60)
+    };
+    let artist_ids: Vec<_> = tracks
+        .iter()
+        .flat_map(|track| track.artists.iter())
+        .filter_map(|artist| artist.id.clone())
+        .unique()
+        .collect();
+    let mut genres = Vec::new();
+    for chunk in artist_ids.chunks(50) {
+        let artists = with_retry(deferred, || spotify.client.artists(chunk.iter().cloned()))
+            .await
+            .context("failed to get artists")?;
+        genres.extend(artists.into_iter().flat_map(|artist| artist.genres));
+    }
+    if genres.is_empty() {
+        // Spotify has no genres for many artists, use albums previously looked up on MusicBrainz
+        let db = handler.db.lock().await;
+        for track in &tracks {
+            let artist = SpotifyOAuth::artists_to_string(&track.album.artists);
+            match MusicBrainz::cached(&db.conn, &artist, &track.album.name) {
+                Ok(Some(info)) => genres.extend(info.genres),
+                Ok(None) => {}
+                Err(e) => eprintln!("Error reading MusicBrainz cache: {e:?}"),
+            }
+        }
+    }
+    let top_genres = most_frequent(genres.iter().map(String::as_str), 5);
+
+    Ok(CreateEmbed::new()
+        .title(format!("Acquiring the Taste #{edition}"))
+        .field("Picks", picks.len().to_string(), true)
+        .field(
+            "Submitters",
+            picks.iter().map(|row| &row[1]).unique().count().to_string(),
+            true,
+        )
+        .field("Average track length", average_length, true)
+        .field("Top submitters", format_counts(&edition_submitters), true)
+        .field(
+            "Top submitters (all time)",
+            format_counts(&all_time_submitters),
+            true,
+        )
+        .field("Top genres", format_counts(&top_genres), true))
+}
+
+#[async_trait]
+impl BotCommand for AttStats {
+    type Data = Handler;
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
         interaction
-            .edit_response(&ctx.http, EditInteractionResponse::new().content(&resp))
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(Default::default()),
+            )
             .await?;
+        let resp =
+            match edition_stats(handler, Some((ctx, interaction)), guild_id, self.edition).await {
+                Ok(embed) => EditInteractionResponse::new().embed(embed),
+                Err(e) => {
+                    eprintln!("{e:?}");
+                    EditInteractionResponse::new().content(e.to_string())
+                }
+            };
+        interaction.edit_response(&ctx.http, resp).await?;
         Ok(CommandResponse::None)
     }
 }
@@ -445,10 +1294,51 @@ impl Module for AcquiringTaste {
         builder
             .module::<SpotifyOAuth>()
             .await?
+            .module::<Spotify>()
+            .await?
             .module::<AlbumLookup>()
+            .await?
+            .module::<SpotifyCache>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await?
+            .module::<GuildSettings>()
             .await
     }
 
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS att_config (
+                guild_id INTEGER PRIMARY KEY,
+                spreadsheet_id STRING NOT NULL,
+                playlist_owner STRING NOT NULL
+            )",
+            [],
+        )?;
+        crate::add_column(&db.conn, "att_config", "pick_limit", "INTEGER")?;
+        crate::add_column(&db.conn, "att_config", "cover_template", "STRING")?;
+        crate::add_column(&db.conn, "att_config", "announce_channel", "INTEGER")?;
+        crate::add_column(&db.conn, "att_config", "youtube_mirror", "BOOLEAN")?;
+        crate::add_column(&db.conn, "att_config", "high_taste_role", "INTEGER")?;
+        crate::add_column(&db.conn, "att_config", "album_mode", "STRING")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS att_youtube_playlists (
+                guild_id INTEGER NOT NULL,
+                edition INTEGER NOT NULL,
+                playlist_id STRING NOT NULL,
+
+                UNIQUE(guild_id, edition)
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "INSERT OR IGNORE INTO att_config (guild_id, spreadsheet_id, playlist_owner)
+                 VALUES (?1, ?2, ?3)",
+            params![GUILD_ID.get(), FORM_SPREADSHEET, USER_ID],
+        )?;
+        Ok(())
+    }
+
     async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
         Ok(AcquiringTaste {})
     }
@@ -459,6 +1349,47 @@ impl Module for AcquiringTaste {
         _completion_handlers: &mut CompletionStore,
     ) {
         store.register::<BuildPlaylist>();
+        store.register::<AttConfigure>();
+        store.register::<AttRemoveTrack>();
+        store.register::<AttStats>();
         // store.register::<GetMySubmissions>();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn round_robin_spreads_submitters() {
+        let pick = |submitter: &str, song: &str| Pick {
+            submitter: submitter.to_string(),
+            song: song.to_string(),
+            link: String::new(),
+        };
+        let picks = vec![
+            pick("a", "1"),
+            pick("a", "2"),
+            pick("a", "3"),
+            pick("b", "1"),
+            pick("c", "1"),
+        ];
+        for seed in 0..10 {
+            let ordered = round_robin(picks.clone(), &mut StdRng::seed_from_u64(seed));
+            let songs = ordered
+                .iter()
+                .map(|pick| format!("{}{}", pick.submitter, pick.song))
+                .sorted()
+                .collect::<Vec<_>>();
+            assert_eq!(songs, ["a1", "a2", "a3", "b1", "c1"]);
+            // every submitter gets a pick in before anyone gets a second one
+            let first_round = ordered[..3]
+                .iter()
+                .map(|pick| pick.submitter.as_str())
+                .unique()
+                .count();
+            assert_eq!(first_round, 3);
+        }
+    }
+}
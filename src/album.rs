@@ -1,12 +1,31 @@
 use std::sync::Arc;
 
+use anyhow::anyhow;
+use rspotify::{
+    clients::BaseClient,
+    model::{Market, SearchResult, SearchType},
+    prelude::Id,
+};
 use serenity::async_trait;
+use serenity_command_handler::{
+    modules::{AlbumLookup, Spotify},
+    Handler,
+};
+
+use crate::{
+    bandcamp::Bandcamp, deezer::Deezer, forms::Forms, odesli, spotify_cache::SpotifyCache,
+    youtube::Youtube,
+};
+
+const TRACK_URL_START: &str = "https://open.spotify.com/track/";
 
 #[derive(Debug, Default)]
 pub struct Album {
     pub name: String,
     pub artist: String,
     pub url: String,
+    /// Tracklist, when the provider gives one
+    pub tracks: Vec<Track>,
 }
 
 #[async_trait]
@@ -48,3 +67,173 @@ impl<P: AlbumProvider + Send> AlbumProvider for Arc<P> {
         self.as_ref().query_album(q).await
     }
 }
+
+#[derive(Debug, Default, Clone)]
+pub struct Track {
+    pub title: String,
+    pub artist: String,
+    pub duration: Option<chrono::Duration>,
+    pub url: String,
+}
+
+impl Track {
+    pub fn format_name(&self) -> String {
+        if self.artist.is_empty() {
+            self.title.to_string()
+        } else {
+            format!("{} - {}", self.artist, self.title)
+        }
+    }
+}
+
+#[async_trait]
+pub trait TrackProvider: Send + Sync {
+    fn url_matches(&self, _url: &str) -> bool;
+
+    fn id(&self) -> &'static str;
+
+    async fn get_track(&self, url: &str) -> anyhow::Result<Track>;
+
+    async fn query_track(&self, _q: &str) -> anyhow::Result<Track>;
+}
+
+#[async_trait]
+impl<P: TrackProvider + Send> TrackProvider for Arc<P> {
+    fn url_matches(&self, url: &str) -> bool {
+        self.as_ref().url_matches(url)
+    }
+
+    fn id(&self) -> &'static str {
+        self.as_ref().id()
+    }
+
+    async fn get_track(&self, url: &str) -> anyhow::Result<Track> {
+        self.as_ref().get_track(url).await
+    }
+
+    async fn query_track(&self, q: &str) -> anyhow::Result<Track> {
+        self.as_ref().query_track(q).await
+    }
+}
+
+/// Spotify tracks, looked up through the cache
+struct SpotifyTracks {
+    spotify: Arc<Spotify>,
+    cache: Arc<SpotifyCache>,
+    market: Option<Market>,
+}
+
+#[async_trait]
+impl TrackProvider for SpotifyTracks {
+    fn url_matches(&self, url: &str) -> bool {
+        url.starts_with(TRACK_URL_START)
+    }
+
+    fn id(&self) -> &'static str {
+        "spotify"
+    }
+
+    async fn get_track(&self, url: &str) -> anyhow::Result<Track> {
+        let song = self
+            .cache
+            .track_from_url(&self.spotify.client, url, self.market)
+            .await?;
+        Ok(Track {
+            title: song.name,
+            artist: Spotify::artists_to_string(&song.artists),
+            duration: Some(song.duration),
+            url: song
+                .id
+                .map(|id| id.url())
+                .unwrap_or_else(|| url.to_string()),
+        })
+    }
+
+    async fn query_track(&self, q: &str) -> anyhow::Result<Track> {
+        let res = self
+            .spotify
+            .client
+            .search(q, SearchType::Track, self.market, None, Some(1), None)
+            .await?;
+        let SearchResult::Tracks(songs) = res else {
+            return Err(anyhow!("Not a track"));
+        };
+        let song = songs
+            .items
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No track found for {q}"))?;
+        Ok(Track {
+            title: song.name,
+            artist: Spotify::artists_to_string(&song.artists),
+            duration: Some(song.duration),
+            url: song.id.map(|id| id.url()).unwrap_or_default(),
+        })
+    }
+}
+
+/// Track providers available to the bot, Spotify first, looking Spotify tracks up in `market`
+///
+/// YouTube uses the Google client of the forms module, and is left out when it isn't loaded
+pub fn track_providers(
+    handler: &Handler,
+    market: Option<Market>,
+) -> anyhow::Result<Vec<Arc<dyn TrackProvider>>> {
+    let spotify = SpotifyTracks {
+        spotify: handler.module_arc()?,
+        cache: handler.module_arc()?,
+        market,
+    };
+    let mut providers: Vec<Arc<dyn TrackProvider>> = vec![
+        Arc::new(spotify),
+        Arc::new(Bandcamp::new()),
+        Arc::new(Deezer::new()),
+    ];
+    if let Ok(forms) = handler.module::<Forms>() {
+        providers.push(Arc::new(Youtube::new(
+            &forms.forms_client.client,
+            &forms.forms_client.authenticator,
+        )));
+    }
+    Ok(providers)
+}
+
+/// Looks a track link up with the matching provider, converting it to a Spotify link through
+/// Odesli when no provider supports it
+pub async fn get_track(providers: &[Arc<dyn TrackProvider>], url: &str) -> anyhow::Result<Track> {
+    if let Some(provider) = providers.iter().find(|p| p.url_matches(url)) {
+        return provider.get_track(url).await;
+    }
+    let converted = odesli::spotify_url(url)
+        .await
+        .map_err(|e| anyhow!("Unsupported link: {e}"))?;
+    let provider = providers
+        .iter()
+        .find(|p| p.url_matches(&converted))
+        .ok_or_else(|| anyhow!("Unsupported link: {url}"))?;
+    provider.get_track(&converted).await
+}
+
+/// Album providers the framework's album lookup does not cover
+pub fn extra_album_providers() -> Vec<Arc<dyn AlbumProvider>> {
+    vec![Arc::new(Deezer::new())]
+}
+
+/// Looks up the album a link points to with the framework's providers, then the extra ones,
+/// None if no provider recognizes the link
+pub async fn lookup_album(handler: &Handler, link: &str) -> anyhow::Result<Option<Album>> {
+    let lookup: &AlbumLookup = handler.module()?;
+    if let Some(p) = lookup.providers().iter().find(|p| p.url_matches(link)) {
+        let album = p.get_from_url(link).await?;
+        return Ok(Some(Album {
+            name: album.name.unwrap_or_default(),
+            artist: album.artist.unwrap_or_default(),
+            url: album.url.unwrap_or_else(|| link.to_string()),
+            tracks: Vec::new(),
+        }));
+    }
+    match extra_album_providers().iter().find(|p| p.url_matches(link)) {
+        Some(p) => Ok(Some(p.get_from_url(link).await?)),
+        None => Ok(None),
+    }
+}
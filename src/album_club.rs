@@ -1,44 +1,204 @@
-use std::str::FromStr;
-
-use anyhow::bail;
+use anyhow::{anyhow, Context as _};
 use chrono::Local;
+use fallible_iterator::FallibleIterator;
 use google_sheets4::api::ValueRange;
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, thread_rng};
+use regex::Regex;
+use rusqlite::{params, OptionalExtension};
 use serenity::{
-    async_trait, builder::CreateApplicationCommandOption, client::Context,
-    model::prelude::interaction::application_command::ApplicationCommandInteraction,
+    async_trait,
+    builder::{CreateCommand, CreateCommandOption, CreateEmbed},
+    client::Context,
+    futures::future::BoxFuture,
+    model::{
+        application::{CommandInteraction, CommandOptionType},
+        prelude::{GuildId, UserId},
+        Permissions,
+    },
+    FutureExt,
 };
-
-use crate::Handler;
-use serenity_command::{BotCommand, CommandResponse};
+use serenity_command::{BotCommand, CommandBuilder, CommandKey, CommandResponse};
 use serenity_command_derive::Command;
+use serenity_command_handler::{
+    db::Db,
+    modules::{AlbumLookup, Spotify},
+    CommandStore, CompletionStore, Handler, HandlerBuilder, Module, ModuleMap,
+};
+
+use crate::album::lookup_album;
+use crate::complete::complete_album_link;
+use crate::forms::Forms;
+use crate::lp_info::parse_month;
+use crate::spotify_market::SpotifyMarket;
 
-const FORM_SPREADSHEET: &str = "10lpL3w0Fm2TFcMdVhNNxfTvOONWI0BL4aP6-tW83RQQ";
 const SUBMISSIONS_RANGE: &str = "A:Z";
+/// Longest category name, as it is used as a command choice
+const MAX_CATEGORY_LEN: usize = 100;
+/// Most categories a guild can have, as Discord allows at most 25 choices per option
+const MAX_CATEGORIES: usize = 25;
+/// Number of picks highlighted in the monthly recap
+const RECAP_TOP: usize = 3;
+/// Days without a pick after which a submitter's odds stop increasing, also used for those who
+/// were never picked
+const MAX_ROTATION_DAYS: i64 = 180;
+
+static SPREADSHEET_URL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"/spreadsheets/d/([a-zA-Z0-9_-]+)").unwrap());
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
-#[repr(u8)]
-enum AlbumCategory {
-    Rock = 1,
-    Metal = 2,
-    Other = 3,
+/// Category albums are submitted to
+struct Category {
+    name: String,
+    /// Index of the pair of columns the album and its link are written to, kept when other
+    /// categories are removed so that the spreadsheet doesn't need to change
+    column: usize,
 }
 
-use AlbumCategory::*;
+/// Per-guild configuration of the album club
+struct AlbumClubConfig {
+    /// Spreadsheet submissions are added to
+    spreadsheet_id: String,
+    categories: Vec<Category>,
+}
 
-impl FromStr for AlbumCategory {
-    type Err = anyhow::Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s {
-            "Rock" => Rock,
-            "Metal" => Metal,
-            "Other" => Other,
-            _ => bail!("Invalid category: {}", s),
-        })
+impl AlbumClubConfig {
+    async fn get(handler: &Handler, guild_id: GuildId) -> anyhow::Result<Option<Self>> {
+        let db = handler.db.lock().await;
+        let Some(spreadsheet_id) = db
+            .conn
+            .query_row(
+                "SELECT spreadsheet_id FROM album_club_config WHERE guild_id = ?1",
+                [guild_id.get()],
+                |row| row.get(0),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let mut stmt = db.conn.prepare(
+            "SELECT name, column_index FROM album_club_categories
+                 WHERE guild_id = ?1 ORDER BY column_index",
+        )?;
+        let categories = stmt
+            .query([guild_id.get()])?
+            .map(|row| {
+                Ok(Category {
+                    name: row.get(0)?,
+                    column: row.get(1)?,
+                })
+            })
+            .collect()?;
+        Ok(Some(AlbumClubConfig {
+            spreadsheet_id,
+            categories,
+        }))
+    }
+
+    // command submitting to the categories of the guild
+    fn submit_command(&self) -> CreateCommand {
+        let category = self.categories.iter().fold(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "category",
+                "Category to submit to",
+            )
+            .required(true),
+            |opt, category| opt.add_string_choice(&category.name, &category.name),
+        );
+        let link = CreateCommandOption::new(
+            CommandOptionType::String,
+            "link",
+            "Link to the album (spotify/bandcamp/youtube preferred)",
+        )
+        .required(true)
+        .set_autocomplete(true);
+        CreateCommand::new(SubmitAlbum::NAME)
+            .description("Submit an album to the weekly Album Club")
+            .add_option(category)
+            .add_option(link)
     }
 }
 
-const CATEGORIES: [AlbumCategory; 3] = [Rock, Metal, Other];
+// creates the submission command of a guild with its current categories, or deletes it when
+// there are none
+async fn update_submit_command(
+    ctx: &Context,
+    guild_id: GuildId,
+    config: &AlbumClubConfig,
+) -> anyhow::Result<()> {
+    if !config.categories.is_empty() {
+        guild_id
+            .create_command(&ctx.http, config.submit_command())
+            .await?;
+        return Ok(());
+    }
+    let commands = guild_id.get_commands(&ctx.http).await?;
+    if let Some(cmd) = commands.iter().find(|cmd| cmd.name == SubmitAlbum::NAME) {
+        guild_id.delete_command(&ctx.http, cmd.id).await?;
+    }
+    Ok(())
+}
+
+/// Submitter with albums waiting to be picked
+struct Submitter {
+    user_id: UserId,
+    username: String,
+    pending: u64,
+    /// Last time one of their albums was picked, as a unix timestamp
+    last_picked: Option<i64>,
+}
 
+impl Submitter {
+    // submitters are more likely to be picked the longer they haven't been
+    fn weight(&self, now: i64) -> u64 {
+        let days = self
+            .last_picked
+            .map(|last| (now - last) / (24 * 60 * 60))
+            .unwrap_or(MAX_ROTATION_DAYS);
+        days.clamp(0, MAX_ROTATION_DAYS) as u64 + 1
+    }
+}
+
+// submitters with albums left to pick, optionally only in one category, most likely to be
+// picked first
+async fn rotation(
+    handler: &Handler,
+    guild_id: GuildId,
+    category: Option<&str>,
+) -> anyhow::Result<Vec<Submitter>> {
+    let db = handler.db.lock().await;
+    let mut stmt = db.conn.prepare(
+        "SELECT s.user_id, MAX(s.username), COUNT(*),
+                 (SELECT MAX(p.picked_at) FROM album_club_submissions p
+                      WHERE p.guild_id = s.guild_id AND p.user_id = s.user_id) AS last_picked
+             FROM album_club_submissions s
+             WHERE s.guild_id = ?1 AND s.picked_at IS NULL AND (?2 IS NULL OR s.category = ?2)
+             GROUP BY s.user_id
+             ORDER BY last_picked IS NOT NULL, last_picked, MIN(s.submitted_at)",
+    )?;
+    let submitters = stmt
+        .query(params![guild_id.get(), category])?
+        .map(|row| {
+            Ok(Submitter {
+                user_id: UserId::new(row.get(0)?),
+                username: row.get(1)?,
+                pending: row.get(2)?,
+                last_picked: row.get(3)?,
+            })
+        })
+        .collect()?;
+    Ok(submitters)
+}
+
+// artist and name of the album a link points to, if a provider recognizes it
+async fn album_info(handler: &Handler, link: &str) -> anyhow::Result<Option<String>> {
+    Ok(lookup_album(handler, link)
+        .await?
+        .map(|album| album.format_name()))
+}
+
+/// Registered in each guild by the module, with the guild's categories as choices
 #[derive(Command)]
 #[cmd(
     name = "submit_album_club",
@@ -62,23 +222,32 @@ impl BotCommand for SubmitAlbum {
         self,
         handler: &Handler,
         _ctx: &Context,
-        interaction: &ApplicationCommandInteraction,
-    ) -> anyhow::Result<serenity_command::CommandResponse> {
-        let category: AlbumCategory = self.category.parse()?;
-        let user = &interaction.user;
-        let user_handle = format!("{}#{:04}", &user.name, user.discriminator);
-        let mut values = vec!["".to_string(); 8];
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let config = AlbumClubConfig::get(handler, guild_id)
+            .await?
+            .ok_or_else(|| anyhow!("The album club is not configured in this server"))?;
+        let category = config
+            .categories
+            .iter()
+            .find(|category| category.name == self.category)
+            .ok_or_else(|| anyhow!("Invalid category: {}", &self.category))?;
+        let last_column = config
+            .categories
+            .iter()
+            .map(|category| category.column)
+            .max()
+            .unwrap_or_default();
+        let mut values = vec!["".to_string(); 2 + 2 * (last_column + 1)];
         let now = Local::now();
         let timestamp = format!("{}", now.format("%m/%d/%Y %H:%M:%S"));
-        let album_info =
-            if let Some(p) = handler.providers.iter().find(|p| p.url_matches(&self.link)) {
-                Some(p.get_from_url(&self.link).await?).map(|info| info.format_name())
-            } else {
-                None
-            };
+        let album_info = album_info(handler, &self.link).await?;
         values[0] = timestamp;
-        values[1] = user_handle;
-        let offset = category as usize * 2;
+        values[1] = interaction.user.name.clone();
+        let offset = 2 + category.column * 2;
         if let Some(info) = album_info.as_deref() {
             values[offset].push_str(info)
         }
@@ -90,26 +259,555 @@ impl BotCommand for SubmitAlbum {
             values: Some(vec![values]),
         };
         handler
+            .module::<Forms>()?
             .sheets_client
             .spreadsheets()
-            .values_append(request, FORM_SPREADSHEET, SUBMISSIONS_RANGE)
+            .values_append(request, &config.spreadsheet_id, SUBMISSIONS_RANGE)
             .value_input_option("USER_ENTERED")
             .doit()
             .await?;
+        // kept locally to pick from
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO album_club_submissions
+                 (guild_id, user_id, username, category, album, link, submitted_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                guild_id.get(),
+                interaction.user.id.get(),
+                &interaction.user.name,
+                &category.name,
+                album_info.as_deref(),
+                &self.link,
+                now.timestamp(),
+            ],
+        )?;
         let resp = format!(
-            "Submitted {} to the {:?} category",
+            "Submitted {} to the {} category",
             album_info.as_deref().unwrap_or(&self.link),
-            category,
+            &category.name,
         );
-        Ok(CommandResponse::Private(resp))
+        CommandResponse::private(resp)
     }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "album_club_configure",
+    desc = "Configure the album club spreadsheet and categories for this server"
+)]
+pub struct AlbumClubConfigure {
+    #[cmd(desc = "ID or URL of the spreadsheet submissions are added to")]
+    spreadsheet_id: Option<String>,
+    #[cmd(desc = "Add a category, written after the existing ones in the spreadsheet")]
+    add_category: Option<String>,
+    #[cmd(desc = "Remove a category, leaving its columns empty in the spreadsheet")]
+    remove_category: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for AlbumClubConfigure {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
 
-    fn setup_options(opt_name: &'static str, opt: &mut CreateApplicationCommandOption) {
-        if opt_name == "category" {
-            for cat in CATEGORIES.as_ref() {
-                let cat_str = format!("{:?}", cat);
-                opt.add_string_choice(&cat_str, &cat_str);
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let configured = AlbumClubConfig::get(handler, guild_id).await?;
+        let mut added = None;
+        let mut removed = None;
+        {
+            let db = handler.db.lock().await;
+            match &self.spreadsheet_id {
+                Some(spreadsheet_id) => {
+                    let spreadsheet_id = SPREADSHEET_URL_RE
+                        .captures(spreadsheet_id)
+                        .map(|cap| cap[1].to_string())
+                        .unwrap_or_else(|| spreadsheet_id.trim().to_string());
+                    db.conn.execute(
+                        "INSERT INTO album_club_config (guild_id, spreadsheet_id) VALUES (?1, ?2)
+                             ON CONFLICT (guild_id) DO UPDATE SET spreadsheet_id = ?2",
+                        params![guild_id.get(), &spreadsheet_id],
+                    )?;
+                }
+                None if configured.is_none() => {
+                    return CommandResponse::private("Set the spreadsheet first")
+                }
+                None => {}
+            }
+            if let Some(name) = &self.add_category {
+                let name = name.trim();
+                if name.is_empty() || name.chars().count() > MAX_CATEGORY_LEN {
+                    return CommandResponse::private("Invalid category name");
+                }
+                let count = configured
+                    .as_ref()
+                    .map_or(0, |config| config.categories.len());
+                if count >= MAX_CATEGORIES {
+                    return CommandResponse::private(format!(
+                        "The album club can't have more than {MAX_CATEGORIES} categories"
+                    ));
+                }
+                let inserted = db.conn.execute(
+                    "INSERT INTO album_club_categories (guild_id, name, column_index)
+                         SELECT ?1, ?2, COALESCE(MAX(column_index) + 1, 0)
+                         FROM album_club_categories WHERE guild_id = ?1",
+                    params![guild_id.get(), name],
+                );
+                match inserted {
+                    Err(rusqlite::Error::SqliteFailure(e, _))
+                        if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+                    {
+                        return CommandResponse::private("This category already exists")
+                    }
+                    res => res?,
+                };
+                added = Some(name);
+            }
+            if let Some(name) = &self.remove_category {
+                let name = name.trim();
+                let column: Option<usize> = db
+                    .conn
+                    .query_row(
+                        "SELECT column_index FROM album_club_categories
+                             WHERE guild_id = ?1 AND name = ?2",
+                        params![guild_id.get(), name],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                let Some(column) = column else {
+                    return CommandResponse::private(format!("No category named {name}"));
+                };
+                db.conn.execute(
+                    "DELETE FROM album_club_categories WHERE guild_id = ?1 AND name = ?2",
+                    params![guild_id.get(), name],
+                )?;
+                removed = Some((name, column));
             }
         }
+        let config = AlbumClubConfig::get(handler, guild_id)
+            .await?
+            .context("album club config was not saved")?;
+        if added.is_some() || removed.is_some() {
+            if let Err(e) = update_submit_command(ctx, guild_id, &config).await {
+                // undo the changes so the categories keep matching the command
+                let db = handler.db.lock().await;
+                if let Some(name) = added {
+                    db.conn.execute(
+                        "DELETE FROM album_club_categories WHERE guild_id = ?1 AND name = ?2",
+                        params![guild_id.get(), name],
+                    )?;
+                }
+                if let Some((name, column)) = removed {
+                    db.conn.execute(
+                        "INSERT INTO album_club_categories (guild_id, name, column_index)
+                             VALUES (?1, ?2, ?3)",
+                        params![guild_id.get(), name, column],
+                    )?;
+                }
+                return Err(e);
+            }
+        }
+        let categories = config
+            .categories
+            .iter()
+            .map(|category| category.name.as_str())
+            .collect::<Vec<_>>();
+        CommandResponse::private(format!(
+            "The album club uses spreadsheet `{}` with categories: {}",
+            &config.spreadsheet_id,
+            if categories.is_empty() {
+                "none, add one to create /submit_album_club".to_string()
+            } else {
+                categories.join(", ")
+            }
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "album_club_pick",
+    desc = "Pick the next album, favoring people whose albums weren't picked lately"
+)]
+pub struct PickAlbum {
+    #[cmd(desc = "Only pick from this category")]
+    category: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for PickAlbum {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let category = self.category.as_deref().map(str::trim);
+        let submitters = rotation(handler, guild_id, category).await?;
+        if submitters.is_empty() {
+            return CommandResponse::private("There are no albums left to pick");
+        }
+        let now = chrono::Utc::now().timestamp();
+        let weights = WeightedIndex::new(submitters.iter().map(|s| s.weight(now)))?;
+        let submitter = &submitters[weights.sample(&mut thread_rng())];
+        let (_, album, link, category): (u64, Option<String>, String, String) = {
+            let db = handler.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT id, album, link, category FROM album_club_submissions
+                     WHERE guild_id = ?1 AND user_id = ?2 AND picked_at IS NULL
+                     AND (?3 IS NULL OR category = ?3)",
+            )?;
+            let pending: Vec<(u64, Option<String>, String, String)> = stmt
+                .query(params![guild_id.get(), submitter.user_id.get(), category])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                .collect()?;
+            let picked = pending
+                .choose(&mut thread_rng())
+                .cloned()
+                .context("submission was picked concurrently")?;
+            db.conn.execute(
+                "UPDATE album_club_submissions SET picked_at = ?2 WHERE id = ?1",
+                params![picked.0, now],
+            )?;
+            picked
+        };
+        CommandResponse::public(format!(
+            "This week's {category} album is {}, submitted by <@{}>\n{link}\n\
+             Rate it with `/album_club_rate` once you've listened to it!",
+            album.as_deref().unwrap_or("this one"),
+            submitter.user_id,
+        ))
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "album_club_rotation",
+    desc = "Show who is next in line to have their album picked"
+)]
+pub struct AlbumClubRotation {
+    #[cmd(desc = "Only show submissions to this category")]
+    category: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for AlbumClubRotation {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let submitters =
+            rotation(handler, guild_id, self.category.as_deref().map(str::trim)).await?;
+        if submitters.is_empty() {
+            return CommandResponse::private("There are no albums left to pick");
+        }
+        let now = chrono::Utc::now().timestamp();
+        let total = submitters.iter().map(|s| s.weight(now)).sum::<u64>() as f64;
+        let queue = submitters
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                let last_picked = s
+                    .last_picked
+                    .map(|ts| format!("last picked <t:{ts}:R>"))
+                    .unwrap_or_else(|| "never picked".to_string());
+                format!(
+                    "{}. {} ({} pending, {last_picked}): {:.0}% chance",
+                    i + 1,
+                    &s.username,
+                    s.pending,
+                    s.weight(now) as f64 * 100.0 / total,
+                )
+            })
+            .join("\n");
+        CommandResponse::private(queue)
+    }
+}
+
+#[derive(Command)]
+#[cmd(name = "album_club_rate", desc = "Rate the album picked last")]
+pub struct RateAlbum {
+    #[cmd(desc = "Rating out of 10")]
+    rating: i64,
+    #[cmd(desc = "Rate the album picked last in this category instead")]
+    category: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for RateAlbum {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        if !(1..=10).contains(&self.rating) {
+            return CommandResponse::private("Ratings go from 1 to 10");
+        }
+        let db = handler.db.lock().await;
+        let Some((id, user_id, album)): Option<(u64, u64, Option<String>)> = db
+            .conn
+            .query_row(
+                "SELECT id, user_id, album FROM album_club_submissions
+                     WHERE guild_id = ?1 AND picked_at IS NOT NULL
+                     AND (?2 IS NULL OR category = ?2)
+                     ORDER BY picked_at DESC LIMIT 1",
+                params![guild_id.get(), self.category.as_deref().map(str::trim)],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+        else {
+            return CommandResponse::private("No album was picked yet");
+        };
+        if user_id == interaction.user.id.get() {
+            return CommandResponse::private("You can't rate your own submission");
+        }
+        db.conn.execute(
+            "INSERT INTO album_club_ratings (submission_id, user_id, rating, rated_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (submission_id, user_id) DO UPDATE SET rating = ?3, rated_at = ?4",
+            params![
+                id,
+                interaction.user.id.get(),
+                self.rating,
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        CommandResponse::private(format!(
+            "You rated {} {}/10",
+            album.as_deref().unwrap_or("the last album"),
+            self.rating
+        ))
+    }
+}
+
+/// Album picked during the month of a recap
+struct RecapPick {
+    username: String,
+    album: Option<String>,
+    link: String,
+    /// Average rating, if it was rated
+    average: Option<f64>,
+    ratings: u64,
+}
+
+impl RecapPick {
+    fn format(&self) -> String {
+        let rating = self
+            .average
+            .map(|average| format!("{average:.1}/10 ({} ratings)", self.ratings))
+            .unwrap_or_else(|| "not rated".to_string());
+        format!(
+            "[{}]({}) from {}: {rating}",
+            self.album.as_deref().unwrap_or("Album"),
+            &self.link,
+            &self.username,
+        )
+    }
+}
+
+#[derive(Command)]
+#[cmd(
+    name = "album_club_recap",
+    desc = "Summarize the ratings of the albums picked this month"
+)]
+pub struct AlbumClubRecap {
+    #[cmd(desc = "Summarize another month (YYYY-MM)")]
+    month: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for AlbumClubRecap {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let month = self
+            .month
+            .as_deref()
+            .map(|month| month.trim().to_string())
+            .unwrap_or_else(|| chrono::Utc::now().format("%Y-%m").to_string());
+        let Some((start, end)) = parse_month(&month) else {
+            return CommandResponse::private("Invalid month, use the YYYY-MM format");
+        };
+        // picks of the month with their average rating, best first
+        let picks: Vec<RecapPick> = {
+            let db = handler.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT s.username, s.album, s.link, AVG(r.rating), COUNT(r.rating)
+                     FROM album_club_submissions s
+                     LEFT JOIN album_club_ratings r ON r.submission_id = s.id
+                     WHERE s.guild_id = ?1 AND s.picked_at >= ?2 AND s.picked_at < ?3
+                     GROUP BY s.id
+                     ORDER BY AVG(r.rating) IS NULL, AVG(r.rating) DESC, s.picked_at",
+            )?;
+            let picks = stmt
+                .query(params![guild_id.get(), start, end])?
+                .map(|row| {
+                    Ok(RecapPick {
+                        username: row.get(0)?,
+                        album: row.get(1)?,
+                        link: row.get(2)?,
+                        average: row.get(3)?,
+                        ratings: row.get(4)?,
+                    })
+                })
+                .collect()?;
+            picks
+        };
+        if picks.is_empty() {
+            return CommandResponse::private(format!("No albums were picked in {month}"));
+        }
+        let ratings = picks.iter().map(|pick| pick.ratings).sum::<u64>();
+        let rated = picks
+            .iter()
+            .filter_map(|pick| pick.average)
+            .collect::<Vec<_>>();
+        let mut description = format!("{} albums picked, {ratings} ratings", picks.len());
+        if !rated.is_empty() {
+            let average = rated.iter().sum::<f64>() / rated.len() as f64;
+            description.push_str(&format!(", {average:.1}/10 on average"));
+        }
+        let top = picks
+            .iter()
+            .filter(|pick| pick.average.is_some())
+            .take(RECAP_TOP)
+            .map(RecapPick::format)
+            .join("\n");
+        let all = picks.iter().map(RecapPick::format).join("\n");
+        let mut embed = CreateEmbed::new()
+            .title(format!("Album Club recap for {month}"))
+            .description(description);
+        if !top.is_empty() {
+            embed = embed.field("Highest rated", top, false);
+        }
+        embed = embed.field("All picks", all, false);
+        CommandResponse::public(embed)
+    }
+}
+
+pub struct AlbumClub;
+
+impl AlbumClub {
+    fn complete_link<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        _key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if ac.data.name != SubmitAlbum::NAME {
+                return Ok(false);
+            }
+            complete_album_link(handler, ctx, ac).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for AlbumClub {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder
+            .module::<Spotify>()
+            .await?
+            .module::<AlbumLookup>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await?
+            .module::<Forms>()
+            .await
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS album_club_config (
+                guild_id INTEGER PRIMARY KEY,
+                spreadsheet_id STRING NOT NULL
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS album_club_categories (
+                guild_id INTEGER NOT NULL,
+                name STRING NOT NULL,
+                column_index INTEGER NOT NULL,
+
+                PRIMARY KEY (guild_id, name)
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS album_club_submissions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                username STRING NOT NULL,
+                category STRING NOT NULL,
+                album STRING,
+                link STRING NOT NULL,
+                submitted_at INTEGER NOT NULL,
+                picked_at INTEGER
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS album_club_ratings (
+                submission_id INTEGER NOT NULL REFERENCES album_club_submissions(id),
+                user_id INTEGER NOT NULL,
+                rating INTEGER NOT NULL,
+                rated_at INTEGER NOT NULL,
+
+                PRIMARY KEY (submission_id, user_id)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(AlbumClub)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<SubmitAlbum>();
+        store.register::<AlbumClubConfigure>();
+        store.register::<PickAlbum>();
+        store.register::<AlbumClubRotation>();
+        store.register::<RateAlbum>();
+        store.register::<AlbumClubRecap>();
+        completions.push(AlbumClub::complete_link);
     }
 }
@@ -0,0 +1,294 @@
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use fallible_iterator::FallibleIterator;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use once_cell::sync::OnceCell;
+use rand::{thread_rng, RngCore};
+use rusqlite::{params, OptionalExtension};
+use serde_json::{json, Value};
+use serenity::{
+    async_trait,
+    model::{
+        prelude::{CommandInteraction, GuildId},
+        Permissions,
+    },
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    acquiring_taste::{self, AcquiringTaste},
+    features::{self, Feature},
+    forms::Forms,
+    lp_info::ModLPInfo,
+};
+
+/// Number of random bytes in an API token
+const TOKEN_LEN: usize = 32;
+
+/// HTTP API for server admins, authenticated with a token for each guild
+pub struct Api {
+    /// Context of the gateway connection, for requests that send messages
+    ctx: OnceCell<Context>,
+}
+
+impl Api {
+    pub fn set_context(&self, ctx: &Context) {
+        _ = self.ctx.set(ctx.clone());
+    }
+}
+
+// only hashes of the tokens are stored
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "error": message }))
+}
+
+// boolean query parameters are true when given without a value
+fn query_flag(req: &Request<Body>, name: &str) -> bool {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, "true"));
+            (key == name).then_some(value)
+        })
+        .any(|value| matches!(value, "true" | "1"))
+}
+
+async fn authenticate(handler: &Handler, req: &Request<Body>) -> anyhow::Result<Option<GuildId>> {
+    let Some(token) = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return Ok(None);
+    };
+    let guild_id: Option<u64> = handler
+        .db
+        .lock()
+        .await
+        .conn
+        .query_row(
+            "SELECT guild_id FROM api_tokens WHERE token_hash = ?1",
+            [hash_token(token.trim())],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(guild_id.map(GuildId::new))
+}
+
+async fn list_forms(handler: &Handler, guild_id: GuildId) -> anyhow::Result<Value> {
+    let forms = handler.module::<Forms>()?.forms.read().await;
+    Ok(forms
+        .iter()
+        .filter(|form| form.guild_id == guild_id.get())
+        .map(|form| {
+            json!({
+                "command": &form.command_name,
+                "title": &form.form.title,
+                "url": &form.form.responder_uri,
+                "submission_type": &form.submission_type,
+            })
+        })
+        .collect())
+}
+
+// None if the guild has no such form
+async fn form_submissions(
+    handler: &Handler,
+    guild_id: GuildId,
+    command_name: &str,
+) -> anyhow::Result<Option<Value>> {
+    let exists = handler
+        .module::<Forms>()?
+        .forms
+        .read()
+        .await
+        .iter()
+        .any(|form| form.guild_id == guild_id.get() && form.command_name == command_name);
+    if !exists {
+        return Ok(None);
+    }
+    let db = handler.db.lock().await;
+    let mut stmt = db.conn.prepare(
+        "SELECT user_id, username, submitted_at, info, link FROM form_submissions
+             WHERE guild_id = ?1 AND command_name = ?2
+             ORDER BY rowid",
+    )?;
+    let submissions: Vec<Value> = stmt
+        .query(params![guild_id.get(), command_name])?
+        .map(|row| {
+            Ok(json!({
+                "user_id": row.get::<_, Option<u64>>(0)?.map(|id| id.to_string()),
+                "username": row.get::<_, String>(1)?,
+                "submitted_at": row.get::<_, Option<String>>(2)?,
+                "info": row.get::<_, Option<String>>(3)?,
+                "link": row.get::<_, Option<String>>(4)?,
+            }))
+        })
+        .collect()?;
+    Ok(Some(submissions.into()))
+}
+
+async fn build_att(
+    handler: &Handler,
+    api: &Api,
+    guild_id: GuildId,
+    req: &Request<Body>,
+) -> anyhow::Result<Value> {
+    let ctx = api
+        .ctx
+        .get()
+        .ok_or_else(|| anyhow!("not connected to Discord yet"))?;
+    let report = acquiring_taste::build_edition(
+        handler,
+        ctx,
+        guild_id,
+        query_flag(req, "reuse"),
+        query_flag(req, "dry_run"),
+        query_flag(req, "shuffle"),
+    )
+    .await?;
+    Ok(json!({ "report": report }))
+}
+
+// whether a feature can be used by the guild, with the module it needs loaded
+async fn available<T: Module>(handler: &Handler, guild_id: GuildId, feature: &Feature) -> bool {
+    handler.module::<T>().is_ok() && features::enabled_in(handler, Some(guild_id), feature).await
+}
+
+/// Answers requests to /api/
+pub async fn respond(handler: &Handler, req: Request<Body>) -> Response<Body> {
+    let Ok(api) = handler.module::<Api>() else {
+        return error_response(StatusCode::NOT_FOUND, "not found");
+    };
+    let guild_id = match authenticate(handler, &req).await {
+        Ok(Some(guild_id)) => guild_id,
+        Ok(None) => return error_response(StatusCode::UNAUTHORIZED, "invalid token"),
+        Err(e) => {
+            eprintln!("Error authenticating API request: {e:?}");
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "internal error");
+        }
+    };
+    let path = req.uri().path().trim_start_matches("/api/").to_string();
+    let segments = path.split('/').collect::<Vec<_>>();
+    let forms = available::<Forms>(handler, guild_id, &features::FORMS).await;
+    let lp = available::<ModLPInfo>(handler, guild_id, &features::LISTENING_PARTIES).await;
+    let att = available::<AcquiringTaste>(handler, guild_id, &features::ACQUIRING_TASTE).await;
+    let res = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["forms"]) if forms => list_forms(handler, guild_id).await.map(Some),
+        (&Method::GET, ["forms", command_name, "submissions"]) if forms => {
+            form_submissions(handler, guild_id, command_name).await
+        }
+        (&Method::GET, ["lp"]) if lp => match handler.module::<ModLPInfo>() {
+            Ok(lp) => Ok(Some(lp.guild_state(guild_id).await)),
+            Err(e) => Err(e),
+        },
+        (&Method::POST, ["att", "build"]) if att => {
+            build_att(handler, api, guild_id, &req).await.map(Some)
+        }
+        _ => Ok(None),
+    };
+    match res {
+        Ok(Some(body)) => json_response(StatusCode::OK, body),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "not found"),
+        Err(e) => {
+            eprintln!("Error answering API request {path}: {e:?}");
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string())
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "api_token",
+    desc = "Create a token to use the bot's HTTP API for this server"
+)]
+pub struct ApiToken {
+    #[cmd(desc = "Revoke the current token without creating a new one")]
+    revoke: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for ApiToken {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let db = data.db.lock().await;
+        if self.revoke == Some(true) {
+            db.conn.execute(
+                "DELETE FROM api_tokens WHERE guild_id = ?1",
+                [guild_id.get()],
+            )?;
+            return CommandResponse::private("The API token was revoked");
+        }
+        let mut bytes = [0; TOKEN_LEN];
+        thread_rng().fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        // each guild has a single token, creating one revokes the previous one
+        db.conn.execute(
+            "INSERT INTO api_tokens (guild_id, token_hash, created_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (guild_id) DO UPDATE SET token_hash = ?2, created_at = ?3",
+            params![
+                guild_id.get(),
+                hash_token(&token),
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        CommandResponse::private(format!(
+            "New API token, it won't be shown again and replaces the previous one:\n\
+             ||`{token}`||\nSend it in an `Authorization: Bearer <token>` header."
+        ))
+    }
+}
+
+#[async_trait]
+impl Module for Api {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS api_tokens (
+                guild_id INTEGER PRIMARY KEY,
+                token_hash STRING NOT NULL UNIQUE,
+                created_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Api {
+            ctx: OnceCell::new(),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<ApiToken>();
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::{
+    all::{CommandDataOption, CommandDataOptionValue},
+    async_trait,
+    builder::CreateEmbed,
+    model::{
+        application::Command,
+        prelude::{CommandInteraction, UserId},
+        Permissions,
+    },
+    prelude::{Context, RwLock},
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::lp_info::USER_MENTION_RE;
+
+/// Number of entries shown by /audit_log when no limit is given
+const DEFAULT_ENTRIES: u64 = 15;
+const MAX_ENTRIES: u64 = 50;
+/// Longest embed description Discord accepts
+const MAX_DESCRIPTION_LEN: usize = 4096;
+
+/// Records uses of the commands restricted to moderators
+pub struct AuditLog {
+    /// Names of the commands that require permissions to be used
+    audited: RwLock<HashSet<String>>,
+}
+
+// formats the options of a command as they would be typed
+fn format_options(options: &[CommandDataOption]) -> String {
+    options
+        .iter()
+        .map(|opt| match &opt.value {
+            CommandDataOptionValue::SubCommand(options)
+            | CommandDataOptionValue::SubCommandGroup(options) => {
+                format!("{} {}", opt.name, format_options(options))
+                    .trim_end()
+                    .to_string()
+            }
+            CommandDataOptionValue::String(value) => format!("{}: {value}", opt.name),
+            CommandDataOptionValue::Integer(value) => format!("{}: {value}", opt.name),
+            CommandDataOptionValue::Number(value) => format!("{}: {value}", opt.name),
+            CommandDataOptionValue::Boolean(value) => format!("{}: {value}", opt.name),
+            CommandDataOptionValue::User(id) => format!("{}: <@{id}>", opt.name),
+            CommandDataOptionValue::Role(id) => format!("{}: <@&{id}>", opt.name),
+            CommandDataOptionValue::Channel(id) => format!("{}: <#{id}>", opt.name),
+            value => format!("{}: {value:?}", opt.name),
+        })
+        .join(" ")
+}
+
+impl AuditLog {
+    /// Remembers which of the registered commands require permissions
+    pub async fn set_commands(&self, commands: &[Command]) {
+        let mut audited = self.audited.write().await;
+        audited.clear();
+        audited.extend(
+            commands
+                .iter()
+                .filter(|cmd| {
+                    cmd.default_member_permissions
+                        .is_some_and(|perms| !perms.is_empty())
+                })
+                .map(|cmd| cmd.name.clone()),
+        );
+    }
+
+    /// Records a command if it is restricted to moderators
+    pub async fn record(&self, handler: &Handler, cmd: &CommandInteraction) {
+        let Some(guild_id) = cmd.guild_id else {
+            return;
+        };
+        if !self.audited.read().await.contains(&cmd.data.name) {
+            return;
+        }
+        let res = handler.db.lock().await.conn.execute(
+            "INSERT INTO audit_log (guild_id, user_id, command, arguments, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                guild_id.get(),
+                cmd.user.id.get(),
+                &cmd.data.name,
+                format_options(&cmd.data.options),
+                chrono::Utc::now().timestamp(),
+            ],
+        );
+        if let Err(e) = res {
+            eprintln!("Error recording /{} in audit log: {e}", &cmd.data.name);
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "audit_log", desc = "Show recent uses of moderator commands")]
+pub struct ShowAuditLog {
+    #[cmd(desc = "Only show commands used by this user (mention)")]
+    user: Option<String>,
+    #[cmd(desc = "Only show uses of this command")]
+    command: Option<String>,
+    #[cmd(desc = "Number of entries to show (default: 15)")]
+    limit: Option<u64>,
+}
+
+#[async_trait]
+impl BotCommand for ShowAuditLog {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let user = match &self.user {
+            None => None,
+            Some(user) => match USER_MENTION_RE
+                .captures(user.trim())
+                .and_then(|caps| caps[1].parse().ok())
+            {
+                Some(id) => Some(UserId::new(id)),
+                None => return CommandResponse::private("Invalid user"),
+            },
+        };
+        let command = self
+            .command
+            .as_deref()
+            .map(|command| command.trim().trim_start_matches('/'));
+        let limit = self.limit.unwrap_or(DEFAULT_ENTRIES).clamp(1, MAX_ENTRIES);
+        let entries: Vec<(u64, String, String, i64)> = {
+            let db = data.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT user_id, command, arguments, created_at FROM audit_log
+                     WHERE guild_id = ?1
+                     AND (?2 IS NULL OR user_id = ?2)
+                     AND (?3 IS NULL OR command = ?3)
+                     ORDER BY id DESC LIMIT ?4",
+            )?;
+            let entries = stmt
+                .query(params![
+                    guild_id.get(),
+                    user.map(UserId::get),
+                    command,
+                    limit
+                ])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                .collect()?;
+            entries
+        };
+        if entries.is_empty() {
+            return CommandResponse::private("No matching commands were used");
+        }
+        let mut description = String::new();
+        for (user_id, command, arguments, created_at) in entries {
+            let line = format!("<t:{created_at}:f> <@{user_id}> `/{command} {arguments}`\n");
+            if description.len() + line.len() > MAX_DESCRIPTION_LEN {
+                break;
+            }
+            description.push_str(&line);
+        }
+        let embed = CreateEmbed::new()
+            .title("Audit log")
+            .description(description);
+        CommandResponse::private(embed)
+    }
+}
+
+#[async_trait]
+impl Module for AuditLog {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                command STRING NOT NULL,
+                arguments TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(AuditLog {
+            audited: Default::default(),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<ShowAuditLog>();
+    }
+}
@@ -0,0 +1,162 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use chrono::Utc;
+use serenity::{
+    async_trait,
+    builder::{
+        CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+    },
+    futures::future::{BoxFuture, FutureExt},
+    model::{prelude::CommandInteraction, Permissions},
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+/// Environment variable setting the directory backups are written to
+const BACKUP_DIR_VAR: &str = "HUMBLE_LEDGER_BACKUP_DIR";
+const DEFAULT_BACKUP_DIR: &str = "backups";
+/// Environment variable setting how many backups are kept
+const BACKUP_KEEP_VAR: &str = "HUMBLE_LEDGER_BACKUP_KEEP";
+const DEFAULT_BACKUP_KEEP: usize = 7;
+const BACKUP_PREFIX: &str = "humble_ledger-";
+const BACKUP_EXTENSION: &str = ".sqlite";
+/// Timestamp in backup file names, sorting them from oldest to newest
+const BACKUP_TIME_FORMAT: &str = "%Y-%m-%d-%H%M%S";
+
+/// Snapshots the database every day, keeping the most recent ones
+pub struct Backups {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl Backups {
+    // names of the existing backups, oldest first
+    fn existing(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut names = entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Writes a snapshot of the database and removes the oldest ones, returning its path
+    pub async fn backup(&self) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let name = format!(
+            "{BACKUP_PREFIX}{}{BACKUP_EXTENSION}",
+            Utc::now().format(BACKUP_TIME_FORMAT)
+        );
+        let path = self.dir.join(name);
+        // a separate connection lets the bot keep using the database while it is copied
+        let dest = path.clone();
+        tokio::task::spawn_blocking(move || snapshot(&dest)).await??;
+        let existing = self.existing();
+        let stale = existing.len().saturating_sub(self.keep);
+        for name in &existing[..stale] {
+            if let Err(e) = std::fs::remove_file(self.dir.join(name)) {
+                eprintln!("Error removing old backup {name}: {e}");
+            }
+        }
+        Ok(path)
+    }
+
+    /// Background task backing up the database unless it was already done today
+    pub fn daily<'a>(handler: &'a Handler, _ctx: &'a Context) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let backups = handler.module::<Backups>()?;
+            let today = format!("{BACKUP_PREFIX}{}", Utc::now().format("%Y-%m-%d"));
+            if !backups
+                .existing()
+                .iter()
+                .any(|name| name.starts_with(&today))
+            {
+                let path = backups.backup().await?;
+                eprintln!("Backed up database to {}", path.display());
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+fn snapshot(dest: &Path) -> anyhow::Result<()> {
+    let conn = crate::open_db()?;
+    conn.execute("VACUUM INTO ?1", [dest.to_string_lossy()])
+        .with_context(|| format!("failed to write backup to {}", dest.display()))?;
+    Ok(())
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "backup_now", desc = "Back up the bot's database")]
+pub struct BackupNow {}
+
+#[async_trait]
+impl BotCommand for BackupNow {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        // the database and its backup rotation are shared by every guild
+        crate::registration::check_owner(ctx, interaction).await?;
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(
+                    CreateInteractionResponseMessage::new().ephemeral(true),
+                ),
+            )
+            .await?;
+        let resp = match data.module::<Backups>()?.backup().await {
+            Ok(path) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                format!("Database backed up to `{name}`")
+            }
+            Err(e) => {
+                eprintln!("{e:?}");
+                format!("Backup failed: {e}")
+            }
+        };
+        interaction
+            .edit_response(&ctx.http, EditInteractionResponse::new().content(resp))
+            .await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+#[async_trait]
+impl Module for Backups {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        let dir = env::var_os(BACKUP_DIR_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_BACKUP_DIR));
+        let keep = match env::var(BACKUP_KEEP_VAR) {
+            Ok(keep) => keep
+                .parse()
+                .with_context(|| format!("{BACKUP_KEEP_VAR} is not a number"))?,
+            Err(_) => DEFAULT_BACKUP_KEEP,
+        };
+        Ok(Backups {
+            dir,
+            // the backup that was just written is always kept
+            keep: keep.max(1),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<BackupNow>();
+    }
+}
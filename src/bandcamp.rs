@@ -1,23 +1,82 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, Context as _};
 use reqwest::{Client, Url};
 use scraper::{Html, Selector};
+use serde_derive::Deserialize;
 use serenity::async_trait;
 
-use crate::album::{Album, AlbumProvider};
+use crate::album::{Album, AlbumProvider, Track, TrackProvider};
 
 const SEARCH_URL: &str = "https://bandcamp.com/search";
 
-fn contents(html: &Html, selector: &Selector) -> Option<String> {
-    Some(
-        html.select(selector)
-            .next()?
-            .text()
-            .next()?
-            .trim()
-            .to_string(),
-    )
+fn duration_from_secs(secs: f64) -> chrono::Duration {
+    chrono::Duration::milliseconds((secs * 1000.0) as i64)
 }
 
+/// Album data embedded in Bandcamp pages
+#[derive(Deserialize)]
+pub struct Tralbum {
+    pub artist: String,
+    pub url: Option<String>,
+    pub current: TralbumCurrent,
+    pub trackinfo: Vec<TralbumTrack>,
+    /// ID of the cover art
+    pub art_id: Option<u64>,
+    /// Release date, e.g. "01 Jan 2020 00:00:00 GMT"
+    pub album_release_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TralbumCurrent {
+    pub title: String,
+}
+
+#[derive(Deserialize)]
+pub struct TralbumTrack {
+    pub title: String,
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    /// Path of the track page, relative to the artist's page
+    pub title_link: Option<String>,
+}
+
+/// Extract the album data from a Bandcamp album page
+pub fn parse_tralbum(page: &str) -> anyhow::Result<Tralbum> {
+    let selector = Selector::parse("[data-tralbum]").unwrap();
+    let html = Html::parse_document(page);
+    let data = html
+        .select(&selector)
+        .next()
+        .and_then(|elem| elem.value().attr("data-tralbum"))
+        .ok_or_else(|| anyhow!("Not a Bandcamp album page"))?;
+    serde_json::from_str(data).context("parsing Bandcamp album")
+}
+
+/// Builds an album with its tracklist from the data embedded in its page
+pub fn album_from_tralbum(url: &Url, tralbum: Tralbum) -> Album {
+    // track links are relative to the artist's page
+    let base = url.origin().ascii_serialization();
+    let tracks = tralbum
+        .trackinfo
+        .into_iter()
+        .map(|track| Track {
+            title: track.title,
+            artist: tralbum.artist.clone(),
+            duration: track.duration.map(duration_from_secs),
+            url: track
+                .title_link
+                .map(|link| format!("{base}{link}"))
+                .unwrap_or_default(),
+        })
+        .collect();
+    Album {
+        name: tralbum.current.title,
+        artist: tralbum.artist,
+        url: url.to_string(),
+        tracks,
+    }
+}
+
+#[derive(Default)]
 pub struct Bandcamp {
     client: Client,
 }
@@ -32,51 +91,78 @@ impl AlbumProvider for Bandcamp {
         let mut url = Url::parse(url)?;
         url.query_pairs_mut().clear();
         let page = self.client.get(url.clone()).send().await?.text().await?;
-        let html = Html::parse_document(&page);
+        let tralbum = parse_tralbum(&page)?;
+        Ok(album_from_tralbum(&url, tralbum))
+    }
 
-        let title_selector = Selector::parse(".trackTitle").unwrap();
-        let title = contents(&html, &title_selector).ok_or_else(|| anyhow!("Not an album page"))?;
+    async fn query_album(&self, q: &str) -> anyhow::Result<Album> {
+        let url = self.search(q, "a").await?;
+        self.get_from_url(&url).await
+    }
 
-        let artist_selector = Selector::parse("#name-section>h3>span>a").unwrap();
-        let artist = contents(&html, &artist_selector)
-            .ok_or_else(|| anyhow!("Could not find album artist"))?;
+    fn url_matches(&self, url: &str) -> bool {
+        url.starts_with("https://") && url.contains(".bandcamp.com")
+    }
+}
 
-        Ok(Album {
-            name: title,
-            artist,
-            url: url.to_string(),
-        })
+impl Bandcamp {
+    pub fn new() -> Self {
+        Bandcamp {
+            client: Client::new(),
+        }
     }
 
-    async fn query_album(&self, q: &str) -> anyhow::Result<Album> {
+    // returns the url of the first search result, item_type is "a" for albums and "t" for tracks
+    async fn search(&self, q: &str, item_type: &str) -> anyhow::Result<String> {
         let mut query_url = Url::parse(SEARCH_URL).unwrap();
         query_url
             .query_pairs_mut()
             .append_pair("q", q)
-            .append_pair("item_type", "a");
+            .append_pair("item_type", item_type);
         let page = self.client.get(query_url).send().await?.text().await?;
 
         let url_selector = Selector::parse(".result-info>.heading>a").unwrap();
-        let url = Html::parse_document(&page)
+        Ok(Html::parse_document(&page)
             .select(&url_selector)
             .next()
             .ok_or_else(|| anyhow!("Not found"))?
             .value()
             .attr("href")
             .ok_or_else(|| anyhow!("Not found"))?
-            .to_string();
-        self.get_from_url(&url).await
+            .to_string())
     }
+}
 
-    fn url_matches(&self, url: &str) -> bool {
-        url.starts_with("https://") && url.contains(".bandcamp.com")
+#[async_trait]
+impl TrackProvider for Bandcamp {
+    fn id(&self) -> &'static str {
+        "bandcamp"
     }
-}
 
-impl Bandcamp {
-    pub fn new() -> Self {
-        Bandcamp {
-            client: Client::new(),
-        }
+    async fn get_track(&self, url: &str) -> anyhow::Result<Track> {
+        let mut url = Url::parse(url)?;
+        url.query_pairs_mut().clear();
+        let page = self.client.get(url.clone()).send().await?.text().await?;
+        let tralbum = parse_tralbum(&page)?;
+        let duration = tralbum
+            .trackinfo
+            .first()
+            .and_then(|track| track.duration)
+            .map(duration_from_secs);
+        Ok(Track {
+            title: tralbum.current.title,
+            artist: tralbum.artist,
+            duration,
+            url: url.to_string(),
+        })
+    }
+
+    async fn query_track(&self, q: &str) -> anyhow::Result<Track> {
+        let url = self.search(q, "t").await?;
+        self.get_track(&url).await
+    }
+
+    fn url_matches(&self, url: &str) -> bool {
+        url.starts_with("https://") && url.contains(".bandcamp.com/track/")
     }
 }
@@ -1,11 +1,14 @@
 use std::borrow::Borrow;
 
 use anyhow::anyhow;
+use itertools::Itertools;
+use reqwest::Url;
 use serenity::all::CommandInteraction;
 use serenity::builder::{CreateAutocompleteResponse, CreateInteractionResponse};
 use serenity::model::prelude::UserId;
 
-use rspotify::clients::BaseClient;
+use rspotify::model::{Market, SearchResult, SearchType, SimplifiedArtist};
+use rspotify::prelude::{BaseClient, Id, OAuthClient};
 use serenity::prelude::Context;
 use serenity_command::CommandBuilder;
 use serenity_command_handler::album::AlbumProvider;
@@ -13,22 +16,65 @@ use serenity_command_handler::command_context::{get_focused_option, get_str_opt_
 use serenity_command_handler::modules::Spotify;
 use serenity_command_handler::prelude::*;
 
+use crate::deezer::Deezer;
 use crate::forms::{
-    DeleteFormCommand, Forms, GetSubmissions, OverrideSubmissionsRange, RefreshFormCommand,
+    sanitize_name, CreateFormSheet, DeleteFormCommand, EditFormQuestion, Forms, GetSubmissions,
+    ImportSubmissions, OverrideSubmissionsRange, RefreshFormCommand, SetFormCooldown,
 };
+use crate::spotify_accounts::SpotifyAccounts;
 use crate::spotify_activity::SpotifyActivity;
+use crate::spotify_cache::SpotifyCache;
+use crate::spotify_link;
+use crate::spotify_market::SpotifyMarket;
 use crate::CompletionType;
 
+/// Longest name and value Discord accepts for autocomplete choices, in characters
+const MAX_CHOICE_LEN: usize = 100;
+/// Most autocomplete choices Discord accepts
+const MAX_CHOICES: usize = 25;
+/// Number of Deezer results suggested after Spotify's
+const DEEZER_RESULTS: usize = 5;
+/// Number of recently played tracks suggested from linked Spotify accounts
+const RECENT_TRACKS: usize = 5;
+/// Number of Spotify results ranked before picking the ones suggested
+const SEARCH_RESULTS: u32 = 20;
+/// Number of Spotify results suggested
+const SPOTIFY_RESULTS: usize = 10;
+/// Markers of re-recordings, which are rarely what users are looking for
+const PENALIZED_WORDS: &[&str] = &[
+    "karaoke",
+    "tribute",
+    "in the style of",
+    "made famous",
+    "originally performed",
+];
+
+// suggests the user's current track, or its album for album options
 async fn get_now_playing(
     handler: &Handler,
     user_id: UserId,
+    ty: &CompletionType,
+    market: Option<Market>,
 ) -> anyhow::Result<Option<(String, String)>> {
     let spotify: &Spotify = handler.module()?;
+    let cache: &SpotifyCache = handler.module()?;
     let activity: &SpotifyActivity = handler.module()?;
     let Some(np) = activity.user_now_playing(user_id).await else {
         return Ok(None);
     };
-    let track = spotify.client.track(np.clone(), None).await?;
+    let track = cache.track(&spotify.client, np.clone(), market).await?;
+    if *ty == CompletionType::Albums {
+        let album = track.album;
+        let Some(id) = album.id else {
+            return Ok(None);
+        };
+        let name = format!(
+            "{} - {}",
+            Spotify::artists_to_string(&album.artists),
+            &album.name
+        );
+        return Ok(Some((name, id.url())));
+    }
     let name = format!(
         "{} - {}",
         Spotify::artists_to_string(&track.artists),
@@ -41,32 +87,278 @@ async fn get_now_playing(
     Ok(Some((name, url)))
 }
 
+// suggests what the user played recently on their linked Spotify account, or the albums
+async fn recently_played(
+    handler: &Handler,
+    user_id: UserId,
+    ty: &CompletionType,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let accounts: &SpotifyAccounts = handler.module()?;
+    let Some(client) = accounts.user_client(handler, user_id).await? else {
+        return Ok(Vec::new());
+    };
+    let history = client.current_user_recently_played(Some(20), None).await;
+    accounts.save_token(handler, user_id, &client).await?;
+    let choices = history?
+        .items
+        .into_iter()
+        .filter_map(|played| {
+            let track = played.track;
+            match ty {
+                CompletionType::Songs => {
+                    let name = format!(
+                        "{} - {}",
+                        Spotify::artists_to_string(&track.artists),
+                        &track.name
+                    );
+                    Some((name, track.id?.url()))
+                }
+                CompletionType::Albums => {
+                    let album = track.album;
+                    let name = format!(
+                        "{} - {}",
+                        Spotify::artists_to_string(&album.artists),
+                        &album.name
+                    );
+                    Some((name, album.id?.url()))
+                }
+            }
+        })
+        .unique_by(|(_, url)| url.clone())
+        .take(RECENT_TRACKS)
+        .collect();
+    Ok(choices)
+}
+
+// lowercases and replaces punctuation with spaces, so names compare regardless of formatting
+fn normalize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .join(" ")
+}
+
+fn contains_words(haystack: &str, needle: &str) -> bool {
+    !needle.is_empty() && format!(" {haystack} ").contains(&format!(" {needle} "))
+}
+
+// scores a search result against the query, higher is better
+fn rank_score(query: &str, artists: &[SimplifiedArtist], name: &str, popularity: u32) -> i64 {
+    let query = normalize(query);
+    let name = normalize(name);
+    let artists = artists
+        .iter()
+        .map(|artist| normalize(&artist.name))
+        .collect::<Vec<_>>();
+    let mut score = i64::from(popularity) / 2;
+    if artists.iter().any(|artist| contains_words(&query, artist)) {
+        score += 40;
+    }
+    if contains_words(&query, &name) {
+        score += 20;
+    }
+    // share of the query's words found in the result, the last one may be incomplete
+    let result = format!("{} {name}", artists.join(" "));
+    let words = query.split_whitespace().collect::<Vec<_>>();
+    let found = words
+        .iter()
+        .filter(|word| result.split_whitespace().any(|w| w.starts_with(*word)))
+        .count();
+    score += (30 * found / words.len().max(1)) as i64;
+    if PENALIZED_WORDS
+        .iter()
+        .any(|word| result.contains(word) && !query.contains(word))
+    {
+        score -= 100;
+    }
+    score
+}
+
+// searches spotify for albums or songs, ranking results so covers don't come first
+async fn spotify_choices(
+    spotify: &Spotify,
+    query: &str,
+    ty: &CompletionType,
+    market: Option<Market>,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let search_type = match ty {
+        CompletionType::Albums => SearchType::Album,
+        CompletionType::Songs => SearchType::Track,
+    };
+    let results = spotify
+        .client
+        .search(query, search_type, market, None, Some(SEARCH_RESULTS), None)
+        .await?;
+    let ranked = match results {
+        SearchResult::Albums(albums) => albums
+            .items
+            .into_iter()
+            .filter_map(|album| {
+                let name = format!(
+                    "{} - {}",
+                    Spotify::artists_to_string(&album.artists),
+                    &album.name
+                );
+                let score = rank_score(query, &album.artists, &album.name, 0);
+                Some((score, name, album.id?.url()))
+            })
+            .collect::<Vec<_>>(),
+        SearchResult::Tracks(tracks) => tracks
+            .items
+            .into_iter()
+            .filter_map(|track| {
+                let name = format!(
+                    "{} - {}",
+                    Spotify::artists_to_string(&track.artists),
+                    &track.name
+                );
+                let score = rank_score(query, &track.artists, &track.name, track.popularity);
+                Some((score, name, track.id?.url()))
+            })
+            .collect(),
+        _ => return Err(anyhow!("Unexpected search result")),
+    };
+    Ok(ranked
+        .into_iter()
+        // stable, so spotify's order breaks ties
+        .sorted_by_key(|(score, _, _)| -score)
+        .take(SPOTIFY_RESULTS)
+        .map(|(_, name, url)| (name, url))
+        .collect())
+}
+
+// searches deezer for albums or songs, returning their names and urls
+async fn deezer_choices(query: &str, ty: &CompletionType) -> anyhow::Result<Vec<(String, String)>> {
+    let deezer = Deezer::new();
+    let choices = match ty {
+        CompletionType::Albums => deezer
+            .search_albums(query, DEEZER_RESULTS)
+            .await?
+            .into_iter()
+            .map(|album| (album.format_name(), album.url))
+            .collect(),
+        CompletionType::Songs => deezer
+            .search_tracks(query, DEEZER_RESULTS)
+            .await?
+            .into_iter()
+            .map(|track| (track.format_name(), track.url))
+            .collect(),
+    };
+    Ok(choices)
+}
+
+// resolves a shortened link, to show what it points to
+async fn shortened_link_choice(
+    handler: &Handler,
+    link: &str,
+    ty: &CompletionType,
+    market: Option<Market>,
+) -> anyhow::Result<(String, String)> {
+    let spotify: &Spotify = handler.module()?;
+    let url = spotify_link::resolve(link).await?;
+    match ty {
+        CompletionType::Albums => {
+            let album = spotify.get_from_url(&url).await?;
+            Ok((album.format_name(), album.url.unwrap_or(url)))
+        }
+        CompletionType::Songs => {
+            let cache: &SpotifyCache = handler.module()?;
+            let track = cache.track_from_url(&spotify.client, &url, market).await?;
+            let name = format!(
+                "{} - {}",
+                Spotify::artists_to_string(&track.artists),
+                &track.name
+            );
+            Ok((name, track.id.map(|id| id.url()).unwrap_or(url)))
+        }
+    }
+}
+
 async fn autocomplete_link(
     handler: &Handler,
     user_id: UserId,
     option: &str,
     ty: CompletionType,
+    market: Option<Market>,
 ) -> Vec<(String, String)> {
     let spotify: &Spotify = handler.module().unwrap();
-    if option.is_empty() && ty == CompletionType::Songs {
-        match get_now_playing(handler, user_id).await {
-            Ok(np) => return np.into_iter().collect(),
+    if option.is_empty() {
+        let mut choices = Vec::new();
+        match get_now_playing(handler, user_id, &ty, market).await {
+            Ok(np) => choices.extend(np),
             Err(e) => {
                 eprintln!("Error getting user's current track: {e}")
             }
         }
+        match recently_played(handler, user_id, &ty).await {
+            Ok(recent) => choices.extend(recent),
+            Err(e) => eprintln!("Error getting user's recently played tracks: {e:?}"),
+        }
+        return choices
+            .into_iter()
+            .unique_by(|(_, url)| url.clone())
+            .collect();
+    }
+    if spotify_link::is_shortened(option) {
+        return match shortened_link_choice(handler, option, &ty, market).await {
+            Ok(choice) => vec![choice],
+            Err(e) => {
+                eprintln!("Error resolving shortened link: {e}");
+                Vec::new()
+            }
+        };
     }
     if option.len() >= 5 && !(option.starts_with("https://") || option.starts_with("http://")) {
-        match ty {
-            CompletionType::Albums => spotify.query_albums(option).await,
-            CompletionType::Songs => spotify.query_songs(option).await,
-        }
-        .unwrap_or_default()
+        let spotify_results = async {
+            spotify_choices(spotify, option, &ty, market)
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Error searching Spotify: {e:?}");
+                    Vec::new()
+                })
+        };
+        let (mut choices, deezer) = futures::join!(spotify_results, deezer_choices(option, &ty));
+        choices.extend(
+            deezer
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, url)| (format!("{name} (Deezer)"), url)),
+        );
+        choices
     } else {
         Vec::new()
     }
 }
 
+// shortens a choice name to what Discord accepts, without splitting characters
+fn truncate_name(name: &str) -> String {
+    if name.chars().count() <= MAX_CHOICE_LEN {
+        return name.to_string();
+    }
+    let mut truncated = name.chars().take(MAX_CHOICE_LEN - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+// shortens a choice value to what Discord accepts, Spotify links are replaced with their URI
+// and other links lose their query string
+//
+// Returns None if the value can't be shortened enough
+fn shorten_value(value: String) -> Option<String> {
+    if value.chars().count() <= MAX_CHOICE_LEN {
+        return Some(value);
+    }
+    let shortened = spotify_link::url_to_uri(&value).or_else(|| {
+        let mut url = Url::parse(&value).ok()?;
+        url.set_query(None);
+        url.set_fragment(None);
+        Some(url.to_string())
+    })?;
+    (shortened.chars().count() <= MAX_CHOICE_LEN).then_some(shortened)
+}
+
 pub async fn process_autocomplete(
     handler: &Handler,
     ctx: &Context,
@@ -81,10 +373,33 @@ pub async fn process_autocomplete(
     let forms: &Forms = handler.module()?;
     let cmd_name = ac.data.name.as_str();
     match cmd_name {
+        EditFormQuestion::NAME if get_focused_option(options) == Some("question") => {
+            let command_name = get_str_opt_ac(options, "command_name").unwrap_or_default();
+            let opt = get_str_opt_ac(options, "question").unwrap_or_default();
+            let forms = forms.forms.read().await;
+            let Some(form) = forms
+                .iter()
+                .find(|form| form.guild_id == guild_id && form.command_name == command_name)
+            else {
+                return Ok(true);
+            };
+            choices = form
+                .form
+                .questions
+                .iter()
+                .map(|q| sanitize_name(&q.title))
+                .filter(|name| name.contains(opt))
+                .map(|name| (name.clone(), name))
+                .collect();
+        }
         DeleteFormCommand::NAME
+        | EditFormQuestion::NAME
         | RefreshFormCommand::NAME
         | GetSubmissions::NAME
-        | OverrideSubmissionsRange::NAME => {
+        | OverrideSubmissionsRange::NAME
+        | SetFormCooldown::NAME
+        | ImportSubmissions::NAME
+        | CreateFormSheet::NAME => {
             let opt = get_str_opt_ac(options, "command_name").unwrap_or_default();
             choices = forms
                 .forms
@@ -113,9 +428,12 @@ pub async fn process_autocomplete(
                     };
                     let ty = match form.submission_type.as_str() {
                         "album" => CompletionType::Albums,
+                        // episodes are not searched, the link has to be pasted
+                        "podcast" => return Ok(true),
                         _ => CompletionType::Songs,
                     };
-                    choices = autocomplete_link(handler, ac.user.id, val, ty).await;
+                    let market = SpotifyMarket::for_guild(handler, ac.guild_id).await;
+                    choices = autocomplete_link(handler, ac.user.id, val, ty, market).await;
                 } else {
                     return Ok(true);
                 }
@@ -124,14 +442,113 @@ pub async fn process_autocomplete(
             }
         }
     }
-    let resp =
-        choices
-            .into_iter()
-            .fold(CreateAutocompleteResponse::new(), |resp, (name, value)| {
-                let len = 100.min(name.len());
-                resp.add_string_choice(&name[..len], value)
-            });
+    respond_choices(ctx, ac, choices).await?;
+    Ok(true)
+}
+
+pub async fn respond_choices(
+    ctx: &Context,
+    ac: &CommandInteraction,
+    choices: Vec<(String, String)>,
+) -> anyhow::Result<()> {
+    let resp = choices
+        .into_iter()
+        .filter_map(|(name, value)| Some((truncate_name(&name), shorten_value(value)?)))
+        .take(MAX_CHOICES)
+        .fold(CreateAutocompleteResponse::new(), |resp, (name, value)| {
+            resp.add_string_choice(name, value)
+        });
     ac.create_response(&ctx.http, CreateInteractionResponse::Autocomplete(resp))
         .await?;
-    Ok(true)
+    Ok(())
+}
+
+/// Suggests albums for the focused option of a command, from the user's listening and searches
+pub async fn complete_album_link(
+    handler: &Handler,
+    ctx: &Context,
+    ac: &CommandInteraction,
+) -> anyhow::Result<()> {
+    complete_link(handler, ctx, ac, CompletionType::Albums).await
+}
+
+/// Suggests songs or albums for the focused option of a command
+pub async fn complete_link(
+    handler: &Handler,
+    ctx: &Context,
+    ac: &CommandInteraction,
+    ty: CompletionType,
+) -> anyhow::Result<()> {
+    let options = &ac.data.options;
+    let Some(val) = get_focused_option(options).and_then(|name| get_str_opt_ac(options, name))
+    else {
+        return Ok(());
+    };
+    let market = SpotifyMarket::for_guild(handler, ac.guild_id).await;
+    let choices = autocomplete_link(handler, ac.user.id, val, ty, market).await;
+    respond_choices(ctx, ac, choices).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_names_are_truncated_on_characters() {
+        let name = format!("{}é{}", "a".repeat(MAX_CHOICE_LEN - 2), "b".repeat(10));
+        let truncated = truncate_name(&name);
+        assert_eq!(truncated.chars().count(), MAX_CHOICE_LEN);
+        assert!(truncated.ends_with("é…"));
+        assert_eq!(truncate_name("Short name"), "Short name");
+    }
+
+    #[test]
+    fn long_values_are_shortened() {
+        let spotify = format!(
+            "https://open.spotify.com/album/4ddRx20FxcGU2ZJhateVym?si={}",
+            "x".repeat(MAX_CHOICE_LEN)
+        );
+        assert_eq!(
+            shorten_value(spotify).as_deref(),
+            Some("spotify:album:4ddRx20FxcGU2ZJhateVym")
+        );
+        let other = format!(
+            "https://example.com/album?ref={}",
+            "x".repeat(MAX_CHOICE_LEN)
+        );
+        assert_eq!(
+            shorten_value(other).as_deref(),
+            Some("https://example.com/album")
+        );
+        let path = format!("https://example.com/{}", "x".repeat(MAX_CHOICE_LEN));
+        assert_eq!(shorten_value(path), None);
+    }
+
+    #[test]
+    fn results_matching_the_query_rank_first() {
+        let artist = |name: &str| SimplifiedArtist {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let query = "radiohead ok computer";
+        let original = rank_score(query, &[artist("Radiohead")], "OK Computer", 60);
+        let other = rank_score(query, &[artist("Björk")], "Homogenic", 80);
+        let tribute = rank_score(
+            query,
+            &[artist("Various Artists")],
+            "OK Computer: A Tribute to Radiohead",
+            70,
+        );
+        assert!(original > other);
+        assert!(original > tribute);
+        // an incomplete last word still counts
+        assert!(
+            rank_score(
+                "radiohead ok comp",
+                &[artist("Radiohead")],
+                "OK Computer",
+                0
+            ) > rank_score("radiohead ok comp", &[artist("Radiohead")], "Kid A", 0)
+        );
+    }
 }
@@ -0,0 +1,169 @@
+use anyhow::{anyhow, bail, Context};
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serenity::async_trait;
+
+use crate::album::{Album, AlbumProvider, Track, TrackProvider};
+
+const API_URL: &str = "https://api.deezer.com";
+
+#[derive(Deserialize)]
+struct DeezerArtist {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct DeezerTrack {
+    title: String,
+    /// Duration in seconds
+    duration: Option<i64>,
+    link: String,
+    artist: DeezerArtist,
+}
+
+#[derive(Deserialize)]
+struct DeezerAlbum {
+    title: String,
+    link: String,
+    artist: DeezerArtist,
+}
+
+#[derive(Deserialize)]
+struct SearchResults<T> {
+    data: Vec<T>,
+}
+
+impl From<DeezerTrack> for Track {
+    fn from(track: DeezerTrack) -> Self {
+        Track {
+            title: track.title,
+            artist: track.artist.name,
+            duration: track.duration.map(chrono::Duration::seconds),
+            url: track.link,
+        }
+    }
+}
+
+impl From<DeezerAlbum> for Album {
+    fn from(album: DeezerAlbum) -> Self {
+        Album {
+            name: album.title,
+            artist: album.artist.name,
+            url: album.link,
+            tracks: Vec::new(),
+        }
+    }
+}
+
+/// Deezer's public API, which does not need authentication
+#[derive(Default)]
+pub struct Deezer {
+    client: Client,
+}
+
+impl Deezer {
+    pub fn new() -> Self {
+        Deezer {
+            client: Client::new(),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        let url = Url::parse_with_params(&format!("{API_URL}/{path}"), query)?;
+        let body = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        // errors are returned with a 200 status
+        let value: serde_json::Value = serde_json::from_str(&body)?;
+        if let Some(message) = value.pointer("/error/message").and_then(|m| m.as_str()) {
+            bail!("Deezer error: {message}");
+        }
+        serde_json::from_value(value).context("Unexpected response from Deezer")
+    }
+
+    // extracts the id from links like https://www.deezer.com/en/track/3135556
+    fn id_from_url<'a>(url: &'a str, kind: &str) -> Option<&'a str> {
+        let (_, rest) = url.split_once("deezer.com/")?;
+        let mut segments = rest.split(['/', '?']);
+        segments.find(|segment| *segment == kind)?;
+        segments
+            .next()
+            .filter(|id| id.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    pub async fn search_tracks(&self, q: &str, limit: usize) -> anyhow::Result<Vec<Track>> {
+        let limit = limit.to_string();
+        let results: SearchResults<DeezerTrack> = self
+            .get("search/track", &[("q", q), ("limit", &limit)])
+            .await?;
+        Ok(results.data.into_iter().map(Track::from).collect())
+    }
+
+    pub async fn search_albums(&self, q: &str, limit: usize) -> anyhow::Result<Vec<Album>> {
+        let limit = limit.to_string();
+        let results: SearchResults<DeezerAlbum> = self
+            .get("search/album", &[("q", q), ("limit", &limit)])
+            .await?;
+        Ok(results.data.into_iter().map(Album::from).collect())
+    }
+}
+
+#[async_trait]
+impl AlbumProvider for Deezer {
+    fn url_matches(&self, url: &str) -> bool {
+        Deezer::id_from_url(url, "album").is_some()
+    }
+
+    fn id(&self) -> &'static str {
+        "deezer"
+    }
+
+    async fn get_from_url(&self, url: &str) -> anyhow::Result<Album> {
+        let id = Deezer::id_from_url(url, "album").ok_or_else(|| anyhow!("Invalid deezer url"))?;
+        let album: DeezerAlbum = self.get(&format!("album/{id}"), &[]).await?;
+        Ok(album.into())
+    }
+
+    async fn query_album(&self, q: &str) -> anyhow::Result<Album> {
+        self.search_albums(q, 1)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Not found"))
+    }
+}
+
+#[async_trait]
+impl TrackProvider for Deezer {
+    fn url_matches(&self, url: &str) -> bool {
+        Deezer::id_from_url(url, "track").is_some()
+    }
+
+    fn id(&self) -> &'static str {
+        "deezer"
+    }
+
+    async fn get_track(&self, url: &str) -> anyhow::Result<Track> {
+        let id = Deezer::id_from_url(url, "track").ok_or_else(|| anyhow!("Invalid deezer url"))?;
+        let track: DeezerTrack = self.get(&format!("track/{id}"), &[]).await?;
+        Ok(track.into())
+    }
+
+    async fn query_track(&self, q: &str) -> anyhow::Result<Track> {
+        self.search_tracks(q, 1)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Not found"))
+    }
+}
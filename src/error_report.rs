@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::{
+    builder::{CreateEmbed, CreateMessage},
+    http::HttpError,
+    model::{
+        prelude::{ChannelId, GuildId},
+        ModelError, Permissions, Timestamp,
+    },
+    prelude::Context,
+};
+use serenity_command_handler::Handler;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::settings::{self, GuildSettings};
+
+/// Environment variable holding the ID of the channel errors are reported in
+const ERROR_CHANNEL_VAR: &str = "HUMBLE_LEDGER_ERROR_CHANNEL";
+/// Environment variables whose values must never appear in reports
+const SECRET_VARS: &[&str] = &[
+    "DISCORD_TOKEN",
+    "RSPOTIFY_CLIENT_SECRET",
+    "LISTENBRAINZ_KEY",
+    "SPOTIFY_ACCOUNTS_KEY",
+];
+/// Discord's error code for requests the bot lacks permissions for
+const MISSING_PERMISSIONS_CODE: isize = 50013;
+/// How long the same error is not reported again, as tasks that fail tend to keep failing
+const REPEAT_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Longest error chain put in a report, leaving room for the code block around it
+const MAX_REPORT_LEN: usize = 4000;
+
+static ERROR_CHANNEL: Lazy<Option<ChannelId>> = Lazy::new(|| {
+    env::var(ERROR_CHANNEL_VAR)
+        .ok()
+        .and_then(|id| id.trim().parse().ok())
+        .map(ChannelId::new)
+});
+
+/// Regex matching secrets passed in URLs or headers
+static SECRET_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)((?:token|key|secret|password|code)=|bearer |token )[^&\s]+").unwrap()
+});
+
+/// When each error was last reported
+static REPORTED: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+
+fn redact(text: &str) -> String {
+    let mut redacted = SECRET_RE.replace_all(text, "$1[redacted]").into_owned();
+    for var in SECRET_VARS {
+        if let Ok(secret) = env::var(var) {
+            if !secret.is_empty() {
+                redacted = redacted.replace(&secret, "[redacted]");
+            }
+        }
+    }
+    redacted
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_REPORT_LEN {
+        let mut end = MAX_REPORT_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push('…');
+    }
+    text
+}
+
+/// Logs an error and posts it to the error channel, if one is configured
+pub async fn report(ctx: &Context, context: &str, error: &anyhow::Error) {
+    eprintln!("Error in {context}: {error:?}");
+    let Some(channel) = *ERROR_CHANNEL else {
+        return;
+    };
+    let chain = error.chain().map(ToString::to_string).collect::<Vec<_>>();
+    let key = format!("{context}: {}", chain.join(": "));
+    {
+        let mut reported = REPORTED.lock().await;
+        reported.retain(|_, at| at.elapsed() < REPEAT_DELAY);
+        if reported.contains_key(&key) {
+            return;
+        }
+        reported.insert(key, Instant::now());
+    }
+    let description = truncate(redact(&chain.join("\ncaused by: ")));
+    let embed = CreateEmbed::new()
+        .title(format!("Error in {context}"))
+        .description(format!("```\n{description}\n```"))
+        .timestamp(Timestamp::now());
+    if let Err(e) = channel
+        .send_message(&ctx.http, CreateMessage::new().embed(embed))
+        .await
+    {
+        eprintln!("Error reporting error to {channel}: {e}");
+    }
+}
+
+/// Whether an error comes from the bot lacking permissions, along with the missing permissions
+/// when they are known (Discord does not say which ones it wanted)
+pub fn permission_error(error: &anyhow::Error) -> Option<Permissions> {
+    error
+        .chain()
+        .find_map(|cause| match cause.downcast_ref::<serenity::Error>()? {
+            serenity::Error::Model(ModelError::InvalidPermissions { required, present }) => {
+                Some(*required - *present)
+            }
+            serenity::Error::Http(HttpError::UnsuccessfulRequest(resp))
+                if resp.error.code == MISSING_PERMISSIONS_CODE =>
+            {
+                Some(Permissions::empty())
+            }
+            _ => None,
+        })
+}
+
+/// Posts a notice in the channel a guild set for its moderators, returning false if it has none
+/// or the notice could not be sent
+pub async fn notify_moderators(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: GuildId,
+    notice: &str,
+) -> bool {
+    let Ok(settings) = handler.module::<GuildSettings>() else {
+        return false;
+    };
+    let Some(channel) = settings.channel(guild_id, &settings::MOD_CHANNEL).await else {
+        return false;
+    };
+    let embed = CreateEmbed::new()
+        .description(notice)
+        .timestamp(Timestamp::now());
+    match channel
+        .send_message(&ctx.http, CreateMessage::new().embed(embed))
+        .await
+    {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Error notifying moderators in {channel}: {e}");
+            false
+        }
+    }
+}
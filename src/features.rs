@@ -0,0 +1,231 @@
+use std::env;
+
+use anyhow::bail;
+use serenity::{
+    builder::{CreateInteractionResponse, CreateInteractionResponseMessage},
+    model::prelude::{CommandInteraction, GuildId},
+    prelude::Context,
+};
+use serenity_command_handler::Handler;
+
+use crate::settings::{self, GuildSettings};
+
+/// Environment variable listing the features turned off for the whole deployment, separated by
+/// commas
+const DISABLED_VAR: &str = "HUMBLE_LEDGER_DISABLED_MODULES";
+
+/// Group of commands and event handlers that can be turned off
+pub struct Feature {
+    pub key: &'static str,
+    pub desc: &'static str,
+    /// Commands registered by the feature, rejected in guilds that turned it off
+    pub commands: &'static [&'static str],
+}
+
+pub const FORMS: Feature = Feature {
+    key: "forms",
+    desc: "Google Forms submission commands",
+    commands: &[
+        "command_from_form",
+        "preview_form_command",
+        "refresh_form_command",
+        "delete_form_command",
+        "list_forms",
+        "override_form_submissions_range",
+        "edit_form_question",
+        "set_form_cooldown",
+        "create_form_sheet",
+        "import_submissions",
+        "submission_digest",
+        "get_submissions",
+        "close_submissions",
+        "open_submissions",
+        "build_playlist_from",
+    ],
+};
+
+pub const ACQUIRING_TASTE: Feature = Feature {
+    key: "att",
+    desc: "Acquiring the Taste playlists",
+    commands: &[
+        "build_playlist",
+        "att_configure",
+        "att_remove_track",
+        "att_stats",
+    ],
+};
+
+pub const SPOTIFY_ACTIVITY: Feature = Feature {
+    key: "spotify_activity",
+    desc: "Tracking what members listen to on Spotify",
+    commands: &["listening_history", "whats_playing", "listening_trends"],
+};
+
+pub const LISTENING_PARTIES: Feature = Feature {
+    key: "lp",
+    desc: "Listening parties",
+    commands: &[
+        "lp_info",
+        "np",
+        "lp_join",
+        "lp_tracklist",
+        "lp_join_auto",
+        "lp_join_offset",
+        "lp_server_join_offset",
+        "lp_timezone",
+        "lp_server_timezone",
+        "lp_queue_add",
+        "lp_list",
+        "lp_select",
+        "lp_set_roles",
+        "lp_add_role",
+        "lp_start",
+        "lp_stop",
+        "lp_seek",
+        "lp_skip",
+        "lp_schedule",
+        "lp",
+        "lp_history",
+        "lp_thread_mode",
+        "lp_start_delay",
+        "lp_auto_ready_check",
+        "lp_event_channel",
+        "listenbrainz",
+    ],
+};
+
+pub const PINBOARD: Feature = Feature {
+    key: "pinboard",
+    desc: "Moving pinned messages to the pinboard",
+    commands: &[],
+};
+
+pub const RANDOM_REACTIONS: Feature = Feature {
+    key: "reactions",
+    desc: "Reacting to messages of some members at random",
+    commands: &[
+        "random_reaction_add",
+        "random_reaction_remove",
+        "random_reactions",
+    ],
+};
+
+pub const ALBUM_CLUB: Feature = Feature {
+    key: "album_club",
+    desc: "Album Club submissions",
+    commands: &[
+        "submit_album_club",
+        "album_club_configure",
+        "album_club_pick",
+        "album_club_rotation",
+        "album_club_rate",
+        "album_club_recap",
+    ],
+};
+
+pub const PLAYLISTS: Feature = Feature {
+    key: "playlists",
+    desc: "Playlist submission rounds",
+    commands: &[
+        "register_playlist",
+        "remove_playlist",
+        "list_playlists",
+        "playlist_submissions",
+        "playlist_finish_round",
+        "promote_backup",
+    ],
+};
+
+pub const RATINGS: Feature = Feature {
+    key: "ratings",
+    desc: "Album ratings and reviews",
+    commands: &["rate", "album_ratings", "my_ratings", "year_end_chart"],
+};
+
+pub const FEATURES: &[Feature] = &[
+    FORMS,
+    ACQUIRING_TASTE,
+    SPOTIFY_ACTIVITY,
+    LISTENING_PARTIES,
+    PINBOARD,
+    RANDOM_REACTIONS,
+    ALBUM_CLUB,
+    PLAYLISTS,
+    RATINGS,
+];
+
+pub fn find(key: &str) -> Option<&'static Feature> {
+    FEATURES.iter().find(|feature| feature.key == key.trim())
+}
+
+/// Features turned off for the whole deployment, whose modules are not loaded
+pub struct Deployment {
+    disabled: Vec<&'static str>,
+}
+
+impl Deployment {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut disabled = Vec::new();
+        for key in env::var(DISABLED_VAR).unwrap_or_default().split(',') {
+            if key.trim().is_empty() {
+                continue;
+            }
+            let Some(feature) = find(key) else {
+                bail!("Unknown module {key} in {DISABLED_VAR}");
+            };
+            disabled.push(feature.key);
+        }
+        let deployment = Deployment { disabled };
+        // Acquiring the Taste and the album club use the spreadsheets client of the forms module
+        if deployment.enabled(&ACQUIRING_TASTE) && !deployment.enabled(&FORMS) {
+            bail!("The att module can't be enabled without the forms module");
+        }
+        if deployment.enabled(&ALBUM_CLUB) && !deployment.enabled(&FORMS) {
+            bail!("The album_club module can't be enabled without the forms module");
+        }
+        Ok(deployment)
+    }
+
+    pub fn enabled(&self, feature: &Feature) -> bool {
+        !self.disabled.contains(&feature.key)
+    }
+}
+
+/// Whether a feature is turned on in a guild, always true outside of guilds
+pub async fn enabled_in(handler: &Handler, guild_id: Option<GuildId>, feature: &Feature) -> bool {
+    let (Some(guild_id), Ok(guild_settings)) = (guild_id, handler.module::<GuildSettings>()) else {
+        return true;
+    };
+    !guild_settings
+        .list(guild_id, &settings::DISABLED_MODULES)
+        .await
+        .iter()
+        .any(|key| key == feature.key)
+}
+
+/// Replies to commands of features turned off in the guild they were used in, returning whether
+/// the command was rejected
+pub async fn reject_disabled(handler: &Handler, ctx: &Context, cmd: &CommandInteraction) -> bool {
+    let Some(feature) = FEATURES
+        .iter()
+        .find(|feature| feature.commands.contains(&cmd.data.name.as_str()))
+    else {
+        return false;
+    };
+    if enabled_in(handler, cmd.guild_id, feature).await {
+        return false;
+    }
+    let resp = CreateInteractionResponseMessage::new()
+        .content(format!(
+            "The {} module is turned off in this server",
+            feature.key
+        ))
+        .ephemeral(true);
+    if let Err(e) = cmd
+        .create_response(&ctx.http, CreateInteractionResponse::Message(resp))
+        .await
+    {
+        eprintln!("Error rejecting /{}: {e}", &cmd.data.name);
+    }
+    true
+}
@@ -1,32 +1,38 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, collections::HashMap, sync::Arc, time::Instant};
 
 use anyhow::{anyhow, bail, Context as _};
-use chrono::Duration;
+use chrono::{Duration, Utc};
 use fallible_iterator::FallibleIterator;
-use google_sheets4::Sheets;
+use google_sheets4::{
+    api::{Spreadsheet, SpreadsheetProperties, ValueRange},
+    Sheets,
+};
 use hyper::{client::HttpConnector, Body, Method, Request, StatusCode};
 use hyper_tls::HttpsConnector;
 use itertools::Itertools;
 use regex::Regex;
-use rspotify::prelude::Id;
-use rusqlite::{params, Connection};
+use reqwest::Url;
+use rspotify::{
+    model::{AlbumId, EpisodeId, Market},
+    prelude::{BaseClient, Id},
+};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde_derive::{Deserialize, Serialize};
 use serenity::{
     async_trait,
-    builder::{CreateCommand, CreateCommandOption, CreateEmbed},
+    builder::{CreateCommand, CreateCommandOption, CreateEmbed, CreateMessage},
     futures::future::BoxFuture,
     model::{
         application::{CommandDataOptionValue, CommandInteraction, CommandOptionType},
-        prelude::GuildId,
+        prelude::{ChannelId, GuildId, UserId},
         user::User,
         Permissions,
     },
     prelude::{Context, RwLock},
     FutureExt,
 };
-use yup_oauth2::{authenticator::Authenticator, ServiceAccountAuthenticator};
 
-use serenity_command::{BotCommand, CommandKey, CommandResponse};
+use serenity_command::{BotCommand, CommandBuilder, CommandKey, CommandResponse};
 use serenity_command_derive::Command;
 use serenity_command_handler::{
     db::Db,
@@ -34,9 +40,20 @@ use serenity_command_handler::{
     prelude::*,
 };
 
+use crate::add_column;
+use crate::album::{self, extra_album_providers, track_providers};
 use crate::complete::process_autocomplete;
+use crate::google_auth::{self, GoogleAuthenticator, GoogleClient};
+use crate::musicbrainz::MusicBrainz;
+use crate::settings::{self, GuildSettings};
+use crate::spotify_cache::{self, SpotifyCache};
+use crate::spotify_market::SpotifyMarket;
+use crate::submission_status::SubmissionStatus;
+use crate::{odesli, rym, spotify_link};
 
 const DEFAULT_RANGE: &str = "B:Z";
+/// How long album submissions wait for MusicBrainz before confirming without its metadata
+const MUSICBRAINZ_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1500);
 
 // use crate::{spotify, Handler};
 
@@ -87,6 +104,12 @@ pub struct QuestionItem {
 #[derive(Deserialize, Debug)]
 pub struct QuestionGroupItem {
     pub questions: Vec<Question>,
+    pub grid: Option<Grid>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Grid {
+    pub columns: ChoiceQuestion,
 }
 
 #[derive(Deserialize, Debug)]
@@ -168,7 +191,9 @@ pub struct TimeQuestion {}
 pub struct FileUploadQuestion {}
 
 #[derive(Deserialize, Debug)]
-pub struct RowQuestion {}
+pub struct RowQuestion {
+    pub title: String,
+}
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SimpleForm {
@@ -177,6 +202,9 @@ pub struct SimpleForm {
     pub questions: Vec<SimpleQuestion>,
     pub responder_uri: String,
     pub sheet_id: Option<String>,
+    /// Whether the sheet was created by the bot, which then has to append responses itself
+    #[serde(default)]
+    pub sheet_managed: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -186,6 +214,8 @@ pub struct SimpleQuestion {
     pub required: bool,
     pub title: String,
     pub ty: QuestionType,
+    #[serde(default)]
+    pub validation: Option<Validation>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -194,37 +224,137 @@ pub enum QuestionType {
     Choice(Vec<String>),
 }
 
+/// Admin-defined rules a submitted value must satisfy
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Validation {
+    pub regex: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub max_length: Option<u64>,
+}
+
+impl Validation {
+    pub fn is_empty(&self) -> bool {
+        self.regex.is_none()
+            && self.min.is_none()
+            && self.max.is_none()
+            && self.max_length.is_none()
+    }
+
+    pub fn check(&self, title: &str, value: &str) -> anyhow::Result<()> {
+        if let Some(max_length) = self.max_length {
+            if value.chars().count() as u64 > max_length {
+                bail!("{title} must be at most {max_length} characters long");
+            }
+        }
+        if let Some(re) = &self.regex {
+            let re = Regex::new(re).context("Invalid validation rule")?;
+            if !re.is_match(value) {
+                bail!("{title} must match the pattern `{}`", re.as_str());
+            }
+        }
+        if self.min.is_some() || self.max.is_some() {
+            let n: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("{title} must be a number"))?;
+            if let Some(min) = self.min.filter(|&min| n < min as f64) {
+                bail!("{title} must be at least {min}");
+            }
+            if let Some(max) = self.max.filter(|&max| n > max as f64) {
+                bail!("{title} must be at most {max}");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let mut rules = Vec::new();
+        if let Some(re) = &self.regex {
+            rules.push(format!("matches `{re}`"));
+        }
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => rules.push(format!("between {min} and {max}")),
+            (Some(min), None) => rules.push(format!("at least {min}")),
+            (None, Some(max)) => rules.push(format!("at most {max}")),
+            (None, None) => {}
+        }
+        if let Some(max_length) = self.max_length {
+            rules.push(format!("at most {max_length} characters"));
+        }
+        rules.join(", ")
+    }
+}
+
+fn choice_values(choice: &ChoiceQuestion) -> anyhow::Result<Vec<String>> {
+    if choice.ty == ChoiceType::Checkbox {
+        bail!("Checkboxes are not supported");
+    }
+    if choice.options.iter().any(|opt| opt.is_other) {
+        bail!("'Other' field is not supported");
+    }
+    Ok(choice.options.iter().map(|opt| opt.value.clone()).collect())
+}
+
 impl Item {
-    pub fn to_simple(&self) -> Option<anyhow::Result<SimpleQuestion>> {
+    // converts this item to the questions it contains, grids yield one question per row
+    pub fn to_simple(&self) -> anyhow::Result<Vec<SimpleQuestion>> {
+        if let Some(group) = &self.question_group {
+            return self.group_to_simple(group);
+        }
         let question = match &self.question {
             Some(q) => &q.question,
-            _ => return None,
+            _ => return Ok(Vec::new()),
         };
         let title = match self.title.as_deref() {
             Some(title) => title.to_string(),
-            None => return Some(Err(anyhow!("Question is missing a title"))),
+            None => bail!("Question is missing a title"),
         };
         let required = question.required;
         let ty = if question.text.is_some() {
             QuestionType::Text
         } else if let Some(choice) = question.choice.as_ref() {
-            if choice.ty == ChoiceType::Checkbox {
-                return Some(Err(anyhow!("Checkboxes are not supported")));
-            }
-            if choice.options.iter().any(|opt| opt.is_other) {
-                return Some(Err(anyhow!("'Other' field is not supported")));
-            }
-            let values = choice.options.iter().map(|opt| opt.value.clone()).collect();
-            QuestionType::Choice(values)
+            QuestionType::Choice(choice_values(choice)?)
         } else {
-            return Some(Err(anyhow!("Can only handle text or choice questions")));
+            bail!("Can only handle text or choice questions");
         };
-        Some(Ok(SimpleQuestion {
+        Ok(vec![SimpleQuestion {
             id: question.id.clone(),
             required,
             title,
             ty,
-        }))
+            validation: None,
+        }])
+    }
+
+    fn group_to_simple(&self, group: &QuestionGroupItem) -> anyhow::Result<Vec<SimpleQuestion>> {
+        let Some(grid) = &group.grid else {
+            bail!("Can only handle grid question groups");
+        };
+        let values = choice_values(&grid.columns)?;
+        let group_title = self.title.as_deref().unwrap_or_default();
+        group
+            .questions
+            .iter()
+            .map(|question| {
+                let row = question
+                    .row
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Grid question is missing a row"))?;
+                let title = if group_title.is_empty() {
+                    row.title.clone()
+                } else {
+                    format!("{group_title} {}", &row.title)
+                };
+                Ok(SimpleQuestion {
+                    id: question.id.clone(),
+                    required: question.required,
+                    title,
+                    ty: QuestionType::Choice(values.clone()),
+                    validation: None,
+                })
+            })
+            .collect()
     }
 }
 
@@ -240,8 +370,11 @@ impl Form {
         let questions = self
             .items
             .iter()
-            .filter_map(Item::to_simple)
-            .collect::<anyhow::Result<Vec<_>>>()?;
+            .map(Item::to_simple)
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
         let responder_uri = self.uri.clone();
         let sheet_id = self
             .linked_sheet_id
@@ -254,6 +387,7 @@ impl Form {
             questions,
             responder_uri,
             sheet_id,
+            sheet_managed: false,
         })
     }
 }
@@ -291,9 +425,16 @@ pub fn sanitize_name(s: &str) -> String {
     out
 }
 
+/// A command option generated from a form question
+pub struct FormOption<'a> {
+    pub name: String,
+    pub question: &'a SimpleQuestion,
+    pub autocomplete: bool,
+}
+
 impl SimpleForm {
-    pub fn to_command(&self, command_name: &str) -> CreateCommand {
-        let mut cmd = CreateCommand::new(sanitize_name(command_name)).description(&self.title);
+    /// Computes the options generated for this form's questions, in registration order
+    pub fn command_options(&self) -> Vec<FormOption<'_>> {
         // skip first question, assumed to be username
         let mut questions = self.questions.iter().skip(1).collect::<Vec<_>>();
         // discord requires required options to be first
@@ -302,9 +443,9 @@ impl SimpleForm {
             (false, true) => Ordering::Greater,
             (true, false) => Ordering::Less,
         });
+        let mut options = Vec::with_capacity(questions.len());
         let mut autocomplete = false;
         for (i, q) in questions.iter().enumerate() {
-            let sanitized = sanitize_name(&q.title);
             if let Some(next) = questions.get(i + 1) {
                 let next_lower = next.title.to_lowercase();
                 if matches!(q.ty, QuestionType::Text)
@@ -316,7 +457,25 @@ impl SimpleForm {
                     continue;
                 }
             }
-            let mut opt = CreateCommandOption::new(CommandOptionType::String, &sanitized, &q.title)
+            options.push(FormOption {
+                name: sanitize_name(&q.title),
+                question: q,
+                autocomplete,
+            });
+            autocomplete = false;
+        }
+        options
+    }
+
+    pub fn to_command(&self, command_name: &str) -> CreateCommand {
+        let mut cmd = CreateCommand::new(sanitize_name(command_name)).description(&self.title);
+        for FormOption {
+            name,
+            question: q,
+            autocomplete,
+        } in self.command_options()
+        {
+            let mut opt = CreateCommandOption::new(CommandOptionType::String, &name, &q.title)
                 .required(q.required)
                 .set_autocomplete(autocomplete);
             if let QuestionType::Choice(values) = &q.ty {
@@ -325,15 +484,52 @@ impl SimpleForm {
                     .fold(opt, |opt, v| opt.add_string_choice(v, v));
             }
             cmd = cmd.add_option(opt);
-            autocomplete = false;
         }
         cmd
     }
+
+    pub fn preview_embed(&self, command_name: &str) -> CreateEmbed {
+        let options = self.command_options();
+        let skipped = self
+            .questions
+            .iter()
+            .filter(|q| !options.iter().any(|opt| opt.question.id == q.id))
+            .map(|q| format!("· {}", &q.title))
+            .join("\n");
+        let mut embed = CreateEmbed::default()
+            .title(format!("Preview of /{}", sanitize_name(command_name)))
+            .description(format!(
+                "Options generated from [{}]({})",
+                &self.title, &self.responder_uri
+            ));
+        for opt in &options {
+            let ty = match &opt.question.ty {
+                QuestionType::Text => "text".to_string(),
+                QuestionType::Choice(values) => format!("choice ({} values)", values.len()),
+            };
+            let yes_no = |b: bool| if b { "yes" } else { "no" };
+            let mut value = format!(
+                "{}\nType: {ty}\nRequired: {}\nAutocomplete: {}",
+                &opt.question.title,
+                yes_no(opt.question.required),
+                yes_no(opt.autocomplete),
+            );
+            if let Some(validation) = &opt.question.validation {
+                value.push_str("\nValidation: ");
+                value.push_str(&validation.describe());
+            }
+            embed = embed.field(&opt.name, value, true);
+        }
+        if !skipped.is_empty() {
+            embed = embed.field("Filled in automatically", skipped, false);
+        }
+        embed
+    }
 }
 
 pub struct FormsClient {
-    pub authenticator: Authenticator<HttpsConnector<HttpConnector>>,
-    pub client: hyper::Client<HttpsConnector<HttpConnector>>,
+    pub authenticator: GoogleAuthenticator,
+    pub client: GoogleClient,
 }
 
 impl FormsClient {
@@ -354,6 +550,32 @@ impl FormsClient {
         let form: Form = serde_json::from_slice(&bytes)?;
         form.to_simple()
     }
+
+    /// Gives an email address edit access to a file created by the bot
+    pub async fn share_file(&self, file_id: &str, email: &str) -> anyhow::Result<()> {
+        let token = self
+            .authenticator
+            .token(&["https://www.googleapis.com/auth/drive.file"])
+            .await?;
+        let body = serde_json::json!({
+            "type": "user",
+            "role": "writer",
+            "emailAddress": email,
+        });
+        let req = Request::builder()
+            .uri(format!(
+                "https://www.googleapis.com/drive/v3/files/{file_id}/permissions?sendNotificationEmail=false"
+            ))
+            .method(Method::POST)
+            .header("Authorization", format!("Bearer {}", token.as_str()))
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&body)?))?;
+        let resp = self.client.request(req).await?;
+        if resp.status() != StatusCode::OK {
+            bail!("Could not share file: status {}", resp.status());
+        }
+        Ok(())
+    }
 }
 
 pub struct FormCommand {
@@ -363,6 +585,8 @@ pub struct FormCommand {
     pub form: SimpleForm,
     pub submission_type: String,
     pub submissions_range: Option<String>,
+    /// Minimum number of seconds between two submissions from the same user
+    pub cooldown: Option<u64>,
 }
 
 #[derive(Command, Debug)]
@@ -393,6 +617,7 @@ impl BotCommand for CommandFromForm {
         let guild_id = interaction
             .guild_id
             .ok_or_else(|| anyhow!("Must be run in a guild"))?;
+        check_name_conflict(handler, ctx, guild_id, &self.command_name).await?;
         self.add_form(handler, ctx, guild_id).await
     }
 
@@ -400,12 +625,56 @@ impl BotCommand for CommandFromForm {
         if opt_name == "submission_type" {
             opt.add_string_choice("song", "song")
                 .add_string_choice("album", "album")
+                .add_string_choice("podcast episode", "podcast")
         } else {
             opt
         }
     }
 }
 
+// makes sure a form command would not shadow another command
+async fn check_name_conflict(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: GuildId,
+    command_name: &str,
+) -> anyhow::Result<()> {
+    let name = sanitize_name(command_name);
+    if name.is_empty() {
+        bail!("Invalid command name: {command_name}");
+    }
+    if handler.commands.read().await.0.keys().any(|k| *k == name) {
+        bail!("/{name} is already one of the bot's commands, please pick another name");
+    }
+    let is_form = handler
+        .module::<Forms>()?
+        .forms
+        .read()
+        .await
+        .iter()
+        .any(|form| form.guild_id == guild_id.get() && form.command_name == name);
+    if !is_form
+        && guild_id
+            .get_commands(&ctx.http)
+            .await?
+            .iter()
+            .any(|cmd| cmd.name == name)
+    {
+        bail!("/{name} already exists in this server, please pick another name");
+    }
+    Ok(())
+}
+
+// extracts the form id from an edit url, or returns the input if it isn't one
+fn parse_form_id(form_id: &str) -> &str {
+    let form_url_re = Regex::new(r#"https://docs.google.com/forms/d/([^/]+)"#).unwrap();
+    form_url_re
+        .captures(form_id)
+        .and_then(|cap| cap.get(1))
+        .map(|m| m.as_str())
+        .unwrap_or(form_id)
+}
+
 impl CommandFromForm {
     async fn add_form(
         mut self,
@@ -413,15 +682,41 @@ impl CommandFromForm {
         ctx: &Context,
         guild_id: GuildId,
     ) -> anyhow::Result<CommandResponse> {
-        let spreadsheet_url_re = Regex::new(r#"https://docs.google.com/forms/d/([^/]+)"#).unwrap();
-        if let Some(cap) = spreadsheet_url_re.captures(&self.form_id) {
-            self.form_id = cap.get(1).unwrap().as_str().to_string();
-        }
+        self.form_id = parse_form_id(&self.form_id).to_string();
         let forms: &Forms = handler.module()?;
-        let form = forms.forms_client.get_form(&self.form_id).await?;
+        let mut form = forms.forms_client.get_form(&self.form_id).await?;
+        // keep settings from a previous version of this command
+        let cooldown = {
+            let forms = forms.forms.read().await;
+            let previous = forms.iter().find(|f| {
+                f.guild_id == guild_id.get() && f.command_name == sanitize_name(&self.command_name)
+            });
+            if let Some(previous) = previous {
+                if form.sheet_id.is_none() && previous.form.sheet_managed {
+                    form.sheet_id = previous.form.sheet_id.clone();
+                    form.sheet_managed = true;
+                }
+                for q in form.questions.iter_mut() {
+                    q.validation = previous
+                        .form
+                        .questions
+                        .iter()
+                        .find(|prev| prev.id == q.id)
+                        .and_then(|prev| prev.validation.clone());
+                }
+            }
+            previous.and_then(|prev| prev.cooldown)
+        };
         let cmd = form.to_command(&self.command_name);
         let cmd = guild_id.create_command(&ctx.http, cmd).await?;
-        let resp = format!("Created command </{}:{}>", &cmd.name, cmd.id.get());
+        let mut resp = format!("Created command </{}:{}>", &cmd.name, cmd.id.get());
+        if form.sheet_id.is_none() {
+            resp.push_str(&format!(
+                "\nThis form has no linked spreadsheet, use `/{} {}` to create one",
+                CreateFormSheet::NAME,
+                &cmd.name
+            ));
+        }
         let form_json = serde_json::to_string(&form)?;
         let submission_type = self
             .submission_type
@@ -453,6 +748,7 @@ impl CommandFromForm {
             form,
             submission_type,
             submissions_range: None,
+            cooldown,
         };
         let mut forms = forms.forms.write().await;
         if let Some(form) = forms
@@ -467,6 +763,38 @@ impl CommandFromForm {
     }
 }
 
+#[derive(Command, Debug)]
+#[cmd(
+    name = "preview_form_command",
+    desc = "Preview the command that would be created from a Google Form"
+)]
+pub struct PreviewFormCommand {
+    #[cmd(desc = "The name of the command")]
+    pub command_name: String,
+    #[cmd(desc = "The edit id of the form to use (found in the url when editing it)")]
+    pub form_id: String,
+}
+
+#[async_trait]
+impl BotCommand for PreviewFormCommand {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        _interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let forms: &Forms = handler.module()?;
+        let form = forms
+            .forms_client
+            .get_form(parse_form_id(&self.form_id))
+            .await?;
+        CommandResponse::private(form.preview_embed(&self.command_name))
+    }
+}
+
 pub async fn check_forms(handler: &Handler, ctx: &Context) -> anyhow::Result<()> {
     let mut to_re_add = Vec::new();
     {
@@ -573,6 +901,10 @@ impl BotCommand for DeleteFormCommand {
             "DELETE FROM forms WHERE guild_id = ?1 AND command_name = ?2",
             params![guild_id.get(), &self.command_name],
         )?;
+        db.conn.execute(
+            "DELETE FROM closed_submissions WHERE guild_id = ?1 AND command_name = ?2",
+            params![guild_id.get(), &self.command_name],
+        )?;
         {
             let mut forms = handler.module::<Forms>()?.forms.write().await;
             forms.retain(|form| form.command_name != self.command_name);
@@ -664,9 +996,138 @@ impl BotCommand for OverrideSubmissionsRange {
     }
 }
 
+#[derive(Command, Debug)]
+#[cmd(
+    name = "edit_form_question",
+    desc = "Set validation rules for a question of a form command"
+)]
+pub struct EditFormQuestion {
+    #[cmd(desc = "The name of the command", autocomplete)]
+    pub command_name: String,
+    #[cmd(desc = "The option corresponding to the question", autocomplete)]
+    pub question: String,
+    #[cmd(desc = "A regular expression the value must match")]
+    pub regex: Option<String>,
+    #[cmd(desc = "The minimum numeric value")]
+    pub min: Option<i64>,
+    #[cmd(desc = "The maximum numeric value")]
+    pub max: Option<i64>,
+    #[cmd(desc = "The maximum length of the value")]
+    pub max_length: Option<u64>,
+    #[cmd(desc = "Remove all validation rules from the question")]
+    pub clear: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for EditFormQuestion {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a guild"))?
+            .get();
+        if let Some(re) = &self.regex {
+            Regex::new(re).context("Invalid regular expression")?;
+        }
+        let module = handler.module::<Forms>()?;
+        let mut forms = module.forms.write().await;
+        let form = forms
+            .iter_mut()
+            .find(|form| form.guild_id == guild_id && form.command_name == self.command_name)
+            .ok_or_else(|| anyhow!("Command {} not found", &self.command_name))?;
+        let question = form
+            .form
+            .questions
+            .iter_mut()
+            .find(|q| sanitize_name(&q.title) == self.question)
+            .ok_or_else(|| anyhow!("Question {} not found", &self.question))?;
+        let validation = if self.clear.unwrap_or(false) {
+            None
+        } else {
+            let mut validation = question.validation.clone().unwrap_or_default();
+            validation.regex = self.regex.or(validation.regex);
+            validation.min = self.min.or(validation.min);
+            validation.max = self.max.or(validation.max);
+            validation.max_length = self.max_length.or(validation.max_length);
+            Some(validation).filter(|v| !v.is_empty())
+        };
+        let resp = match &validation {
+            Some(v) => format!("Values for `{}` must be {}", &self.question, v.describe()),
+            None => format!("Removed validation rules from `{}`", &self.question),
+        };
+        question.validation = validation;
+        let form_json = serde_json::to_string(&form.form)?;
+        let db = handler.db.lock().await;
+        db.conn
+            .execute(
+                "UPDATE forms SET form = ?3 WHERE guild_id = ?1 AND command_name = ?2",
+                params![guild_id, &self.command_name, form_json],
+            )
+            .context("Failed to save validation rules")?;
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "set_form_cooldown",
+    desc = "Set how long users must wait between two submissions to a form"
+)]
+pub struct SetFormCooldown {
+    #[cmd(desc = "The name of the command", autocomplete)]
+    pub command_name: String,
+    #[cmd(desc = "Cooldown in seconds, leave empty to disable")]
+    pub seconds: Option<u64>,
+}
+
+#[async_trait]
+impl BotCommand for SetFormCooldown {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a guild"))?
+            .get();
+        let cooldown = self.seconds.filter(|&secs| secs > 0);
+        let module = handler.module::<Forms>()?;
+        let mut forms = module.forms.write().await;
+        let form = forms
+            .iter_mut()
+            .find(|form| form.guild_id == guild_id && form.command_name == self.command_name)
+            .ok_or_else(|| anyhow!("Command {} not found", &self.command_name))?;
+        form.cooldown = cooldown;
+        let db = handler.db.lock().await;
+        db.conn
+            .execute(
+                "UPDATE forms SET cooldown = ?3 WHERE guild_id = ?1 AND command_name = ?2",
+                params![guild_id, &self.command_name, cooldown],
+            )
+            .context("Failed to update cooldown")?;
+        let resp = match cooldown {
+            Some(secs) => format!("Users can submit to /{} every {secs}s", &self.command_name),
+            None => format!("Removed the cooldown on /{}", &self.command_name),
+        };
+        CommandResponse::public(resp)
+    }
+}
+
 pub fn load_forms(db: &Connection) -> anyhow::Result<Vec<FormCommand>> {
     let mut stmt =
-        db.prepare("SELECT guild_id, command_name, command_id, form, submission_type, submissions_range FROM forms")?;
+        db.prepare("SELECT guild_id, command_name, command_id, form, submission_type, submissions_range, cooldown FROM forms")?;
     let commands = stmt
         .query([])?
         .map(|row| {
@@ -677,12 +1138,172 @@ pub fn load_forms(db: &Connection) -> anyhow::Result<Vec<FormCommand>> {
                 form: serde_json::from_slice(row.get::<_, String>(3)?.as_bytes()).unwrap(),
                 submission_type: row.get(4)?,
                 submissions_range: row.get(5)?,
+                cooldown: row.get(6)?,
             })
         })
         .collect::<Vec<_>>()?;
     Ok(commands)
 }
 
+/// Spotify podcast link submitted to a form
+enum PodcastLink {
+    Episode(String),
+    Show,
+}
+
+// recognizes open.spotify.com episode and show links, which other providers cannot resolve
+fn podcast_link(url: &str) -> Option<PodcastLink> {
+    let url = Url::parse(url).ok()?;
+    if url.domain() != Some("open.spotify.com") {
+        return None;
+    }
+    // localized links start with the locale, e.g. /intl-fr/episode/...
+    let mut segments = url
+        .path_segments()?
+        .skip_while(|segment| segment.starts_with("intl-"));
+    match (segments.next()?, segments.next()) {
+        ("episode", Some(id)) => Some(PodcastLink::Episode(id.to_string())),
+        ("show", _) => Some(PodcastLink::Show),
+        _ => None,
+    }
+}
+
+/// Details of a submitted song or album, shown in the confirmation to catch wrong versions of
+/// a release
+#[derive(Default)]
+struct SubmissionDetails {
+    year: Option<String>,
+    genres: Vec<String>,
+    duration: Option<Duration>,
+    /// Artist and name of the album, to look its genres up on MusicBrainz
+    release: Option<(String, String)>,
+    /// Recording of a song, to catch it being submitted again from another link
+    isrc: Option<String>,
+}
+
+impl SubmissionDetails {
+    fn summary(&self) -> String {
+        let genres = (!self.genres.is_empty()).then(|| self.genres.join(", "));
+        let duration = self.duration.map(|duration| {
+            let seconds = duration.num_seconds();
+            match seconds / 3600 {
+                0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+                hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+            }
+        });
+        [self.year.clone(), genres, duration]
+            .into_iter()
+            .flatten()
+            .join(" • ")
+    }
+
+    // fills in what Spotify knows about a track or album link
+    async fn add_spotify_details(
+        &mut self,
+        handler: &Handler,
+        url: &str,
+        market: Option<Market>,
+    ) -> anyhow::Result<()> {
+        let spotify: &Spotify = handler.module()?;
+        let cache: &SpotifyCache = handler.module()?;
+        let Ok(parsed) = Url::parse(url) else {
+            return Ok(());
+        };
+        if parsed.domain() != Some("open.spotify.com") {
+            return Ok(());
+        }
+        if parsed.path().starts_with("/track/") {
+            let track = cache.track_from_url(&spotify.client, url, market).await?;
+            let album = track.album;
+            self.year = self
+                .year
+                .take()
+                .or_else(|| album.release_date.as_deref().and_then(year));
+            self.duration = self.duration.or(Some(track.duration));
+            if self.release.is_none() {
+                let artist = Spotify::artists_to_string(&album.artists);
+                self.release = Some((artist, album.name));
+            }
+        } else if let Some(id) = parsed.path().strip_prefix("/album/") {
+            let album = cache
+                .album(&spotify.client, AlbumId::from_id(id)?, market)
+                .await?;
+            self.year = self.year.take().or_else(|| year(&album.release_date));
+            if self.duration.is_none() && album.tracks.next.is_none() {
+                self.duration = Some(
+                    album
+                        .tracks
+                        .items
+                        .iter()
+                        .fold(Duration::zero(), |total, track| total + track.duration),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // adds genres from MusicBrainz, left out when the lookup is too slow
+    async fn add_musicbrainz_details(&mut self, handler: &Handler) {
+        let Some((artist, name)) = &self.release else {
+            return;
+        };
+        let Ok(musicbrainz) = handler.module::<MusicBrainz>() else {
+            return;
+        };
+        let lookup = musicbrainz.release_info(handler, artist, name);
+        match tokio::time::timeout(MUSICBRAINZ_TIMEOUT, lookup).await {
+            Ok(Ok(Some(info))) => {
+                self.year = self.year.take().or(info.year);
+                self.genres = info.genres;
+            }
+            Ok(Ok(None)) | Err(_) => {}
+            Ok(Err(e)) => eprintln!("Error looking up album on MusicBrainz: {e:?}"),
+        }
+    }
+}
+
+fn year(date: &str) -> Option<String> {
+    date.get(..4).map(str::to_string)
+}
+
+// gets the ISRC of a song through its Spotify version, wherever it was linked from
+async fn recording_isrc(
+    handler: &Handler,
+    url: &str,
+    market: Option<Market>,
+) -> anyhow::Result<Option<String>> {
+    let spotify: &Spotify = handler.module()?;
+    let cache: &SpotifyCache = handler.module()?;
+    let is_spotify = Url::parse(url).is_ok_and(|url| url.domain() == Some("open.spotify.com"));
+    let url = if is_spotify {
+        url.to_string()
+    } else {
+        odesli::spotify_url(url).await?
+    };
+    let track = cache.track_from_url(&spotify.client, &url, market).await?;
+    Ok(spotify_cache::isrc(&track).map(str::to_uppercase))
+}
+
+// finds an earlier submission of the same recording to a form
+async fn previous_submission(
+    handler: &Handler,
+    guild_id: u64,
+    command_name: &str,
+    isrc: &str,
+) -> anyhow::Result<Option<String>> {
+    let db = handler.db.lock().await;
+    let previous = db
+        .conn
+        .query_row(
+            "SELECT COALESCE(info, link) FROM form_submissions
+                 WHERE guild_id = ?1 AND command_name = ?2 AND isrc = ?3 LIMIT 1",
+            params![guild_id, command_name, isrc],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(previous)
+}
+
 impl SimpleForm {
     pub fn responder_id(&self) -> &str {
         self.responder_uri
@@ -702,6 +1323,7 @@ impl SimpleForm {
         handler: &Handler,
         _ctx: &Context,
         interaction: &CommandInteraction,
+        command_name: &str,
         submission_type: &str,
     ) -> anyhow::Result<CommandResponse> {
         let user = &interaction.user;
@@ -713,10 +1335,12 @@ impl SimpleForm {
         };
 
         let forms: &Forms = handler.module()?;
-        let spotify: &Spotify = handler.module()?;
         let lookup: &AlbumLookup = handler.module()?;
+        let market = SpotifyMarket::for_guild(handler, interaction.guild_id).await;
+        let track_providers = track_providers(handler, market)?;
         let mut song_infos = Vec::new();
         let mut song_urls = Vec::new();
+        let mut details = Vec::new();
         let mut value_pairs = Vec::with_capacity(self.questions.len());
         let mut next_value = None;
         for q in self.questions.iter().rev() {
@@ -752,29 +1376,143 @@ impl SimpleForm {
                 }
                 None => continue,
             };
+            if let Some(validation) = &q.validation {
+                validation.check(&q.title, &value)?;
+            }
 
             // determine whether question is asking for a link to a song/album
             if sanitized.contains("spotify") || sanitized.contains("link") {
-                if submission_type == "album" {
+                value = spotify_link::expand(&value).await?;
+                let podcast = podcast_link(&value);
+                if submission_type == "podcast" {
+                    let Some(PodcastLink::Episode(id)) = podcast else {
+                        bail!("This form only accepts links to Spotify podcast episodes");
+                    };
+                    let spotify: &Spotify = handler.module()?;
+                    let episode = spotify
+                        .client
+                        .get_an_episode(EpisodeId::from_id(&id)?, market)
+                        .await
+                        .context("fetching podcast episode")?;
+                    let max_duration = handler
+                        .module::<GuildSettings>()?
+                        .duration(interaction.guild_id, &settings::MAX_EPISODE_MINUTES)
+                        .await;
+                    if episode.duration > max_duration {
+                        bail!("This episode is too long!")
+                    }
+                    let episode_info = format!("{} - {}", episode.show.name, episode.name);
+                    next_value = Some(episode_info.clone());
+                    value = episode.id.url();
+                    details.push(SubmissionDetails {
+                        year: year(&episode.release_date),
+                        duration: Some(episode.duration),
+                        ..Default::default()
+                    });
+                    song_infos.push(episode_info);
+                    song_urls.push(value.clone());
+                } else if let Some(podcast) = podcast {
+                    let kind = match podcast {
+                        PodcastLink::Episode(_) => "podcast episode",
+                        PodcastLink::Show => "podcast",
+                    };
+                    bail!("This is a link to a {kind}, please submit a {submission_type} instead");
+                } else if submission_type == "album" {
+                    if rym::url_matches(&value) {
+                        // RYM pages only give the artist and title, submit the Spotify version
+                        value = rym::spotify_url(handler, &value, market).await?;
+                    }
                     if let Some(p) = lookup.providers().iter().find(|p| p.url_matches(&value)) {
                         let album = p.get_from_url(&value).await?;
                         let album_info = album.format_name();
                         next_value = Some(album_info.clone());
                         value = album.url.clone().unwrap_or_default();
-                        song_infos.push(album_info)
+                        details.push(SubmissionDetails {
+                            release: Some((
+                                album.artist.unwrap_or_default(),
+                                album.name.unwrap_or_default(),
+                            )),
+                            ..Default::default()
+                        });
+                        song_infos.push(album_info);
+                        song_urls.push(value.clone());
+                    } else if let Some(p) = extra_album_providers()
+                        .iter()
+                        .find(|p| p.url_matches(&value))
+                    {
+                        let album = p.get_from_url(&value).await?;
+                        let album_info = album.format_name();
+                        next_value = Some(album_info.clone());
+                        value = album.url;
+                        let duration = album
+                            .tracks
+                            .iter()
+                            .map(|track| track.duration)
+                            .sum::<Option<Duration>>();
+                        details.push(SubmissionDetails {
+                            duration: duration.filter(|_| !album.tracks.is_empty()),
+                            release: Some((album.artist, album.name)),
+                            ..Default::default()
+                        });
+                        song_infos.push(album_info);
+                        song_urls.push(value.clone());
+                    } else if let Ok(converted) = odesli::spotify_url(&value).await {
+                        // unknown platform, fall back to the album's spotify link
+                        if let Some(p) = lookup
+                            .providers()
+                            .iter()
+                            .find(|p| p.url_matches(&converted))
+                        {
+                            let album = p.get_from_url(&converted).await?;
+                            let album_info = album.format_name();
+                            next_value = Some(album_info.clone());
+                            value = album.url.clone().unwrap_or_default();
+                            details.push(SubmissionDetails {
+                                release: Some((
+                                    album.artist.unwrap_or_default(),
+                                    album.name.unwrap_or_default(),
+                                )),
+                                ..Default::default()
+                            });
+                            song_infos.push(album_info);
+                            song_urls.push(value.clone());
+                        }
                     }
                 } else {
-                    let song = spotify.get_song_from_url(&value).await?;
-                    if song.duration > Duration::seconds(60 * 45) {
+                    let song = album::get_track(&track_providers, &value).await?;
+                    let max_duration = handler
+                        .module::<GuildSettings>()?
+                        .duration(interaction.guild_id, &settings::MAX_SONG_MINUTES)
+                        .await;
+                    if song
+                        .duration
+                        .is_some_and(|duration| duration > max_duration)
+                    {
                         bail!("This song is too long!")
                     }
-                    let song_info = format!(
-                        "{} - {}",
-                        Spotify::artists_to_string(&song.artists),
-                        &song.name,
-                    );
+                    let isrc = recording_isrc(handler, &song.url, market)
+                        .await
+                        .unwrap_or_else(|e| {
+                            eprintln!("Error getting ISRC of {}: {e:?}", &song.url);
+                            None
+                        });
+                    if let (Some(guild_id), Some(isrc)) = (interaction.guild_id, &isrc) {
+                        if let Some(previous) =
+                            previous_submission(handler, guild_id.get(), command_name, isrc).await?
+                        {
+                            bail!(
+                                "This song was already submitted to /{command_name} as {previous}"
+                            );
+                        }
+                    }
+                    let song_info = song.format_name();
                     next_value = Some(song_info.clone());
-                    value = song.id.unwrap().url();
+                    value = song.url;
+                    details.push(SubmissionDetails {
+                        duration: song.duration,
+                        isrc,
+                        ..Default::default()
+                    });
                     song_infos.push(song_info);
                     song_urls.push(value.to_string());
                 }
@@ -782,6 +1520,19 @@ impl SimpleForm {
             value_pairs.push((question_id, value));
         }
 
+        // responses only reach bot-created sheets through the bot
+        let sheet_row = self.sheet_managed.then(|| {
+            let mut row = vec![Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()];
+            row.extend(self.questions.iter().map(|q| {
+                value_pairs
+                    .iter()
+                    .find(|(id, _)| u64::from_str_radix(&q.id, 16).ok() == Some(*id))
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default()
+            }));
+            row
+        });
+
         // build request payload
         let form_data = value_pairs
             .into_iter()
@@ -799,17 +1550,72 @@ impl SimpleForm {
             bail!("Failed to send response: status {}", resp.status());
         }
 
-        let contents = if !song_infos.is_empty() {
-            let songs = song_infos
+        if let (Some(sheet_id), Some(row)) = (&self.sheet_id, sheet_row) {
+            let req = ValueRange {
+                values: Some(vec![row]),
+                ..Default::default()
+            };
+            forms
+                .sheets_client
+                .spreadsheets()
+                .values_append(req, sheet_id, "A:Z")
+                .value_input_option("USER_ENTERED")
+                .doit()
+                .await
+                .context("Failed to save response to spreadsheet")?;
+        }
+
+        if let Some(guild_id) = interaction.guild_id {
+            let submission = Submission {
+                guild_id: guild_id.get(),
+                command_name: command_name.to_string(),
+                user_id: Some(user.id.get()),
+                username: user_handle.clone(),
+                submitted_at: Some(Utc::now().to_rfc3339()),
+                sheet_row: None,
+            };
+            let db = handler.db.lock().await;
+            let res = if song_infos.is_empty() {
+                submission.insert(&db.conn, None, None, None)
+            } else {
+                song_infos
+                    .iter()
+                    .zip(&song_urls)
+                    .zip(&details)
+                    .try_for_each(|((info, url), details)| {
+                        submission.insert(
+                            &db.conn,
+                            Some(info.as_str()),
+                            Some(url.as_str()),
+                            details.isrc.as_deref(),
+                        )
+                    })
+            };
+            if let Err(e) = res {
+                eprintln!("Failed to record submission to /{command_name}: {e:?}");
+            }
+        }
+
+        for (details, url) in details.iter_mut().zip(&song_urls) {
+            if let Err(e) = details.add_spotify_details(handler, url, market).await {
+                eprintln!("Error getting submission details from Spotify: {e:?}");
+            }
+            details.add_musicbrainz_details(handler).await;
+        }
+        let mut embed = CreateEmbed::new().title(format!("Submitted to {}", &self.title));
+        if !song_infos.is_empty() {
+            let description = song_infos
                 .iter()
                 .zip(&song_urls)
-                .map(|(info, url)| format!("[{info}]({url})"))
-                .join(", ");
-            format!("Submitted {songs} to **{}**", &self.title)
-        } else {
-            format!("Submitted to **{}**", &self.title)
-        };
-        CommandResponse::private(contents)
+                .zip(&details)
+                .map(|((info, url), details)| match details.summary() {
+                    summary if summary.is_empty() => format!("[{info}]({url})"),
+                    summary => format!("[{info}]({url})\n{summary}"),
+                })
+                .join("\n\n");
+            embed = embed.description(description);
+        }
+        CommandResponse::private(embed)
     }
 
     pub async fn get_submissions_for_user(
@@ -832,17 +1638,11 @@ impl SimpleForm {
         let Some(values) = rows.values else {
             bail!("No submissions found on this sheet");
         };
-        let username = user.name.to_lowercase();
         let rows = values
             .into_iter()
             .filter(|row| {
                 row.get(0)
-                    .map(|submitter| {
-                        submitter
-                            .trim_start_matches('@')
-                            .to_lowercase()
-                            .starts_with(&username)
-                    })
+                    .map(|submitter| submitter_matches(submitter, &user.name))
                     .unwrap_or(false)
             })
             .rev()
@@ -865,6 +1665,416 @@ impl SimpleForm {
     }
 }
 
+#[derive(Command, Debug)]
+#[cmd(
+    name = "create_form_sheet",
+    desc = "Create a spreadsheet for a form command's responses if the form has none"
+)]
+pub struct CreateFormSheet {
+    #[cmd(desc = "The name of the command", autocomplete)]
+    pub command_name: String,
+}
+
+#[async_trait]
+impl BotCommand for CreateFormSheet {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a guild"))?
+            .get();
+        let module = handler.module::<Forms>()?;
+        // the lock is not held while calling Google
+        let (title, header) = {
+            let forms = module.forms.read().await;
+            let form = forms
+                .iter()
+                .find(|form| form.guild_id == guild_id && form.command_name == self.command_name)
+                .ok_or_else(|| anyhow!("Command {} not found", &self.command_name))?;
+            if form.form.sheet_id.is_some() {
+                bail!("/{} already has a linked spreadsheet", &self.command_name);
+            }
+            let header = std::iter::once("Timestamp".to_string())
+                .chain(form.form.questions.iter().map(|q| q.title.clone()))
+                .collect_vec();
+            (form.form.title.clone(), header)
+        };
+
+        let sheets = module.sheets_client.spreadsheets();
+        let req = Spreadsheet {
+            properties: Some(SpreadsheetProperties {
+                title: Some(format!("{title} (Responses)")),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let spreadsheet = sheets
+            .create(req)
+            .doit()
+            .await
+            .context("Failed to create spreadsheet")?
+            .1;
+        let sheet_id = spreadsheet
+            .spreadsheet_id
+            .ok_or_else(|| anyhow!("Created spreadsheet has no id"))?;
+        let req = ValueRange {
+            values: Some(vec![header]),
+            ..Default::default()
+        };
+        sheets
+            .values_update(req, &sheet_id, "A1")
+            .value_input_option("USER_ENTERED")
+            .doit()
+            .await
+            .context("Failed to write spreadsheet header")?;
+        let editor = handler
+            .module::<GuildSettings>()?
+            .email(GuildId::new(guild_id), &settings::SHEET_EDITOR)
+            .await;
+        if let Some(editor) = &editor {
+            module
+                .forms_client
+                .share_file(&sheet_id, editor)
+                .await
+                .with_context(|| format!("Failed to share spreadsheet with {editor}"))?;
+        }
+
+        {
+            let mut forms = module.forms.write().await;
+            let form = forms
+                .iter_mut()
+                .find(|form| form.guild_id == guild_id && form.command_name == self.command_name)
+                .ok_or_else(|| anyhow!("Command {} was removed", &self.command_name))?;
+            if form.form.sheet_id.is_some() {
+                bail!("/{} already has a linked spreadsheet", &self.command_name);
+            }
+            form.form.sheet_id = Some(sheet_id);
+            form.form.sheet_managed = true;
+            let form_json = serde_json::to_string(&form.form)?;
+            let db = handler.db.lock().await;
+            db.conn
+                .execute(
+                    "UPDATE forms SET form = ?3 WHERE guild_id = ?1 AND command_name = ?2",
+                    params![guild_id, &self.command_name, form_json],
+                )
+                .context("Failed to link spreadsheet")?;
+        }
+        let msg = format!(
+            "Created a spreadsheet for /{}, responses sent through the bot will be saved to it",
+            &self.command_name
+        );
+        match editor {
+            Some(editor) => {
+                let url = spreadsheet.spreadsheet_url.unwrap_or_default();
+                CommandResponse::private(format!("{msg}\nShared with {editor}: {url}"))
+            }
+            None => CommandResponse::private(format!(
+                "{msg}\nIt is only accessible to the bot, set `{}` with /config to share the next ones",
+                settings::SHEET_EDITOR.key
+            )),
+        }
+    }
+}
+
+/// A submission to a form command, recorded locally
+pub struct Submission {
+    pub guild_id: u64,
+    pub command_name: String,
+    /// Unknown for rows imported from the spreadsheet
+    pub user_id: Option<u64>,
+    pub username: String,
+    pub submitted_at: Option<String>,
+    /// Index of the row in the responses sheet, for imported rows
+    pub sheet_row: Option<usize>,
+}
+
+impl Submission {
+    pub fn insert(
+        &self,
+        conn: &Connection,
+        info: Option<&str>,
+        link: Option<&str>,
+        isrc: Option<&str>,
+    ) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT INTO form_submissions
+                 (guild_id, command_name, user_id, username, submitted_at, info, link, sheet_row,
+                  isrc)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                 ON CONFLICT (guild_id, command_name, sheet_row) DO NOTHING",
+            params![
+                self.guild_id,
+                &self.command_name,
+                self.user_id,
+                &self.username,
+                self.submitted_at.as_deref(),
+                info,
+                link,
+                self.sheet_row,
+                isrc,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "import_submissions",
+    desc = "Import the existing responses of a form's linked sheet into the bot's history"
+)]
+pub struct ImportSubmissions {
+    #[cmd(desc = "The name of the command", autocomplete)]
+    pub command_name: String,
+}
+
+#[async_trait]
+impl BotCommand for ImportSubmissions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a guild"))?
+            .get();
+        let module = handler.module::<Forms>()?;
+        let (sheet_id, range) = {
+            let forms = module.forms.read().await;
+            let form = forms
+                .iter()
+                .find(|form| form.guild_id == guild_id && form.command_name == self.command_name)
+                .ok_or_else(|| anyhow!("Command {} not found", &self.command_name))?;
+            let Some(sheet_id) = form.form.sheet_id.clone() else {
+                bail!("No linked spreadsheet, cannot import submissions");
+            };
+            (sheet_id, form.submissions_range.clone())
+        };
+        let rows = module
+            .sheets_client
+            .spreadsheets()
+            .values_get(&sheet_id, range.as_deref().unwrap_or(DEFAULT_RANGE))
+            .doit()
+            .await?
+            .1
+            .values
+            .unwrap_or_default();
+
+        let db = handler.db.lock().await;
+        let tx = db.conn.unchecked_transaction()?;
+        let mut imported = 0;
+        // skip the header row
+        for (i, row) in rows.iter().enumerate().skip(1) {
+            let Some(username) = row.first().filter(|name| !name.is_empty()) else {
+                continue;
+            };
+            let link = row
+                .iter()
+                .skip(1)
+                .find(|value| value.starts_with("https://"));
+            let info = row
+                .iter()
+                .skip(1)
+                .filter(|value| !(value.is_empty() || value.starts_with("https://")))
+                .join(" - ");
+            // submissions made through the bot are already recorded
+            let recorded: bool = tx.query_row(
+                "SELECT COUNT(*) > 0 FROM form_submissions
+                     WHERE guild_id = ?1 AND command_name = ?2 AND username = ?3
+                     AND link IS ?4 AND sheet_row IS NULL",
+                params![guild_id, &self.command_name, username, link],
+                |row| row.get(0),
+            )?;
+            if recorded {
+                continue;
+            }
+            let submission = Submission {
+                guild_id,
+                command_name: self.command_name.clone(),
+                user_id: None,
+                username: username.clone(),
+                submitted_at: None,
+                sheet_row: Some(i),
+            };
+            submission.insert(
+                &tx,
+                Some(info.as_str()).filter(|info| !info.is_empty()),
+                link.map(String::as_str),
+                None,
+            )?;
+            imported += 1;
+        }
+        tx.commit()?;
+        CommandResponse::private(format!(
+            "Imported {imported} submissions to /{}",
+            &self.command_name
+        ))
+    }
+}
+
+// whether a submitter handle from the sheet or history refers to the given username
+fn submitter_matches(submitter: &str, username: &str) -> bool {
+    let handle = submitter.trim().trim_start_matches('@');
+    // legacy handles include a discriminator
+    let name = handle.split('#').next().unwrap_or(handle);
+    name.eq_ignore_ascii_case(username)
+}
+
+// gets a user's 5 latest submissions from the local history, matching on user ID and only
+// falling back to the username for imported rows
+async fn recorded_submissions_for_user(
+    handler: &Handler,
+    guild_id: u64,
+    command_name: &str,
+    user: &User,
+) -> anyhow::Result<Vec<String>> {
+    let db = handler.db.lock().await;
+    let mut stmt = db.conn.prepare(
+        "SELECT user_id, username, info, link FROM form_submissions
+             WHERE guild_id = ?1 AND command_name = ?2
+             ORDER BY rowid",
+    )?;
+    let rows = stmt
+        .query(params![guild_id, command_name])?
+        .map(|row| {
+            Ok((
+                row.get::<_, Option<u64>>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .filter(|(user_id, username, _, _)| {
+            Ok(match user_id {
+                Some(id) => *id == user.id.get(),
+                None => submitter_matches(username, &user.name),
+            })
+        })
+        .collect::<Vec<_>>()?;
+    let submissions = rows
+        .into_iter()
+        .rev()
+        .take(5)
+        .rev()
+        .filter_map(|(_, _, info, link)| match (info, link) {
+            (Some(info), Some(link)) => Some(format!("[{info}]({link})")),
+            (info, link) => info.or(link),
+        })
+        .collect();
+    Ok(submissions)
+}
+
+const DIGEST_PERIOD_DAYS: i64 = 7;
+
+// builds an embed listing the submissions of the past week for each form of a guild
+fn build_digest(conn: &Connection, guild_id: u64) -> anyhow::Result<CreateEmbed> {
+    let since = (Utc::now() - Duration::days(DIGEST_PERIOD_DAYS)).to_rfc3339();
+    let mut stmt = conn.prepare(
+        "SELECT command_name, info, link FROM form_submissions
+             WHERE guild_id = ?1 AND submitted_at >= ?2
+             ORDER BY command_name, submitted_at",
+    )?;
+    let rows: Vec<(String, Option<String>, Option<String>)> = stmt
+        .query(params![guild_id, since])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .collect()?;
+    let mut embed = CreateEmbed::default().title("Submissions this week");
+    if rows.is_empty() {
+        return Ok(embed.description("No submissions this week"));
+    }
+    for (command_name, submissions) in &rows.into_iter().group_by(|(name, _, _)| name.clone()) {
+        let submissions = submissions.collect_vec();
+        let mut list = String::new();
+        for line in submissions
+            .iter()
+            .filter_map(|(_, info, link)| match (info, link) {
+                (Some(info), Some(link)) => Some(format!("· [{info}]({link})")),
+                (info, link) => info
+                    .clone()
+                    .or_else(|| link.clone())
+                    .map(|s| format!("· {s}")),
+            })
+        {
+            // embed field values are limited to 1024 characters
+            if list.len() + line.len() + 1 > 1000 {
+                list.push_str("\n…");
+                break;
+            }
+            if !list.is_empty() {
+                list.push('\n');
+            }
+            list.push_str(&line);
+        }
+        embed = embed.field(
+            format!("/{command_name} ({})", submissions.len()),
+            list,
+            false,
+        );
+    }
+    Ok(embed)
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "submission_digest",
+    desc = "Show a summary of this week's form submissions"
+)]
+pub struct SubmissionDigest {
+    #[cmd(desc = "Post the digest in this channel every week (true) or stop posting it (false)")]
+    pub weekly: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for SubmissionDigest {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a guild"))?
+            .get();
+        let db = handler.db.lock().await;
+        match self.weekly {
+            Some(true) => {
+                db.conn.execute(
+                    "INSERT INTO form_digests (guild_id, channel_id, last_sent) VALUES (?1, ?2, ?3)
+                         ON CONFLICT (guild_id) DO UPDATE SET channel_id = ?2",
+                    params![
+                        guild_id,
+                        interaction.channel_id.get(),
+                        Utc::now().to_rfc3339()
+                    ],
+                )?;
+                CommandResponse::public("A submission digest will be posted here every week")
+            }
+            Some(false) => {
+                db.conn
+                    .execute("DELETE FROM form_digests WHERE guild_id = ?1", [guild_id])?;
+                CommandResponse::public("Stopped posting weekly submission digests")
+            }
+            None => CommandResponse::public(build_digest(&db.conn, guild_id)?),
+        }
+    }
+}
+
 #[derive(Command)]
 #[cmd(name = "get_submissions", desc = "Get your submissions to a form")]
 pub struct GetSubmissions {
@@ -882,12 +2092,24 @@ impl BotCommand for GetSubmissions {
         _ctx: &Context,
         interaction: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a guild"))?
+            .get();
         let forms: &Forms = handler.module()?;
         let forms = forms.forms.read().await;
         let cmd_name = &self.command_name;
-        let Some(form) = forms.iter().find(|form| &form.command_name == cmd_name) else {
+        let Some(form) = forms
+            .iter()
+            .find(|form| form.guild_id == guild_id && &form.command_name == cmd_name)
+        else {
             bail!("Command {} not found", cmd_name);
         };
+        let recorded =
+            recorded_submissions_for_user(handler, guild_id, cmd_name, &interaction.user).await?;
+        if !recorded.is_empty() {
+            return CommandResponse::private(recorded.join("\n"));
+        }
         form.form
             .get_submissions_for_user(
                 handler,
@@ -902,9 +2124,62 @@ pub struct Forms {
     pub sheets_client: Sheets<HttpsConnector<HttpConnector>>,
     pub forms_client: FormsClient,
     pub forms: Arc<RwLock<Vec<FormCommand>>>,
+    // time of the last submission for each (command id, user)
+    last_submissions: RwLock<HashMap<(u64, UserId), Instant>>,
 }
 
 impl Forms {
+    /// Posts the weekly digest in guilds where it is due
+    pub fn post_digests<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let now = Utc::now();
+            let due_before = (now - Duration::days(DIGEST_PERIOD_DAYS)).to_rfc3339();
+            let digests = {
+                let db = handler.db.lock().await;
+                let mut stmt = db.conn.prepare(
+                    "SELECT guild_id, channel_id FROM form_digests
+                         WHERE last_sent IS NULL OR last_sent <= ?1",
+                )?;
+                let due: Vec<(u64, u64)> = stmt
+                    .query([&due_before])?
+                    .map(|row| Ok((row.get(0)?, row.get(1)?)))
+                    .collect()?;
+                due.into_iter()
+                    .filter_map(
+                        |(guild_id, channel_id)| match build_digest(&db.conn, guild_id) {
+                            Ok(embed) => Some((guild_id, channel_id, embed)),
+                            Err(e) => {
+                                eprintln!("failed to build digest for guild {guild_id}: {e:?}");
+                                None
+                            }
+                        },
+                    )
+                    .collect::<Vec<_>>()
+            };
+            // a failing guild should not prevent the others from getting their digest
+            for (guild_id, channel_id, embed) in digests {
+                if let Err(e) = ChannelId::new(channel_id)
+                    .send_message(&ctx.http, CreateMessage::new().embed(embed))
+                    .await
+                {
+                    eprintln!("failed to post digest in guild {guild_id}: {e:?}");
+                    continue;
+                }
+                if let Err(e) = handler.db.lock().await.conn.execute(
+                    "UPDATE form_digests SET last_sent = ?2 WHERE guild_id = ?1",
+                    params![guild_id, now.to_rfc3339()],
+                ) {
+                    eprintln!("failed to save digest time for guild {guild_id}: {e:?}");
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
     fn complete_forms<'a>(
         handler: &'a Handler,
         ctx: &'a Context,
@@ -929,13 +2204,43 @@ impl Forms {
             let form = forms
                 .iter()
                 .find(|form| form.guild_id == guild_id && form.command_name == data.name);
-            if let Some(form) = form {
-                return form
-                    .form
-                    .submit(handler, ctx, cmd, &form.submission_type)
-                    .await;
+            let Some(form) = form else {
+                bail!("Command not found")
+            };
+            if let Some(closed) =
+                SubmissionStatus::closed_message(handler, GuildId::new(guild_id), &data.name)
+                    .await?
+            {
+                return CommandResponse::private(closed);
             }
-            bail!("Command not found")
+            let module = handler.module::<Forms>()?;
+            let key = (form.command_id, cmd.user.id);
+            // the cooldown is reserved before submitting so concurrent submissions can't both pass
+            let mut previous = None;
+            if let Some(cooldown) = form.cooldown.map(std::time::Duration::from_secs) {
+                let mut last_submissions = module.last_submissions.write().await;
+                let last = last_submissions.get(&key).copied();
+                if let Some(remaining) = last.and_then(|last| cooldown.checked_sub(last.elapsed()))
+                {
+                    return CommandResponse::private(format!(
+                        "You already submitted recently, try again in {}s",
+                        remaining.as_secs() + 1
+                    ));
+                }
+                previous = last_submissions.insert(key, Instant::now());
+            }
+            let resp = form
+                .form
+                .submit(handler, ctx, cmd, &form.command_name, &form.submission_type)
+                .await;
+            if resp.is_err() && form.cooldown.is_some() {
+                let mut last_submissions = module.last_submissions.write().await;
+                match previous {
+                    Some(previous) => last_submissions.insert(key, previous),
+                    None => last_submissions.remove(&key),
+                };
+            }
+            resp
         }
         .boxed()
     }
@@ -948,6 +2253,16 @@ impl Module for Forms {
             .module::<Spotify>()
             .await?
             .module::<AlbumLookup>()
+            .await?
+            .module::<MusicBrainz>()
+            .await?
+            .module::<SpotifyCache>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await?
+            .module::<GuildSettings>()
+            .await?
+            .module::<SubmissionStatus>()
             .await
     }
 
@@ -960,11 +2275,37 @@ impl Module for Forms {
                 form STRING NOT NULL,
                 submission_type STRING NOT NULL DEFAULT('song'),
                 submissions_range STRING,
+                cooldown INTEGER,
 
                 UNIQUE(guild_id, command_name)
             )",
             [],
         )?;
+        add_column(&db.conn, "forms", "cooldown", "INTEGER")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS form_submissions (
+                guild_id INTEGER NOT NULL,
+                command_name STRING NOT NULL,
+                user_id INTEGER,
+                username STRING NOT NULL,
+                submitted_at STRING,
+                info STRING,
+                link STRING,
+                sheet_row INTEGER,
+
+                UNIQUE(guild_id, command_name, sheet_row)
+            )",
+            [],
+        )?;
+        add_column(&db.conn, "form_submissions", "isrc", "STRING")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS form_digests (
+                guild_id INTEGER NOT NULL PRIMARY KEY,
+                channel_id INTEGER NOT NULL,
+                last_sent STRING
+            )",
+            [],
+        )?;
         let forms = load_forms(&db.conn).unwrap();
         *self.forms.write().await = forms;
         Ok(())
@@ -973,13 +2314,9 @@ impl Module for Forms {
     async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
         let conn = hyper_tls::HttpsConnector::new();
         let client = hyper::Client::builder().build(conn);
-        let client_secret = yup_oauth2::read_service_account_key(&"credentials.json".to_string())
-            .await
-            .unwrap();
-        let authenticator = ServiceAccountAuthenticator::with_client(client_secret, client.clone())
-            .build()
+        let authenticator = google_auth::authenticator(client.clone())
             .await
-            .unwrap();
+            .context("google authentication")?;
         let sheets_client = google_sheets4::api::Sheets::new(client.clone(), authenticator.clone());
         let forms_client = FormsClient {
             authenticator,
@@ -990,17 +2327,92 @@ impl Module for Forms {
             sheets_client,
             forms_client,
             forms,
+            last_submissions: Default::default(),
         })
     }
 
     fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
         store.register::<CommandFromForm>();
+        store.register::<PreviewFormCommand>();
         store.register::<ListForms>();
         store.register::<DeleteFormCommand>();
         store.register::<RefreshFormCommand>();
         store.register::<GetSubmissions>();
         store.register::<OverrideSubmissionsRange>();
+        store.register::<EditFormQuestion>();
+        store.register::<SetFormCooldown>();
+        store.register::<ImportSubmissions>();
+        store.register::<CreateFormSheet>();
+        store.register::<SubmissionDigest>();
 
         completions.push(Forms::complete_forms);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation_numeric_range() {
+        let validation = Validation {
+            min: Some(1),
+            max: Some(10),
+            ..Default::default()
+        };
+        assert!(validation.check("Score", "7").is_ok());
+        assert!(validation.check("Score", "11").is_err());
+        assert!(validation.check("Score", "seven").is_err());
+    }
+
+    #[test]
+    fn validation_regex_and_length() {
+        let validation = Validation {
+            regex: Some("^[0-9]{4}$".to_string()),
+            max_length: Some(4),
+            ..Default::default()
+        };
+        assert!(validation.check("Year", "1997").is_ok());
+        assert!(validation.check("Year", "97").is_err());
+        assert!(validation.check("Year", "19977").is_err());
+    }
+
+    #[test]
+    fn submitter_matching() {
+        assert!(submitter_matches("@etwyniel", "etwyniel"));
+        assert!(submitter_matches("Etwyniel#1234", "etwyniel"));
+        assert!(!submitter_matches("@etwyniel2", "etwyniel"));
+        assert!(!submitter_matches("@etwy", "etwyniel"));
+    }
+
+    #[test]
+    fn grid_rows_become_questions() {
+        let item: Item = serde_json::from_str(
+            r#"{
+                "itemId": "1",
+                "title": "Rate",
+                "questionGroupItem": {
+                    "questions": [
+                        {"questionId": "a1", "rowQuestion": {"title": "Production"}},
+                        {"questionId": "a2", "rowQuestion": {"title": "Lyrics"}}
+                    ],
+                    "grid": {
+                        "columns": {
+                            "type": "RADIO",
+                            "options": [{"value": "1"}, {"value": "2"}, {"value": "3"}]
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let questions = item.to_simple().unwrap();
+        let names = questions
+            .iter()
+            .map(|q| sanitize_name(&q.title))
+            .collect_vec();
+        assert_eq!(names, ["rate_production", "rate_lyrics"]);
+        assert_eq!(questions[1].id, "a2");
+        assert!(matches!(&questions[0].ty, QuestionType::Choice(values) if values.len() == 3));
+    }
+}
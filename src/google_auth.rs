@@ -0,0 +1,123 @@
+use std::{env, sync::Mutex};
+
+use anyhow::{anyhow, Context as _};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::async_trait;
+use yup_oauth2::{
+    authenticator::Authenticator,
+    storage::{TokenInfo, TokenStorage},
+    InstalledFlowAuthenticator, InstalledFlowReturnMethod, ServiceAccountAuthenticator,
+};
+
+use crate::open_db;
+
+pub type GoogleClient = hyper::Client<HttpsConnector<HttpConnector>>;
+pub type GoogleAuthenticator = Authenticator<HttpsConnector<HttpConnector>>;
+
+// path to the OAuth client secret, if set the installed-app flow is used instead of the
+// service account in credentials.json
+const OAUTH_SECRET_VAR: &str = "GOOGLE_OAUTH_SECRET";
+const SERVICE_ACCOUNT_KEY: &str = "credentials.json";
+
+/// Caches OAuth tokens obtained through the installed-app flow in the bot's database, so the
+/// interactive consent only has to happen once.
+pub struct SqliteTokenStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteTokenStorage {
+    pub fn open() -> anyhow::Result<Self> {
+        let conn = open_db()?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS google_tokens (
+                scopes STRING NOT NULL PRIMARY KEY,
+                token STRING NOT NULL
+            )",
+            [],
+        )?;
+        Ok(SqliteTokenStorage {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn scopes_key(scopes: &[&str]) -> String {
+        let mut scopes = scopes.to_vec();
+        scopes.sort_unstable();
+        scopes.dedup();
+        scopes.join(" ")
+    }
+}
+
+#[async_trait]
+impl TokenStorage for SqliteTokenStorage {
+    async fn set(&self, scopes: &[&str], token: TokenInfo) -> anyhow::Result<()> {
+        let token = serde_json::to_string(&token)?;
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| anyhow!("token storage poisoned"))?;
+        conn.execute(
+            "INSERT INTO google_tokens (scopes, token) VALUES (?1, ?2)
+                 ON CONFLICT (scopes) DO UPDATE SET token = ?2",
+            params![Self::scopes_key(scopes), token],
+        )?;
+        Ok(())
+    }
+
+    async fn get(&self, scopes: &[&str]) -> Option<TokenInfo> {
+        let conn = self.conn.lock().ok()?;
+        let token: Option<String> = conn
+            .query_row(
+                "SELECT token FROM google_tokens WHERE scopes = ?1",
+                [Self::scopes_key(scopes)],
+                |row| row.get(0),
+            )
+            .optional()
+            .ok()?;
+        serde_json::from_str(&token?).ok()
+    }
+}
+
+async fn installed_flow_authenticator(
+    client: GoogleClient,
+    secret_path: &str,
+) -> anyhow::Result<GoogleAuthenticator> {
+    let secret = yup_oauth2::read_application_secret(secret_path)
+        .await
+        .with_context(|| format!("failed to read OAuth client secret {secret_path}"))?;
+    let storage = SqliteTokenStorage::open().context("failed to open token storage")?;
+    let authenticator = InstalledFlowAuthenticator::with_client(
+        secret,
+        InstalledFlowReturnMethod::Interactive,
+        client,
+    )
+    .with_storage(Box::new(storage))
+    .build()
+    .await?;
+    Ok(authenticator)
+}
+
+async fn service_account_authenticator(
+    client: GoogleClient,
+) -> anyhow::Result<GoogleAuthenticator> {
+    let key = yup_oauth2::read_service_account_key(SERVICE_ACCOUNT_KEY)
+        .await
+        .with_context(|| format!("failed to read service account key {SERVICE_ACCOUNT_KEY}"))?;
+    let authenticator = ServiceAccountAuthenticator::with_client(key, client)
+        .build()
+        .await?;
+    Ok(authenticator)
+}
+
+/// Builds the authenticator shared by the Forms and Sheets clients.
+///
+/// Uses the OAuth installed-app flow when `GOOGLE_OAUTH_SECRET` points to a client secret,
+/// for sheets that can't be shared with a service account, and the service account otherwise.
+pub async fn authenticator(client: GoogleClient) -> anyhow::Result<GoogleAuthenticator> {
+    match env::var(OAUTH_SECRET_VAR) {
+        Ok(path) if !path.is_empty() => installed_flow_authenticator(client, &path).await,
+        _ => service_account_authenticator(client).await,
+    }
+}
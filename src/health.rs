@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rspotify::clients::BaseClient;
+use serde_json::json;
+use serenity::async_trait;
+use serenity_command_handler::modules::SpotifyOAuth;
+use serenity_command_handler::{Handler, Module, ModuleMap};
+
+use crate::api;
+use crate::forms::Forms;
+
+/// Environment variable holding the address the health check and API server listens on, the
+/// server is not started when it is not set
+const HEALTH_ADDR_VAR: &str = "HUMBLE_LEDGER_HEALTH_ADDR";
+/// How long each check may take before it is considered failed, as a wedged bot tends to hang
+/// rather than error
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const GOOGLE_SCOPES: &[&str] = &["https://www.googleapis.com/auth/spreadsheets"];
+
+/// State of the bot reported by /healthz
+pub struct Health {
+    /// Whether each shard is connected to the gateway
+    shards: Mutex<HashMap<u32, bool>>,
+}
+
+impl Health {
+    pub fn set_connected(&self, shard: u32, connected: bool) {
+        self.shards.lock().unwrap().insert(shard, connected);
+    }
+
+    // a shard missing events is enough for members to notice
+    fn gateway_connected(&self) -> bool {
+        let shards = self.shards.lock().unwrap();
+        !shards.is_empty() && shards.values().all(|&connected| connected)
+    }
+}
+
+async fn database_ok(handler: &Handler) -> bool {
+    let check = async {
+        let db = handler.db.lock().await;
+        db.conn
+            .query_row("SELECT 1", [], |row| row.get::<_, i64>(0))
+    };
+    matches!(tokio::time::timeout(CHECK_TIMEOUT, check).await, Ok(Ok(_)))
+}
+
+// None when the module using the token is not loaded
+async fn google_ok(handler: &Handler) -> Option<bool> {
+    let forms = handler.module::<Forms>().ok()?;
+    let token = forms.forms_client.authenticator.token(GOOGLE_SCOPES);
+    Some(matches!(
+        tokio::time::timeout(CHECK_TIMEOUT, token).await,
+        Ok(Ok(_))
+    ))
+}
+
+async fn spotify_ok(handler: &Handler) -> Option<bool> {
+    let spotify = handler.module::<SpotifyOAuth>().ok()?;
+    let token = spotify.client.get_token();
+    let token = token.lock().await.ok()?;
+    // expired tokens are refreshed on the next request
+    Some(
+        token
+            .as_ref()
+            .is_some_and(|token| !token.is_expired() || token.refresh_token.is_some()),
+    )
+}
+
+async fn respond(handler: &Handler, req: Request<Body>) -> Response<Body> {
+    if req.uri().path().starts_with("/api/") {
+        return api::respond(handler, req).await;
+    }
+    if req.uri().path() != "/healthz" {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let gateway = handler
+        .module::<Health>()
+        .is_ok_and(|health| health.gateway_connected());
+    let database = database_ok(handler).await;
+    let google = google_ok(handler).await;
+    let spotify = spotify_ok(handler).await;
+    // restarting doesn't fix invalid tokens, only report them
+    let status = if gateway && database {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "gateway": gateway,
+        "database": database,
+        "google": google,
+        "spotify": spotify,
+    });
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Address to serve health checks on, if configured
+pub fn address() -> anyhow::Result<Option<SocketAddr>> {
+    let Ok(addr) = env::var(HEALTH_ADDR_VAR) else {
+        return Ok(None);
+    };
+    let addr = addr
+        .parse()
+        .with_context(|| format!("{HEALTH_ADDR_VAR} is not a valid address"))?;
+    Ok(Some(addr))
+}
+
+/// Serves /healthz and the API until the bot stops
+pub async fn serve(addr: SocketAddr, handler: Arc<Handler>) -> anyhow::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let handler = Arc::clone(&handler);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let handler = Arc::clone(&handler);
+                async move { Ok::<_, Infallible>(respond(&handler, req).await) }
+            }))
+        }
+    });
+    Server::try_bind(&addr)
+        .with_context(|| format!("failed to listen on {addr}"))?
+        .serve(make_service)
+        .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl Module for Health {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Health {
+            shards: Default::default(),
+        })
+    }
+}
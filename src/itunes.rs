@@ -0,0 +1,50 @@
+use anyhow::Context as _;
+use once_cell::sync::Lazy;
+use serde_derive::Deserialize;
+
+const LOOKUP_URL: &str = "https://itunes.apple.com/lookup";
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+#[derive(Deserialize)]
+struct Lookup {
+    results: Vec<LookupResult>,
+}
+
+/// Album or song returned by the iTunes lookup API
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookupResult {
+    pub wrapper_type: String,
+    #[serde(default)]
+    pub artist_name: String,
+    pub collection_name: Option<String>,
+    pub collection_view_url: Option<String>,
+    pub artwork_url_100: Option<String>,
+    pub release_date: Option<String>,
+    pub track_name: Option<String>,
+    pub track_view_url: Option<String>,
+    pub track_time_millis: Option<i64>,
+    pub disc_number: Option<u32>,
+    pub track_number: Option<u32>,
+}
+
+/// Looks up Apple Music items by ID, with the given extra query parameters
+pub async fn lookup(
+    id: &str,
+    country: &str,
+    params: &[(&str, &str)],
+) -> anyhow::Result<Vec<LookupResult>> {
+    let body = HTTP_CLIENT
+        .get(LOOKUP_URL)
+        .query(&[("id", id), ("country", country)])
+        .query(params)
+        .send()
+        .await
+        .context("fetching from the iTunes API")?
+        .error_for_status()?
+        .text()
+        .await?;
+    let lookup: Lookup = serde_json::from_str(&body).context("parsing iTunes lookup")?;
+    Ok(lookup.results)
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, Context as _};
+use reqwest::header::AUTHORIZATION;
+use rusqlite::params;
+use serde_derive::Deserialize;
+use serde_json::json;
+use serenity::{
+    async_trait,
+    model::prelude::{CommandInteraction, UserId},
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::token_cipher::TokenCipher;
+
+const API_URL: &str = "https://api.listenbrainz.org/1";
+/// Base64-encoded 32 bytes key user tokens are encrypted with
+const KEY_VAR: &str = "LISTENBRAINZ_KEY";
+
+/// Track played during a listening party, submitted as a listen to attendees' profiles
+pub struct Listen {
+    /// Unix timestamp of when the track started playing
+    pub listened_at: i64,
+    pub artist: String,
+    pub track: String,
+    pub release: String,
+    pub duration: chrono::Duration,
+}
+
+#[derive(Deserialize)]
+struct TokenValidation {
+    valid: bool,
+    user_name: Option<String>,
+}
+
+/// Exports listening party attendance to the ListenBrainz profiles of users who opted in
+pub struct ListenBrainz {
+    client: reqwest::Client,
+    // exporting is disabled when no key is configured
+    cipher: Option<TokenCipher>,
+}
+
+impl ListenBrainz {
+    fn cipher(&self) -> anyhow::Result<&TokenCipher> {
+        self.cipher
+            .as_ref()
+            .ok_or_else(|| anyhow!("ListenBrainz export is not configured"))
+    }
+
+    // checks a user token, returning the name of its ListenBrainz account
+    async fn validate(&self, token: &str) -> anyhow::Result<Option<String>> {
+        let validation: TokenValidation = self
+            .client
+            .get(format!("{API_URL}/validate-token"))
+            .header(AUTHORIZATION, format!("Token {token}"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(validation.user_name.filter(|_| validation.valid))
+    }
+
+    async fn submit(&self, token: &str, listens: &[Listen]) -> anyhow::Result<()> {
+        let payload = listens
+            .iter()
+            .map(|listen| {
+                json!({
+                    "listened_at": listen.listened_at,
+                    "track_metadata": {
+                        "artist_name": &listen.artist,
+                        "track_name": &listen.track,
+                        "release_name": &listen.release,
+                        "additional_info": {
+                            "duration_ms": listen.duration.num_milliseconds(),
+                            "submission_client": "humble_ledger",
+                        },
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        self.client
+            .post(format!("{API_URL}/submit-listens"))
+            .header(AUTHORIZATION, format!("Token {token}"))
+            .json(&json!({ "listen_type": "import", "payload": payload }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Submits the tracks of a listening party as listens for the attendees who opted in
+    pub async fn export_attendance(&self, handler: &Handler, users: &[UserId], listens: &[Listen]) {
+        if self.cipher.is_none() || users.is_empty() || listens.is_empty() {
+            return;
+        }
+        let tokens = {
+            let db = handler.db.lock().await;
+            let mut stmt = match db
+                .conn
+                .prepare("SELECT token FROM listenbrainz_tokens WHERE user_id = ?1")
+            {
+                Ok(stmt) => stmt,
+                Err(e) => {
+                    eprintln!("Error reading ListenBrainz tokens: {e}");
+                    return;
+                }
+            };
+            users
+                .iter()
+                .filter_map(|user| {
+                    stmt.query_row([user.get()], |row| row.get::<_, String>(0))
+                        .ok()
+                        .map(|token| (*user, token))
+                })
+                .collect::<Vec<_>>()
+        };
+        for (user, stored) in tokens {
+            let res = match self.cipher().and_then(|cipher| cipher.decrypt(&stored)) {
+                Ok(token) => self.submit(&token, listens).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                eprintln!("Error submitting listens to ListenBrainz for {user}: {e:?}");
+            }
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "listenbrainz",
+    desc = "Submit the tracks of listening parties you attend to ListenBrainz"
+)]
+pub struct LinkListenBrainz {
+    #[cmd(desc = "Your ListenBrainz user token, leave empty to stop submitting listens")]
+    token: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for LinkListenBrainz {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let listenbrainz: &ListenBrainz = data.module()?;
+        let user_id = interaction.user.id.get();
+        let Some(token) = self.token else {
+            data.db.lock().await.conn.execute(
+                "DELETE FROM listenbrainz_tokens WHERE user_id = ?1",
+                [user_id],
+            )?;
+            return CommandResponse::private("Listening parties will no longer be submitted");
+        };
+        let token = token.trim();
+        let encrypted = listenbrainz.cipher()?.encrypt(token)?;
+        let Some(user_name) = listenbrainz
+            .validate(token)
+            .await
+            .context("failed to validate token")?
+        else {
+            return CommandResponse::private("This token is not valid");
+        };
+        data.db.lock().await.conn.execute(
+            "INSERT INTO listenbrainz_tokens (user_id, token) VALUES (?1, ?2)
+                 ON CONFLICT (user_id) DO UPDATE SET token = ?2",
+            params![user_id, &encrypted],
+        )?;
+        CommandResponse::private(format!(
+            "Listening parties you attend will be submitted to {user_name}'s ListenBrainz profile"
+        ))
+    }
+}
+
+#[async_trait]
+impl Module for ListenBrainz {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS listenbrainz_tokens (
+                user_id INTEGER PRIMARY KEY,
+                token STRING NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(ListenBrainz {
+            client: reqwest::Client::new(),
+            cipher: TokenCipher::from_env(KEY_VAR)?,
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<LinkListenBrainz>();
+    }
+}
@@ -1,26 +1,58 @@
-use anyhow::Context as _;
-use futures_util::stream::{StreamExt, TryStreamExt};
+use anyhow::{anyhow, Context as _};
+use chrono::TimeZone;
+use fallible_iterator::FallibleIterator;
+use futures_util::stream::TryStreamExt;
+use itertools::Itertools;
 use once_cell::sync::Lazy;
 use regex::Regex;
-use rspotify::clients::BaseClient;
-use rspotify::model::{FullEpisode, FullTrack, PlayableItem, PlaylistItem};
-use serenity::builder::CreateEmbed;
+use rspotify::clients::{BaseClient, OAuthClient};
+use rspotify::model::{
+    FullEpisode, FullTrack, Market, PlayableId, PlayableItem, PlaylistItem,
+    TrackId,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::builder::{
+    CreateAllowedMentions, CreateAttachment, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    CreateScheduledEvent, CreateThread, EditMessage, EditScheduledEvent,
+};
+use serenity::futures::future::{BoxFuture, FutureExt};
+use serenity::http::Http;
+use serenity::model::guild::{ScheduledEventStatus, ScheduledEventType};
 use serenity::model::prelude::CommandInteraction;
-use serenity::model::prelude::{ChannelId, Message};
+use serenity::model::prelude::{
+    ChannelId, ChannelType, GuildId, Message, MessageId, MessageUpdateEvent,
+    RoleId, ScheduledEventId, User, UserId,
+};
+use serenity::model::{Permissions, Timestamp};
 use serenity::{async_trait, prelude::Context};
 use serenity_command::{BotCommand, CommandResponse, ResponseType};
 use serenity_command_derive::Command;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 
 use serenity_command_handler::events; // serenity-command-handler, for hooking
 
 use serenity_command_handler::modules::polls::ReadyPollStarted;
-use serenity_command_handler::modules::Spotify;
+use serenity_command_handler::modules::{ModPoll, Spotify};
+
+use crate::bandcamp::{album_from_tralbum, parse_tralbum};
+use crate::itunes;
+use crate::listenbrainz::{Listen, ListenBrainz};
+use crate::musicbrainz::{MusicBrainz, ReleaseInfo};
+use crate::settings::{self, GuildSettings};
+use crate::spotify_accounts::SpotifyAccounts;
+use crate::spotify_cache::SpotifyCache;
+use crate::spotify_link;
+use crate::spotify_market::SpotifyMarket;
+use crate::GO_EMOJI;
 
 use serenity_command_handler::{
-    CommandStore, CompletionStore, Handler, HandlerBuilder, Module, ModuleMap,
+    db::Db, CommandStore, CompletionStore, Handler, HandlerBuilder, Module,
+    ModuleMap,
 };
 
 #[derive(Debug)]
@@ -54,66 +86,119 @@ pub struct LPInfo {
     tracks: Vec<TrackInfo>,
     /// If and when the listening party has started
     started: Option<chrono::DateTime<chrono::Utc>>,
+    /// Guild the listening party was pinged in
+    guild_id: Option<GuildId>,
+    /// Number of the last track announced in the channel
+    announced: Option<usize>,
+    /// User who pinged or started the listening party
+    host: Option<UserId>,
+    /// Message the listening party was pinged in
+    ping_message: Option<MessageId>,
+    /// Start automatically when the previous listening party of the channel
+    /// finishes
+    chain: bool,
+    /// Users who talked in the channel while the listening party was playing
+    participants: HashSet<UserId>,
+    /// Link to the first message sent during the listening party
+    discussion_start: Option<String>,
+    /// Whether the end summary was posted
+    summarized: bool,
+    /// Cover art of the album or playlist
+    image: Option<String>,
+    /// Year the album was released
+    release_year: Option<String>,
+    /// Main genres of the album, from MusicBrainz
+    genres: Vec<String>,
+    /// Event created in the guild for the listening party
+    event: Option<ScheduledEventId>,
+    /// Whether creating the event was attempted, so it is not retried
+    event_attempted: bool,
+    /// Whether the listening party was logged in the guild's history, which
+    /// happens once it starts playing
+    recorded: bool,
 }
 
 impl LPInfo {
+    fn new(playlist: PlaylistInfo, tracks: Vec<TrackInfo>) -> Self {
+        LPInfo {
+            playlist,
+            tracks,
+            started: None,
+            guild_id: None,
+            announced: None,
+            host: None,
+            ping_message: None,
+            chain: false,
+            participants: HashSet::new(),
+            discussion_start: None,
+            summarized: false,
+            image: None,
+            release_year: None,
+            genres: Vec::new(),
+            event: None,
+            event_attempted: false,
+            recorded: false,
+        }
+    }
+
     /// Look up an album from a spotify ID
     async fn from_spotify_album_id<C: BaseClient>(
         client: &C,
+        cache: &SpotifyCache,
+        market: Option<Market>,
         album_id_str: &str,
     ) -> anyhow::Result<Self> {
         let album_id = rspotify::model::AlbumId::from_id(album_id_str)
             .context("trying to parse album ID")?;
 
-        let album = client
-            .album(album_id.clone(), None)
-            .await
-            .context("fetching album")?;
+        let album = cache.album(client, album_id.clone(), market).await?;
         let artists = album
             .artists
             .iter()
             .map(|a| a.name.as_ref())
             .collect::<Vec<_>>()
             .join(", ");
-        let tracks = client
-            .album_track(album_id, None)
+        let tracks = cache
+            .album_tracks(client, album_id, market)
+            .await?
+            .into_iter()
             .enumerate()
-            .map(|(count, mb)| mb.map(|x| (count, x)))
-            .map_ok(|(count, track)| TrackInfo {
+            .map(|(count, track)| TrackInfo {
                 number: count + 1,
                 name: track.name.to_string(),
                 duration: track.duration.clone(),
                 uri: track.external_urls.get("spotify").map(|s| s.to_owned()),
             })
-            .try_collect::<Vec<TrackInfo>>()
-            .await?;
+            .collect();
 
+        let playlist = PlaylistInfo::AlbumInfo {
+            id: album.id.to_string(),
+            artist: artists.clone(),
+            name: album.name.to_string(),
+            uri: album.external_urls.get("spotify").map(|s| s.to_owned()),
+        };
         Ok(LPInfo {
-            playlist: PlaylistInfo::AlbumInfo {
-                id: album.id.to_string(),
-                artist: artists.clone(),
-                name: album.name.to_string(),
-                uri: album.external_urls.get("spotify").map(|s| s.to_owned()),
-            },
-            tracks,
-            started: None,
+            image: album.images.first().map(|image| image.url.clone()),
+            release_year: release_year(&album.release_date),
+            ..LPInfo::new(playlist, tracks)
         })
     }
     /// Look up a playlist from a spotify ID
     async fn from_spotify_playlist_id<C: BaseClient>(
         client: &C,
+        market: Option<Market>,
         album_id_str: &str,
     ) -> anyhow::Result<Self> {
         let playlist_id = rspotify::model::PlaylistId::from_id(album_id_str)
             .context("trying to parse playlist ID")?;
 
         let playlist = client
-            .playlist(playlist_id.clone(), None, None)
+            .playlist(playlist_id.clone(), None, market)
             .await
             .context("fetching playlist")?;
 
         let items = client
-            .playlist_items(playlist_id, None, None)
+            .playlist_items(playlist_id, None, market)
             .try_collect::<Vec<PlaylistItem>>()
             .await?;
         let tracks = items
@@ -142,37 +227,151 @@ impl LPInfo {
             })
             .collect::<Vec<_>>();
 
+        let playlist = PlaylistInfo::PlaylistInfo {
+            id: playlist.id.to_string(),
+            name: playlist.name.to_string(),
+            uri: playlist.external_urls.get("spotify").map(|s| s.to_owned()),
+        };
+        Ok(LPInfo {
+            image: playlist.images.first().map(|image| image.url.clone()),
+            ..LPInfo::new(playlist, tracks)
+        })
+    }
+
+    /// Look up an album from an Apple Music ID, using the iTunes API
+    async fn from_apple_music_album_id(
+        country: &str,
+        album_id: &str,
+    ) -> anyhow::Result<Self> {
+        let results = itunes::lookup(album_id, country, &[("entity", "song")])
+            .await
+            .context("fetching Apple Music album")?;
+        let mut results = results.into_iter();
+        let album = results
+            .next()
+            .filter(|album| album.wrapper_type == "collection")
+            .ok_or_else(|| anyhow!("Apple Music album not found"))?;
+        let mut songs = results
+            .filter(|song| song.wrapper_type == "track")
+            .collect::<Vec<_>>();
+        songs.sort_by_key(|song| (song.disc_number, song.track_number));
+        let tracks = songs
+            .into_iter()
+            .enumerate()
+            .map(|(count, song)| TrackInfo {
+                number: count + 1,
+                name: song.track_name.unwrap_or_default(),
+                uri: song.track_view_url,
+                duration: chrono::Duration::milliseconds(
+                    song.track_time_millis.unwrap_or(0),
+                ),
+            })
+            .collect();
+
+        let playlist = PlaylistInfo::AlbumInfo {
+            id: album_id.to_string(),
+            artist: album.artist_name,
+            name: album.collection_name.unwrap_or_default(),
+            uri: album.collection_view_url,
+        };
         Ok(LPInfo {
-            playlist: PlaylistInfo::PlaylistInfo {
-                id: playlist.id.to_string(),
-                name: playlist.name.to_string(),
-                uri: playlist
-                    .external_urls
-                    .get("spotify")
-                    .map(|s| s.to_owned()),
-            },
-            tracks,
-            started: None,
+            image: album
+                .artwork_url_100
+                .map(|url| url.replace("100x100", "600x600")),
+            release_year: album.release_date.as_deref().and_then(release_year),
+            ..LPInfo::new(playlist, tracks)
+        })
+    }
+
+    /// Look up an album from its Bandcamp page
+    async fn from_bandcamp_album_url(url: &str) -> anyhow::Result<Self> {
+        let page = HTTP_CLIENT
+            .get(url)
+            .send()
+            .await
+            .context("fetching Bandcamp album")?
+            .error_for_status()?
+            .text()
+            .await?;
+        let tralbum = parse_tralbum(&page)?;
+        let image = tralbum.art_id.map(|art_id| {
+            format!("https://f4.bcbits.com/img/a{art_id}_10.jpg")
+        });
+        let year = tralbum.album_release_date.as_deref().and_then(release_year);
+        let album_url = tralbum.url.clone().unwrap_or_else(|| url.to_string());
+        let album = album_from_tralbum(&reqwest::Url::parse(url)?, tralbum);
+        let tracks = album
+            .tracks
+            .into_iter()
+            .enumerate()
+            .map(|(count, track)| TrackInfo {
+                number: count + 1,
+                name: track.title,
+                uri: Some(track.url).filter(|uri| !uri.is_empty()),
+                duration: track.duration.unwrap_or_else(chrono::Duration::zero),
+            })
+            .collect();
+
+        let playlist = PlaylistInfo::AlbumInfo {
+            id: url.to_string(),
+            artist: album.artist,
+            name: album.name,
+            uri: Some(album_url),
+        };
+        Ok(LPInfo {
+            image,
+            release_year: year,
+            ..LPInfo::new(playlist, tracks)
         })
     }
 
-    /// Find spotify album or playlist in chat line and fetch info
+    /// Find spotify, Apple Music or Bandcamp album or playlist in chat line
+    /// and fetch info
     async fn from_match_string<C: BaseClient>(
         client: &C,
+        cache: &SpotifyCache,
+        market: Option<Market>,
         string: &str,
     ) -> anyhow::Result<Option<Self>> {
+        let resolved;
+        let string = match spotify_link::find_shortened(string) {
+            Some(link) => {
+                resolved = spotify_link::resolve(link).await?;
+                resolved.as_str()
+            }
+            None => string,
+        };
         if let Some(aid) = match_spotify_album(string) {
-            return Ok(Some(Self::from_spotify_album_id(client, aid).await?));
+            return Ok(Some(
+                Self::from_spotify_album_id(client, cache, market, aid).await?,
+            ));
         }
         if let Some(pid) = match_spotify_playlist(string) {
             return Ok(Some(
-                Self::from_spotify_playlist_id(client, pid).await?,
+                Self::from_spotify_playlist_id(client, market, pid).await?,
+            ));
+        }
+        if let Some((country, aid)) = match_apple_music_album(string) {
+            return Ok(Some(
+                Self::from_apple_music_album_id(country, aid).await?,
             ));
         }
+        if let Some(url) = match_bandcamp_album(string) {
+            return Ok(Some(Self::from_bandcamp_album_url(url).await?));
+        }
         return Ok(None);
     }
 }
 
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// Find the year in a release date, whatever its format
+fn release_year(date: &str) -> Option<String> {
+    static YEAR_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\b(\d{4})\b").unwrap());
+    YEAR_RE.captures(date).map(|cap| cap[1].to_string())
+}
+
 /// State of the listening party
 enum PlayState<'a> {
     NotStarted,
@@ -200,6 +399,101 @@ fn maybe_uri<S: AsRef<str>, T: AsRef<str>>(
 }
 
 impl LPInfo {
+    /// Name of the album or playlist, without links
+    fn display_name(&self) -> String {
+        match &self.playlist {
+            PlaylistInfo::AlbumInfo { artist, name, .. } => {
+                format!("{artist} - {name}")
+            }
+            PlaylistInfo::PlaylistInfo { name, .. } => name.clone(),
+        }
+    }
+
+    /// ID of the album or playlist on its platform
+    fn id(&self) -> &str {
+        match &self.playlist {
+            PlaylistInfo::AlbumInfo { id, .. }
+            | PlaylistInfo::PlaylistInfo { id, .. } => id,
+        }
+    }
+
+    /// Link to the album or playlist
+    fn uri(&self) -> Option<&str> {
+        match &self.playlist {
+            PlaylistInfo::AlbumInfo { uri, .. }
+            | PlaylistInfo::PlaylistInfo { uri, .. } => uri.as_deref(),
+        }
+    }
+
+    /// Log the listening party in the guild's history
+    fn record_history(
+        &self,
+        conn: &Connection,
+        guild_id: GuildId,
+        channel: ChannelId,
+        host: Option<UserId>,
+        // Unix timestamp
+        played_at: i64,
+    ) -> anyhow::Result<()> {
+        conn.execute(
+            "INSERT INTO lp_history (guild_id, channel_id, host_id, album_id,
+                 name, link, played_at, duration, message_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                guild_id.get(),
+                channel.get(),
+                host.map(UserId::get),
+                self.id(),
+                self.display_name(),
+                self.uri(),
+                played_at,
+                self.duration().num_seconds(),
+                self.ping_message.map(MessageId::get),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Tracks of the album at the times they played, for ListenBrainz
+    ///
+    /// Playlist tracks are left out as their artists are not known
+    fn listens(&self) -> Vec<Listen> {
+        let (PlaylistInfo::AlbumInfo { artist, name, .. }, Some(started)) =
+            (&self.playlist, self.started)
+        else {
+            return Vec::new();
+        };
+        let mut listened_at = started;
+        self.tracks
+            .iter()
+            .map(|track| {
+                let listen = Listen {
+                    listened_at: listened_at.timestamp(),
+                    artist: artist.clone(),
+                    track: track.name.clone(),
+                    release: name.clone(),
+                    duration: track.duration,
+                };
+                listened_at += track.duration;
+                listen
+            })
+            .collect()
+    }
+
+    /// Short description of the state of the listening party
+    fn status(&self) -> &'static str {
+        match self.now_playing(chrono::Duration::zero()) {
+            PlayState::NotStarted => "not started",
+            PlayState::Finished(_) => "finished",
+            PlayState::Playing { .. } => "playing",
+        }
+    }
+
+    /// Total duration of the album or playlist
+    fn duration(&self) -> chrono::Duration {
+        self.tracks.iter().map(|t| t.duration).sum()
+    }
+
     /// Calculate which track is playing `offset` seconds from now
     fn now_playing(&self, offset: chrono::Duration) -> PlayState {
         let started = match self.started {
@@ -209,11 +503,8 @@ impl LPInfo {
             Some(started) => started,
         };
         let now = chrono::offset::Utc::now();
-        if started > now {
-            eprintln!(
-                "LPInfo: Started timestamp in the future! started={} > now={}",
-                started, now
-            );
+        // The start is delayed to later
+        if started > now + offset {
             return PlayState::NotStarted;
         }
         let mut remain = now - started + offset;
@@ -233,6 +524,128 @@ impl LPInfo {
         PlayState::Finished(remain)
     }
 
+    /// State of the listening party, as returned by the API
+    fn to_json(&self, channel: ChannelId) -> serde_json::Value {
+        let (track, position) = match self.now_playing(chrono::Duration::zero())
+        {
+            PlayState::Playing { track, position } => {
+                (Some(track.name.as_str()), Some(position.num_seconds()))
+            }
+            _ => (None, None),
+        };
+        serde_json::json!({
+            "channel_id": channel.to_string(),
+            "name": self.display_name(),
+            "link": self.uri(),
+            "status": self.status(),
+            "started_at": self.started.map(|started| started.to_rfc3339()),
+            "host_id": self.host.map(|host| host.to_string()),
+            "track": track,
+            "position": position,
+            "duration": self.duration().num_seconds(),
+        })
+    }
+
+    /// Move the listening party to `position` in the track at `index`
+    ///
+    /// Returns false if there is no such track
+    fn seek(&mut self, index: usize, position: chrono::Duration) -> bool {
+        if index >= self.tracks.len() {
+            return false;
+        }
+        let before: chrono::Duration =
+            self.tracks[..index].iter().map(|t| t.duration).sum();
+        self.started = Some(chrono::offset::Utc::now() - before - position);
+        true
+    }
+
+    /// Jump to the start of the next track
+    ///
+    /// Returns the index of the new track, if any
+    fn skip(&mut self) -> Option<usize> {
+        let PlayState::Playing { track, .. } =
+            self.now_playing(chrono::Duration::zero())
+        else {
+            return None;
+        };
+        let next = track.number;
+        self.seek(next, chrono::Duration::zero()).then_some(next)
+    }
+
+    /// Release year, genres and track count, shown in embed footers
+    fn metadata(&self) -> String {
+        let count = match self.tracks.len() {
+            1 => "1 track".to_string(),
+            n => format!("{n} tracks"),
+        };
+        let genres = (!self.genres.is_empty()).then(|| self.genres.join(", "));
+        [self.release_year.clone(), genres, Some(count)]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" • ")
+    }
+
+    fn apply_release_info(&mut self, info: ReleaseInfo) {
+        if self.release_year.is_none() {
+            self.release_year = info.year;
+        }
+        self.genres = info.genres;
+    }
+
+    /// Fill in the release year and genres of an album from MusicBrainz
+    async fn enrich(&mut self, handler: &Handler) {
+        let PlaylistInfo::AlbumInfo { artist, name, .. } = &self.playlist
+        else {
+            return;
+        };
+        let Ok(musicbrainz) = handler.module::<MusicBrainz>() else {
+            return;
+        };
+        match musicbrainz.release_info(handler, artist, name).await {
+            Ok(Some(info)) => self.apply_release_info(info),
+            Ok(None) => {}
+            Err(e) => eprintln!("Error looking up album on MusicBrainz: {e:?}"),
+        }
+    }
+
+    /// Same as `enrich`, only using previous lookups, for commands that have
+    /// to answer quickly
+    fn enrich_cached(&mut self, conn: &Connection) {
+        let PlaylistInfo::AlbumInfo { artist, name, .. } = &self.playlist
+        else {
+            return;
+        };
+        match MusicBrainz::cached(conn, artist, name) {
+            Ok(Some(info)) => self.apply_release_info(info),
+            Ok(None) => {}
+            Err(e) => eprintln!("Error reading MusicBrainz cache: {e:?}"),
+        }
+    }
+
+    /// Add the cover art and metadata to an embed
+    fn decorate_embed(&self, mut embed: CreateEmbed) -> CreateEmbed {
+        if let Some(image) = &self.image {
+            embed = embed.thumbnail(image);
+        }
+        embed.footer(CreateEmbedFooter::new(self.metadata()))
+    }
+
+    /// Replace the album or playlist after the ping was edited, keeping
+    /// everything if it did not change
+    fn reresolved(self, new: LPInfo) -> LPInfo {
+        if new.id() == self.id() {
+            return self;
+        }
+        LPInfo {
+            guild_id: self.guild_id,
+            host: self.host,
+            ping_message: self.ping_message,
+            chain: self.chain,
+            ..new
+        }
+    }
+
     /// Build discord embed for lp_info
     fn build_info_embed(&self) -> CreateEmbed {
         let (lp_name, lp_id) = match &self.playlist {
@@ -251,16 +664,30 @@ impl LPInfo {
                 (format!("**Playlist**: {playlist_name}"), id.clone())
             }
         };
-        let playlist_duration = self.tracks.iter().map(|t| t.duration).sum();
+        let playlist_duration = self.duration();
         let mut embed = CreateEmbed::new().description(format!(
             "{} - \\[{}\\]",
             lp_name,
             display_duration(playlist_duration),
         ));
         match self.now_playing(chrono::Duration::seconds(0)) {
-            PlayState::NotStarted => {
-                embed = embed.title("Listening Party has not started yet.");
-            }
+            PlayState::NotStarted => match self.started {
+                Some(started) => {
+                    embed =
+                        embed.title("Listening Party starting soon!").field(
+                            "",
+                            format!(
+                                "**Starts**: <t:{}:t> (<t:{}:R>)",
+                                started.timestamp(),
+                                started.timestamp()
+                            ),
+                            true,
+                        );
+                }
+                None => {
+                    embed = embed.title("Listening Party has not started yet.");
+                }
+            },
             PlayState::Finished(_) => {
                 embed = embed.title("Listening Party has finished.");
             }
@@ -271,9 +698,15 @@ impl LPInfo {
                 let track_uri_ctx = track
                     .uri
                     .as_ref()
-                    .map(|uri| format!("{}?context={}", uri, &lp_id));
+                    .map(|uri| track_uri_in_context(uri, &lp_id));
                 let playlist_end =
                     (self.started.unwrap() + playlist_duration).timestamp();
+                let elapsed = now - self.started.unwrap();
+                let track_index = self
+                    .tracks
+                    .iter()
+                    .position(|t| std::ptr::eq(t, track))
+                    .unwrap_or_default();
                 embed = embed
                     .title("Listening Party in full swing! Join in!")
                     .field(
@@ -290,17 +723,95 @@ impl LPInfo {
                     .field(
                         "Now playing",
                         format!(
-                            "Track {} - {} - [{}]\nTrack started <t:{}:R>",
+                            "Track {} - {} - [{}]\nTrack started <t:{}:R>\n\
+                             `{}` {} / {}",
                             track.number,
                             maybe_uri(&track.name, track_uri_ctx.as_ref()),
                             display_duration(track.duration),
                             (now - position).timestamp(),
+                            progress_bar(position, track.duration),
+                            display_duration(position),
+                            display_duration(track.duration),
+                        ),
+                        false,
+                    )
+                    .field(
+                        "Progress",
+                        format!(
+                            "Track {} of {}\n`{}` {} remaining",
+                            track_index + 1,
+                            self.tracks.len(),
+                            progress_bar(elapsed, playlist_duration),
+                            display_duration(playlist_duration - elapsed),
                         ),
                         false,
                     );
             }
         }
-        embed
+        self.decorate_embed(embed)
+    }
+
+    /// Build discord embed posted when the listening party finishes
+    fn build_summary_embed(&self) -> CreateEmbed {
+        let name = maybe_uri(self.display_name(), self.uri());
+        let mut embed = CreateEmbed::new()
+            .title("Listening Party finished!")
+            .description(format!(
+                "{name} - \\[{}\\]",
+                display_duration(self.duration())
+            ))
+            .field("Participants", self.participants.len().to_string(), true);
+        if let Some(link) = &self.discussion_start {
+            embed = embed.field(
+                "Discussion",
+                format!("[Jump to the start]({link})"),
+                true,
+            );
+        }
+        embed.field(
+            "",
+            "How did you like it? Share your rating out of 10!",
+            false,
+        )
+    }
+
+    /// Build discord embed for lp_tracklist
+    fn build_tracklist_embed(&self) -> CreateEmbed {
+        // Leave room for the truncation notice
+        const MAX_LEN: usize = 4000;
+        let mut offset = chrono::Duration::zero();
+        let mut description = String::new();
+        for (i, track) in self.tracks.iter().enumerate() {
+            let start = match self.started {
+                Some(started) => {
+                    format!("<t:{}:t>", (started + offset).timestamp())
+                }
+                None => format!("+{}", display_duration(offset)),
+            };
+            let line = format!(
+                "{}. {} - \\[{}\\] - {}\n",
+                track.number,
+                maybe_uri(&track.name, track.uri.as_ref()),
+                display_duration(track.duration),
+                start,
+            );
+            if description.len() + line.len() > MAX_LEN {
+                description.push_str(&format!(
+                    "... and {} more",
+                    self.tracks.len() - i
+                ));
+                break;
+            }
+            description.push_str(&line);
+            offset = offset + track.duration;
+        }
+        CreateEmbed::new()
+            .title(format!(
+                "{} - \\[{}\\]",
+                self.display_name(),
+                display_duration(self.duration())
+            ))
+            .description(description)
     }
 
     /// Build discord embed for lp_join
@@ -311,9 +822,17 @@ impl LPInfo {
         };
         let mut embed = CreateEmbed::new();
         match self.now_playing(offset) {
-            PlayState::NotStarted => {
-                embed = embed.title("Listening Party has not started yet.");
-            }
+            PlayState::NotStarted => match self.started {
+                Some(started) => {
+                    embed = embed.title(format!(
+                        "Listening Party starting <t:{}:R>, join again then!",
+                        started.timestamp()
+                    ));
+                }
+                None => {
+                    embed = embed.title("Listening Party has not started yet.");
+                }
+            },
             PlayState::Finished(_) => {
                 embed = embed.title("Listening Party has finished.");
             }
@@ -322,7 +841,7 @@ impl LPInfo {
                 let track_uri_ctx = track
                     .uri
                     .as_ref()
-                    .map(|uri| format!("{}?context={}", uri, &lp_id));
+                    .map(|uri| track_uri_in_context(uri, &lp_id));
                 embed = embed.title("Join this listening party").field(
                     "Select track",
                     format!(
@@ -338,12 +857,24 @@ impl LPInfo {
                 );
             }
         }
-        embed
+        self.decorate_embed(embed)
     }
 }
 
+/// Draw a bar showing how much of `total` is `done`
+fn progress_bar(done: chrono::Duration, total: chrono::Duration) -> String {
+    const WIDTH: i64 = 16;
+    let filled = if total > chrono::Duration::zero() {
+        (done.num_milliseconds() * WIDTH / total.num_milliseconds())
+            .clamp(0, WIDTH)
+    } else {
+        0
+    };
+    "▰".repeat(filled as usize) + &"▱".repeat((WIDTH - filled) as usize)
+}
+
 /// Format Duration as [hh:]mm:ss
-fn display_duration(duration: chrono::Duration) -> String {
+pub fn display_duration(duration: chrono::Duration) -> String {
     let allsecs = duration.num_seconds();
     let seconds = allsecs % 60;
     let minutes = allsecs / 60 % 60;
@@ -371,6 +902,65 @@ fn match_spotify_album(string: &str) -> Option<&str> {
         .map(|caps| caps.get(1).unwrap().as_str())
 }
 
+/// Regex to identify Apple Music album URIs and extract the storefront and
+/// album id
+static APPLE_MUSIC_ALBUM_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        "\\bhttps://music.apple.com/([a-z]{2})\
+            /album/(?:[^/\\s]+/)?([0-9]+)\\b",
+    )
+    .unwrap()
+});
+
+/// Find Apple Music album URI and extract the storefront and album ID
+fn match_apple_music_album(string: &str) -> Option<(&str, &str)> {
+    APPLE_MUSIC_ALBUM_RE.captures(string).map(|caps| {
+        (caps.get(1).unwrap().as_str(), caps.get(2).unwrap().as_str())
+    })
+}
+
+/// Regex to identify Bandcamp album URIs
+static BANDCAMP_ALBUM_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new("\\bhttps://[a-z0-9-]+\\.bandcamp\\.com/album/[a-z0-9-]+")
+        .unwrap()
+});
+
+/// Find Bandcamp album URI, without its query string
+fn match_bandcamp_album(string: &str) -> Option<&str> {
+    BANDCAMP_ALBUM_RE.find(string).map(|m| m.as_str())
+}
+
+/// Whether a string contains a link to a supported album or playlist
+fn contains_lp_link(string: &str) -> bool {
+    match_spotify_album(string).is_some()
+        || match_spotify_playlist(string).is_some()
+        || match_apple_music_album(string).is_some()
+        || match_bandcamp_album(string).is_some()
+}
+
+/// Text of a message in which to look for links, including its embeds since
+/// some apps and bots share links through embeds only
+fn message_text(msg: &Message) -> String {
+    let mut text = msg.content.clone();
+    for embed in &msg.embeds {
+        for part in [&embed.url, &embed.description].into_iter().flatten() {
+            text.push('\n');
+            text.push_str(part);
+        }
+    }
+    text
+}
+
+/// Link to a track in the context of its album or playlist, so that spotify
+/// keeps playing the following tracks
+fn track_uri_in_context(uri: &str, lp_id: &str) -> String {
+    if uri.starts_with("https://open.spotify.com") {
+        format!("{uri}?context={lp_id}")
+    } else {
+        uri.to_string()
+    }
+}
+
 /// Regex to identity spotify playlist URIs and extract album id
 const SPOTIFY_PLAYLIST_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
@@ -423,11 +1013,133 @@ impl BotCommand for CurrentLP {
     }
 }
 
+/// Seconds to start playing used by lp_join when nothing is configured
+const DEFAULT_JOIN_OFFSET: u64 = 15;
+
+/// Find the default lp_join offset of a user, falling back to their guild's
+///
+/// Returns the offset and where it comes from
+fn default_join_offset(
+    conn: &Connection,
+    guild_id: Option<GuildId>,
+    user: UserId,
+) -> anyhow::Result<(u64, &'static str)> {
+    let user_offset: Option<u64> = conn
+        .query_row(
+            "SELECT join_offset FROM lp_user_settings WHERE user_id = ?1",
+            [user.get()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(offset) = user_offset {
+        return Ok((offset, "your default"));
+    }
+    let guild_offset: Option<u64> = match guild_id {
+        Some(guild_id) => conn
+            .query_row(
+                "SELECT join_offset FROM lp_settings WHERE guild_id = ?1",
+                [guild_id.get()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten(),
+        None => None,
+    };
+    Ok(match guild_offset {
+        Some(offset) => (offset, "server default"),
+        None => (DEFAULT_JOIN_OFFSET, "default"),
+    })
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "np",
+    desc = "What is playing in the listening party right now?"
+)]
+pub struct NowPlayingLP {}
+
+#[async_trait]
+impl BotCommand for NowPlayingLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let lps = data.module::<ModLPInfo>()?.last_pinged.read().await;
+        let Some(lp) = lps.get(&interaction.channel_id) else {
+            return CommandResponse::private(
+                "There is no listening party at the moment.",
+            );
+        };
+        let msg = match lp.now_playing(chrono::Duration::zero()) {
+            PlayState::NotStarted => {
+                "The listening party has not started yet.".to_string()
+            }
+            PlayState::Finished(_) => {
+                "The listening party has finished.".to_string()
+            }
+            PlayState::Playing { track, position } => {
+                let uri = track
+                    .uri
+                    .as_ref()
+                    .map(|uri| track_uri_in_context(uri, lp.id()));
+                format!(
+                    "Track {} - {} - {}s remaining",
+                    track.number,
+                    maybe_uri(&track.name, uri.as_ref()),
+                    (track.duration - position).num_seconds(),
+                )
+            }
+        };
+        CommandResponse::private(msg)
+    }
+}
+
 #[derive(Command, Debug)]
 #[cmd(name = "lp_join", desc = "Join a listening party (privately)")]
 pub struct JoinLP {
     #[cmd(desc = "Seconds to start playing")]
     offset: Option<u64>,
+    #[cmd(desc = "Send me a countdown to the start point in DMs")]
+    countdown: Option<bool>,
+}
+
+/// DM a countdown to `start` to the user, updated every second for the last
+/// few seconds
+async fn dm_countdown(
+    http: Arc<Http>,
+    user: User,
+    start: Instant,
+    embed: CreateEmbed,
+) -> anyhow::Result<()> {
+    const COUNTDOWN: Duration = Duration::from_secs(10);
+    let mut msg = user
+        .direct_message(
+            &http,
+            CreateMessage::new().content("Get ready...").embed(embed),
+        )
+        .await?;
+    tokio::time::sleep_until(start.checked_sub(COUNTDOWN).unwrap_or(start))
+        .await;
+    loop {
+        let remaining = start.saturating_duration_since(Instant::now());
+        // Round up so the last second displayed is 1
+        let secs = (remaining.as_millis() as u64 + 999) / 1000;
+        if secs == 0 {
+            break;
+        }
+        msg.edit(
+            &http,
+            EditMessage::new().content(format!("Start playback in **{secs}**")),
+        )
+        .await?;
+        tokio::time::sleep(remaining - Duration::from_secs(secs - 1)).await;
+    }
+    msg.edit(&http, EditMessage::new().content("**Start playback now!**"))
+        .await?;
+    Ok(())
 }
 
 #[async_trait]
@@ -436,11 +1148,17 @@ impl BotCommand for JoinLP {
     async fn run(
         self,
         data: &Handler,
-        _ctx: &Context,
+        ctx: &Context,
         interaction: &CommandInteraction,
     ) -> anyhow::Result<CommandResponse> {
-        let offset =
-            chrono::Duration::seconds(self.offset.unwrap_or(15) as i64);
+        let (offset, source) = match self.offset {
+            Some(offset) => (offset, "chosen"),
+            None => default_join_offset(
+                &data.db.lock().await.conn,
+                interaction.guild_id,
+                interaction.user.id,
+            )?,
+        };
         // Find last LP
         let lps = data.module::<ModLPInfo>().unwrap().last_pinged.read().await;
         let lp = lps.get(&interaction.channel_id);
@@ -449,65 +1167,1910 @@ impl BotCommand for JoinLP {
                 "There is no listening party at the moment.",
             ),
             Some(lpinfo) => {
-                CommandResponse::private(lpinfo.build_join_embed(offset))
+                let join_offset = chrono::Duration::seconds(offset as i64);
+                let embed = lpinfo.build_join_embed(join_offset);
+                let playing = matches!(
+                    lpinfo.now_playing(join_offset),
+                    PlayState::Playing { .. }
+                );
+                if self.countdown.unwrap_or(false) && playing {
+                    let start = Instant::now() + Duration::from_secs(offset);
+                    let http = Arc::clone(&ctx.http);
+                    let user = interaction.user.clone();
+                    let dm_embed = embed.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) =
+                            dm_countdown(http, user, start, dm_embed).await
+                        {
+                            eprintln!("Error sending lp_join countdown: {e}");
+                        }
+                    });
+                }
+                CommandResponse::private(embed.footer(CreateEmbedFooter::new(
+                    format!(
+                        "{} • Offset: {offset}s ({source})",
+                        lpinfo.metadata()
+                    ),
+                )))
             }
         }
     }
 }
 
-pub struct ModLPInfo {
-    last_pinged: Arc<RwLock<HashMap<ChannelId, LPInfo>>>,
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_tracklist",
+    desc = "Show the tracklist of the current listening party"
+)]
+pub struct TracklistLP {
+    #[cmd(desc = "Should the answer be visible to everyone?")]
+    visible: Option<bool>,
 }
 
-impl Clone for ModLPInfo {
-    fn clone(&self) -> Self {
-        ModLPInfo {
-            last_pinged: Arc::clone(&self.last_pinged),
+#[async_trait]
+impl BotCommand for TracklistLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let msg: ResponseType = {
+            let lps = data.module::<ModLPInfo>()?.last_pinged.read().await;
+            match lps.get(&interaction.channel_id) {
+                None => "There is no listening party at the moment.".into(),
+                Some(lpinfo) => lpinfo.build_tracklist_embed().into(),
+            }
+        };
+
+        if self.visible.unwrap_or(false) {
+            CommandResponse::public(msg)
+        } else {
+            CommandResponse::private(msg)
         }
     }
 }
 
-// Roles used for pinging listening parties
-const LP_ROLES: &'static [&'static str] =
-    &[&"Listening Party", &"Impromptu Listening Party"];
-
-impl ModLPInfo {
-    pub fn new() -> Self {
-        ModLPInfo {
-            last_pinged: Default::default(),
-        }
-    }
+/// Extract the ID of a spotify track from its URL
+fn spotify_track_id(uri: &str) -> Option<&str> {
+    uri.strip_prefix("https://open.spotify.com/track/")
+        .map(|id| id.split('?').next().unwrap_or(id))
+}
 
-    // Handle messages to remember the last pinged album
-    //
-    // We consider a message a LP ping if if mentions one of the LP roles
-    // and it contains a spotify playlist or album link
-    pub async fn handle_message<C: BaseClient>(
-        &self,
-        client: &C,
-        ctx: &Context,
-        msg: &Message,
-    ) {
-        let msg_txt: &str = &msg.content;
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_join_auto",
+    desc = "Join a listening party on your active Spotify device"
+)]
+pub struct JoinLPAuto {}
 
-        // Check if the specified roles were mentioned
-        if msg
-            .mention_roles
+#[async_trait]
+impl BotCommand for JoinLPAuto {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let accounts = data.module::<SpotifyAccounts>()?;
+        let user = interaction.user.id;
+        let Some(client) = accounts.user_client(data, user).await? else {
+            return CommandResponse::private(
+                "Link your Spotify account with /spotify_link first",
+            );
+        };
+        let (track_ids, position) = {
+            let lps = data.module::<ModLPInfo>()?.last_pinged.read().await;
+            let Some(lp) = lps.get(&interaction.channel_id) else {
+                return CommandResponse::private(
+                    "There is no listening party at the moment.",
+                );
+            };
+            let PlayState::Playing { track, position } =
+                lp.now_playing(chrono::Duration::zero())
+            else {
+                return CommandResponse::private(
+                    "The listening party is not playing",
+                );
+            };
+            // Queue the rest of the party after the current track
+            let current = lp
+                .tracks
+                .iter()
+                .position(|t| std::ptr::eq(t, track))
+                .unwrap_or_default();
+            let track_ids = lp.tracks[current..]
+                .iter()
+                .map_while(|track| {
+                    track.uri.as_deref().and_then(spotify_track_id)
+                })
+                .map(|id| TrackId::from_id(id.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            (track_ids, position)
+        };
+        if track_ids.is_empty() {
+            return CommandResponse::private(
+                "The current track is not available on Spotify",
+            );
+        }
+        client
+            .start_uris_playback(
+                track_ids.into_iter().map(PlayableId::from),
+                None,
+                None,
+                Some(position),
+            )
+            .await
+            .context(
+                "starting playback, is Spotify open on one of your devices?",
+            )?;
+        accounts.save_token(data, user, &client).await?;
+        CommandResponse::private("Playback started, enjoy the listening party!")
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_join_offset",
+    desc = "Set your default number of seconds to start playing for lp_join"
+)]
+pub struct SetJoinOffset {
+    #[cmd(desc = "Seconds to start playing, leave empty to use the default")]
+    seconds: Option<u64>,
+}
+
+#[async_trait]
+impl BotCommand for SetJoinOffset {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let user = interaction.user.id.get();
+        let db = data.db.lock().await;
+        match self.seconds {
+            Some(seconds) => {
+                db.conn.execute(
+                    "INSERT INTO lp_user_settings (user_id, join_offset)
+                         VALUES (?1, ?2)
+                         ON CONFLICT (user_id) DO UPDATE SET join_offset = ?2",
+                    params![user, seconds],
+                )?;
+                CommandResponse::private(format!(
+                    "lp_join will use a {seconds}s offset by default"
+                ))
+            }
+            None => {
+                db.conn.execute(
+                    "DELETE FROM lp_user_settings WHERE user_id = ?1",
+                    [user],
+                )?;
+                CommandResponse::private(
+                    "lp_join will use the server's default offset",
+                )
+            }
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_server_join_offset",
+    desc = "Set the server's default number of seconds to start playing"
+)]
+pub struct SetServerJoinOffset {
+    #[cmd(desc = "Seconds to start playing, leave empty to use the default")]
+    seconds: Option<u64>,
+}
+
+#[async_trait]
+impl BotCommand for SetServerJoinOffset {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        data.db.lock().await.conn.execute(
+            "INSERT INTO lp_settings (guild_id, join_offset) VALUES (?1, ?2)
+                 ON CONFLICT (guild_id) DO UPDATE SET join_offset = ?2",
+            params![guild_id.get(), self.seconds],
+        )?;
+        CommandResponse::private(format!(
+            "Default lp_join offset set to {}s",
+            self.seconds.unwrap_or(DEFAULT_JOIN_OFFSET)
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_timezone",
+    desc = "Set the timezone used for the start times you give"
+)]
+pub struct SetLPTimezone {
+    #[cmd(
+        desc = "Timezone name (e.g. Europe/Paris, America/New_York), leave empty to use the server's"
+    )]
+    timezone: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SetLPTimezone {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let user = interaction.user.id.get();
+        let db = data.db.lock().await;
+        let Some(timezone) = self.timezone else {
+            db.conn.execute(
+                "DELETE FROM lp_user_zones WHERE user_id = ?1",
+                [user],
+            )?;
+            return CommandResponse::private(
+                "Start times will use the server's timezone",
+            );
+        };
+        let Some(tz) = parse_timezone(&timezone) else {
+            return CommandResponse::private(INVALID_TIMEZONE);
+        };
+        db.conn.execute(
+            "INSERT INTO lp_user_zones (user_id, timezone) VALUES (?1, ?2)
+                 ON CONFLICT (user_id) DO UPDATE SET timezone = ?2",
+            params![user, tz.name()],
+        )?;
+        CommandResponse::private(format!(
+            "Start times you give will be in {}",
+            tz.name()
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_server_timezone",
+    desc = "Set the server's default timezone for listening party start times"
+)]
+pub struct SetServerLPTimezone {
+    #[cmd(
+        desc = "Timezone name (e.g. Europe/Paris, America/New_York), leave empty to use UTC"
+    )]
+    timezone: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SetServerLPTimezone {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let tz = match self.timezone.as_deref().map(parse_timezone) {
+            None => None,
+            Some(Some(tz)) => Some(tz),
+            Some(None) => return CommandResponse::private(INVALID_TIMEZONE),
+        };
+        data.db.lock().await.conn.execute(
+            "INSERT INTO lp_settings (guild_id, timezone) VALUES (?1, ?2)
+                 ON CONFLICT (guild_id) DO UPDATE SET timezone = ?2",
+            params![guild_id.get(), tz.map(|tz| tz.name())],
+        )?;
+        CommandResponse::private(format!(
+            "Default timezone set to {}",
+            tz.unwrap_or(chrono_tz::UTC).name()
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_queue_add",
+    desc = "Queue an album to play right after the current listening party"
+)]
+pub struct QueueLP {
+    #[cmd(desc = "Spotify, Apple Music or Bandcamp link to the album")]
+    link: String,
+}
+
+#[async_trait]
+impl BotCommand for QueueLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let module = data.module::<ModLPInfo>()?;
+        let channel = interaction.channel_id;
+        let spotify = data.module::<Spotify>()?;
+        let cache = data.module::<SpotifyCache>()?;
+        let market = SpotifyMarket::for_guild(data, interaction.guild_id).await;
+        let lp = LPInfo::from_match_string(
+            &spotify.client,
+            cache,
+            market,
+            &self.link,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("Not a supported album or playlist link"))?;
+        let name = lp.display_name();
+        let mut lp = LPInfo {
+            guild_id: interaction.guild_id,
+            host: Some(interaction.user.id),
+            chain: true,
+            ..lp
+        };
+        lp.enrich_cached(&data.db.lock().await.conn);
+        let mut lps = module.last_pinged.write().await;
+        let Some(current) = lps.get(&channel) else {
+            lps.insert(channel, lp);
+            return CommandResponse::public(format!(
+                "Nothing is playing, **{name}** is now the active listening \
+                 party"
+            ));
+        };
+        if !can_control(interaction, current) {
+            return CommandResponse::private(
+                "Only the host can queue albums for the listening party",
+            );
+        }
+        let mut queues = module.queue.write().await;
+        let queue = queues.entry(channel).or_default();
+        // Chained albums play before the other pinged parties
+        let position = queue.iter().take_while(|lp| lp.chain).count();
+        if position >= MAX_QUEUED {
+            return CommandResponse::private("The queue is full");
+        }
+        queue.insert(position, lp);
+        CommandResponse::public(format!(
+            "**{name}** will start after {} listening part{}",
+            position + 1,
+            if position == 0 { "y" } else { "ies" }
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "lp_list", desc = "List the listening parties in this channel")]
+pub struct ListLPs {}
+
+#[async_trait]
+impl BotCommand for ListLPs {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let module = data.module::<ModLPInfo>()?;
+        let channel = interaction.channel_id;
+        let lps = module.last_pinged.read().await;
+        let queues = module.queue.read().await;
+        let mut lines = Vec::new();
+        if let Some(lp) = lps.get(&channel) {
+            lines.push(format!(
+                "**Active**: {} ({})",
+                lp.display_name(),
+                lp.status()
+            ));
+        }
+        for (i, lp) in queues.get(&channel).into_iter().flatten().enumerate() {
+            lines.push(format!("{}. {}", i + 1, lp.display_name()));
+        }
+        if lines.is_empty() {
+            return CommandResponse::private(
+                "There is no listening party at the moment.",
+            );
+        }
+        CommandResponse::private(
+            CreateEmbed::new()
+                .title("Listening parties")
+                .description(lines.join("\n")),
+        )
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_select",
+    desc = "Make a queued listening party the active one in this channel"
+)]
+pub struct SelectLP {
+    #[cmd(desc = "Number of the listening party in lp_list")]
+    number: u64,
+}
+
+#[async_trait]
+impl BotCommand for SelectLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let module = data.module::<ModLPInfo>()?;
+        let channel = interaction.channel_id;
+        let mut lps = module.last_pinged.write().await;
+        if lps
+            .get(&channel)
+            .is_some_and(|current| !can_control(interaction, current))
+        {
+            return CommandResponse::private(
+                "Only the host can replace the active listening party",
+            );
+        }
+        let mut queues = module.queue.write().await;
+        let queue = queues.entry(channel).or_default();
+        let selected = (self.number as usize)
+            .checked_sub(1)
+            .and_then(|index| queue.remove(index));
+        let Some(selected) = selected else {
+            return CommandResponse::private(
+                "There is no such listening party, see lp_list",
+            );
+        };
+        // The previously active party goes back to the front of the queue, to
+        // start over when it is selected again
+        if let Some(mut previous) = lps.insert(channel, selected) {
+            if let (Some(guild_id), Some(event)) =
+                (previous.guild_id, previous.event.take())
+            {
+                module.ended_events.write().await.push((guild_id, event));
+            }
+            previous.started = None;
+            previous.recorded = false;
+            queue.push_front(previous);
+        }
+        let embed = lps[&channel].build_info_embed();
+        CommandResponse::public(embed)
+    }
+}
+
+/// Regex to extract role IDs from role mentions
+static ROLE_MENTION_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("<@&([0-9]+)>").unwrap());
+
+/// Find all role mentions in a string
+pub fn parse_role_mentions(string: &str) -> Vec<RoleId> {
+    ROLE_MENTION_RE
+        .captures_iter(string)
+        .filter_map(|caps| caps.get(1).unwrap().as_str().parse().ok())
+        .map(RoleId::new)
+        .collect()
+}
+
+pub fn format_roles(roles: &[RoleId]) -> String {
+    roles
+        .iter()
+        .map(|role| format!("<@&{role}>"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_set_roles",
+    desc = "Set the roles used to ping listening parties"
+)]
+pub struct SetLPRoles {
+    #[cmd(
+        desc = "Mentions of the roles, leave empty to use the default roles"
+    )]
+    roles: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SetLPRoles {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let roles = parse_role_mentions(self.roles.as_deref().unwrap_or(""));
+        if self.roles.is_some() && roles.is_empty() {
+            return CommandResponse::private("No roles mentioned");
+        }
+        let value = (!roles.is_empty()).then(|| roles.iter().join(","));
+        data.module::<GuildSettings>()?
+            .set(data, guild_id, &settings::LP_ROLES, value)
+            .await?;
+        let resp = if roles.is_empty() {
+            format!("Using the default roles: {}", LP_ROLES.join(", "))
+        } else {
+            format!("Listening party roles: {}", format_roles(&roles))
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_add_role",
+    desc = "Add a role used to ping listening parties"
+)]
+pub struct AddLPRole {
+    #[cmd(desc = "Mention of the role")]
+    role: String,
+}
+
+#[async_trait]
+impl BotCommand for AddLPRole {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let Some(&role) = parse_role_mentions(&self.role).first() else {
+            return CommandResponse::private("No role mentioned");
+        };
+        let guild_settings = data.module::<GuildSettings>()?;
+        let mut roles =
+            guild_settings.roles(guild_id, &settings::LP_ROLES).await;
+        if !roles.contains(&role) {
+            roles.push(role);
+        }
+        guild_settings
+            .set(
+                data,
+                guild_id,
+                &settings::LP_ROLES,
+                Some(roles.iter().join(",")),
+            )
+            .await?;
+        CommandResponse::private(format!(
+            "Listening party roles: {}",
+            format_roles(&roles)
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "lp_start", desc = "Start the listening party in this channel")]
+pub struct StartLP {
+    #[cmd(
+        desc = "Link to the album or playlist, defaults to the last pinged one"
+    )]
+    link: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for StartLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let module = data.module::<ModLPInfo>()?;
+        let channel = interaction.channel_id;
+        // Also covers replacing another host's listening party with a link
+        if let Some(current) = module.last_pinged.read().await.get(&channel) {
+            if !can_control(interaction, current) {
+                return CommandResponse::private(
+                    "Only the host can start the listening party",
+                );
+            }
+        }
+        if let Some(link) = &self.link {
+            let spotify = data.module::<Spotify>()?;
+            let cache = data.module::<SpotifyCache>()?;
+            let market =
+                SpotifyMarket::for_guild(data, interaction.guild_id).await;
+            let lp =
+                LPInfo::from_match_string(&spotify.client, cache, market, link)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow!("Not a supported album or playlist link")
+                    })?;
+            let mut lp = LPInfo {
+                guild_id: interaction.guild_id,
+                host: Some(interaction.user.id),
+                ..lp
+            };
+            lp.enrich_cached(&data.db.lock().await.conn);
+            let previous = module.last_pinged.write().await.insert(channel, lp);
+            module.end_event(previous).await;
+        }
+        if !module.start_lp(&channel).await {
+            return CommandResponse::private(
+                "There is no listening party to start, \
+                 ping one or provide a link.",
+            );
+        }
+        let lps = module.last_pinged.read().await;
+        match lps.get(&channel) {
+            Some(lpinfo) => CommandResponse::public(lpinfo.build_info_embed()),
+            None => CommandResponse::public("Listening party started!"),
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "lp_stop", desc = "Stop the listening party in this channel")]
+pub struct StopLP {}
+
+#[async_trait]
+impl BotCommand for StopLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let module = data.module::<ModLPInfo>()?;
+        if let Some(current) =
+            module.last_pinged.read().await.get(&interaction.channel_id)
+        {
+            if !can_control(interaction, current) {
+                return CommandResponse::private(
+                    "Only the host can stop the listening party",
+                );
+            }
+        }
+        if module.stop_lp(&interaction.channel_id).await {
+            CommandResponse::public("Listening party stopped.")
+        } else {
+            CommandResponse::private(
+                "There is no listening party at the moment.",
+            )
+        }
+    }
+}
+
+/// Parse a position in a track, as mm:ss or seconds
+fn parse_position(position: &str) -> Option<chrono::Duration> {
+    let position = position.trim();
+    let seconds = match position.split_once(':') {
+        Some((minutes, seconds)) => {
+            let seconds = seconds.parse::<i64>().ok()?;
+            if seconds >= 60 {
+                return None;
+            }
+            minutes.parse::<i64>().ok()? * 60 + seconds
+        }
+        None => position.parse().ok()?,
+    };
+    (seconds >= 0).then(|| chrono::Duration::seconds(seconds))
+}
+
+/// Check whether the user is allowed to control a listening party
+fn can_control(interaction: &CommandInteraction, lp: &LPInfo) -> bool {
+    lp.host == Some(interaction.user.id)
+        || interaction
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .map_or(false, |perms| perms.manage_events())
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_seek",
+    desc = "Move the listening party to a track (host only)"
+)]
+pub struct SeekLP {
+    #[cmd(desc = "Track number")]
+    track: u64,
+    #[cmd(desc = "Position in the track, as mm:ss or seconds")]
+    position: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SeekLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let position = match self.position.as_deref().map(parse_position) {
+            None => chrono::Duration::zero(),
+            Some(Some(position)) => position,
+            Some(None) => {
+                return CommandResponse::private(
+                    "Invalid position, use mm:ss or seconds",
+                )
+            }
+        };
+        let module = data.module::<ModLPInfo>()?;
+        let mut lps = module.last_pinged.write().await;
+        let Some(lp) = lps.get_mut(&interaction.channel_id) else {
+            return CommandResponse::private(
+                "There is no listening party at the moment.",
+            );
+        };
+        if !can_control(interaction, lp) {
+            return CommandResponse::private(
+                "Only the host can control the listening party",
+            );
+        }
+        if self.track == 0 || !lp.seek(self.track as usize - 1, position) {
+            return CommandResponse::private(format!(
+                "Track number must be between 1 and {}",
+                lp.tracks.len()
+            ));
+        }
+        CommandResponse::public(lp.build_info_embed())
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_skip",
+    desc = "Skip to the next track of the listening party (host only)"
+)]
+pub struct SkipLP {}
+
+#[async_trait]
+impl BotCommand for SkipLP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let module = data.module::<ModLPInfo>()?;
+        let mut lps = module.last_pinged.write().await;
+        let Some(lp) = lps.get_mut(&interaction.channel_id) else {
+            return CommandResponse::private(
+                "There is no listening party at the moment.",
+            );
+        };
+        if !can_control(interaction, lp) {
+            return CommandResponse::private(
+                "Only the host can control the listening party",
+            );
+        }
+        if lp.skip().is_none() {
+            return CommandResponse::private(
+                "The listening party is not playing or on its last track",
+            );
+        }
+        CommandResponse::public(lp.build_info_embed())
+    }
+}
+
+/// Regex to parse delays such as 2h, 45m or 1h30m
+static START_DELAY_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("^(?:([0-9]+)h)?\\s*(?:([0-9]+)m)?$").unwrap());
+
+/// Parse the start time of a scheduled listening party
+///
+/// Accepts a unix timestamp, a time of day such as 21:15 or 9pm (in `tz`,
+/// next occurrence) or a delay from now such as 45m, 2h or 1h30m.
+fn parse_start_time(
+    input: &str,
+    now: chrono::DateTime<chrono::Utc>,
+    tz: chrono_tz::Tz,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let input = input.trim();
+    if let Ok(timestamp) = input.parse::<i64>() {
+        return chrono::Utc.timestamp_opt(timestamp, 0).single();
+    }
+    if let Some(time) = parse_time_of_day(input) {
+        let today = now.with_timezone(&tz).date_naive();
+        let start = tz
+            .from_local_datetime(&today.and_time(time))
+            .earliest()?
+            .with_timezone(&chrono::Utc);
+        return Some(if start <= now {
+            start + chrono::Duration::days(1)
+        } else {
+            start
+        });
+    }
+    let caps = START_DELAY_RE.captures(input)?;
+    let hours = caps.get(1).map(|h| h.as_str().parse::<i64>());
+    let minutes = caps.get(2).map(|m| m.as_str().parse::<i64>());
+    if hours.is_none() && minutes.is_none() {
+        return None;
+    }
+    let delay = chrono::Duration::hours(hours.unwrap_or(Ok(0)).ok()?)
+        + chrono::Duration::minutes(minutes.unwrap_or(Ok(0)).ok()?);
+    Some(now + delay)
+}
+
+/// Parse a time of day such as 21:15, 9pm or 9:15pm
+fn parse_time_of_day(input: &str) -> Option<chrono::NaiveTime> {
+    let input = input.to_lowercase().replace(' ', "");
+    if let Ok(time) = chrono::NaiveTime::parse_from_str(&input, "%H:%M") {
+        return Some(time);
+    }
+    if let Ok(time) = chrono::NaiveTime::parse_from_str(&input, "%I:%M%p") {
+        return Some(time);
+    }
+    // Minutes are required by chrono
+    let hour = input
+        .strip_suffix("am")
+        .or_else(|| input.strip_suffix("pm"))?;
+    chrono::NaiveTime::parse_from_str(
+        &format!("{hour}:00{}", &input[hour.len()..]),
+        "%I:%M%p",
+    )
+    .ok()
+}
+
+const INVALID_TIMEZONE: &str =
+    "Unknown timezone, use a name such as Europe/Paris or America/New_York";
+
+/// Parse an IANA timezone name such as Europe/Paris, ignoring case
+fn parse_timezone(input: &str) -> Option<chrono_tz::Tz> {
+    chrono_tz::Tz::from_str_insensitive(input.trim()).ok()
+}
+
+/// Zones standing for the UTC offsets that are not a whole number of hours,
+/// which have no Etc zone, in minutes
+const PARTIAL_HOUR_ZONES: &[(i32, &str)] = &[
+    (-570, "Pacific/Marquesas"),
+    (-210, "America/St_Johns"),
+    (-150, "America/St_Johns"),
+    (210, "Asia/Tehran"),
+    (270, "Asia/Kabul"),
+    (330, "Asia/Kolkata"),
+    (345, "Asia/Kathmandu"),
+    (390, "Asia/Yangon"),
+    (525, "Australia/Eucla"),
+    (570, "Australia/Darwin"),
+    (630, "Australia/Adelaide"),
+    (765, "Pacific/Chatham"),
+    (825, "Pacific/Chatham"),
+];
+
+/// Name of the zone of a UTC offset in minutes: its Etc zone, or a zone using
+/// it if it is not a whole number of hours, None if no zone does
+fn offset_zone(minutes: i32) -> Option<&'static str> {
+    if minutes % 60 != 0 {
+        return PARTIAL_HOUR_ZONES
+            .iter()
+            .find(|(offset, _)| *offset == minutes)
+            .map(|(_, zone)| *zone);
+    }
+    // Etc zones have the sign reversed: Etc/GMT-2 is UTC+2
+    let name = match minutes / 60 {
+        0 => "UTC".to_string(),
+        hours => format!("Etc/GMT{:+}", -hours),
+    };
+    parse_timezone(&name).map(|tz| tz.name())
+}
+
+/// Find the timezone times given by a user are in, falling back to their
+/// guild's, then UTC
+fn user_timezone(
+    conn: &Connection,
+    guild_id: Option<GuildId>,
+    user: UserId,
+) -> anyhow::Result<chrono_tz::Tz> {
+    let user_zone: Option<String> = conn
+        .query_row(
+            "SELECT timezone FROM lp_user_zones WHERE user_id = ?1",
+            [user.get()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let zone = match (user_zone, guild_id) {
+        (Some(zone), _) => Some(zone),
+        (None, Some(guild_id)) => conn
+            .query_row(
+                "SELECT timezone FROM lp_settings WHERE guild_id = ?1",
+                [guild_id.get()],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten(),
+        (None, None) => None,
+    };
+    match zone {
+        Some(zone) => parse_timezone(&zone)
+            .ok_or_else(|| anyhow!("Invalid stored timezone {zone}")),
+        None => Ok(chrono_tz::UTC),
+    }
+}
+
+// Moves the UTC offsets stored before timezones were stored by name to the
+// matching zones, keeping the ones no zone uses until they are set again
+fn migrate_utc_offsets(conn: &Connection) -> anyhow::Result<()> {
+    let has_user_offsets: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master
+             WHERE type = 'table' AND name = 'lp_user_timezones'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_user_offsets {
+        let mut stmt =
+            conn.prepare("SELECT user_id, utc_offset FROM lp_user_timezones")?;
+        let offsets: Vec<(u64, i32)> = stmt
+            .query([])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        drop(stmt);
+        let mut unmapped = false;
+        for (user_id, offset) in offsets {
+            let Some(zone) = offset_zone(offset) else {
+                eprintln!("No timezone for the UTC offset of user {user_id}");
+                unmapped = true;
+                continue;
+            };
+            conn.execute(
+                "INSERT OR IGNORE INTO lp_user_zones (user_id, timezone)
+                     VALUES (?1, ?2)",
+                params![user_id, zone],
+            )?;
+            conn.execute(
+                "DELETE FROM lp_user_timezones WHERE user_id = ?1",
+                [user_id],
+            )?;
+        }
+        if !unmapped {
+            conn.execute("DROP TABLE lp_user_timezones", [])?;
+        }
+    }
+    let mut stmt = conn.prepare(
+        "SELECT guild_id, utc_offset FROM lp_settings
+             WHERE utc_offset IS NOT NULL",
+    )?;
+    let offsets: Vec<(u64, i32)> = stmt
+        .query([])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?)))
+        .collect()?;
+    drop(stmt);
+    for (guild_id, offset) in offsets {
+        let Some(zone) = offset_zone(offset) else {
+            eprintln!("No timezone for the UTC offset of guild {guild_id}");
+            continue;
+        };
+        conn.execute(
+            "UPDATE lp_settings SET timezone = ?2, utc_offset = NULL
+                 WHERE guild_id = ?1",
+            params![guild_id, zone],
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_schedule",
+    desc = "Schedule a listening party in this channel"
+)]
+pub struct ScheduleLP {
+    #[cmd(desc = "Spotify, Apple Music or Bandcamp link to the album")]
+    link: String,
+    #[cmd(
+        desc = "Start time: HH:MM (your timezone), unix timestamp or delay (e.g. 1h30m)"
+    )]
+    time: String,
+}
+
+#[async_trait]
+impl BotCommand for ScheduleLP {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let now = chrono::offset::Utc::now();
+        let tz = user_timezone(
+            &data.db.lock().await.conn,
+            Some(guild_id),
+            interaction.user.id,
+        )?;
+        let Some(start) = parse_start_time(&self.time, now, tz) else {
+            return CommandResponse::private(
+                "Invalid start time, use HH:MM, a unix timestamp or a delay \
+                 such as 1h30m",
+            );
+        };
+        if start <= now {
+            return CommandResponse::private("Start time is in the past");
+        }
+        let spotify = data.module::<Spotify>()?;
+        let cache = data.module::<SpotifyCache>()?;
+        let market = SpotifyMarket::for_guild(data, interaction.guild_id).await;
+        let lp = LPInfo::from_match_string(
+            &spotify.client,
+            cache,
+            market,
+            &self.link,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("Not a supported album or playlist link"))?;
+        let channel = interaction.channel_id;
+        let name = lp.display_name();
+        let start_timestamp =
+            Timestamp::from_unix_timestamp(start.timestamp())?;
+        let end_timestamp = Timestamp::from_unix_timestamp(
+            (start + lp.duration()).timestamp(),
+        )?;
+
+        let event = guild_id
+            .create_scheduled_event(
+                ctx,
+                CreateScheduledEvent::new(
+                    ScheduledEventType::External,
+                    format!("Listening Party: {name}"),
+                    start_timestamp,
+                )
+                .end_time(end_timestamp)
+                .location(format!("<#{channel}>"))
+                .description(&self.link),
+            )
+            .await
+            .context("creating scheduled event")?;
+        data.db.lock().await.conn.execute(
+            "INSERT INTO lp_schedule
+                 (guild_id, channel_id, link, start_time, event_id, host_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                guild_id.get(),
+                channel.get(),
+                &self.link,
+                start.timestamp(),
+                event.id.get(),
+                interaction.user.id.get()
+            ],
+        )?;
+
+        let embed = CreateEmbed::new()
+            .title("Listening Party scheduled!")
+            .description(format!(
+                "{} - \\[{}\\]",
+                maybe_uri(&name, Some(&self.link)),
+                display_duration(lp.duration())
+            ))
+            .field(
+                "Starts",
+                format!(
+                    "<t:{}:F> (<t:{}:R>)",
+                    start.timestamp(),
+                    start.timestamp()
+                ),
+                false,
+            );
+        CommandResponse::public(embed)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "lp", desc = "Ping a listening party")]
+pub struct LP {
+    #[cmd(desc = "Spotify, Apple Music or Bandcamp link to the album")]
+    link: String,
+    #[cmd(
+        desc = "Start time: HH:MM (your timezone), unix timestamp or delay (e.g. 1h30m), defaults to now"
+    )]
+    time: Option<String>,
+    #[cmd(
+        desc = "Role to ping, defaults to the server's listening party role"
+    )]
+    role: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for LP {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let now = chrono::offset::Utc::now();
+        let tz = user_timezone(
+            &data.db.lock().await.conn,
+            Some(guild_id),
+            interaction.user.id,
+        )?;
+        let start =
+            match self.time.as_deref() {
+                None => None,
+                Some(time) => match parse_start_time(time, now, tz) {
+                    Some(start) if start > now => Some(start),
+                    Some(_) => {
+                        return CommandResponse::private(
+                            "Start time is in the past",
+                        )
+                    }
+                    None => return CommandResponse::private(
+                        "Invalid start time, use HH:MM, a unix timestamp or \
+                         a delay such as 1h30m",
+                    ),
+                },
+            };
+        let module = data.module::<ModLPInfo>()?;
+        let role = match &self.role {
+            Some(role) => parse_role_mentions(role).first().copied(),
+            None => module.default_role(data, ctx, guild_id).await,
+        };
+        let spotify = data.module::<Spotify>()?;
+        let cache = data.module::<SpotifyCache>()?;
+        let market = SpotifyMarket::for_guild(data, interaction.guild_id).await;
+        let lp = LPInfo::from_match_string(
+            &spotify.client,
+            cache,
+            market,
+            &self.link,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("Not a supported album or playlist link"))?;
+        let channel = interaction.channel_id;
+        let host = interaction.user.id;
+        let name = lp.display_name();
+
+        let (when, current) = match start {
+            Some(start) => {
+                data.db.lock().await.conn.execute(
+                    "INSERT INTO lp_schedule
+                         (guild_id, channel_id, link, start_time, host_id)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        guild_id.get(),
+                        channel.get(),
+                        &self.link,
+                        start.timestamp(),
+                        host.get()
+                    ],
+                )?;
+                (format!("starting <t:{}:R>", start.timestamp()), false)
+            }
+            None => {
+                let lp = LPInfo {
+                    guild_id: Some(guild_id),
+                    host: Some(host),
+                    ..lp
+                };
+                match module.add_lp(channel, lp).await {
+                    Ok(true) => ("starting now".to_string(), true),
+                    Ok(false) => {
+                        ("starting after the current one".to_string(), false)
+                    }
+                    Err(e) => return CommandResponse::private(e.to_string()),
+                }
+            }
+        };
+        let role = role.map(|role| format!("<@&{role}> ")).unwrap_or_default();
+        let content =
+            format!("{role}Listening party: **{name}** {when}!\n{}", self.link);
+        // Queued listening parties are started from the queue instead
+        if !current || !auto_ready_check(data, Some(guild_id)).await {
+            return CommandResponse::public(content);
+        }
+        // The ready poll follows the ping, which must be sent first
+        let resp = CreateInteractionResponseMessage::new().content(content);
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Message(resp),
+            )
+            .await?;
+        post_ready_poll(data, ctx, channel, host).await;
+        Ok(CommandResponse::None)
+    }
+}
+
+// Whether a guild has the bot post a ready poll when a listening party is
+// pinged
+async fn auto_ready_check(
+    handler: &Handler,
+    guild_id: Option<GuildId>,
+) -> bool {
+    match (handler.module::<GuildSettings>(), guild_id) {
+        (Ok(settings), Some(guild_id)) => {
+            settings
+                .enabled(guild_id, &settings::LP_AUTO_READY_CHECK)
+                .await
+        }
+        _ => false,
+    }
+}
+
+// Post a ready poll for a pinged listening party, ModPoll starts it through
+// ReadyPollStarted once its host gives the go
+async fn post_ready_poll(
+    handler: &Handler,
+    ctx: &Context,
+    channel: ChannelId,
+    host: UserId,
+) {
+    if let Err(e) =
+        ModPoll::create_ready_poll(handler, ctx, channel, host).await
+    {
+        eprintln!("Error posting ready poll: {e}");
+    }
+}
+
+/// Create an active voice or stage event for a listening party
+async fn create_lp_event(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel: ChannelId,
+    name: String,
+    image: Option<String>,
+    end: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<ScheduledEventId> {
+    let kind = match channel.to_channel(ctx).await?.guild() {
+        Some(channel) if channel.kind == ChannelType::Stage => {
+            ScheduledEventType::StageInstance
+        }
+        _ => ScheduledEventType::Voice,
+    };
+    // Events must be created in the future, they are started right after
+    let start = chrono::offset::Utc::now() + chrono::Duration::seconds(5);
+    let mut event = CreateScheduledEvent::new(
+        kind,
+        name,
+        Timestamp::from_unix_timestamp(start.timestamp())?,
+    )
+    .channel_id(channel)
+    .end_time(Timestamp::from_unix_timestamp(end.max(start).timestamp())?);
+    let cover = match image {
+        Some(url) => match CreateAttachment::url(&ctx.http, &url).await {
+            Ok(cover) => Some(cover),
+            Err(e) => {
+                eprintln!("Error fetching listening party cover: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some(cover) = &cover {
+        event = event.image(cover);
+    }
+    let event = guild_id.create_scheduled_event(ctx, event).await?;
+    guild_id
+        .edit_scheduled_event(
+            ctx,
+            event.id,
+            EditScheduledEvent::new().status(ScheduledEventStatus::Active),
+        )
+        .await?;
+    Ok(event.id)
+}
+
+/// Listening party waiting to be started by the scheduler
+struct ScheduledLP {
+    id: i64,
+    guild_id: GuildId,
+    channel: ChannelId,
+    link: String,
+    /// Unix timestamp
+    start_time: i64,
+    event_id: Option<ScheduledEventId>,
+    host: Option<UserId>,
+}
+
+/// Find when an album or playlist was last played in a guild, and who hosted
+///
+/// The listening party pinged in `ping` is not counted, in case it already
+/// started
+fn last_played(
+    conn: &Connection,
+    guild_id: GuildId,
+    album_id: &str,
+    ping: MessageId,
+) -> anyhow::Result<Option<(Option<u64>, i64)>> {
+    let last = conn
+        .query_row(
+            "SELECT host_id, played_at FROM lp_history
+                 WHERE guild_id = ?1 AND album_id = ?2
+                     AND (message_id IS NULL OR message_id != ?3)
+                 ORDER BY played_at DESC LIMIT 1",
+            params![guild_id.get(), album_id, ping.get()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    Ok(last)
+}
+
+/// Regex to extract user IDs from user mentions
+pub static USER_MENTION_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("^<@!?([0-9]+)>$").unwrap());
+
+/// Parse a month formatted as YYYY-MM into the range of timestamps it covers
+pub fn parse_month(month: &str) -> Option<(i64, i64)> {
+    let start =
+        chrono::NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d")
+            .ok()?;
+    let end = start.checked_add_months(chrono::Months::new(1))?;
+    let timestamp = |date: chrono::NaiveDate| {
+        chrono::Utc
+            .from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            .timestamp()
+    };
+    Some((timestamp(start), timestamp(end)))
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "lp_history", desc = "List the listening parties held here")]
+pub struct LPHistory {
+    #[cmd(desc = "Only show listening parties hosted by this user (mention)")]
+    user: Option<String>,
+    #[cmd(desc = "Only show listening parties from this month (YYYY-MM)")]
+    month: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for LPHistory {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        const MAX_ENTRIES: usize = 20;
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let host = match &self.user {
+            None => None,
+            Some(user) => match USER_MENTION_RE
+                .captures(user.trim())
+                .and_then(|caps| caps.get(1).unwrap().as_str().parse().ok())
+            {
+                Some(id) => Some(UserId::new(id)),
+                None => {
+                    return CommandResponse::private("Invalid user mention")
+                }
+            },
+        };
+        let range = match &self.month {
+            None => None,
+            Some(month) => match parse_month(month.trim()) {
+                Some(range) => Some(range),
+                None => {
+                    return CommandResponse::private(
+                        "Invalid month, use YYYY-MM",
+                    )
+                }
+            },
+        };
+        let entries: Vec<(Option<u64>, u64, String, Option<String>, i64)> = {
+            let db = data.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT host_id, channel_id, name, link, played_at
+                     FROM lp_history
+                     WHERE guild_id = ?1
+                         AND (?2 IS NULL OR host_id = ?2)
+                         AND (?3 IS NULL OR played_at >= ?3)
+                         AND (?4 IS NULL OR played_at < ?4)
+                     ORDER BY played_at DESC LIMIT ?5",
+            )?;
+            let entries = stmt
+                .query(params![
+                    guild_id.get(),
+                    host.map(UserId::get),
+                    range.map(|(start, _)| start),
+                    range.map(|(_, end)| end),
+                    MAX_ENTRIES,
+                ])?
+                .map(|row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                })
+                .collect()?;
+            entries
+        };
+        if entries.is_empty() {
+            return CommandResponse::private("No listening parties found.");
+        }
+        let description = entries
+            .iter()
+            .map(|(host, channel, name, link, played_at)| {
+                let host = host
+                    .map(|host| format!(", hosted by <@{host}>"))
+                    .unwrap_or_default();
+                format!(
+                    "<t:{played_at}:d> - {} in <#{channel}>{host}",
+                    maybe_uri(name, link.as_ref()),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        CommandResponse::private(
+            CreateEmbed::new()
+                .title("Listening party history")
+                .description(description),
+        )
+    }
+}
+
+/// How tracks are announced in the channel while a listening party plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum ThreadMode {
+    #[default]
+    Off,
+    /// Post a message when each track starts
+    Dividers,
+    /// Open a thread for each track
+    Threads,
+}
+
+impl ThreadMode {
+    fn parse(mode: &str) -> Option<Self> {
+        match mode.trim().to_lowercase().as_str() {
+            "off" => Some(ThreadMode::Off),
+            "dividers" => Some(ThreadMode::Dividers),
+            "threads" => Some(ThreadMode::Threads),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ThreadMode::Off => "off",
+            ThreadMode::Dividers => "dividers",
+            ThreadMode::Threads => "threads",
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_thread_mode",
+    desc = "Announce each track of listening parties in this server"
+)]
+pub struct SetLPThreadMode {
+    #[cmd(desc = "off, dividers (one message per track) or threads")]
+    mode: String,
+}
+
+#[async_trait]
+impl BotCommand for SetLPThreadMode {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let Some(mode) = ThreadMode::parse(&self.mode) else {
+            return CommandResponse::private(
+                "Invalid mode, use off, dividers or threads",
+            );
+        };
+        data.db.lock().await.conn.execute(
+            "INSERT INTO lp_settings (guild_id, thread_mode) VALUES (?1, ?2)
+                 ON CONFLICT (guild_id) DO UPDATE SET thread_mode = ?2",
+            params![guild_id.get(), mode.as_str()],
+        )?;
+        data.module::<ModLPInfo>()?
+            .thread_modes
+            .write()
+            .await
+            .insert(guild_id, mode);
+        CommandResponse::private(format!(
+            "Listening party thread mode set to {}",
+            mode.as_str()
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_start_delay",
+    desc = "Set a grace period between the start of listening parties and the first track"
+)]
+pub struct SetLPStartDelay {
+    #[cmd(desc = "Delay in seconds, leave empty to start right away")]
+    seconds: Option<u64>,
+}
+
+#[async_trait]
+impl BotCommand for SetLPStartDelay {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let delay = self.seconds.unwrap_or(0) as i64;
+        data.db.lock().await.conn.execute(
+            "INSERT INTO lp_settings (guild_id, start_delay) VALUES (?1, ?2)
+                 ON CONFLICT (guild_id) DO UPDATE SET start_delay = ?2",
+            params![guild_id.get(), delay],
+        )?;
+        data.module::<ModLPInfo>()?
+            .start_delays
+            .write()
+            .await
+            .insert(guild_id, delay);
+        CommandResponse::private(format!(
+            "Listening parties will start {delay}s after the ready poll"
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_auto_ready_check",
+    desc = "Post a ready poll when a listening party is pinged"
+)]
+pub struct SetLPAutoReadyCheck {
+    #[cmd(
+        desc = "Whether the bot posts a ready poll on listening party pings"
+    )]
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for SetLPAutoReadyCheck {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let settings = data.module::<GuildSettings>()?;
+        let value = self.enabled.then(|| "on".to_string());
+        settings
+            .set(data, guild_id, &settings::LP_AUTO_READY_CHECK, value)
+            .await?;
+        if self.enabled {
+            let go = settings
+                .emoji(guild_id, &settings::POLL_GO_EMOJI)
+                .await
+                .map_or_else(
+                    || GO_EMOJI.to_string(),
+                    |emoji| emoji.to_string(),
+                );
+            CommandResponse::private(format!(
+                "A ready poll will be posted when listening parties are \
+                 pinged, hosts start them with {go}"
+            ))
+        } else {
+            CommandResponse::private("Ready polls will no longer be posted")
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "lp_event_channel",
+    desc = "Create voice or stage events in a channel when listening parties start"
+)]
+pub struct SetLPEventChannel {
+    #[cmd(
+        desc = "Mention or ID of the channel, leave empty to disable events"
+    )]
+    channel: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SetLPEventChannel {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let channel = match &self.channel {
+            None => None,
+            Some(channel) => {
+                let Some(channel) = settings::parse_channel(channel) else {
+                    return CommandResponse::private("Invalid channel");
+                };
+                let kind = channel
+                    .to_channel(ctx)
+                    .await?
+                    .guild()
+                    .map(|channel| channel.kind);
+                if !matches!(
+                    kind,
+                    Some(ChannelType::Voice | ChannelType::Stage)
+                ) {
+                    return CommandResponse::private(
+                        "Events can only be created in voice or stage channels",
+                    );
+                }
+                Some(channel)
+            }
+        };
+        data.module::<GuildSettings>()?
+            .set(
+                data,
+                guild_id,
+                &settings::LP_EVENT_CHANNEL,
+                channel.map(|channel| channel.to_string()),
+            )
+            .await?;
+        match channel {
+            Some(channel) => CommandResponse::private(format!(
+                "Listening party events will be created in <#{channel}>"
+            )),
+            None => CommandResponse::private("Listening party events disabled"),
+        }
+    }
+}
+
+pub struct ModLPInfo {
+    last_pinged: Arc<RwLock<HashMap<ChannelId, LPInfo>>>,
+    /// Listening parties pinged while another one was playing in the channel
+    queue: Arc<RwLock<HashMap<ChannelId, VecDeque<LPInfo>>>>,
+    thread_modes: Arc<RwLock<HashMap<GuildId, ThreadMode>>>,
+    /// Seconds between the start of a listening party and the first track
+    start_delays: Arc<RwLock<HashMap<GuildId, i64>>>,
+    /// Cached default LP roles of each guild, with when they were fetched
+    default_roles: Arc<RwLock<HashMap<GuildId, (Instant, Vec<RoleId>)>>>,
+    /// Events of listening parties that were stopped or replaced, for
+    /// sync_events to end
+    ended_events: Arc<RwLock<Vec<(GuildId, ScheduledEventId)>>>,
+}
+
+impl Clone for ModLPInfo {
+    fn clone(&self) -> Self {
+        ModLPInfo {
+            last_pinged: Arc::clone(&self.last_pinged),
+            queue: Arc::clone(&self.queue),
+            thread_modes: Arc::clone(&self.thread_modes),
+            start_delays: Arc::clone(&self.start_delays),
+            default_roles: Arc::clone(&self.default_roles),
+            ended_events: Arc::clone(&self.ended_events),
+        }
+    }
+}
+
+// How long the default LP roles of a guild are cached
+const ROLE_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+// Number of listening parties kept in each channel's queue
+const MAX_QUEUED: usize = 5;
+
+// Roles used for pinging listening parties in guilds that did not configure any
+const LP_ROLES: &'static [&'static str] =
+    &[&"Listening Party", &"Impromptu Listening Party"];
+
+impl ModLPInfo {
+    pub fn new() -> Self {
+        ModLPInfo {
+            last_pinged: Default::default(),
+            queue: Default::default(),
+            thread_modes: Default::default(),
+            start_delays: Default::default(),
+            default_roles: Default::default(),
+            ended_events: Default::default(),
+        }
+    }
+
+    // Role pinged by the lp command when none is specified: the first
+    // configured role, or the first default role found in the guild
+    async fn default_role(
+        &self,
+        handler: &Handler,
+        ctx: &Context,
+        guild_id: GuildId,
+    ) -> Option<RoleId> {
+        let configured = match handler.module::<GuildSettings>() {
+            Ok(guild_settings) => {
+                guild_settings.roles(guild_id, &settings::LP_ROLES).await
+            }
+            Err(_) => Vec::new(),
+        };
+        if let Some(role) = configured.first() {
+            return Some(*role);
+        }
+        self.default_roles(ctx, guild_id).await.first().copied()
+    }
+
+    // Roles of a guild named after one of the default LP roles, in the order
+    // of LP_ROLES
+    //
+    // Fetched roles are cached for ROLE_CACHE_TTL, stale roles are used if
+    // they can't be fetched again
+    async fn default_roles(
+        &self,
+        ctx: &Context,
+        guild_id: GuildId,
+    ) -> Vec<RoleId> {
+        let cached = self.default_roles.read().await.get(&guild_id).cloned();
+        if let Some((fetched, roles)) = &cached {
+            if fetched.elapsed() < ROLE_CACHE_TTL {
+                return roles.clone();
+            }
+        }
+        let guild_roles = match guild_id.roles(&ctx.http).await {
+            Ok(roles) => roles,
+            Err(e) => {
+                eprintln!("Error fetching roles of guild {guild_id}: {e}");
+                return cached.map(|(_, roles)| roles).unwrap_or_default();
+            }
+        };
+        let roles = LP_ROLES
             .iter()
-            // Resolve ID to role
-            .filter_map(|rid| {
-                rid.to_role_cached(&ctx.cache).or_else(|| {
-                    // Message contains a role mention that does not resolve
-                    // to a role. Not much we can do.
-                    eprintln!("Role {rid} not found");
-                    None
-                })
+            .flat_map(|name| {
+                guild_roles
+                    .values()
+                    .filter(move |role| role.name == *name)
+                    .map(|role| role.id)
+            })
+            .collect::<Vec<_>>();
+        self.default_roles
+            .write()
+            .await
+            .insert(guild_id, (Instant::now(), roles.clone()));
+        roles
+    }
+
+    // Forget the cached roles of a guild, called when its roles change
+    pub async fn invalidate_roles(&self, guild_id: GuildId) {
+        self.default_roles.write().await.remove(&guild_id);
+    }
+
+    /// Listening parties pinged in a guild and the ones queued after them
+    pub async fn guild_state(&self, guild_id: GuildId) -> serde_json::Value {
+        let lps = self.last_pinged.read().await;
+        let queues = self.queue.read().await;
+        let channels = lps
+            .iter()
+            .filter(|(_, lp)| lp.guild_id == Some(guild_id))
+            .map(|(channel, lp)| {
+                let mut state = lp.to_json(*channel);
+                let queued = queues
+                    .get(channel)
+                    .into_iter()
+                    .flatten()
+                    .map(|queued| queued.to_json(*channel))
+                    .collect::<Vec<_>>();
+                state["queue"] = queued.into();
+                state
             })
-            .any(|role| LP_ROLES.contains(&role.name.as_ref()))
+            .collect::<Vec<_>>();
+        channels.into()
+    }
+
+    // Check whether a message mentions one of the LP roles of its guild
+    async fn mentions_lp_role(
+        &self,
+        handler: &Handler,
+        ctx: &Context,
+        msg: &Message,
+    ) -> bool {
+        let configured = match (msg.guild_id, handler.module::<GuildSettings>())
         {
-            let pl = match LPInfo::from_match_string(client, msg_txt).await {
+            (Some(guild_id), Ok(guild_settings)) => {
+                guild_settings.roles(guild_id, &settings::LP_ROLES).await
+            }
+            _ => Vec::new(),
+        };
+        if !configured.is_empty() {
+            return msg
+                .mention_roles
+                .iter()
+                .any(|rid| configured.contains(rid));
+        }
+        let (Some(guild_id), false) =
+            (msg.guild_id, msg.mention_roles.is_empty())
+        else {
+            return false;
+        };
+        let roles = self.default_roles(ctx, guild_id).await;
+        msg.mention_roles.iter().any(|rid| roles.contains(rid))
+    }
+
+    // Handle messages to remember the last pinged album
+    //
+    // We consider a message a LP ping if if mentions one of the LP roles
+    // and it contains a spotify playlist or album link
+    pub async fn handle_message<C: BaseClient>(
+        &self,
+        handler: &Handler,
+        client: &C,
+        ctx: &Context,
+        msg: &Message,
+    ) {
+        if !msg.author.bot {
+            self.track_participant(msg).await;
+        }
+        self.handle_ping(handler, client, ctx, msg).await;
+    }
+
+    // Remember the album or playlist of a message if it is a LP ping
+    async fn handle_ping<C: BaseClient>(
+        &self,
+        handler: &Handler,
+        client: &C,
+        ctx: &Context,
+        msg: &Message,
+    ) {
+        let msg_txt = message_text(msg);
+
+        // Pings sent through the lp command are already handled
+        if handler.self_id.get() == Some(&msg.author.id) {
+            return;
+        }
+
+        // Check if the specified roles were mentioned
+        if self.mentions_lp_role(handler, ctx, msg).await {
+            let Ok(cache) = handler.module::<SpotifyCache>() else {
+                return;
+            };
+            let market = SpotifyMarket::for_guild(handler, msg.guild_id).await;
+            let mut pl = match LPInfo::from_match_string(
+                client, cache, market, &msg_txt,
+            )
+            .await
+            {
                 Err(e) => {
-                    eprintln!("Error resolving spotify link: {}", e);
+                    eprintln!("Error resolving album link: {}", e);
                     return;
                 }
                 Ok(Some(pl)) => {
@@ -530,23 +3093,780 @@ impl ModLPInfo {
                         }
                     };
                     eprintln!("{guild_name}{username}: Pinged Listening Party: {pinged}");
-                    pl
+                    LPInfo {
+                        guild_id: msg.guild_id,
+                        host: Some(msg.author.id),
+                        ping_message: Some(msg.id),
+                        ..pl
+                    }
                 }
                 Ok(None) => return,
             };
+            pl.enrich(handler).await;
+            if let Some(guild_id) = msg.guild_id {
+                let previous = last_played(
+                    &handler.db.lock().await.conn,
+                    guild_id,
+                    pl.id(),
+                    msg.id,
+                );
+                match previous {
+                    Ok(Some((host, played_at))) => {
+                        let host = host
+                            .map(|host| format!(", hosted by <@{host}>"))
+                            .unwrap_or_default();
+                        let notice = CreateMessage::new()
+                            .content(format!(
+                                "Heads up, this was already played on \
+                                 <t:{played_at}:D>{host}."
+                            ))
+                            .reference_message(msg)
+                            .allowed_mentions(CreateAllowedMentions::new());
+                        if let Err(e) =
+                            msg.channel_id.send_message(&ctx.http, notice).await
+                        {
+                            eprintln!("Error sending replay notice: {e}");
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        eprintln!("Error looking up listening party: {e:?}")
+                    }
+                }
+            }
             // Store album/playlist in channel info
-            let mut channels = self.last_pinged.write().await;
-            (*channels).insert(msg.channel_id, pl);
+            let current = match self.add_lp(msg.channel_id, pl).await {
+                Ok(current) => current,
+                Err(e) => {
+                    let notice = CreateMessage::new()
+                        .content(e.to_string())
+                        .reference_message(msg)
+                        .allowed_mentions(CreateAllowedMentions::new());
+                    if let Err(e) =
+                        msg.channel_id.send_message(&ctx.http, notice).await
+                    {
+                        eprintln!("Error sending full queue notice: {e}");
+                    }
+                    return;
+                }
+            };
+            // Queued listening parties are started from the queue instead
+            if current && auto_ready_check(handler, msg.guild_id).await {
+                post_ready_poll(handler, ctx, msg.channel_id, msg.author.id)
+                    .await;
+            }
+        };
+    }
+
+    // Handle embeds added after a message was sent, as Discord resolves links
+    // asynchronously
+    pub async fn handle_message_update<C: BaseClient>(
+        &self,
+        handler: &Handler,
+        client: &C,
+        ctx: &Context,
+        new: Option<Message>,
+        event: &MessageUpdateEvent,
+    ) {
+        // Embeds being resolved do not mark the message as edited
+        let edited_ping = event.edited_timestamp.is_some()
+            && self.is_pending_ping(event.channel_id, event.id).await;
+        if !edited_ping && event.embeds.as_ref().map_or(true, Vec::is_empty) {
+            return;
+        }
+        let msg = match new {
+            Some(msg) => msg,
+            None => match event.channel_id.message(ctx, event.id).await {
+                Ok(msg) => msg,
+                Err(e) => {
+                    eprintln!("Error fetching updated message: {e}");
+                    return;
+                }
+            },
+        };
+        if edited_ping {
+            let Ok(cache) = handler.module::<SpotifyCache>() else {
+                return;
+            };
+            let market = SpotifyMarket::for_guild(handler, msg.guild_id).await;
+            self.reresolve_ping(handler, client, cache, market, ctx, &msg)
+                .await;
+            return;
+        }
+        // Links in the content were handled when the message was sent
+        if contains_lp_link(&msg.content)
+            || self.is_known_ping(msg.channel_id, msg.id).await
+        {
+            return;
+        }
+        // The message itself was already handled when it was sent
+        self.handle_ping(handler, client, ctx, &msg).await;
+    }
+
+    // Whether a message pinged a listening party that is still known, started
+    // or not
+    async fn is_known_ping(&self, channel: ChannelId, msg: MessageId) -> bool {
+        let pinged = |lp: &LPInfo| lp.ping_message == Some(msg);
+        if self
+            .last_pinged
+            .read()
+            .await
+            .get(&channel)
+            .is_some_and(pinged)
+        {
+            return true;
+        }
+        self.queue
+            .read()
+            .await
+            .get(&channel)
+            .is_some_and(|queue| queue.iter().any(pinged))
+    }
+
+    // Whether a message pinged a listening party that has not started yet
+    async fn is_pending_ping(
+        &self,
+        channel: ChannelId,
+        msg: MessageId,
+    ) -> bool {
+        let pending =
+            |lp: &LPInfo| lp.ping_message == Some(msg) && lp.started.is_none();
+        if self
+            .last_pinged
+            .read()
+            .await
+            .get(&channel)
+            .is_some_and(pending)
+        {
+            return true;
+        }
+        self.queue
+            .read()
+            .await
+            .get(&channel)
+            .is_some_and(|queue| queue.iter().any(pending))
+    }
+
+    // Update a listening party that has not started yet after its ping was
+    // edited, forgetting it if the message no longer pings one
+    async fn reresolve_ping<C: BaseClient>(
+        &self,
+        handler: &Handler,
+        client: &C,
+        cache: &SpotifyCache,
+        market: Option<Market>,
+        ctx: &Context,
+        msg: &Message,
+    ) {
+        let resolved = if self.mentions_lp_role(handler, ctx, msg).await {
+            match LPInfo::from_match_string(
+                client,
+                cache,
+                market,
+                &message_text(msg),
+            )
+            .await
+            {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    eprintln!("Error resolving edited album link: {e}");
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let mut lps = self.last_pinged.write().await;
+        let mut queues = self.queue.write().await;
+        let pending = |lp: &LPInfo| {
+            lp.ping_message == Some(msg.id) && lp.started.is_none()
+        };
+        if lps.get(&msg.channel_id).is_some_and(pending) {
+            let old = lps.remove(&msg.channel_id).unwrap();
+            if let Some(new) = resolved {
+                lps.insert(msg.channel_id, old.reresolved(new));
+            }
+        } else if let Some(queue) = queues.get_mut(&msg.channel_id) {
+            if let Some(index) = queue.iter().position(pending) {
+                let old = queue.remove(index).unwrap();
+                if let Some(new) = resolved {
+                    queue.insert(index, old.reresolved(new));
+                }
+            }
+        }
+    }
+
+    // Forget a listening party that has not started yet if its ping was
+    // deleted
+    pub async fn handle_message_delete(
+        &self,
+        channel: ChannelId,
+        msg: MessageId,
+    ) {
+        let pending =
+            |lp: &LPInfo| lp.ping_message == Some(msg) && lp.started.is_none();
+        let mut lps = self.last_pinged.write().await;
+        let mut queues = self.queue.write().await;
+        if lps.get(&channel).is_some_and(pending) {
+            lps.remove(&channel);
+        }
+        if let Some(queue) = queues.get_mut(&channel) {
+            queue.retain(|lp| !pending(lp));
+        }
+    }
+
+    // Remember who is talking during a listening party, for the end summary
+    async fn track_participant(&self, msg: &Message) {
+        let mut lps = self.last_pinged.write().await;
+        let Some(lp) = lps.get_mut(&msg.channel_id) else {
+            return;
         };
+        if !matches!(
+            lp.now_playing(chrono::Duration::zero()),
+            PlayState::Playing { .. }
+        ) {
+            return;
+        }
+        lp.participants.insert(msg.author.id);
+        if lp.discussion_start.is_none() {
+            lp.discussion_start = Some(msg.link());
+        }
+    }
+
+    // Make a newly pinged listening party the active one in its channel,
+    // unless another one is playing, in which case it is queued
+    //
+    // Returns whether it became the active listening party, fails if the queue
+    // is full
+    async fn add_lp(
+        &self,
+        channel: ChannelId,
+        lp: LPInfo,
+    ) -> anyhow::Result<bool> {
+        let mut lps = self.last_pinged.write().await;
+        let busy = lps.get(&channel).map_or(false, |current| {
+            matches!(
+                current.now_playing(chrono::Duration::zero()),
+                PlayState::Playing { .. }
+            )
+        });
+        if !busy {
+            self.end_event(lps.insert(channel, lp)).await;
+            return Ok(true);
+        }
+        let mut queues = self.queue.write().await;
+        let queue = queues.entry(channel).or_default();
+        if queue.len() >= MAX_QUEUED {
+            return Err(anyhow!(
+                "The queue is full, wait for a listening party to end"
+            ));
+        }
+        queue.push_back(lp);
+        Ok(false)
     }
 
     // Set the Listening party as started
-    pub async fn start_lp(&self, channel: &ChannelId) {
+    //
+    // Returns false if no listening party was pinged in the channel
+    pub async fn start_lp(&self, channel: &ChannelId) -> bool {
         let now = chrono::offset::Utc::now();
+        let delays = self.start_delays.read().await;
         let mut channels = self.last_pinged.write().await;
-        channels
-            .entry(*channel)
-            .and_modify(|lp_info| lp_info.started = Some(now));
+        match channels.get_mut(channel) {
+            Some(lp_info) => {
+                let delay = lp_info
+                    .guild_id
+                    .and_then(|guild_id| delays.get(&guild_id).copied())
+                    .unwrap_or(0);
+                lp_info.started = Some(now + chrono::Duration::seconds(delay));
+                lp_info.announced = None;
+                lp_info.summarized = false;
+                lp_info.participants.clear();
+                lp_info.discussion_start = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Forget the listening party in a channel
+    //
+    // Returns false if there was none
+    pub async fn stop_lp(&self, channel: &ChannelId) -> bool {
+        let stopped = self.last_pinged.write().await.remove(channel);
+        let found = stopped.is_some();
+        self.end_event(stopped).await;
+        found
+    }
+
+    // Queue the event of a listening party that was stopped or replaced to be
+    // ended by sync_events
+    async fn end_event(&self, lp: Option<LPInfo>) {
+        if let Some(LPInfo {
+            guild_id: Some(guild_id),
+            event: Some(event),
+            ..
+        }) = lp
+        {
+            self.ended_events.write().await.push((guild_id, event));
+        }
+    }
+
+    /// Background task starting the scheduled listening parties that are due
+    pub fn start_scheduled<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let now = chrono::offset::Utc::now();
+            let due = {
+                let db = handler.db.lock().await;
+                let mut stmt = db.conn.prepare(
+                    "SELECT id, guild_id, channel_id, link, start_time, event_id,
+                         host_id
+                         FROM lp_schedule WHERE start_time <= ?1",
+                )?;
+                let due: Vec<ScheduledLP> = stmt
+                    .query([now.timestamp()])?
+                    .map(|row| {
+                        Ok(ScheduledLP {
+                            id: row.get(0)?,
+                            guild_id: GuildId::new(row.get(1)?),
+                            channel: ChannelId::new(row.get(2)?),
+                            link: row.get(3)?,
+                            start_time: row.get(4)?,
+                            event_id: row
+                                .get::<_, Option<u64>>(5)?
+                                .map(ScheduledEventId::new),
+                            host: row.get::<_, Option<u64>>(6)?.map(UserId::new),
+                        })
+                    })
+                    .collect()?;
+                due
+            };
+            if due.is_empty() {
+                return Ok(());
+            }
+            let module = handler.module::<ModLPInfo>()?;
+            let spotify = handler.module::<Spotify>()?;
+            let cache = handler.module::<SpotifyCache>()?;
+            for scheduled in due {
+                // Forget the entry first so a failing link is not retried
+                handler.db.lock().await.conn.execute(
+                    "DELETE FROM lp_schedule WHERE id = ?1",
+                    [scheduled.id],
+                )?;
+                let channel = scheduled.channel;
+                let guild_id = scheduled.guild_id;
+                let market =
+                    SpotifyMarket::for_guild(handler, Some(guild_id)).await;
+                let mut lp = match LPInfo::from_match_string(
+                    &spotify.client,
+                    cache,
+                    market,
+                    &scheduled.link,
+                )
+                .await
+                {
+                        Ok(Some(lp)) => lp,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            eprintln!("Error resolving scheduled LP: {e:?}");
+                            continue;
+                        }
+                    };
+                lp.started = chrono::Utc
+                    .timestamp_opt(scheduled.start_time, 0)
+                    .single();
+                lp.guild_id = Some(guild_id);
+                lp.host = scheduled.host;
+                lp.enrich(handler).await;
+                let embed = lp.build_info_embed();
+                let previous =
+                    module.last_pinged.write().await.insert(channel, lp);
+                module.end_event(previous).await;
+                if let Some(event_id) = scheduled.event_id {
+                    if let Err(e) = guild_id
+                        .edit_scheduled_event(
+                            ctx,
+                            event_id,
+                            EditScheduledEvent::new()
+                                .status(ScheduledEventStatus::Active),
+                        )
+                        .await
+                    {
+                        eprintln!("Error starting scheduled event: {e}");
+                    }
+                }
+                if let Err(e) = channel
+                    .send_message(&ctx.http, CreateMessage::new().embed(embed))
+                    .await
+                {
+                    eprintln!("Error posting scheduled listening party: {e}");
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Background task creating events for the listening parties that start in
+    /// guilds with an event channel, and ending them when they finish
+    pub fn sync_events<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let module = handler.module::<ModLPInfo>()?;
+            let guild_settings = handler.module::<GuildSettings>()?;
+            // read before locking the listening parties, as the settings are
+            // behind an async lock
+            let mut event_channels = HashMap::new();
+            for lp in module.last_pinged.read().await.values() {
+                let Some(guild_id) = lp.guild_id else {
+                    continue;
+                };
+                if let Some(channel) = guild_settings
+                    .channel(guild_id, &settings::LP_EVENT_CHANNEL)
+                    .await
+                {
+                    event_channels.insert(guild_id, channel);
+                }
+            }
+            let (to_create, to_end) = {
+                let mut lps = module.last_pinged.write().await;
+                let mut to_create = Vec::new();
+                let mut to_end =
+                    std::mem::take(&mut *module.ended_events.write().await);
+                for (channel, lp) in lps.iter_mut() {
+                    let (Some(guild_id), Some(started)) =
+                        (lp.guild_id, lp.started)
+                    else {
+                        continue;
+                    };
+                    let finished = matches!(
+                        lp.now_playing(chrono::Duration::zero()),
+                        PlayState::Finished(_)
+                    );
+                    match (lp.event, finished) {
+                        (Some(event), true) => {
+                            lp.event = None;
+                            to_end.push((guild_id, event));
+                        }
+                        (None, false) if !lp.event_attempted => {
+                            if let Some(event_channel) =
+                                event_channels.get(&guild_id)
+                            {
+                                lp.event_attempted = true;
+                                to_create.push((
+                                    *channel,
+                                    guild_id,
+                                    *event_channel,
+                                    format!(
+                                        "Listening Party: {}",
+                                        lp.display_name()
+                                    ),
+                                    lp.image.clone(),
+                                    started + lp.duration(),
+                                ));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                (to_create, to_end)
+            };
+            for (channel, guild_id, event_channel, name, image, end) in
+                to_create
+            {
+                let event_id = match create_lp_event(
+                    ctx,
+                    guild_id,
+                    event_channel,
+                    name,
+                    image,
+                    end,
+                )
+                .await
+                {
+                    Ok(event_id) => event_id,
+                    Err(e) => {
+                        eprintln!(
+                            "Error creating listening party event: {e:?}"
+                        );
+                        continue;
+                    }
+                };
+                if let Some(lp) =
+                    module.last_pinged.write().await.get_mut(&channel)
+                {
+                    lp.event = Some(event_id);
+                }
+            }
+            for (guild_id, event_id) in to_end {
+                if let Err(e) = guild_id
+                    .edit_scheduled_event(
+                        ctx,
+                        event_id,
+                        EditScheduledEvent::new()
+                            .status(ScheduledEventStatus::Completed),
+                    )
+                    .await
+                {
+                    eprintln!("Error ending listening party event: {e}");
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Background task posting a summary when a listening party finishes
+    pub fn post_summaries<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        // Parties that finished longer ago than this are not summarized
+        const MAX_DELAY: i64 = 10;
+        async move {
+            let module = handler.module::<ModLPInfo>()?;
+            let mut attendance = Vec::new();
+            let summaries = {
+                let mut lps = module.last_pinged.write().await;
+                let mut summaries = Vec::new();
+                for (channel, lp) in lps.iter_mut() {
+                    if lp.summarized {
+                        continue;
+                    }
+                    let PlayState::Finished(ago) =
+                        lp.now_playing(chrono::Duration::zero())
+                    else {
+                        continue;
+                    };
+                    lp.summarized = true;
+                    if let Some(guild_id) = lp.guild_id {
+                        attendance.push((
+                            guild_id,
+                            *channel,
+                            lp.id().to_string(),
+                            lp.participants.len(),
+                        ));
+                    }
+                    if ago < chrono::Duration::minutes(MAX_DELAY) {
+                        summaries.push((
+                            *channel,
+                            lp.build_summary_embed(),
+                            lp.participants.iter().copied().collect::<Vec<_>>(),
+                            lp.listens(),
+                        ));
+                    }
+                }
+                summaries
+            };
+            {
+                let db = handler.db.lock().await;
+                for (guild_id, channel, album_id, participants) in attendance {
+                    // Counted on the latest listening party of the album
+                    let updated = db.conn.execute(
+                        "UPDATE lp_history SET participants = ?4
+                             WHERE id = (
+                                 SELECT MAX(id) FROM lp_history WHERE guild_id = ?1
+                                     AND channel_id = ?2 AND album_id = ?3
+                             )",
+                        params![
+                            guild_id.get(),
+                            channel.get(),
+                            album_id,
+                            participants
+                        ],
+                    );
+                    if let Err(e) = updated {
+                        eprintln!("Error recording participants: {e:?}");
+                    }
+                }
+            }
+            let listenbrainz = handler.module::<ListenBrainz>()?;
+            for (channel, embed, participants, listens) in summaries {
+                if let Err(e) = channel
+                    .send_message(&ctx.http, CreateMessage::new().embed(embed))
+                    .await
+                {
+                    eprintln!("Error posting listening party summary: {e}");
+                }
+                listenbrainz
+                    .export_attendance(handler, &participants, &listens)
+                    .await;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Background task starting the next queued album when a listening party
+    /// finishes
+    pub fn advance_queues<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let module = handler.module::<ModLPInfo>()?;
+            let now = chrono::offset::Utc::now();
+            let started = {
+                let mut lps = module.last_pinged.write().await;
+                let mut queues = module.queue.write().await;
+                let mut started = Vec::new();
+                for (channel, queue) in queues.iter_mut() {
+                    let finished = lps.get(channel).map_or(false, |lp| {
+                        matches!(
+                            lp.now_playing(chrono::Duration::zero()),
+                            PlayState::Finished(_)
+                        )
+                    });
+                    if !finished || !queue.front().map_or(false, |lp| lp.chain)
+                    {
+                        continue;
+                    }
+                    // Wait for the summary of the previous party
+                    if !lps.get(channel).map_or(true, |lp| lp.summarized) {
+                        continue;
+                    }
+                    let Some(mut next) = queue.pop_front() else {
+                        continue;
+                    };
+                    next.started = Some(now);
+                    started.push((*channel, next.build_info_embed()));
+                    module.end_event(lps.insert(*channel, next)).await;
+                }
+                started
+            };
+            for (channel, embed) in started {
+                if let Err(e) = channel
+                    .send_message(
+                        &ctx.http,
+                        CreateMessage::new().content("Up next!").embed(embed),
+                    )
+                    .await
+                {
+                    eprintln!("Error announcing next listening party: {e}");
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Background task logging listening parties in their guild's history
+    /// once they start playing
+    pub fn record_started<'a>(
+        handler: &'a Handler,
+        _ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let module = handler.module::<ModLPInfo>()?;
+            let now = chrono::offset::Utc::now();
+            let mut lps = module.last_pinged.write().await;
+            let db = handler.db.lock().await;
+            for (channel, lp) in lps.iter_mut() {
+                let (Some(guild_id), Some(started)) = (lp.guild_id, lp.started)
+                else {
+                    continue;
+                };
+                // Start delays set the start time in the future
+                if lp.recorded || started > now {
+                    continue;
+                }
+                lp.recorded = true;
+                if let Err(e) = lp.record_history(
+                    &db.conn,
+                    guild_id,
+                    *channel,
+                    lp.host,
+                    started.timestamp(),
+                ) {
+                    eprintln!("Error recording listening party: {e:?}");
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Background task announcing tracks as they start, in guilds that
+    /// enabled it
+    pub fn announce_tracks<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let module = handler.module::<ModLPInfo>()?;
+            let announcements = {
+                let modes = module.thread_modes.read().await;
+                let mut lps = module.last_pinged.write().await;
+                let mut announcements = Vec::new();
+                for (channel, lp) in lps.iter_mut() {
+                    let mode = lp
+                        .guild_id
+                        .and_then(|guild_id| modes.get(&guild_id).copied())
+                        .unwrap_or_default();
+                    if mode == ThreadMode::Off {
+                        continue;
+                    }
+                    let PlayState::Playing { track, .. } =
+                        lp.now_playing(chrono::Duration::zero())
+                    else {
+                        continue;
+                    };
+                    if lp.announced == Some(track.number) {
+                        continue;
+                    }
+                    let number = track.number;
+                    let divider = format!(
+                        "**Track {} - {}** \\[{}\\]",
+                        track.number,
+                        maybe_uri(&track.name, track.uri.as_ref()),
+                        display_duration(track.duration),
+                    );
+                    // Thread names are limited to 100 characters
+                    let thread_name =
+                        format!("{}. {}", track.number, track.name)
+                            .chars()
+                            .take(100)
+                            .collect::<String>();
+                    lp.announced = Some(number);
+                    announcements.push((*channel, mode, divider, thread_name));
+                }
+                announcements
+            };
+            for (channel, mode, divider, thread_name) in announcements {
+                let msg = match channel
+                    .send_message(
+                        &ctx.http,
+                        CreateMessage::new().content(divider),
+                    )
+                    .await
+                {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        eprintln!("Error announcing track: {e}");
+                        continue;
+                    }
+                };
+                if mode == ThreadMode::Threads {
+                    if let Err(e) = channel
+                        .create_thread_from_message(
+                            ctx,
+                            msg.id,
+                            CreateThread::new(thread_name),
+                        )
+                        .await
+                    {
+                        eprintln!("Error creating track thread: {e}");
+                    }
+                }
+            }
+            Ok(())
+        }
+        .boxed()
     }
 }
 
@@ -555,7 +3875,21 @@ impl Module for ModLPInfo {
     async fn add_dependencies(
         builder: HandlerBuilder,
     ) -> anyhow::Result<HandlerBuilder> {
-        builder.module::<Spotify>().await
+        builder
+            .module::<Spotify>()
+            .await?
+            .module::<SpotifyAccounts>()
+            .await?
+            .module::<MusicBrainz>()
+            .await?
+            .module::<SpotifyCache>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await?
+            .module::<ListenBrainz>()
+            .await?
+            .module::<GuildSettings>()
+            .await
     }
 
     fn register_event_handlers(&self, handlers: &mut events::EventHandlers) {
@@ -569,13 +3903,115 @@ impl Module for ModLPInfo {
         });
     }
 
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_settings (
+                guild_id INTEGER PRIMARY KEY,
+                thread_mode STRING NOT NULL DEFAULT 'off'
+            )",
+            [],
+        )?;
+        crate::add_column(&db.conn, "lp_settings", "join_offset", "INTEGER")?;
+        crate::add_column(&db.conn, "lp_settings", "start_delay", "INTEGER")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_user_settings (
+                user_id INTEGER PRIMARY KEY,
+                join_offset INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        crate::add_column(&db.conn, "lp_settings", "utc_offset", "INTEGER")?;
+        crate::add_column(&db.conn, "lp_settings", "timezone", "STRING")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_user_zones (
+                user_id INTEGER PRIMARY KEY,
+                timezone STRING NOT NULL
+            )",
+            [],
+        )?;
+        migrate_utc_offsets(&db.conn)?;
+        let mut stmt = db.conn.prepare(
+            "SELECT guild_id, thread_mode, start_delay FROM lp_settings",
+        )?;
+        let settings: Vec<(u64, String, Option<i64>)> = stmt
+            .query([])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .collect()?;
+        drop(stmt);
+        let mut thread_modes = self.thread_modes.write().await;
+        let mut start_delays = self.start_delays.write().await;
+        for (guild_id, mode, delay) in settings {
+            let guild_id = GuildId::new(guild_id);
+            if let Some(mode) = ThreadMode::parse(&mode) {
+                thread_modes.insert(guild_id, mode);
+            }
+            if let Some(delay) = delay {
+                start_delays.insert(guild_id, delay);
+            }
+        }
+        drop(thread_modes);
+        drop(start_delays);
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_schedule (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                link STRING NOT NULL,
+                start_time INTEGER NOT NULL,
+                event_id INTEGER
+            )",
+            [],
+        )?;
+        crate::add_column(&db.conn, "lp_schedule", "host_id", "INTEGER")?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS lp_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER NOT NULL,
+                channel_id INTEGER NOT NULL,
+                host_id INTEGER,
+                album_id STRING NOT NULL,
+                name STRING NOT NULL,
+                link STRING,
+                played_at INTEGER NOT NULL,
+                duration INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        crate::add_column(&db.conn, "lp_history", "participants", "INTEGER")?;
+        crate::add_column(&db.conn, "lp_history", "message_id", "INTEGER")?;
+        Ok(())
+    }
+
     fn register_commands(
         &self,
         store: &mut CommandStore,
         _completions: &mut CompletionStore,
     ) {
+        store.register::<LP>();
         store.register::<CurrentLP>();
         store.register::<JoinLP>();
+        store.register::<SetLPRoles>();
+        store.register::<AddLPRole>();
+        store.register::<StartLP>();
+        store.register::<StopLP>();
+        store.register::<ScheduleLP>();
+        store.register::<TracklistLP>();
+        store.register::<SetLPThreadMode>();
+        store.register::<LPHistory>();
+        store.register::<SeekLP>();
+        store.register::<SkipLP>();
+        store.register::<SetJoinOffset>();
+        store.register::<JoinLPAuto>();
+        store.register::<ListLPs>();
+        store.register::<SelectLP>();
+        store.register::<QueueLP>();
+        store.register::<SetLPStartDelay>();
+        store.register::<SetLPAutoReadyCheck>();
+        store.register::<SetLPEventChannel>();
+        store.register::<NowPlayingLP>();
+        store.register::<SetLPTimezone>();
+        store.register::<SetServerLPTimezone>();
+        store.register::<SetServerJoinOffset>();
     }
 
     async fn init(_m: &ModuleMap) -> anyhow::Result<Self> {
@@ -612,6 +4048,125 @@ mod tests {
         }
     }
 
+    #[test]
+    fn start_time_parsing() {
+        let now = chrono::Utc.with_ymd_and_hms(2023, 5, 1, 20, 0, 0).unwrap();
+        let utc = chrono_tz::UTC;
+        let paris = chrono_tz::Europe::Paris;
+        assert_eq!(
+            parse_start_time("1h30m", now, utc),
+            Some(now + chrono::Duration::minutes(90))
+        );
+        assert_eq!(
+            parse_start_time("45m", now, utc),
+            Some(now + chrono::Duration::minutes(45))
+        );
+        assert_eq!(
+            parse_start_time("21:15", now, utc),
+            chrono::Utc.with_ymd_and_hms(2023, 5, 1, 21, 15, 0).single()
+        );
+        assert_eq!(
+            parse_start_time("08:00", now, utc),
+            chrono::Utc.with_ymd_and_hms(2023, 5, 2, 8, 0, 0).single()
+        );
+        assert_eq!(
+            parse_start_time("1683000000", now, utc),
+            chrono::Utc.timestamp_opt(1683000000, 0).single()
+        );
+        assert_eq!(
+            parse_start_time("9pm", now, utc),
+            chrono::Utc.with_ymd_and_hms(2023, 5, 1, 21, 0, 0).single()
+        );
+        assert_eq!(
+            parse_start_time("23:00", now, paris),
+            chrono::Utc.with_ymd_and_hms(2023, 5, 1, 21, 0, 0).single()
+        );
+        assert_eq!(
+            parse_start_time("21:00", now, paris),
+            chrono::Utc.with_ymd_and_hms(2023, 5, 2, 19, 0, 0).single()
+        );
+        // Paris is an hour closer to UTC in winter
+        let winter =
+            chrono::Utc.with_ymd_and_hms(2023, 1, 1, 20, 0, 0).unwrap();
+        assert_eq!(
+            parse_start_time("23:00", winter, paris),
+            chrono::Utc.with_ymd_and_hms(2023, 1, 1, 22, 0, 0).single()
+        );
+        assert_eq!(parse_start_time("", now, utc), None);
+        assert_eq!(parse_start_time("tomorrow", now, utc), None);
+    }
+
+    #[test]
+    fn timezone_parsing() {
+        let zone = |input| parse_timezone(input).map(|tz| tz.name());
+        assert_eq!(zone("Europe/Paris"), Some("Europe/Paris"));
+        assert_eq!(zone(" america/new_york "), Some("America/New_York"));
+        assert_eq!(zone("utc"), Some("UTC"));
+        assert_eq!(zone("UTC+2"), None);
+        assert_eq!(offset_zone(120), Some("Etc/GMT-2"));
+        assert_eq!(offset_zone(-300), Some("Etc/GMT+5"));
+        assert_eq!(offset_zone(0), Some("UTC"));
+        assert_eq!(offset_zone(330), Some("Asia/Kolkata"));
+        assert_eq!(offset_zone(75), None);
+    }
+
+    #[test]
+    fn progress_bars() {
+        let minutes = chrono::Duration::minutes;
+        assert_eq!(progress_bar(minutes(0), minutes(4)), "▱".repeat(16));
+        assert_eq!(
+            progress_bar(minutes(1), minutes(4)),
+            "▰".repeat(4) + &"▱".repeat(12)
+        );
+        assert_eq!(progress_bar(minutes(5), minutes(4)), "▰".repeat(16));
+    }
+
+    #[test]
+    fn position_parsing() {
+        assert_eq!(parse_position("1:30"), Some(chrono::Duration::seconds(90)));
+        assert_eq!(parse_position("75"), Some(chrono::Duration::seconds(75)));
+        assert_eq!(parse_position("1:75"), None);
+        assert_eq!(parse_position("-5"), None);
+    }
+
+    #[test]
+    fn month_parsing() {
+        assert_eq!(parse_month("2024-12"), Some((1733011200, 1735689600)));
+        assert_eq!(parse_month("2024-13"), None);
+        assert_eq!(parse_month("december"), None);
+    }
+
+    #[test]
+    fn apple_music_and_bandcamp_links() {
+        assert_eq!(
+            match_apple_music_album(
+                "https://music.apple.com/us/album/in-rainbows/1109714933"
+            ),
+            Some(("us", "1109714933"))
+        );
+        assert_eq!(
+            match_bandcamp_album(
+                "https://artist.bandcamp.com/album/some-album?from=discover"
+            ),
+            Some("https://artist.bandcamp.com/album/some-album")
+        );
+        assert_eq!(
+            match_bandcamp_album("https://artist.bandcamp.com/track/song"),
+            None
+        );
+    }
+
+    #[test]
+    fn release_years() {
+        assert_eq!(release_year("2007-10-10").as_deref(), Some("2007"));
+        assert_eq!(release_year("1999").as_deref(), Some("1999"));
+        assert_eq!(
+            release_year("01 Jan 2020 00:00:00 GMT").as_deref(),
+            Some("2020")
+        );
+        assert_eq!(release_year("unknown"), None);
+    }
+
     mod match_spotify_playlist {
         use super::*;
         test_parser! {
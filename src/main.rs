@@ -1,34 +1,91 @@
-use std::collections::hash_map::DefaultHasher;
-use std::sync::Arc;
-use std::{env, hash::Hasher};
+use std::collections::HashSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Context as _;
 use rspotify::scopes;
 use rusqlite::Connection;
-use serenity::all::{ApplicationId, CommandDataOptionValue};
+use serenity::all::{
+    ApplicationId, CommandDataOptionValue, ConnectionStage, ResumedEvent, ShardId,
+    ShardStageUpdateEvent,
+};
 use serenity::async_trait;
-use serenity::model::application::Command;
-use serenity::model::prelude::Interaction;
-use serenity::model::prelude::{ChannelPinsUpdateEvent, Presence};
+use serenity::builder::EditMessage;
+use serenity::futures::future::BoxFuture;
+use serenity::model::prelude::{
+    ChannelId, ChannelPinsUpdateEvent, GuildId, MessageId, MessageUpdateEvent, Presence, Reaction,
+    ReactionType, Role, RoleId,
+};
+use serenity::model::prelude::{CommandInteraction, Interaction};
+use serenity::model::Permissions;
 use serenity::prelude::{Context, EventHandler};
 use serenity::{
     model::application::CommandDataOption, model::channel::Message, prelude::GatewayIntents,
+    FutureExt,
 };
-// use youtube::Youtube;
+use serenity_command::CommandResponse;
 
 use serenity_command_handler::Handler;
 
 use acquiring_taste::AcquiringTaste;
+use album_club::AlbumClub;
+use api::Api;
+use audit_log::AuditLog;
+use features::Deployment;
 use forms::Forms;
-use serenity_command_handler::modules::{spotify, ModLp, ModPoll, Pinboard, SpotifyOAuth};
+use health::Health;
+use playlist::Playlists;
+use playlist_builder::PlaylistBuilder;
+use rate_limit::RateLimiter;
+use ratings::Ratings;
+use reactions::RandomReactions;
+use registration::CommandRegistry;
+use scheduler::Scheduler;
+use serenity_command_handler::modules::{spotify, ModPoll, Pinboard, SpotifyOAuth};
+use settings::GuildSettings;
 use spotify_activity::SpotifyActivity;
+use stats::{BotStats, CommandUse};
 
 mod acquiring_taste;
+mod album;
+mod album_club;
+mod api;
+mod audit_log;
+mod backup;
+mod bandcamp;
 mod complete;
+mod deezer;
+mod error_report;
+mod features;
 mod forms;
-mod spotify_activity;
-// mod youtube;
+mod google_auth;
+mod health;
+mod itunes;
+mod listenbrainz;
 mod lp_info;
+mod musicbrainz;
+mod odesli;
+mod playlist;
+mod playlist_builder;
+mod rate_limit;
+mod ratings;
+mod reactions;
+mod registration;
+mod rym;
+mod scheduler;
+mod settings;
+mod spotify_accounts;
+mod spotify_activity;
+mod spotify_cache;
+mod spotify_link;
+mod spotify_market;
+mod spotify_retry;
+mod stats;
+mod submission_status;
+mod token_cipher;
+mod youtube;
 
 pub fn get_str_opt_ac<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
     options
@@ -47,78 +104,307 @@ pub fn get_focused_option(options: &[CommandDataOption]) -> Option<&str> {
     })
 }
 
+/// Environment variable overriding where the database is stored
+const DB_PATH_VAR: &str = "HUMBLE_LEDGER_DB";
+const DEFAULT_DB_PATH: &str = "humble_ledger.sqlite";
+/// How long a connection waits for another one to release the database before failing
+const DB_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Environment variable holding the number of shards to start, or "auto" to use the number
+/// recommended by Discord
+const SHARDS_VAR: &str = "HUMBLE_LEDGER_SHARDS";
+/// Emojis ready polls are created with, guilds can set others to be accepted as well
+const READY_EMOJI: &str = "✅";
+pub const GO_EMOJI: &str = "▶️";
+/// Emoji posted when a ready poll starts, replaced in guilds that set another one
+const CELEBRATION_EMOJI: &str = "🎉";
+
+// None when the shard count is picked by Discord
+fn shard_count() -> anyhow::Result<Option<u32>> {
+    let Ok(shards) = env::var(SHARDS_VAR) else {
+        return Ok(Some(1));
+    };
+    if shards.trim() == "auto" {
+        return Ok(None);
+    }
+    match shards.trim().parse() {
+        Ok(count) if count > 0 => Ok(Some(count)),
+        _ => anyhow::bail!("{SHARDS_VAR} must be a positive number or \"auto\""),
+    }
+}
+
+pub fn db_path() -> PathBuf {
+    env::var_os(DB_PATH_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_DB_PATH))
+}
+
+/// Opens a connection to the database, in WAL mode so that modules keeping their own connection
+/// don't block each other
+pub fn open_db() -> anyhow::Result<Connection> {
+    let path = db_path();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // SQLite needs to create journal files next to the database, not just write to it
+    let probe = dir.join(".humble_ledger_write_test");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .with_context(|| {
+            format!(
+                "database directory {} is not writable, set {DB_PATH_VAR} to use another path",
+                dir.display()
+            )
+        })?;
+    let conn = Connection::open(&path)
+        .with_context(|| format!("failed to open database {}", path.display()))?;
+    conn.busy_timeout(DB_BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    Ok(conn)
+}
+
+/// Adds a column to an existing table if it is missing, for tables created by older versions
+pub fn add_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> anyhow::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
 #[derive(Eq, PartialEq)]
 enum CompletionType {
     Albums,
     Songs,
 }
 
-struct HandlerWrapper(Handler);
+/// Handler, scheduled tasks and shards that received a ready event
+struct HandlerWrapper(Arc<Handler>, Scheduler, Mutex<HashSet<u32>>);
+
+impl HandlerWrapper {
+    fn set_connected(&self, shard: ShardId, connected: bool) {
+        if let Ok(health) = self.0.module::<Health>() {
+            health.set_connected(shard.0, connected);
+        }
+    }
+
+    async fn invalidate_lp_roles(&self, guild_id: GuildId) {
+        if let Ok(lp) = self.0.module::<lp_info::ModLPInfo>() {
+            lp.invalidate_roles(guild_id).await;
+        }
+    }
+}
 
 #[async_trait]
 impl EventHandler for HandlerWrapper {
     async fn ready(&self, ctx: Context, data_about_bot: serenity::model::gateway::Ready) {
         _ = self.0.http.set(Arc::clone(&ctx.http));
-        let commands = Command::get_global_commands(&ctx.http).await.unwrap();
-        for cmd in commands {
-            if cmd.name == "build_playlist" {
-                Command::delete_global_command(&ctx.http, cmd.id)
-                    .await
-                    .unwrap();
+        self.set_connected(ctx.shard_id, true);
+        // ready is received again whenever a shard can't resume its session
+        if !self.2.lock().unwrap().insert(ctx.shard_id.0) {
+            let e = anyhow::anyhow!(
+                "shard {} started a new session, events sent while it was disconnected were missed",
+                ctx.shard_id.0
+            );
+            error_report::report(&ctx, "gateway connection", &e).await;
+            return;
+        }
+        eprintln!(
+            "{} is running on shard {}!",
+            &data_about_bot.user.name, ctx.shard_id.0
+        );
+        // the rest only needs to happen once, when the first shard is ready
+        if self.0.self_id.set(data_about_bot.user.id).is_err() {
+            return;
+        }
+        if let Ok(api) = self.0.module::<Api>() {
+            api.set_context(&ctx);
+        }
+        match CommandRegistry::sync(&self.0, &ctx).await {
+            Ok(registered) => {
+                if let Ok(audit_log) = self.0.module::<AuditLog>() {
+                    audit_log.set_commands(&registered).await;
+                }
             }
+            Err(e) => error_report::report(&ctx, "registering commands", &e).await,
         }
-        self.0.self_id.set(data_about_bot.user.id).unwrap();
-        eprintln!("{} is running!", &data_about_bot.user.name);
-        for runner in self.0.commands.read().await.0.values() {
-            if let Some(guild) = runner.guild() {
-                guild
-                    .create_command(&ctx.http, runner.register())
-                    .await
-                    .unwrap();
-            } else {
-                Command::create_global_command(&ctx.http, runner.register())
-                    .await
-                    .unwrap();
+        if self.0.module::<Forms>().is_ok() {
+            if let Err(e) = forms::check_forms(&self.0, &ctx).await {
+                error_report::report(&ctx, "checking forms", &e).await;
             }
         }
-        forms::check_forms(&self.0, &ctx).await.unwrap();
+        self.1.start(&self.0, &ctx);
+    }
+
+    async fn resume(&self, ctx: Context, _: ResumedEvent) {
+        eprintln!("Shard {} resumed its session", ctx.shard_id.0);
+        self.set_connected(ctx.shard_id, true);
+    }
+
+    async fn shard_stage_update(&self, _: Context, event: ShardStageUpdateEvent) {
+        eprintln!(
+            "Shard {} went from {:?} to {:?}",
+            event.shard_id.0, event.old, event.new
+        );
+        self.set_connected(event.shard_id, event.new == ConnectionStage::Connected);
     }
 
     async fn message(&self, ctx: Context, new_message: Message) {
-        if new_message.author.id.get() == 513626599330152458 {
-            let mut hasher = DefaultHasher::new();
-            hasher.write_u64(new_message.id.get());
-            let val = hasher.finish();
-            if val % 150 == 0 {
-                new_message.react(&ctx.http, '🖕').await.unwrap();
-            } else if val % 301 == 0 {
-                new_message.react(&ctx.http, '👍').await.unwrap();
+        if Some(new_message.author.id) == self.0.self_id.get().copied() {
+            apply_poll_celebration(
+                &self.0,
+                &ctx,
+                new_message.guild_id,
+                new_message.channel_id,
+                new_message.id,
+                &new_message.content,
+            )
+            .await;
+        }
+        if let Ok(reactions) = self.0.module::<RandomReactions>() {
+            if features::enabled_in(&self.0, new_message.guild_id, &features::RANDOM_REACTIONS)
+                .await
+            {
+                reactions.handle_message(&ctx, &new_message).await;
             }
         }
 
-        let spotify = self.0.module::<SpotifyOAuth>()
+        let Ok(lp) = self.0.module::<lp_info::ModLPInfo>() else {
+            return;
+        };
+        if !features::enabled_in(&self.0, new_message.guild_id, &features::LISTENING_PARTIES).await
+        {
+            return;
+        }
+        let spotify = self
+            .0
+            .module::<SpotifyOAuth>()
             .expect("Could not find spotify module");
-        self.0.module::<lp_info::ModLPInfo>().expect("LP module not found")
-            .handle_message(&spotify.client, &ctx, &new_message).await;
+        lp.handle_message(&self.0, &spotify.client, &ctx, &new_message)
+            .await;
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old: Option<Message>,
+        new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        if let (Some(author), Some(content)) = (&event.author, &event.content) {
+            if Some(author.id) == self.0.self_id.get().copied() {
+                apply_poll_celebration(
+                    &self.0,
+                    &ctx,
+                    event.guild_id,
+                    event.channel_id,
+                    event.id,
+                    content,
+                )
+                .await;
+            }
+        }
+        let Ok(spotify) = self.0.module::<SpotifyOAuth>() else {
+            return;
+        };
+        if !features::enabled_in(&self.0, event.guild_id, &features::LISTENING_PARTIES).await {
+            return;
+        }
+        if let Ok(lp) = self.0.module::<lp_info::ModLPInfo>() {
+            lp.handle_message_update(&self.0, &spotify.client, &ctx, new, &event)
+                .await;
+        }
+    }
+
+    async fn message_delete(
+        &self,
+        _: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _: Option<GuildId>,
+    ) {
+        if let Ok(lp) = self.0.module::<lp_info::ModLPInfo>() {
+            lp.handle_message_delete(channel_id, deleted_message_id)
+                .await;
+        }
+    }
+
+    async fn guild_role_create(&self, _: Context, new: Role) {
+        self.invalidate_lp_roles(new.guild_id).await;
+    }
+
+    async fn guild_role_update(&self, _: Context, _old: Option<Role>, new: Role) {
+        self.invalidate_lp_roles(new.guild_id).await;
+    }
+
+    async fn guild_role_delete(&self, _: Context, guild_id: GuildId, _: RoleId, _: Option<Role>) {
+        self.invalidate_lp_roles(guild_id).await;
     }
 
     async fn presence_update(&self, _: Context, presence: Presence) {
+        if !features::enabled_in(&self.0, presence.guild_id, &features::SPOTIFY_ACTIVITY).await {
+            return;
+        }
         if let Ok(spt_act) = self.0.module::<SpotifyActivity>() {
-            spt_act.presence_update(&presence).await
+            spt_act.presence_update(&self.0, &presence).await
         }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        self.0.process_interaction(ctx, interaction).await;
+        if let Ok(rate_limiter) = self.0.module::<RateLimiter>() {
+            if rate_limiter.reject_limited(&ctx, &interaction).await {
+                return;
+            }
+        }
+        let mut usage = None;
+        if let Interaction::Command(cmd) = &interaction {
+            if features::reject_disabled(&self.0, &ctx, cmd).await {
+                return;
+            }
+            if let Ok(audit_log) = self.0.module::<AuditLog>() {
+                audit_log.record(&self.0, cmd).await;
+            }
+            usage = Some(CommandUse::start(cmd));
+        }
+        self.0.process_interaction(ctx.clone(), interaction).await;
+        if let Some(usage) = usage {
+            // command errors are answered by the framework, only commands it couldn't answer
+            // at all can be told apart
+            let answered = usage.answered(&ctx).await;
+            if !answered {
+                let error = anyhow::anyhow!("/{} failed without answering", usage.command());
+                error_report::report(&ctx, "command", &error).await;
+            }
+            if let Ok(stats) = self.0.module::<BotStats>() {
+                stats.record(&self.0, usage, answered).await;
+            }
+        }
     }
 
     async fn reaction_add(&self, ctx: Context, add_reaction: serenity::model::prelude::Reaction) {
         if add_reaction.user_id == self.0.self_id.get().copied() {
             return;
         }
-        ModPoll::handle_ready_poll(&self.0, &ctx, &add_reaction)
-            .await
-            .unwrap();
+        let poll_reaction = with_default_poll_emoji(&self.0, add_reaction.clone()).await;
+        if let Err(e) = ModPoll::handle_ready_poll(&self.0, &ctx, &poll_reaction).await {
+            error_report::report(&ctx, "ready poll reaction", &e).await;
+        }
+        if let Err(e) = apply_poll_threshold(&self.0, &ctx, &poll_reaction).await {
+            error_report::report(&ctx, "ready poll threshold", &e).await;
+        }
         _ = spotify::handle_reaction(&self.0, &ctx.http, &add_reaction).await;
     }
 
@@ -127,9 +413,10 @@ impl EventHandler for HandlerWrapper {
         ctx: Context,
         remove_reaction: serenity::model::prelude::Reaction,
     ) {
-        ModPoll::handle_remove_react(&self.0, &ctx, &remove_reaction)
-            .await
-            .unwrap()
+        let poll_reaction = with_default_poll_emoji(&self.0, remove_reaction).await;
+        if let Err(e) = ModPoll::handle_remove_react(&self.0, &ctx, &poll_reaction).await {
+            error_report::report(&ctx, "ready poll reaction removal", &e).await;
+        }
     }
 
     async fn channel_pins_update(&self, ctx: Context, pin: ChannelPinsUpdateEvent) {
@@ -137,64 +424,368 @@ impl EventHandler for HandlerWrapper {
             Some(gid) => gid,
             None => return,
         };
+        if self.0.module::<Pinboard>().is_err()
+            || !features::enabled_in(&self.0, Some(guild_id), &features::PINBOARD).await
+        {
+            return;
+        }
         if let Err(e) =
             Pinboard::move_pin_to_pinboard(&self.0, &ctx, pin.channel_id, guild_id).await
         {
-            let guild_name = guild_id
-                .name(&ctx.cache)
-                .map(|name| format!("[{name}] "))
-                .unwrap_or_default();
-            eprintln!("{guild_name}Error moving message to pinboard: {e:?}");
+            let failure = format!(
+                "Could not move the latest pin of <#{}> to the pinboard",
+                pin.channel_id
+            );
+            let notice = match error_report::permission_error(&e) {
+                Some(missing) => {
+                    eprintln!("Error in pinboard: {e:?}");
+                    let missing = if missing.is_empty() {
+                        missing_pin_permissions(&ctx, guild_id, pin.channel_id)
+                    } else {
+                        missing
+                    };
+                    if missing.is_empty() {
+                        format!("{failure}: I am missing permissions in this channel or the pinboard channel")
+                    } else {
+                        format!(
+                            "{failure}: I am missing the following permissions: {}",
+                            missing.get_permission_names().join(", ")
+                        )
+                    }
+                }
+                None => {
+                    let guild_name = guild_id
+                        .name(&ctx.cache)
+                        .map(|name| format!(" ({name})"))
+                        .unwrap_or_default();
+                    error_report::report(&ctx, &format!("pinboard{guild_name}"), &e).await;
+                    format!("{failure}: {e}")
+                }
+            };
+            error_report::notify_moderators(&self.0, &ctx, guild_id, &notice).await;
+        }
+    }
+}
+
+// permissions moving pins needs in the pinned channel that the bot does not have there,
+// empty if they can't be checked from the cache
+fn missing_pin_permissions(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> Permissions {
+    const REQUIRED: Permissions = Permissions::VIEW_CHANNEL
+        .union(Permissions::READ_MESSAGE_HISTORY)
+        .union(Permissions::MANAGE_MESSAGES);
+    let Some(guild) = ctx.cache.guild(guild_id) else {
+        return Permissions::empty();
+    };
+    let bot_id = ctx.cache.current_user().id;
+    match (guild.channels.get(&channel_id), guild.members.get(&bot_id)) {
+        (Some(channel), Some(member)) => REQUIRED - guild.user_permissions_in(channel, member),
+        _ => Permissions::empty(),
+    }
+}
+
+// replaces the emojis a guild set for ready polls with the ones polls are created with, so
+// reacting with either counts
+async fn with_default_poll_emoji(handler: &Handler, mut reaction: Reaction) -> Reaction {
+    let (Some(guild_id), Ok(settings)) = (reaction.guild_id, handler.module::<GuildSettings>())
+    else {
+        return reaction;
+    };
+    for (setting, default) in [
+        (&settings::POLL_READY_EMOJI, READY_EMOJI),
+        (&settings::POLL_GO_EMOJI, GO_EMOJI),
+    ] {
+        let Some(emoji) = settings.emoji(guild_id, setting).await else {
+            continue;
+        };
+        if settings::same_emoji(&emoji, &reaction.emoji) {
+            reaction.emoji = ReactionType::Unicode(default.to_string());
+            break;
+        }
+    }
+    reaction
+}
+
+// gives the go to a ready poll once as many users as the guild's threshold are ready, as polls
+// are shared by all guilds and only start when their host gives the go
+async fn apply_poll_threshold(
+    handler: &Handler,
+    ctx: &Context,
+    reaction: &Reaction,
+) -> anyhow::Result<()> {
+    let ready = ReactionType::Unicode(READY_EMOJI.to_string());
+    let (Some(guild_id), Some(user), Ok(settings)) = (
+        reaction.guild_id,
+        reaction.user_id,
+        handler.module::<GuildSettings>(),
+    ) else {
+        return Ok(());
+    };
+    if reaction.emoji != ready {
+        return Ok(());
+    }
+    let Ok(threshold) = u64::try_from(settings.count(guild_id, &settings::POLL_THRESHOLD).await)
+    else {
+        return Ok(());
+    };
+    if threshold == 0 {
+        return Ok(());
+    }
+    let msg = reaction.message(&ctx.http).await?;
+    if Some(msg.author.id) != handler.self_id.get().copied() {
+        return Ok(());
+    }
+    let guild_ready = settings.emoji(guild_id, &settings::POLL_READY_EMOJI).await;
+    let ready_count: u64 = msg
+        .reactions
+        .iter()
+        .filter(|r| {
+            r.reaction_type == ready
+                || guild_ready
+                    .as_ref()
+                    .is_some_and(|emoji| settings::same_emoji(emoji, &r.reaction_type))
+        })
+        .map(|r| r.count - u64::from(r.me))
+        .sum();
+    // only the reaction reaching the threshold starts the poll
+    if ready_count != threshold {
+        return Ok(());
+    }
+    let mut go = reaction.clone();
+    go.emoji = ReactionType::Unicode(GO_EMOJI.to_string());
+    go.user_id = Some(
+        msg.interaction
+            .map_or(user, |interaction| interaction.user.id),
+    );
+    go.member = None;
+    ModPoll::handle_ready_poll(handler, ctx, &go).await
+}
+
+// replaces the celebration ModPoll posts when a ready poll starts with the guild's own
+async fn apply_poll_celebration(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    content: &str,
+) {
+    let (Some(guild_id), Ok(settings)) = (guild_id, handler.module::<GuildSettings>()) else {
+        return;
+    };
+    if content != CELEBRATION_EMOJI {
+        return;
+    }
+    let Some(emoji) = settings
+        .emoji(guild_id, &settings::POLL_CELEBRATION_EMOJI)
+        .await
+    else {
+        return;
+    };
+    let edit = EditMessage::new().content(emoji.to_string());
+    if let Err(e) = channel_id.edit_message(&ctx.http, message_id, edit).await {
+        error_report::report(ctx, "ready poll celebration", &e.into()).await;
+    }
+}
+
+// runs form commands, reporting their errors like the ones of scheduled tasks
+fn process_form_command<'a>(
+    handler: &'a Handler,
+    ctx: &'a Context,
+    cmd: &'a CommandInteraction,
+) -> BoxFuture<'a, anyhow::Result<CommandResponse>> {
+    async move {
+        let res = Forms::process_form_command(handler, ctx, cmd).await;
+        if let Err(e) = &res {
+            error_report::report(ctx, &format!("/{}", &cmd.data.name), e).await;
         }
+        res
     }
+    .boxed()
 }
 
-async fn build_handler() -> anyhow::Result<Handler> {
-    let conn = Connection::open("humble_ledger.sqlite")?;
-    let polls = ModPoll::new("✅", "❎", "▶️", None, "<a:crabrave:996854529742094417>");
+async fn build_handler(deployment: &Deployment) -> anyhow::Result<Handler> {
+    let conn = open_db()?;
+    let polls = ModPoll::new(READY_EMOJI, "❎", GO_EMOJI, None, CELEBRATION_EMOJI);
     let spotify_oauth = SpotifyOAuth::new_auth_code(scopes!(
         "playlist-modify-public",
         "playlist-read-private",
         "playlist-read-collaborative",
         "user-library-read",
         "user-read-private",
-        "playlist-modify-private"
+        "playlist-modify-private",
+        "ugc-image-upload"
     ))
     .await
     .context("spotify client")?;
 
-    Ok(Handler::builder(conn)
-        .module::<Forms>()
-        .await
-        .context("forms module")?
+    let mut builder = Handler::builder(conn)
         .with_module(polls)
         .await
         .context("polls module")?
         .with_module(spotify_oauth)
         .await
         .context("spotify module")?
-        .module::<AcquiringTaste>()
+        .module::<GuildSettings>()
+        .await
+        .context("settings module")?;
+    // disabled modules are not loaded at all, so their commands are not registered
+    if deployment.enabled(&features::FORMS) {
+        builder = builder
+            .module::<Forms>()
+            .await
+            .context("forms module")?
+            .default_command_handler(process_form_command);
+    }
+    if deployment.enabled(&features::ACQUIRING_TASTE) {
+        builder = builder
+            .module::<AcquiringTaste>()
+            .await
+            .context("att module")?;
+    }
+    if deployment.enabled(&features::ALBUM_CLUB) {
+        builder = builder
+            .module::<AlbumClub>()
+            .await
+            .context("album club module")?;
+    }
+    if deployment.enabled(&features::PLAYLISTS) {
+        // playlist commands are created in guilds like form commands, and handled first
+        builder = builder
+            .module::<Playlists>()
+            .await
+            .context("playlists module")?
+            .default_command_handler(Playlists::process_command);
+    }
+    if deployment.enabled(&features::RATINGS) {
+        builder = builder
+            .module::<Ratings>()
+            .await
+            .context("ratings module")?;
+    }
+    if deployment.enabled(&features::SPOTIFY_ACTIVITY) {
+        builder = builder
+            .module::<SpotifyActivity>()
+            .await
+            .context("spotify activity module")?;
+    }
+    if deployment.enabled(&features::PINBOARD) {
+        builder = builder
+            .module::<Pinboard>()
+            .await
+            .context("pinboard module")?;
+    }
+    if deployment.enabled(&features::RANDOM_REACTIONS) {
+        builder = builder
+            .module::<RandomReactions>()
+            .await
+            .context("random reactions module")?;
+    }
+    builder = builder
+        .module::<odesli::Odesli>()
+        .await
+        .context("odesli module")?
+        .module::<PlaylistBuilder>()
+        .await
+        .context("playlist builder module")?
+        .module::<backup::Backups>()
         .await
-        .context("att module")?
-        .module::<SpotifyActivity>()
+        .context("backup module")?
+        .module::<AuditLog>()
         .await
-        .context("spotify activity module")?
-        .module::<Pinboard>()
+        .context("audit log module")?
+        .module::<Health>()
         .await
-        .context("pinboard module")?
-        .module::<ModLp>()
+        .context("health module")?
+        .module::<Api>()
         .await
-        .context("lp module")?
-        .default_command_handler(Forms::process_form_command)
-        .module::<lp_info::ModLPInfo>()
+        .context("API module")?
+        .module::<RateLimiter>()
         .await
-        .context("LP module")?
-        .build())
+        .context("rate limit module")?
+        .module::<BotStats>()
+        .await
+        .context("stats module")?
+        .module::<CommandRegistry>()
+        .await
+        .context("command registry module")?;
+    if deployment.enabled(&features::LISTENING_PARTIES) {
+        builder = builder
+            .module::<lp_info::ModLPInfo>()
+            .await
+            .context("LP module")?;
+    }
+    Ok(builder.build())
+}
+
+fn build_scheduler(deployment: &Deployment) -> Scheduler {
+    let mut scheduler = Scheduler::new().every(
+        "database backup",
+        Duration::from_secs(60 * 60),
+        backup::Backups::daily,
+    );
+    if deployment.enabled(&features::FORMS) {
+        scheduler = scheduler.every(
+            "submission digest",
+            Duration::from_secs(60 * 60),
+            Forms::post_digests,
+        );
+    }
+    if deployment.enabled(&features::LISTENING_PARTIES) {
+        scheduler = scheduler
+            .every(
+                "scheduled listening parties",
+                Duration::from_secs(15),
+                lp_info::ModLPInfo::start_scheduled,
+            )
+            .every(
+                "listening party history",
+                Duration::from_secs(5),
+                lp_info::ModLPInfo::record_started,
+            )
+            .every(
+                "listening party tracks",
+                Duration::from_secs(5),
+                lp_info::ModLPInfo::announce_tracks,
+            )
+            .every(
+                "listening party events",
+                Duration::from_secs(10),
+                lp_info::ModLPInfo::sync_events,
+            )
+            .every(
+                "listening party summaries",
+                Duration::from_secs(5),
+                lp_info::ModLPInfo::post_summaries,
+            )
+            .every(
+                "listening party queues",
+                Duration::from_secs(5),
+                lp_info::ModLPInfo::advance_queues,
+            );
+    }
+    if deployment.enabled(&features::SPOTIFY_ACTIVITY) {
+        scheduler = scheduler.every(
+            "spotify activity cleanup",
+            Duration::from_secs(60),
+            SpotifyActivity::prune,
+        );
+    }
+    scheduler
 }
 
 #[tokio::main]
 async fn main() {
-    let handler = build_handler().await.unwrap();
+    let deployment = Deployment::from_env().unwrap();
+    let shards = shard_count().unwrap();
+    let handler = Arc::new(build_handler(&deployment).await.unwrap());
+    if let Some(addr) = health::address().unwrap() {
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(addr, handler).await {
+                eprintln!("Health check server stopped: {e:?}");
+            }
+        });
+    }
 
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
 
@@ -212,16 +803,22 @@ async fn main() {
             | GatewayIntents::MESSAGE_CONTENT
             | GatewayIntents::GUILDS,
     )
-    .event_handler(HandlerWrapper(handler))
+    .event_handler(HandlerWrapper(
+        handler,
+        build_scheduler(&deployment),
+        Default::default(),
+    ))
     .application_id(ApplicationId::new(application_id))
     .await
     .expect("Error creating client");
 
-    // Start a single shard, and start listening to events.
-    //
     // Shards will automatically attempt to reconnect, and will perform
-    // exponential backoff until it reconnects.
-    if let Err(why) = client.start().await {
+    // exponential backoff until they reconnect.
+    let res = match shards {
+        Some(count) => client.start_shards(count).await,
+        None => client.start_autosharded().await,
+    };
+    if let Err(why) = res {
         println!("Client error: {:?}", why);
     }
 }
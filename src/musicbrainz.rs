@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
+use reqwest::{header::USER_AGENT, Url};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serenity::async_trait;
+use serenity_command_handler::{db::Db, Handler, Module, ModuleMap};
+use tokio::{sync::Mutex, time::Instant};
+
+const API_URL: &str = "https://musicbrainz.org/ws/2";
+/// MusicBrainz asks clients to identify themselves
+const CLIENT_ID: &str = concat!(
+    "humble_ledger/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/etwyniel/humble-ledger )"
+);
+/// MusicBrainz allows one request per second
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of genres kept for each release
+const MAX_GENRES: usize = 3;
+
+/// Metadata of a release found on MusicBrainz
+#[derive(Debug, Clone)]
+pub struct ReleaseInfo {
+    pub mbid: String,
+    pub year: Option<String>,
+    pub genres: Vec<String>,
+    pub country: Option<String>,
+}
+
+impl ReleaseInfo {
+    /// Year, genres and country, e.g. "2007 • shoegaze, dream pop • GB"
+    pub fn summary(&self) -> String {
+        let genres = (!self.genres.is_empty()).then(|| self.genres.join(", "));
+        [self.year.clone(), genres, self.country.clone()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" • ")
+    }
+}
+
+#[derive(Deserialize)]
+struct ReleaseSearch {
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    id: String,
+    date: Option<String>,
+    country: Option<String>,
+    #[serde(rename = "release-group")]
+    release_group: Option<ReleaseGroupRef>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseGroupRef {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct ReleaseGroup {
+    #[serde(rename = "first-release-date")]
+    first_release_date: Option<String>,
+    #[serde(default)]
+    genres: Vec<Genre>,
+}
+
+#[derive(Deserialize)]
+struct Genre {
+    name: String,
+    count: u32,
+}
+
+fn year(date: &str) -> Option<String> {
+    date.get(..4)
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+        .map(str::to_string)
+}
+
+// escapes a value for use in a quoted search term
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub struct MusicBrainz {
+    client: reqwest::Client,
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl MusicBrainz {
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        {
+            let mut last_request = self.last_request.lock().await;
+            if let Some(elapsed) = last_request.map(|last| last.elapsed()) {
+                if elapsed < REQUEST_INTERVAL {
+                    tokio::time::sleep(REQUEST_INTERVAL - elapsed).await;
+                }
+            }
+            *last_request = Some(Instant::now());
+        }
+        let url = Url::parse_with_params(
+            &format!("{API_URL}/{path}"),
+            query.iter().copied().chain([("fmt", "json")]),
+        )?;
+        let body = self
+            .client
+            .get(url)
+            .header(USER_AGENT, CLIENT_ID)
+            .send()
+            .await
+            .context("querying MusicBrainz")?
+            .error_for_status()?
+            .text()
+            .await?;
+        serde_json::from_str(&body).context("parsing MusicBrainz response")
+    }
+
+    async fn fetch(&self, artist: &str, album: &str) -> anyhow::Result<Option<ReleaseInfo>> {
+        let query = format!(
+            "artist:\"{}\" AND release:\"{}\"",
+            escape(artist),
+            escape(album)
+        );
+        let search: ReleaseSearch = self
+            .get("release", &[("query", &query), ("limit", "1")])
+            .await?;
+        let Some(release) = search.releases.into_iter().next() else {
+            return Ok(None);
+        };
+        let mut info = ReleaseInfo {
+            mbid: release.id,
+            year: release.date.as_deref().and_then(year),
+            genres: Vec::new(),
+            country: release.country,
+        };
+        // genres and the original release date are set on the release group
+        if let Some(group) = release.release_group {
+            let group: ReleaseGroup = self
+                .get(&format!("release-group/{}", group.id), &[("inc", "genres")])
+                .await?;
+            if let Some(first_year) = group.first_release_date.as_deref().and_then(year) {
+                info.year = Some(first_year);
+            }
+            let mut genres = group.genres;
+            genres.sort_by(|a, b| b.count.cmp(&a.count));
+            info.genres = genres
+                .into_iter()
+                .take(MAX_GENRES)
+                .map(|genre| genre.name)
+                .collect();
+        }
+        Ok(Some(info))
+    }
+
+    /// Release info already fetched for an album, without querying MusicBrainz
+    pub fn cached(
+        conn: &Connection,
+        artist: &str,
+        album: &str,
+    ) -> anyhow::Result<Option<ReleaseInfo>> {
+        let info = conn
+            .query_row(
+                "SELECT mbid, year, genres, country FROM musicbrainz_releases
+                 WHERE artist = ?1 AND album = ?2",
+                [artist.to_lowercase(), album.to_lowercase()],
+                |row| {
+                    Ok(ReleaseInfo {
+                        mbid: row.get(0)?,
+                        year: row.get(1)?,
+                        genres: row
+                            .get::<_, String>(2)?
+                            .split(',')
+                            .filter(|genre| !genre.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                        country: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(info)
+    }
+
+    /// Looks an album up on MusicBrainz, caching the result
+    pub async fn release_info(
+        &self,
+        handler: &Handler,
+        artist: &str,
+        album: &str,
+    ) -> anyhow::Result<Option<ReleaseInfo>> {
+        if let Some(info) = Self::cached(&handler.db.lock().await.conn, artist, album)? {
+            return Ok(Some(info));
+        }
+        let Some(info) = self.fetch(artist, album).await? else {
+            return Ok(None);
+        };
+        handler.db.lock().await.conn.execute(
+            "INSERT OR REPLACE INTO musicbrainz_releases
+                 (mbid, artist, album, year, genres, country)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                &info.mbid,
+                artist.to_lowercase(),
+                album.to_lowercase(),
+                &info.year,
+                info.genres.join(","),
+                &info.country
+            ],
+        )?;
+        Ok(Some(info))
+    }
+}
+
+#[async_trait]
+impl Module for MusicBrainz {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS musicbrainz_releases (
+                mbid STRING PRIMARY KEY,
+                artist STRING NOT NULL,
+                album STRING NOT NULL,
+                year STRING,
+                genres STRING NOT NULL DEFAULT(''),
+                country STRING,
+
+                UNIQUE(artist, album)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(MusicBrainz {
+            client: reqwest::Client::new(),
+            last_request: Mutex::new(None),
+        })
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context as _};
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde_derive::Deserialize;
+use serenity::{
+    async_trait, builder::CreateEmbed, model::prelude::CommandInteraction, prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+const API_URL: &str = "https://api.song.link/v1-alpha.1/links";
+
+/// Platforms listed by /convert, with their key in Odesli responses
+const PLATFORMS: &[(&str, &str)] = &[
+    ("spotify", "Spotify"),
+    ("appleMusic", "Apple Music"),
+    ("youtube", "YouTube"),
+    ("bandcamp", "Bandcamp"),
+    ("deezer", "Deezer"),
+];
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+/// Links to the same song or album on every platform Odesli knows about
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Links {
+    entity_unique_id: String,
+    page_url: String,
+    links_by_platform: HashMap<String, PlatformLink>,
+    entities_by_unique_id: HashMap<String, Entity>,
+}
+
+#[derive(Deserialize)]
+struct PlatformLink {
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entity {
+    title: Option<String>,
+    artist_name: Option<String>,
+}
+
+impl Links {
+    pub fn link(&self, platform: &str) -> Option<&str> {
+        self.links_by_platform
+            .get(platform)
+            .map(|link| link.url.as_str())
+    }
+
+    pub fn name(&self) -> Option<String> {
+        let entity = self.entities_by_unique_id.get(&self.entity_unique_id)?;
+        let title = entity.title.as_deref()?;
+        Some(match entity.artist_name.as_deref() {
+            Some(artist) => format!("{artist} - {title}"),
+            None => title.to_string(),
+        })
+    }
+}
+
+/// Looks up a song or album link from any platform
+pub async fn lookup(url: &str) -> anyhow::Result<Links> {
+    let api_url = Url::parse_with_params(API_URL, [("url", url)])?;
+    let body = HTTP_CLIENT
+        .get(api_url)
+        .send()
+        .await
+        .context("querying Odesli")?
+        .error_for_status()
+        .context("Odesli could not find this link")?
+        .text()
+        .await?;
+    serde_json::from_str(&body).context("parsing Odesli response")
+}
+
+/// Converts a link from any platform to a Spotify link
+pub async fn spotify_url(url: &str) -> anyhow::Result<String> {
+    lookup(url)
+        .await?
+        .link("spotify")
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Not available on Spotify"))
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "convert",
+    desc = "Find a song or album on other streaming platforms"
+)]
+pub struct Convert {
+    #[cmd(desc = "Link to a song or album")]
+    link: String,
+}
+
+#[async_trait]
+impl BotCommand for Convert {
+    type Data = Handler;
+    async fn run(
+        self,
+        _data: &Handler,
+        _ctx: &Context,
+        _interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let links = lookup(self.link.trim()).await?;
+        let description = PLATFORMS
+            .iter()
+            .filter_map(|(key, platform)| {
+                links.link(key).map(|url| format!("**{platform}**: {url}"))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        if description.is_empty() {
+            return CommandResponse::private("No other links found");
+        }
+        let embed = CreateEmbed::new()
+            .title(links.name().unwrap_or_else(|| "Links".to_string()))
+            .url(&links.page_url)
+            .description(description);
+        CommandResponse::public(embed)
+    }
+}
+
+pub struct Odesli;
+
+#[async_trait]
+impl Module for Odesli {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Odesli)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<Convert>();
+    }
+}
@@ -1,73 +1,364 @@
-use std::time::Duration;
+use std::{fmt::Write, sync::Arc};
 
 use anyhow::{anyhow, bail, Context as _};
 use chrono::Local;
+use fallible_iterator::FallibleIterator;
 use google_sheets4::api::ValueRange;
-use rspotify::prelude::Id;
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
 use serenity::{
     async_trait,
-    builder::CreateEmbed,
-    client::Context,
-    http::Http,
-    model::prelude::{
-        command::CommandOptionType,
-        interaction::application_command::ApplicationCommandInteraction, CommandId, GuildId,
+    builder::{
+        CreateCommand, CreateCommandOption, CreateEmbed, CreateInteractionResponse, CreateMessage,
+    },
+    futures::future::BoxFuture,
+    model::{
+        application::{CommandDataOptionValue, CommandInteraction, CommandOptionType},
+        prelude::{GuildId, UserId},
+        Permissions,
     },
-    model::{prelude::command, Permissions},
-    prelude::TypeMapKey,
+    prelude::{Context, RwLock},
+    FutureExt,
 };
-
-use crate::{album_club::SubmitAlbum, spotify, Handler};
-use regex::Regex;
-use serenity_command::{BotCommand, CommandBuilder, CommandResponse};
+use serenity_command::{BotCommand, CommandBuilder, CommandKey, CommandResponse};
 use serenity_command_derive::Command;
+use serenity_command_handler::{
+    command_context::{get_focused_option, get_str_opt_ac},
+    db::Db,
+    modules::{Spotify, SpotifyOAuth},
+    CommandStore, CompletionStore, Handler, HandlerBuilder, Module, ModuleMap,
+};
+
+use crate::album::{self, track_providers, Track, TrackProvider};
+use crate::album_club::SubmitAlbum;
+use crate::complete::{complete_link, respond_choices};
+use crate::forms::{sanitize_name, Forms};
+use crate::lp_info::USER_MENTION_RE;
+use crate::playlist_builder::{build_from_picks, Pick, Progress};
+use crate::spotify_cache::SpotifyCache;
+use crate::spotify_market::SpotifyMarket;
+use crate::submission_status::SubmissionStatus;
+use crate::CompletionType;
 
 const SUBMISSIONS_RANGE: &str = "A:F";
+/// Longest song accepted in a playlist, in minutes
+const MAX_SONG_MINUTES: i64 = 20;
+
+static SPREADSHEET_URL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"/spreadsheets/d/([a-zA-Z0-9_-]+)").unwrap());
+
+/// Playlist event users submit songs to with a command created in its guild
+#[derive(Clone)]
+pub struct Playlist {
+    pub guild_id: u64,
+    pub name: String,
+    pub command_name: String,
+    pub command_id: u64,
+    /// Spreadsheet submissions are added to, submissions are only kept in the database
+    /// without one
+    pub spreadsheet_id: Option<String>,
+    /// Whether users submit a backup pick along with their song
+    pub has_backup: bool,
+    /// Submission round in progress, each user has one pick per round
+    pub round: u32,
+}
+
+impl Playlist {
+    fn to_command(&self) -> CreateCommand {
+        let mut cmd = CreateCommand::new(&self.command_name)
+            .description(format!("Submit a song to the {} playlist", &self.name))
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "link", "Link to your pick")
+                    .required(true)
+                    .set_autocomplete(true),
+            );
+        if self.has_backup {
+            cmd = cmd.add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "backup_link",
+                    "Link to your backup pick",
+                )
+                .required(true)
+                .set_autocomplete(true),
+            );
+        }
+        cmd
+    }
 
-#[derive(Command)]
+    async fn submit(
+        &self,
+        handler: &Handler,
+        cmd: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let option = |name: &str| {
+            cmd.data
+                .options
+                .iter()
+                .find(|opt| opt.name == name)
+                .and_then(|opt| match &opt.value {
+                    CommandDataOptionValue::String(s) => Some(s.as_str()),
+                    _ => None,
+                })
+        };
+        let link = option("link").ok_or_else(|| anyhow!("Missing link"))?;
+        let market = SpotifyMarket::for_guild(handler, cmd.guild_id).await;
+        let providers = track_providers(handler, market)?;
+        let song = submission_track(&providers, link).await?;
+        let backup = match option("backup_link") {
+            Some(link) if self.has_backup => Some(submission_track(&providers, link).await?),
+            _ => None,
+        };
+
+        let user = &cmd.user;
+        let user_handle = if let Some(discriminator) = user.discriminator {
+            format!("{}#{:04}", &user.name, discriminator)
+        } else {
+            format!("@{}", &user.name)
+        };
+        let now = Local::now();
+        if let Some(spreadsheet_id) = &self.spreadsheet_id {
+            let timestamp = now.format("%m/%d/%Y %H:%M:%S").to_string();
+            let mut values = vec![
+                timestamp,
+                user_handle.clone(),
+                song.format_name(),
+                song.url.clone(),
+            ];
+            if let Some(backup) = &backup {
+                values.push(backup.format_name());
+                values.push(backup.url.clone());
+            }
+            let request = ValueRange {
+                major_dimension: None,
+                range: Some(SUBMISSIONS_RANGE.to_string()),
+                values: Some(vec![values]),
+            };
+            handler
+                .module::<Forms>()
+                .context("Spreadsheets can't be used without the forms module")?
+                .sheets_client
+                .spreadsheets()
+                .values_append(request, spreadsheet_id, SUBMISSIONS_RANGE)
+                .value_input_option("USER_ENTERED")
+                .doit()
+                .await
+                .context("Error appending to google sheet")?;
+        }
+        // submitting again replaces the user's pick for the round
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO playlist_submissions (guild_id, command_name, round, user_id, username,
+                 song, link, backup_song, backup_link, submitted_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT (guild_id, command_name, round, user_id) DO UPDATE
+                 SET username = ?5, song = ?6, link = ?7, backup_song = ?8, backup_link = ?9,
+                     submitted_at = ?10",
+            params![
+                self.guild_id,
+                &self.command_name,
+                self.round,
+                user.id.get(),
+                &user_handle,
+                song.format_name(),
+                &song.url,
+                backup.as_ref().map(Track::format_name),
+                backup.as_ref().map(|backup| &backup.url),
+                now.timestamp(),
+            ],
+        )?;
+        let resp = match backup {
+            Some(backup) => format!(
+                "Submitted {} and {} to {}\n{}\n{}",
+                song.format_name(),
+                backup.format_name(),
+                &self.name,
+                &song.url,
+                &backup.url
+            ),
+            None => format!(
+                "Submitted {} to {}\n{}",
+                song.format_name(),
+                &self.name,
+                &song.url
+            ),
+        };
+        CommandResponse::private(resp)
+    }
+}
+
+// looks a submitted song up, rejecting the ones too long for a playlist
+async fn submission_track(
+    providers: &[Arc<dyn TrackProvider>],
+    link: &str,
+) -> anyhow::Result<Track> {
+    let track = album::get_track(providers, link.trim()).await?;
+    if track
+        .duration
+        .is_some_and(|duration| duration > chrono::Duration::minutes(MAX_SONG_MINUTES))
+    {
+        bail!("{} is too long!", track.format_name());
+    }
+    Ok(track)
+}
+
+fn load_playlists(conn: &Connection) -> anyhow::Result<Vec<Playlist>> {
+    let mut stmt = conn.prepare(
+        "SELECT guild_id, name, command_name, command_id, spreadsheet_id, has_backup, round
+             FROM playlists",
+    )?;
+    let playlists = stmt
+        .query([])?
+        .map(|row| {
+            Ok(Playlist {
+                guild_id: row.get(0)?,
+                name: row.get(1)?,
+                command_name: row.get(2)?,
+                command_id: row.get(3)?,
+                spreadsheet_id: row.get(4)?,
+                has_backup: row.get(5)?,
+                round: row.get(6)?,
+            })
+        })
+        .collect()?;
+    Ok(playlists)
+}
+
+// makes sure a playlist command would not shadow another command
+async fn check_name_conflict(
+    handler: &Handler,
+    ctx: &Context,
+    guild_id: GuildId,
+    command_name: &str,
+) -> anyhow::Result<()> {
+    if command_name == "submit_" {
+        bail!("Invalid playlist name");
+    }
+    if handler
+        .commands
+        .read()
+        .await
+        .0
+        .keys()
+        .any(|k| *k == command_name)
+    {
+        bail!("/{command_name} is already one of the bot's commands, please pick another name");
+    }
+    let is_playlist = handler
+        .module::<Playlists>()?
+        .playlists
+        .read()
+        .await
+        .iter()
+        .any(|p| p.guild_id == guild_id.get() && p.command_name == command_name);
+    if !is_playlist
+        && guild_id
+            .get_commands(&ctx.http)
+            .await?
+            .iter()
+            .any(|cmd| cmd.name == command_name)
+    {
+        bail!("/{command_name} already exists in this server, please pick another name");
+    }
+    Ok(())
+}
+
+#[derive(Command, Debug)]
 #[cmd(name = "register_playlist", desc = "Register a playlist")]
 pub struct RegisterPlaylist {
     #[cmd(desc = "The name of the playlist event. The submission command will use this name.")]
     pub name: String,
-    #[cmd(desc = "The identifier of the submissions google sheet.")]
-    pub spreadsheet_id: String,
+    #[cmd(desc = "The identifier or URL of the submissions google sheet, if any.")]
+    pub spreadsheet_id: Option<String>,
     #[cmd(desc = "Does this playlist allow backup picks?")]
     pub has_backup: bool,
 }
 
-pub type Playlist = RegisterPlaylist;
-
 #[async_trait]
 impl BotCommand for RegisterPlaylist {
     type Data = Handler;
     const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
 
     async fn run(
-        mut self,
+        self,
         handler: &Handler,
         ctx: &Context,
-        interaction: &ApplicationCommandInteraction,
-    ) -> anyhow::Result<serenity_command::CommandResponse> {
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
         let guild_id = interaction
             .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild!"))?
-            .0;
-        let spreadsheet_url_re =
-            Regex::new(r#"https://docs.google.com/spreadsheets/d/([^/]+)"#).unwrap();
-        if let Some(cap) = spreadsheet_url_re.captures(&self.spreadsheet_id) {
-            self.spreadsheet_id = cap.get(1).unwrap().as_str().to_string();
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let name = self.name.trim().to_string();
+        let command_name: String = format!("submit_{}", sanitize_name(&name))
+            .chars()
+            .take(32)
+            .collect();
+        check_name_conflict(handler, ctx, guild_id, &command_name).await?;
+        if self.spreadsheet_id.is_some() && handler.module::<Forms>().is_err() {
+            return CommandResponse::private(
+                "Spreadsheets are not available, register the playlist without one",
+            );
         }
-        handler.save_playlist(guild_id, &self).await?;
-        let command_id = self.register(guild_id, &ctx.http).await?;
-        let command_embed = format!("</{}:{}>", self.command_name(), command_id.0);
-        Ok(CommandResponse::Public(format!(
-            "Registered playlist '{}'\nUsers can add submissions with {command_embed} (`{command_embed}`)",
-            &self.name,
-        )))
+        let spreadsheet_id = self.spreadsheet_id.as_deref().map(|id| {
+            SPREADSHEET_URL_RE
+                .captures(id)
+                .map(|cap| cap[1].to_string())
+                .unwrap_or_else(|| id.trim().to_string())
+        });
+        // registering a playlist again keeps its current round
+        let round = handler
+            .module::<Playlists>()?
+            .playlists
+            .read()
+            .await
+            .iter()
+            .find(|p| p.guild_id == guild_id.get() && p.command_name == command_name)
+            .map(|p| p.round)
+            .unwrap_or(1);
+        let mut playlist = Playlist {
+            guild_id: guild_id.get(),
+            name,
+            command_name,
+            command_id: 0,
+            spreadsheet_id,
+            has_backup: self.has_backup,
+            round,
+        };
+        let cmd = guild_id
+            .create_command(&ctx.http, playlist.to_command())
+            .await?;
+        playlist.command_id = cmd.id.get();
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO playlists
+                 (guild_id, name, command_name, command_id, spreadsheet_id, has_backup)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (guild_id, command_name) DO UPDATE
+                 SET name = ?2, command_id = ?4, spreadsheet_id = ?5, has_backup = ?6",
+            params![
+                playlist.guild_id,
+                &playlist.name,
+                &playlist.command_name,
+                playlist.command_id,
+                &playlist.spreadsheet_id,
+                playlist.has_backup,
+            ],
+        )?;
+        let command_mention = format!("</{}:{}>", &playlist.command_name, playlist.command_id);
+        let resp = format!(
+            "Registered playlist '{}'\nUsers can add submissions with {command_mention} \
+             (`{command_mention}`)",
+            &playlist.name,
+        );
+        let mut playlists = handler.module::<Playlists>()?.playlists.write().await;
+        playlists
+            .retain(|p| p.guild_id != playlist.guild_id || p.command_name != playlist.command_name);
+        playlists.push(playlist);
+        CommandResponse::public(resp)
     }
 }
 
-#[derive(Command)]
+#[derive(Command, Debug)]
 #[cmd(
     name = "remove_playlist",
     desc = "Remove a playlist submission command"
@@ -86,30 +377,48 @@ impl BotCommand for RemovePlaylist {
         self,
         handler: &Handler,
         ctx: &Context,
-        interaction: &ApplicationCommandInteraction,
-    ) -> anyhow::Result<serenity_command::CommandResponse> {
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
         let guild_id = interaction
             .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild!"))?;
-        for command in guild_id.get_application_commands(&ctx.http).await? {
-            if command.name == self.command_name {
-                guild_id
-                    .delete_application_command(&ctx.http, command.id)
-                    .await?;
-                break;
-            }
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let removed = {
+            let db = handler.db.lock().await;
+            db.conn.execute(
+                "DELETE FROM playlist_submissions WHERE guild_id = ?1 AND command_name = ?2",
+                params![guild_id.get(), &self.command_name],
+            )?;
+            db.conn.execute(
+                "DELETE FROM closed_submissions WHERE guild_id = ?1 AND command_name = ?2",
+                params![guild_id.get(), &self.command_name],
+            )?;
+            db.conn.execute(
+                "DELETE FROM playlists WHERE guild_id = ?1 AND command_name = ?2",
+                params![guild_id.get(), &self.command_name],
+            )?
+        };
+        if removed == 0 {
+            return CommandResponse::private(format!("No playlist uses /{}", &self.command_name));
         }
         handler
-            .delete_playlist(guild_id.0, &self.command_name)
-            .await?;
-        Ok(CommandResponse::Public(format!(
-            "Removed command /{}",
-            &self.command_name
-        )))
+            .module::<Playlists>()?
+            .playlists
+            .write()
+            .await
+            .retain(|p| p.guild_id != guild_id.get() || p.command_name != self.command_name);
+        if let Some(cmd) = guild_id
+            .get_commands(&ctx.http)
+            .await?
+            .iter()
+            .find(|cmd| cmd.name == self.command_name)
+        {
+            guild_id.delete_command(&ctx.http, cmd.id).await?;
+        }
+        CommandResponse::public(format!("Removed command /{}", &self.command_name))
     }
 }
 
-#[derive(Command)]
+#[derive(Command, Debug)]
 #[cmd(name = "list_playlists", desc = "List registered playlists")]
 pub struct ListPlaylists {}
 
@@ -121,178 +430,545 @@ impl BotCommand for ListPlaylists {
         self,
         handler: &Handler,
         ctx: &Context,
-        interaction: &ApplicationCommandInteraction,
-    ) -> anyhow::Result<serenity_command::CommandResponse> {
-        let album_club_line = command::Command::get_global_application_commands(&ctx.http)
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let album_club_line = guild_id
+            .get_commands(&ctx.http)
             .await?
             .iter()
             .find(|cmd| cmd.name == SubmitAlbum::NAME)
-            .map(|cmd| format!("· Album Club: </{}:{}>", SubmitAlbum::NAME, cmd.id.0));
-        let guild_id = interaction
-            .guild_id
-            .ok_or_else(|| anyhow!("Must be run in a guild"))?;
-        let commands = guild_id.get_application_commands(&ctx.http).await?;
-        let playlists = handler.list_playlists(guild_id.0).await?;
+            .map(|cmd| format!("· Album Club: </{}:{}>", SubmitAlbum::NAME, cmd.id.get()));
+        let playlists = handler.module::<Playlists>()?.playlists.read().await;
         let playlist_lines = playlists
-            .into_iter()
-            .filter_map(|playlist| {
-                commands
-                    .iter()
-                    .find(|cmd| cmd.name == playlist.command_name())
-                    .map(|cmd| (cmd.id.0, playlist))
-            })
-            .map(|(command_id, playlist)| {
-                format!(
-                    "· {}: </{}:{}>",
-                    &playlist.name,
-                    playlist.command_name(),
-                    command_id
-                )
-            });
-        let contents = album_club_line
-            .into_iter()
-            .chain(playlist_lines)
-            .collect::<Vec<_>>()
-            .join("\n");
-        let mut embed = CreateEmbed::default();
-        embed.title("Registered playlists");
-        embed.description(contents);
-        Ok(CommandResponse::Embed(embed))
+            .iter()
+            .filter(|p| p.guild_id == guild_id.get())
+            .map(|p| format!("· {}: </{}:{}>", &p.name, &p.command_name, p.command_id));
+        let contents = album_club_line.into_iter().chain(playlist_lines).join("\n");
+        if contents.is_empty() {
+            return CommandResponse::private("No playlists are registered in this server");
+        }
+        let embed = CreateEmbed::default()
+            .title("Registered playlists")
+            .description(contents);
+        CommandResponse::public(embed)
     }
 }
 
-impl Playlist {
-    pub fn command_name(&self) -> String {
-        let slug_chars = self
-            .name
-            .chars()
-            .filter(|c| c.is_ascii())
-            .map(|c| {
-                if c.is_whitespace() {
-                    '_'
-                } else {
-                    c.to_ascii_lowercase()
-                }
+/// Picks of the current round of a playlist, in the order they were submitted
+pub async fn round_picks(handler: &Handler, playlist: &Playlist) -> anyhow::Result<Vec<Pick>> {
+    let db = handler.db.lock().await;
+    let mut stmt = db.conn.prepare(
+        "SELECT username, song, link FROM playlist_submissions
+             WHERE guild_id = ?1 AND command_name = ?2 AND round = ?3
+             ORDER BY submitted_at",
+    )?;
+    let picks = stmt
+        .query(params![
+            playlist.guild_id,
+            &playlist.command_name,
+            playlist.round
+        ])?
+        .map(|row| {
+            Ok(Pick {
+                submitter: row.get(0)?,
+                song: row.get(1)?,
+                link: row.get(2)?,
             })
-            .filter(|&c| c.is_alphanumeric() || c == '_');
-        "submit_".chars().chain(slug_chars).collect()
+        })
+        .collect()?;
+    Ok(picks)
+}
+
+/// Finds a playlist of a guild by the name of its command
+pub async fn find_playlist(
+    handler: &Handler,
+    guild_id: GuildId,
+    command_name: &str,
+) -> anyhow::Result<Playlist> {
+    handler
+        .module::<Playlists>()?
+        .playlists
+        .read()
+        .await
+        .iter()
+        .find(|p| p.guild_id == guild_id.get() && p.command_name == command_name.trim())
+        .cloned()
+        .ok_or_else(|| anyhow!("No playlist uses /{command_name}"))
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "playlist_submissions",
+    desc = "List the picks submitted to the current round of a playlist"
+)]
+pub struct PlaylistSubmissions {
+    #[cmd(desc = "The name of the submission command", autocomplete)]
+    command_name: String,
+}
+
+#[async_trait]
+impl BotCommand for PlaylistSubmissions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let playlist = find_playlist(handler, guild_id, &self.command_name).await?;
+        let picks = round_picks(handler, &playlist).await?;
+        if picks.is_empty() {
+            return CommandResponse::private(format!(
+                "No picks were submitted to {} yet",
+                &playlist.name
+            ));
+        }
+        let contents = picks
+            .iter()
+            .map(|pick| format!("· {}: [{}]({})", &pick.submitter, &pick.song, &pick.link))
+            .join("\n");
+        let embed = CreateEmbed::default()
+            .title(format!(
+                "{}, round {} ({} picks)",
+                &playlist.name,
+                playlist.round,
+                picks.len()
+            ))
+            .description(contents);
+        CommandResponse::private(embed)
     }
+}
 
-    pub async fn register(&self, guild_id: u64, http: &Http) -> anyhow::Result<CommandId> {
-        let id = GuildId(guild_id)
-            .create_application_command(http, |command| {
-                command
-                    .name(&self.command_name())
-                    .description(&format!("Submit a song to the {} playlist", &self.name))
-                    .create_option(|opt| {
-                        opt.name("link")
-                            .description("Spotify link to your pick")
-                            .kind(CommandOptionType::String)
-                            .set_autocomplete(true)
-                            .required(true)
-                    });
-                if self.has_backup {
-                    command.create_option(|opt| {
-                        opt.name("backup_link")
-                            .description("Spotify link to your backup pick")
-                            .kind(CommandOptionType::String)
-                            .set_autocomplete(true)
-                            .required(true)
-                    });
-                }
-                command
-            })
-            .await?
-            .id;
-        Ok(id)
+#[derive(Command, Debug)]
+#[cmd(
+    name = "playlist_finish_round",
+    desc = "Create a Spotify playlist from the picks of the current round and start a new one"
+)]
+pub struct FinishPlaylistRound {
+    #[cmd(desc = "The name of the submission command", autocomplete)]
+    command_name: String,
+    #[cmd(desc = "Title of the Spotify playlist (default: the playlist name and round)")]
+    title: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for FinishPlaylistRound {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(Default::default()),
+            )
+            .await?;
+        let mut progress = Progress::new(ctx, interaction);
+        let resp = match self.finish(handler, &mut progress, guild_id).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                eprintln!("{e:?}");
+                e.to_string()
+            }
+        };
+        progress.finish(&resp).await?;
+        Ok(CommandResponse::None)
     }
 }
 
-impl TypeMapKey for Playlist {
-    type Value = Self;
+impl FinishPlaylistRound {
+    async fn finish(
+        &self,
+        handler: &Handler,
+        progress: &mut Progress<'_>,
+        guild_id: GuildId,
+    ) -> anyhow::Result<String> {
+        let playlist = find_playlist(handler, guild_id, &self.command_name).await?;
+        let picks = round_picks(handler, &playlist).await?;
+        if picks.is_empty() {
+            bail!("No picks were submitted to {} yet", &playlist.name);
+        }
+        let title = self
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("{} #{}", &playlist.name, playlist.round));
+        let built = build_from_picks(handler, progress, guild_id, &title, &picks, false).await?;
+        // the round stays open if the playlist was not created
+        if built.playlist.is_none() {
+            return Ok(built.report);
+        }
+        handler.db.lock().await.conn.execute(
+            "UPDATE playlists SET round = round + 1 WHERE guild_id = ?1 AND command_name = ?2",
+            params![playlist.guild_id, &playlist.command_name],
+        )?;
+        if let Some(p) = handler
+            .module::<Playlists>()?
+            .playlists
+            .write()
+            .await
+            .iter_mut()
+            .find(|p| p.guild_id == playlist.guild_id && p.command_name == playlist.command_name)
+        {
+            p.round += 1;
+        }
+        Ok(format!(
+            "{}\nRound {} is now open",
+            built.report,
+            playlist.round + 1
+        ))
+    }
 }
 
-#[derive(Command)]
-#[cmd(name = "", desc = "Submit a song to a playlist")]
-pub struct SubmitPlaylist {
-    #[cmd(desc = "Spotify link to the song", autocomplete)]
-    link: String,
-    #[cmd(desc = "Spotify link to the backup song", autocomplete)]
-    backup_link: Option<String>,
+#[derive(Command, Debug)]
+#[cmd(
+    name = "promote_backup",
+    desc = "Replace a user's pick for the current round with their backup pick"
+)]
+pub struct PromoteBackup {
+    #[cmd(desc = "The name of the submission command", autocomplete)]
+    command_name: String,
+    #[cmd(desc = "The user whose backup pick should be used (mention)")]
+    user: String,
+    #[cmd(desc = "Why the main pick can't be used, e.g. region-locked (sent to the user)")]
+    reason: Option<String>,
 }
 
 #[async_trait]
-impl BotCommand for SubmitPlaylist {
+impl BotCommand for PromoteBackup {
     type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
 
     async fn run(
         self,
         handler: &Handler,
         ctx: &Context,
-        interaction: &ApplicationCommandInteraction,
-    ) -> anyhow::Result<serenity_command::CommandResponse> {
-        let ctx_data = ctx.data.read().await;
-        let playlist = ctx_data
-            .get::<Playlist>()
-            .ok_or_else(|| anyhow!("Playlist not found"))?;
-        let user = &interaction.user;
-        let user_handle = format!("{}#{:04}", &user.name, user.discriminator);
-        let mut values = vec!["".to_string(); 6];
-        let now = Local::now();
-        let timestamp = format!("{}", now.format("%m/%d/%Y %H:%M:%S"));
-        let song = handler.spotify.get_song_from_url(&self.link).await?;
-        if song.duration > Duration::from_secs(60 * 20) {
-            bail!("This song is too long!")
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let Some(user_id) = USER_MENTION_RE
+            .captures(self.user.trim())
+            .and_then(|caps| caps[1].parse().ok())
+            .map(UserId::new)
+        else {
+            return CommandResponse::private("Invalid user");
+        };
+        let playlist = find_playlist(handler, guild_id, &self.command_name).await?;
+        if !playlist.has_backup {
+            return CommandResponse::private(format!(
+                "{} does not take backup picks",
+                &playlist.name
+            ));
         }
-        let song_info = format!(
-            "{} - {}",
-            spotify::artists_to_string(&song.artists),
-            &song.name
+        let submission: Option<(String, String, String, Option<String>, Option<String>)> = {
+            let db = handler.db.lock().await;
+            db.conn
+                .query_row(
+                    "SELECT username, song, link, backup_song, backup_link
+                         FROM playlist_submissions
+                         WHERE guild_id = ?1 AND command_name = ?2 AND round = ?3
+                         AND user_id = ?4",
+                    params![
+                        playlist.guild_id,
+                        &playlist.command_name,
+                        playlist.round,
+                        user_id.get()
+                    ],
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                        ))
+                    },
+                )
+                .optional()?
+        };
+        let Some((username, song, link, backup_song, backup_link)) = submission else {
+            return CommandResponse::private(format!(
+                "<@{user_id}> has no pick in the current round of {}",
+                &playlist.name
+            ));
+        };
+        let (Some(backup_song), Some(backup_link)) = (backup_song, backup_link) else {
+            return CommandResponse::private(format!("<@{user_id}> has no backup pick"));
+        };
+        // the main pick becomes the backup, so the swap can be undone by running this again
+        handler.db.lock().await.conn.execute(
+            "UPDATE playlist_submissions
+                 SET song = backup_song, link = backup_link, backup_song = song,
+                     backup_link = link
+                 WHERE guild_id = ?1 AND command_name = ?2 AND round = ?3 AND user_id = ?4",
+            params![
+                playlist.guild_id,
+                &playlist.command_name,
+                playlist.round,
+                user_id.get()
+            ],
+        )?;
+        let mut resp = format!("Replaced {username}'s pick {song} with their backup {backup_song}");
+        if let Some(spreadsheet_id) = &playlist.spreadsheet_id {
+            let swapped = vec![backup_song.clone(), backup_link.clone(), song.clone(), link];
+            if let Err(e) = swap_sheet_picks(handler, spreadsheet_id, &username, swapped).await {
+                eprintln!("{e:?}");
+                _ = write!(&mut resp, "\nCould not update the spreadsheet: {e}");
+            }
+        }
+        let mut notice = format!(
+            "Your pick {song} for {} was replaced with your backup {backup_song}",
+            &playlist.name
         );
-        let url = song.id.unwrap().url();
-        values[0] = timestamp;
-        values[1] = user_handle;
-        values[2].push_str(&song_info);
-        values[3].push_str(&url);
-        let mut backup = None;
-        if let Some(link) = self.backup_link {
-            let song = handler.spotify.get_song_from_url(&link).await?;
-            if song.duration > Duration::from_secs(60 * 20) {
-                bail!("This song is too long!")
+        if let Some(reason) = &self.reason {
+            _ = write!(&mut notice, ": {reason}");
+        }
+        _ = write!(&mut notice, "\n{backup_link}");
+        let notified = match user_id.create_dm_channel(&ctx.http).await {
+            Ok(channel) => channel
+                .send_message(&ctx.http, CreateMessage::new().content(notice))
+                .await
+                .map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = notified {
+            _ = write!(&mut resp, "\nCould not notify them: {e}");
+        }
+        CommandResponse::private(resp)
+    }
+}
+
+// replaces the picks of a user's latest row in a playlist's spreadsheet
+async fn swap_sheet_picks(
+    handler: &Handler,
+    spreadsheet_id: &str,
+    username: &str,
+    picks: Vec<String>,
+) -> anyhow::Result<()> {
+    let sheets = handler
+        .module::<Forms>()
+        .context("Spreadsheets can't be used without the forms module")?
+        .sheets_client
+        .spreadsheets();
+    let rows = sheets
+        .values_get(spreadsheet_id, SUBMISSIONS_RANGE)
+        .doit()
+        .await
+        .context("failed to get submissions")?
+        .1
+        .values
+        .unwrap_or_default();
+    // submitting again appends a new row, the latest one holds the current picks
+    let row = rows
+        .iter()
+        .rposition(|row| row.get(1).map(String::as_str) == Some(username))
+        .ok_or_else(|| anyhow!("{username}'s submission is not in the spreadsheet"))?;
+    let req = ValueRange {
+        values: Some(vec![picks]),
+        ..Default::default()
+    };
+    let range = format!("C{0}:F{0}", row + 1);
+    sheets
+        .values_update(req, spreadsheet_id, &range)
+        .value_input_option("USER_ENTERED")
+        .doit()
+        .await
+        .context("failed to update submission")?;
+    Ok(())
+}
+
+/// Playlists of each guild, kept in memory to recognize their submission commands
+pub struct Playlists {
+    playlists: RwLock<Vec<Playlist>>,
+}
+
+impl Playlists {
+    /// Names of the submission commands of a guild's playlists
+    pub async fn command_names(&self, guild_id: GuildId) -> Vec<String> {
+        self.playlists
+            .read()
+            .await
+            .iter()
+            .filter(|p| p.guild_id == guild_id.get())
+            .map(|p| p.command_name.clone())
+            .collect()
+    }
+
+    /// Answers playlist submission commands, passing the other commands created in guilds on to
+    /// forms when they are loaded
+    pub fn process_command<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        cmd: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<CommandResponse>> {
+        async move {
+            let guild_id = cmd
+                .guild_id
+                .ok_or_else(|| anyhow!("Must be run in a server"))?
+                .get();
+            let playlist = handler
+                .module::<Playlists>()?
+                .playlists
+                .read()
+                .await
+                .iter()
+                .find(|p| p.guild_id == guild_id && p.command_name == cmd.data.name)
+                .cloned();
+            match playlist {
+                Some(playlist) => {
+                    let closed = SubmissionStatus::closed_message(
+                        handler,
+                        GuildId::new(guild_id),
+                        &playlist.command_name,
+                    )
+                    .await?;
+                    match closed {
+                        Some(closed) => CommandResponse::private(closed),
+                        None => playlist.submit(handler, cmd).await,
+                    }
+                }
+                None if handler.module::<Forms>().is_ok() => {
+                    Forms::process_form_command(handler, ctx, cmd).await
+                }
+                None => bail!("Command not found"),
             }
-            let song_info = format!(
-                "{} - {}",
-                spotify::artists_to_string(&song.artists),
-                &song.name,
-            );
-            let url = song.id.unwrap().url();
-            values[4].push_str(&song_info);
-            values[5].push_str(&url);
-            backup = Some((song_info, url));
         }
-        eprintln!("Values: {:?}", &values);
+        .boxed()
+    }
 
-        let request = ValueRange {
-            major_dimension: None,
-            range: Some(SUBMISSIONS_RANGE.to_string()),
-            values: Some(vec![values]),
-        };
-        handler
-            .sheets_client
-            .spreadsheets()
-            .values_append(request, &playlist.spreadsheet_id, SUBMISSIONS_RANGE)
-            .value_input_option("USER_ENTERED")
-            .doit()
+    fn complete_playlists<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        _key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            let Some(guild_id) = ac.guild_id.map(GuildId::get) else {
+                return Ok(false);
+            };
+            let name = ac.data.name.as_str();
+            if [
+                RemovePlaylist::NAME,
+                PlaylistSubmissions::NAME,
+                FinishPlaylistRound::NAME,
+                PromoteBackup::NAME,
+            ]
+            .contains(&name)
+            {
+                let opt = get_str_opt_ac(&ac.data.options, "command_name").unwrap_or_default();
+                let choices = handler
+                    .module::<Playlists>()?
+                    .playlists
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|p| p.guild_id == guild_id && p.command_name.contains(opt))
+                    .map(|p| (p.command_name.clone(), p.command_name.clone()))
+                    .collect();
+                respond_choices(ctx, ac, choices).await?;
+                return Ok(true);
+            }
+            let is_playlist = handler
+                .module::<Playlists>()?
+                .playlists
+                .read()
+                .await
+                .iter()
+                .any(|p| p.guild_id == guild_id && p.command_name == name);
+            if !is_playlist {
+                return Ok(false);
+            }
+            if get_focused_option(&ac.data.options).is_some() {
+                complete_link(handler, ctx, ac, CompletionType::Songs).await?;
+            }
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for Playlists {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder
+            .module::<Spotify>()
+            .await?
+            .module::<SpotifyCache>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await?
+            .module::<SpotifyOAuth>()
+            .await?
+            .module::<SubmissionStatus>()
             .await
-            .context("Error appending to google sheet")?;
-        let resp = if let Some((backup_info, backup_url)) = backup {
-            format!(
-                "Submitted {} and {} to playlist\n{}\n{}",
-                song_info, backup_info, url, backup_url
-            )
-        } else {
-            format!("Submitted {} to playlist\n{}", song_info, url)
-        };
-        Ok(CommandResponse::Private(resp))
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS playlists (
+                guild_id INTEGER NOT NULL,
+                name STRING NOT NULL,
+                command_name STRING NOT NULL,
+                command_id INTEGER NOT NULL,
+                spreadsheet_id STRING,
+                has_backup BOOLEAN NOT NULL,
+                round INTEGER NOT NULL DEFAULT(1),
+
+                UNIQUE(guild_id, command_name)
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS playlist_submissions (
+                guild_id INTEGER NOT NULL,
+                command_name STRING NOT NULL,
+                round INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                username STRING NOT NULL,
+                song STRING NOT NULL,
+                link STRING NOT NULL,
+                backup_song STRING,
+                backup_link STRING,
+                submitted_at INTEGER NOT NULL,
+
+                UNIQUE(guild_id, command_name, round, user_id)
+            )",
+            [],
+        )?;
+        *self.playlists.write().await = load_playlists(&db.conn)?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Playlists {
+            playlists: Default::default(),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<RegisterPlaylist>();
+        store.register::<RemovePlaylist>();
+        store.register::<ListPlaylists>();
+        store.register::<PlaylistSubmissions>();
+        store.register::<FinishPlaylistRound>();
+        store.register::<PromoteBackup>();
+        completions.push(Playlists::complete_playlists);
     }
 }
@@ -0,0 +1,923 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context as _};
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use reqwest::Url;
+use rspotify::{
+    model::{AlbumId, FullTrack, Id, Market, PlaylistId, SearchResult, SearchType, TrackId},
+    prelude::{BaseClient, OAuthClient, PlayableId},
+};
+use rusqlite::params;
+use serenity::{
+    async_trait,
+    builder::{
+        CreateActionRow, CreateAttachment, CreateButton, CreateInteractionResponse,
+        CreateInteractionResponseMessage, EditInteractionResponse,
+    },
+    futures::future::BoxFuture,
+    model::{
+        application::{ButtonStyle, CommandInteraction},
+        prelude::GuildId,
+        Permissions,
+    },
+    prelude::Context,
+    FutureExt,
+};
+use serenity_command::{BotCommand, CommandBuilder, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{
+    command_context::get_str_opt_ac,
+    modules::{Spotify, SpotifyOAuth},
+    CommandStore, CompletionStore, Handler, HandlerBuilder, Module, ModuleMap,
+};
+use tokio::{task::JoinSet, time::Instant};
+
+use crate::album::{self, Track, TrackProvider};
+use crate::complete::respond_choices;
+use crate::forms::Forms;
+use crate::itunes;
+use crate::odesli;
+use crate::playlist;
+use crate::spotify_cache::SpotifyCache;
+use crate::spotify_link;
+use crate::spotify_market::SpotifyMarket;
+use crate::spotify_retry::with_retry;
+use crate::submission_status::submission_commands;
+
+/// Prefix of the reason given for picks from other services with no exact match on Spotify
+const LOW_CONFIDENCE_MATCH: &str = "Low-confidence match";
+/// Spotify's limit on the number of tracks added to a playlist per request
+pub const PLAYLIST_ADD_LIMIT: usize = 100;
+/// Minimum delay between edits of the progress message, to avoid hitting rate limits
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// How long to wait for a build to be confirmed before cancelling it
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+const CONFIRM_ID: &str = "playlist_build_confirm";
+/// Longest message Discord accepts, longer reports are attached as a file
+const MAX_MESSAGE_LEN: usize = 2000;
+/// Length of the report excerpt shown above an attached report
+const REPORT_EXCERPT_LEN: usize = 300;
+const CANCEL_ID: &str = "playlist_build_cancel";
+
+/// Song submitted for a playlist, by the name of its submitter
+#[derive(Clone, Debug)]
+pub struct Pick {
+    pub submitter: String,
+    pub song: String,
+    pub link: String,
+}
+
+pub async fn pick_from_track_id(
+    spotify: Arc<SpotifyOAuth>,
+    cache: &SpotifyCache,
+    market: Option<Market>,
+    submitter: &str,
+    id: &str,
+) -> anyhow::Result<Pick> {
+    let track = cache
+        .track(&spotify.client, TrackId::from_id(id)?, market)
+        .await?;
+    Ok(pick_from_track(submitter, &track))
+}
+
+pub fn pick_from_track(submitter: &str, track: &FullTrack) -> Pick {
+    let artists = SpotifyOAuth::artists_to_string(&track.artists);
+    let title = &track.name;
+    Pick {
+        submitter: submitter.to_string(),
+        song: format!("{artists} - {title}"),
+        link: track.id.as_ref().unwrap().url(),
+    }
+}
+
+// finds the artist and title of a track link from another service than spotify
+async fn external_track(
+    providers: &[Arc<dyn TrackProvider>],
+    url: &Url,
+) -> anyhow::Result<Option<Track>> {
+    let segments = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let track = match url.domain().unwrap_or_default() {
+        "music.apple.com" => {
+            // song links look like /us/song/name/id, album links select a song with ?i=id
+            let id = url
+                .query_pairs()
+                .find(|(key, _)| key == "i")
+                .map(|(_, id)| id.into_owned())
+                .or_else(|| {
+                    (segments.get(1) == Some(&"song"))
+                        .then(|| segments.last().map(|id| id.to_string()))
+                        .flatten()
+                })
+                .ok_or_else(|| anyhow!("Not an Apple Music song URL"))?;
+            let country = segments.first().copied().unwrap_or("us");
+            let song = itunes::lookup(&id, country, &[])
+                .await
+                .context("failed to look up Apple Music song")?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Apple Music song not found"))?;
+            Track {
+                title: song.track_name.unwrap_or_default(),
+                artist: song.artist_name,
+                duration: None,
+                url: url.to_string(),
+            }
+        }
+        _ => {
+            let provider = providers
+                .iter()
+                .find(|p| p.id() != "spotify" && p.url_matches(url.as_str()));
+            match provider {
+                Some(provider) => provider.get_track(url.as_str()).await?,
+                None => return Ok(None),
+            }
+        }
+    };
+    Ok(Some(track))
+}
+
+// lowercases a name and strips everything but letters and digits, for comparison
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_confident_match(external: &Track, track: &FullTrack) -> bool {
+    let (title, name) = (normalize_name(&external.title), normalize_name(&track.name));
+    // allow suffixes such as "- Remastered" on either side
+    let title_matches = !title.is_empty() && (name.starts_with(&title) || title.starts_with(&name));
+    let artist = normalize_name(&external.artist);
+    let artist_matches = track.artists.iter().any(|a| {
+        let a = normalize_name(&a.name);
+        !a.is_empty() && artist.contains(&a)
+    });
+    title_matches && artist_matches
+}
+
+// searches spotify for a track submitted from another service
+async fn match_external_track(
+    spotify: Arc<SpotifyOAuth>,
+    market: Option<Market>,
+    submitter: &str,
+    external: Track,
+) -> anyhow::Result<Pick> {
+    let query = format!("track:{} artist:{}", external.title, external.artist);
+    let SearchResult::Tracks(results) = spotify
+        .client
+        .search(&query, SearchType::Track, market, None, Some(5), None)
+        .await?
+    else {
+        bail!("Unexpected search result");
+    };
+    if let Some(track) = results
+        .items
+        .iter()
+        .find(|track| is_confident_match(&external, track))
+    {
+        return Ok(pick_from_track(submitter, track));
+    }
+    match results.items.first() {
+        Some(track) => {
+            let guess = pick_from_track(submitter, track);
+            bail!(
+                "{LOW_CONFIDENCE_MATCH} for {} - {}, best guess is {} <{}>",
+                external.artist,
+                external.title,
+                guess.song,
+                guess.link
+            )
+        }
+        None => bail!(
+            "No match on Spotify for {} - {}",
+            external.artist,
+            external.title
+        ),
+    }
+}
+
+/// How album links submitted as picks are handled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlbumMode {
+    /// Add the most popular track of the album
+    TopTrack,
+    /// Add every track of the album
+    AllTracks,
+}
+
+impl AlbumMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "top" => Some(AlbumMode::TopTrack),
+            "all" => Some(AlbumMode::AllTracks),
+            _ => None,
+        }
+    }
+}
+
+async fn picks_from_album_id(
+    spotify: Arc<SpotifyOAuth>,
+    cache: &SpotifyCache,
+    market: Option<Market>,
+    submitter: &str,
+    id: &str,
+    mode: AlbumMode,
+) -> anyhow::Result<Vec<Pick>> {
+    let album_id = AlbumId::from_id(id)?;
+    let album = cache
+        .album(&spotify.client, album_id, market)
+        .await
+        .context("failed to get album")?;
+    let mut tracks = album.tracks.items;
+    if mode == AlbumMode::TopTrack {
+        // popularity is only available on full track objects
+        let ids = tracks.iter().filter_map(|track| track.id.clone()).take(50);
+        let top = spotify
+            .client
+            .tracks(ids, market)
+            .await?
+            .into_iter()
+            .max_by_key(|track| track.popularity)
+            .and_then(|track| track.id);
+        tracks.retain(|track| track.id.is_some() && track.id == top);
+    }
+    if tracks.is_empty() {
+        bail!("Album has no tracks");
+    }
+    Ok(tracks
+        .into_iter()
+        .filter_map(|track| {
+            let artists = SpotifyOAuth::artists_to_string(&track.artists);
+            Some(Pick {
+                submitter: submitter.to_string(),
+                song: format!("{artists} - {}", track.name),
+                link: track.id?.url(),
+            })
+        })
+        .collect())
+}
+
+/// Resolves a pick to the tracks it stands for, albums may give several
+pub async fn resolve_pick(
+    spotify: Arc<SpotifyOAuth>,
+    cache: &SpotifyCache,
+    market: Option<Market>,
+    providers: &[Arc<dyn TrackProvider>],
+    pick: Pick,
+    album_mode: AlbumMode,
+) -> Result<Vec<Pick>, (Pick, anyhow::Error)> {
+    let link = spotify_link::expand(&pick.link)
+        .await
+        .map_err(|e| (pick.clone(), e))?;
+    let url = Url::parse(&link)
+        .context("Not a valid URL")
+        .map_err(|e| (pick.clone(), e))?;
+    let segments = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .take(2)
+        .collect::<Vec<_>>();
+    match (url.domain(), segments.as_slice()) {
+        (Some("open.spotify.com"), ["track", id]) => {
+            pick_from_track_id(spotify, cache, market, &pick.submitter, id)
+                .await
+                .map(|pick| vec![pick])
+        }
+        (Some("open.spotify.com"), ["album", id]) => {
+            picks_from_album_id(spotify, cache, market, &pick.submitter, id, album_mode).await
+        }
+        _ => match external_track(providers, &url).await {
+            Ok(Some(external)) => match_external_track(spotify, market, &pick.submitter, external)
+                .await
+                .map(|found| vec![found]),
+            // unknown platform, let odesli find the track on spotify
+            Ok(None) => match odesli::spotify_url(&link).await {
+                Ok(converted) => match track_id_from_url(&converted) {
+                    Some(id) => pick_from_track_id(spotify, cache, market, &pick.submitter, &id)
+                        .await
+                        .map(|pick| vec![pick]),
+                    None => Err(anyhow!("Not a track: {converted}")),
+                },
+                Err(e) => Err(anyhow!("Unsupported link: {e}")),
+            },
+            Err(e) => Err(e),
+        },
+    }
+    .map_err(|e| (pick, e))
+}
+
+/// Reports the progress of a long running command by editing its deferred response
+pub struct Progress<'a> {
+    ctx: &'a Context,
+    /// None when not run from a command, progress is then not reported
+    interaction: Option<&'a CommandInteraction>,
+    last_update: Option<Instant>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(ctx: &'a Context, interaction: &'a CommandInteraction) -> Self {
+        Progress {
+            ctx,
+            interaction: Some(interaction),
+            last_update: None,
+        }
+    }
+
+    /// Progress of a build requested outside of a command, which is not reported
+    pub fn detached(ctx: &'a Context) -> Self {
+        Progress {
+            ctx,
+            interaction: None,
+            last_update: None,
+        }
+    }
+
+    /// Lets Spotify requests report when they wait for the rate limit
+    pub fn deferred(&self) -> Option<(&'a Context, &'a CommandInteraction)> {
+        self.interaction.map(|interaction| (self.ctx, interaction))
+    }
+
+    pub async fn update(&mut self, status: &str) {
+        let Some(interaction) = self.interaction else {
+            return;
+        };
+        self.last_update = Some(Instant::now());
+        let edit = EditInteractionResponse::new().content(status);
+        if let Err(e) = interaction.edit_response(&self.ctx.http, edit).await {
+            eprintln!("failed to update progress: {e:?}");
+        }
+    }
+
+    /// Replaces the progress message with the final report of the build
+    pub async fn finish(&self, report: &str) -> anyhow::Result<()> {
+        let Some(interaction) = self.interaction else {
+            return Ok(());
+        };
+        let edit = if report.chars().count() <= MAX_MESSAGE_LEN {
+            EditInteractionResponse::new().content(report)
+        } else {
+            // the first line sums up the build
+            let excerpt = report
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .take(REPORT_EXCERPT_LEN)
+                .collect::<String>();
+            EditInteractionResponse::new()
+                .content(format!("{excerpt}\nThe full report is attached."))
+                .new_attachment(CreateAttachment::bytes(
+                    report.as_bytes().to_vec(),
+                    "report.txt",
+                ))
+        };
+        interaction.edit_response(&self.ctx.http, edit).await?;
+        Ok(())
+    }
+
+    /// Asks the user who ran the command to confirm, returning false if they cancel or time out
+    pub async fn confirm(&mut self, prompt: &str) -> anyhow::Result<bool> {
+        // builds requested without a command are confirmed by whoever requested them
+        let Some(interaction) = self.interaction else {
+            return Ok(true);
+        };
+        let buttons = vec![
+            CreateButton::new(CONFIRM_ID)
+                .label("Confirm")
+                .style(ButtonStyle::Success),
+            CreateButton::new(CANCEL_ID)
+                .label("Cancel")
+                .style(ButtonStyle::Danger),
+        ];
+        let edit = EditInteractionResponse::new()
+            .content(prompt)
+            .components(vec![CreateActionRow::Buttons(buttons)]);
+        let msg = interaction.edit_response(&self.ctx.http, edit).await?;
+        let answer = msg
+            .await_component_interaction(self.ctx)
+            .author_id(interaction.user.id)
+            .timeout(CONFIRM_TIMEOUT)
+            .await;
+        let confirmed = answer
+            .as_ref()
+            .is_some_and(|answer| answer.data.custom_id == CONFIRM_ID);
+        let status = if confirmed {
+            "Building playlist…"
+        } else {
+            "Cancelled, nothing was changed"
+        };
+        self.last_update = Some(Instant::now());
+        match answer {
+            Some(answer) => {
+                let update = CreateInteractionResponseMessage::new()
+                    .content(status)
+                    .components(vec![]);
+                answer
+                    .create_response(
+                        &self.ctx.http,
+                        CreateInteractionResponse::UpdateMessage(update),
+                    )
+                    .await?;
+            }
+            None => {
+                let edit = EditInteractionResponse::new()
+                    .content(status)
+                    .components(vec![]);
+                interaction.edit_response(&self.ctx.http, edit).await?;
+            }
+        }
+        Ok(confirmed)
+    }
+
+    // skips the update if the previous one was too recent, for frequent updates
+    async fn update_throttled(&mut self, status: &str) {
+        if self
+            .last_update
+            .is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.update(status).await
+    }
+}
+
+/// Resolves picks to spotify tracks, separating the ones that could not be resolved
+pub async fn resolve_picks(
+    handler: &Handler,
+    progress: &mut Progress<'_>,
+    picks: &[Pick],
+    album_mode: AlbumMode,
+    market: Option<Market>,
+) -> anyhow::Result<(Vec<(Pick, TrackId<'static>)>, Vec<(Pick, String)>)> {
+    let mut invalid = Vec::new();
+    let mut valid = Vec::new();
+    let spotify: Arc<SpotifyOAuth> = handler.module_arc()?;
+    let cache: Arc<SpotifyCache> = handler.module_arc()?;
+    let providers = Arc::new(album::track_providers(handler, market)?);
+    let mut set = JoinSet::new();
+    for (i, pick) in picks.iter().enumerate() {
+        let spotify = Arc::clone(&spotify);
+        let cache = Arc::clone(&cache);
+        let providers = Arc::clone(&providers);
+        let pick = pick.clone();
+        set.spawn(async move {
+            let resolved =
+                resolve_pick(spotify, &cache, market, &providers, pick, album_mode).await;
+            (i, resolved)
+        });
+    }
+    let mut picks_resolved = Vec::with_capacity(picks.len());
+    let mut done = 0;
+    while let Some(res) = set.join_next().await {
+        done += 1;
+        progress
+            .update_throttled(&format!("Resolved {done}/{} picks…", picks.len()))
+            .await;
+        match res.unwrap() {
+            (i, Ok(picks)) => picks_resolved.extend(picks.into_iter().map(|pick| (i, pick))),
+            (_, Err((pick, e))) => invalid.push((pick, e.to_string())),
+        }
+    }
+    // keep the order the picks were given in
+    picks_resolved.sort_by_key(|(i, _)| *i);
+    for (_, pick) in picks_resolved {
+        let Ok(url) = Url::parse(&pick.link) else {
+            let reason = format!("not a url: {}", &pick.link);
+            invalid.push((pick, reason));
+            continue;
+        };
+        let Some(id) = url.path().strip_prefix("/track/") else {
+            let reason = format!("not a spotify track url: <{}>", &pick.link);
+            invalid.push((pick, reason));
+            continue;
+        };
+        match TrackId::from_id_or_uri(id) {
+            Ok(id) => {
+                let id = id.clone_static();
+                valid.push((pick, id));
+            }
+            Err(e) => invalid.push((pick, e.to_string())),
+        }
+    }
+    Ok((valid, invalid))
+}
+
+pub fn track_id_from_url(link: &str) -> Option<String> {
+    let url = Url::parse(link).ok()?;
+    url.path().strip_prefix("/track/").map(str::to_string)
+}
+
+/// Gets the ISRCs of current and past picks, so the same recording is caught from another
+/// release, dedupe falls back to track IDs if they can't be fetched
+pub async fn pick_isrcs(
+    handler: &Handler,
+    valid: &[(Pick, TrackId<'static>)],
+    past: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let (Ok(spotify), Ok(cache)) = (
+        handler.module::<SpotifyOAuth>(),
+        handler.module::<SpotifyCache>(),
+    ) else {
+        return HashMap::new();
+    };
+    let ids = valid
+        .iter()
+        .map(|(_, id)| id.clone())
+        .chain(
+            past.keys()
+                .filter_map(|id| TrackId::from_id(id).ok().map(|id| id.clone_static())),
+        )
+        .unique()
+        .collect::<Vec<_>>();
+    cache
+        .isrcs(&spotify.client, &ids)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error getting ISRCs of picks: {e:?}");
+            HashMap::new()
+        })
+}
+
+/// Drops picks that already appeared in a previous playlist or earlier in this one, either as
+/// the same track or as the same recording from another release
+///
+/// `past` maps the track IDs of previous picks to the playlist they appeared in
+pub fn dedupe_picks(
+    valid: Vec<(Pick, TrackId<'static>)>,
+    past: &HashMap<String, String>,
+    isrcs: &HashMap<String, String>,
+    invalid: &mut Vec<(Pick, String)>,
+) -> Vec<(Pick, TrackId<'static>)> {
+    let past_isrcs = past
+        .iter()
+        .filter_map(|(id, playlist)| Some((isrcs.get(id)?, playlist)))
+        .collect::<HashMap<_, _>>();
+    let mut seen = HashSet::new();
+    let mut seen_isrcs = HashSet::new();
+    let mut fresh = Vec::with_capacity(valid.len());
+    for (pick, id) in valid {
+        let isrc = isrcs.get(id.id());
+        if let Some(playlist) = past.get(id.id()).or_else(|| past_isrcs.get(isrc?).copied()) {
+            invalid.push((pick, format!("already picked in {playlist}")));
+        } else if !seen.insert(id.id().to_string())
+            || isrc.is_some_and(|isrc| !seen_isrcs.insert(isrc))
+        {
+            invalid.push((pick, "already picked in this playlist".to_string()));
+        } else {
+            fresh.push((pick, id));
+        }
+    }
+    fresh
+}
+
+/// Lists the picks that could not be added to a playlist and why
+pub fn write_invalid(resp: &mut String, invalid: Vec<(Pick, String)>) {
+    let (low_confidence, invalid): (Vec<_>, Vec<_>) = invalid
+        .into_iter()
+        .partition(|(_, reason)| reason.starts_with(LOW_CONFIDENCE_MATCH));
+    if !low_confidence.is_empty() {
+        _ = write!(
+            resp,
+            "\n{} picks could not be matched on Spotify with confidence and need to be added \
+             manually:",
+            low_confidence.len()
+        );
+        low_confidence.into_iter().for_each(|(pick, reason)| {
+            _ = write!(
+                resp,
+                "\n{}'s pick (<{}>): {}",
+                pick.submitter, pick.link, reason
+            );
+        })
+    }
+    if !invalid.is_empty() {
+        _ = write!(
+            resp,
+            "\n{} picks were invalid and could not be added:",
+            invalid.len()
+        );
+        invalid.into_iter().for_each(|(pick, reason)| {
+            _ = write!(
+                resp,
+                "\n{}'s pick ({}): {}",
+                pick.submitter, pick.song, reason
+            );
+        })
+    }
+}
+
+/// Describes what the playlist would contain without creating it
+pub fn dry_run_report(valid: &[(Pick, TrackId<'static>)], invalid: Vec<(Pick, String)>) -> String {
+    let mut resp = format!(
+        "Dry run: {} picks are valid, {} are invalid.\nThe playlist would contain:",
+        valid.len(),
+        invalid.len()
+    );
+    for (i, (pick, _)) in valid.iter().enumerate() {
+        _ = write!(&mut resp, "\n{}. {} ({})", i + 1, pick.song, pick.submitter);
+    }
+    write_invalid(&mut resp, invalid);
+    resp
+}
+
+/// Adds resolved picks to a playlist, in as few requests as Spotify allows
+pub async fn add_tracks(
+    spotify: &SpotifyOAuth,
+    deferred: Option<(&Context, &CommandInteraction)>,
+    playlist: PlaylistId<'_>,
+    tracks: &[(Pick, TrackId<'static>)],
+) -> anyhow::Result<()> {
+    for chunk in tracks.chunks(PLAYLIST_ADD_LIMIT) {
+        with_retry(deferred, || {
+            spotify.client.playlist_add_items(
+                playlist.as_ref(),
+                chunk.iter().map(|(_, id)| PlayableId::from(id.clone())),
+                None,
+            )
+        })
+        .await
+        .context("failed to add songs to playlist")?;
+    }
+    Ok(())
+}
+
+/// Outcome of building a playlist from picks
+pub struct BuildReport {
+    /// The playlist that was created, None for dry runs or cancelled builds
+    pub playlist: Option<PlaylistId<'static>>,
+    /// What was added and which picks were invalid, to show to whoever requested the build
+    pub report: String,
+}
+
+/// Creates a Spotify playlist owned by the bot's account from picks, resolving links from other
+/// services and dropping duplicates
+pub async fn build_from_picks(
+    handler: &Handler,
+    progress: &mut Progress<'_>,
+    guild_id: GuildId,
+    title: &str,
+    picks: &[Pick],
+    dry_run: bool,
+) -> anyhow::Result<BuildReport> {
+    let market = SpotifyMarket::for_guild(handler, Some(guild_id)).await;
+    let (valid, mut invalid) =
+        resolve_picks(handler, progress, picks, AlbumMode::TopTrack, market).await?;
+    let past = HashMap::new();
+    let isrcs = pick_isrcs(handler, &valid, &past).await;
+    let valid = dedupe_picks(valid, &past, &isrcs, &mut invalid);
+    let not_built = |report| {
+        Ok(BuildReport {
+            playlist: None,
+            report,
+        })
+    };
+    if dry_run {
+        return not_built(dry_run_report(&valid, invalid));
+    }
+    if valid.is_empty() {
+        let mut report = "None of the picks could be found on Spotify".to_string();
+        write_invalid(&mut report, invalid);
+        return not_built(report);
+    }
+    let prompt = format!(
+        "Found {} picks: {} tracks to add, {} invalid picks.\nThis will create the playlist \
+         {title}. Continue?",
+        picks.len(),
+        valid.len(),
+        invalid.len()
+    );
+    if !progress.confirm(&prompt).await? {
+        return not_built("Cancelled, nothing was changed".to_string());
+    }
+    progress
+        .update(&format!("Adding {} tracks to the playlist…", valid.len()))
+        .await;
+    let deferred = progress.deferred();
+    let spotify: &SpotifyOAuth = handler.module()?;
+    spotify.client.refresh_token().await?;
+    let user = spotify.client.current_user().await?;
+    let description = format!(
+        "Picks by {}",
+        valid
+            .iter()
+            .map(|(pick, _)| pick.submitter.as_str())
+            .unique()
+            .join(", ")
+    );
+    // spotify truncates descriptions over 300 characters
+    let description = description.chars().take(300).collect::<String>();
+    let created = with_retry(deferred, || {
+        spotify.client.user_playlist_create(
+            user.id.as_ref(),
+            title,
+            Some(true),
+            None,
+            Some(&description),
+        )
+    })
+    .await
+    .context("failed to create playlist")?;
+    add_tracks(spotify, deferred, created.id.as_ref(), &valid).await?;
+    let mut report = format!(
+        "Created {title} with {} tracks\n{}",
+        valid.len(),
+        created.id.url()
+    );
+    write_invalid(&mut report, invalid);
+    Ok(BuildReport {
+        playlist: Some(created.id),
+        report,
+    })
+}
+
+// links submitted to a form, in the order they were submitted
+async fn form_picks(
+    handler: &Handler,
+    guild_id: GuildId,
+    command_name: &str,
+) -> anyhow::Result<Vec<Pick>> {
+    let db = handler.db.lock().await;
+    let mut stmt = db.conn.prepare(
+        "SELECT username, COALESCE(info, link), link FROM form_submissions
+             WHERE guild_id = ?1 AND command_name = ?2 AND link IS NOT NULL
+             ORDER BY rowid",
+    )?;
+    let picks = stmt
+        .query(params![guild_id.get(), command_name])?
+        .map(|row| {
+            Ok(Pick {
+                submitter: row.get(0)?,
+                song: row.get(1)?,
+                link: row.get(2)?,
+            })
+        })
+        .collect()?;
+    Ok(picks)
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "build_playlist_from",
+    desc = "Create a Spotify playlist from the links submitted to a form or playlist"
+)]
+pub struct BuildPlaylistFrom {
+    #[cmd(desc = "The name of the submission command", autocomplete)]
+    command_name: String,
+    #[cmd(desc = "Title of the Spotify playlist (default: the name of the form or playlist)")]
+    title: Option<String>,
+    #[cmd(desc = "Only report what the playlist would contain, without creating it")]
+    dry_run: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for BuildPlaylistFrom {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        interaction
+            .create_response(
+                &ctx.http,
+                CreateInteractionResponse::Defer(Default::default()),
+            )
+            .await?;
+        let mut progress = Progress::new(ctx, interaction);
+        let resp = match self.build(handler, &mut progress, guild_id).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                eprintln!("{e:?}");
+                e.to_string()
+            }
+        };
+        progress.finish(&resp).await?;
+        Ok(CommandResponse::None)
+    }
+}
+
+impl BuildPlaylistFrom {
+    async fn build(
+        &self,
+        handler: &Handler,
+        progress: &mut Progress<'_>,
+        guild_id: GuildId,
+    ) -> anyhow::Result<String> {
+        let command_name = self.command_name.trim();
+        progress.update("Fetching submissions…").await;
+        let (name, picks) = match playlist::find_playlist(handler, guild_id, command_name).await {
+            Ok(playlist) => {
+                let picks = playlist::round_picks(handler, &playlist).await?;
+                (playlist.name, picks)
+            }
+            Err(_) => {
+                let form_title = match handler.module::<Forms>() {
+                    Ok(forms) => forms
+                        .forms
+                        .read()
+                        .await
+                        .iter()
+                        .find(|form| {
+                            form.guild_id == guild_id.get() && form.command_name == command_name
+                        })
+                        .map(|form| form.form.title.clone()),
+                    Err(_) => None,
+                };
+                let Some(form_title) = form_title else {
+                    bail!("/{command_name} is not a submission command");
+                };
+                (
+                    form_title,
+                    form_picks(handler, guild_id, command_name).await?,
+                )
+            }
+        };
+        if picks.is_empty() {
+            bail!("No links were submitted to /{command_name} yet");
+        }
+        let title = self.title.clone().unwrap_or(name);
+        let built = build_from_picks(
+            handler,
+            progress,
+            guild_id,
+            &title,
+            &picks,
+            self.dry_run.unwrap_or_default(),
+        )
+        .await?;
+        Ok(built.report)
+    }
+}
+
+/// Builds Spotify playlists from the picks submitted to forms and playlists
+pub struct PlaylistBuilder;
+
+impl PlaylistBuilder {
+    fn complete_commands<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        _key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if ac.data.name != BuildPlaylistFrom::NAME {
+                return Ok(false);
+            }
+            let Some(guild_id) = ac.guild_id else {
+                return Ok(false);
+            };
+            let opt = get_str_opt_ac(&ac.data.options, "command_name").unwrap_or_default();
+            let choices = submission_commands(handler, guild_id)
+                .await
+                .into_iter()
+                .filter(|name| name.contains(opt))
+                .map(|name| (name.clone(), name))
+                .collect();
+            respond_choices(ctx, ac, choices).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for PlaylistBuilder {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder
+            .module::<Spotify>()
+            .await?
+            .module::<SpotifyCache>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await?
+            .module::<SpotifyOAuth>()
+            .await
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(PlaylistBuilder)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<BuildPlaylistFrom>();
+        completions.push(PlaylistBuilder::complete_commands);
+    }
+}
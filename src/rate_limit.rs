@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use serenity::{
+    async_trait,
+    builder::{
+        CreateAutocompleteResponse, CreateInteractionResponse, CreateInteractionResponseMessage,
+    },
+    model::prelude::{Interaction, UserId},
+    prelude::Context,
+};
+use serenity_command_handler::{Module, ModuleMap};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// How many interactions of one kind a user can send in a burst, and how fast they can send
+/// more after that
+struct Limit {
+    burst: f64,
+    per_second: f64,
+}
+
+/// Limit on each command, which can call Google or Spotify several times
+const COMMAND_LIMIT: Limit = Limit {
+    burst: 5.0,
+    per_second: 0.2,
+};
+/// Limit on autocompletions of each command, sent as users type
+const AUTOCOMPLETE_LIMIT: Limit = Limit {
+    burst: 10.0,
+    per_second: 2.0,
+};
+/// Number of buckets above which the ones that refilled are dropped
+const MAX_BUCKETS: usize = 1000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.updated = now;
+    }
+}
+
+/// Token buckets for each user, command and kind of interaction
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(UserId, String, bool), Bucket>>,
+}
+
+impl RateLimiter {
+    // takes a token from the bucket of an interaction, returning false if it is empty
+    async fn allow(&self, user_id: UserId, command: &str, autocomplete: bool) -> bool {
+        let limit = if autocomplete {
+            &AUTOCOMPLETE_LIMIT
+        } else {
+            &COMMAND_LIMIT
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|(_, _, autocomplete), bucket| {
+                let limit = if *autocomplete {
+                    &AUTOCOMPLETE_LIMIT
+                } else {
+                    &COMMAND_LIMIT
+                };
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst
+            });
+        }
+        let bucket = buckets
+            .entry((user_id, command.to_string(), autocomplete))
+            .or_insert(Bucket {
+                tokens: limit.burst,
+                updated: now,
+            });
+        bucket.refill(limit, now);
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Answers interactions of users who send too many, returning whether the interaction was
+    /// rejected
+    pub async fn reject_limited(&self, ctx: &Context, interaction: &Interaction) -> bool {
+        let (cmd, autocomplete) = match interaction {
+            Interaction::Command(cmd) => (cmd, false),
+            Interaction::Autocomplete(cmd) => (cmd, true),
+            _ => return false,
+        };
+        if self.allow(cmd.user.id, &cmd.data.name, autocomplete).await {
+            return false;
+        }
+        let resp = if autocomplete {
+            CreateInteractionResponse::Autocomplete(CreateAutocompleteResponse::new())
+        } else {
+            let msg = CreateInteractionResponseMessage::new()
+                .content("Slow down! Try again in a few seconds.")
+                .ephemeral(true);
+            CreateInteractionResponse::Message(msg)
+        };
+        if let Err(e) = cmd.create_response(&ctx.http, resp).await {
+            eprintln!("Error rate limiting /{}: {e}", &cmd.data.name);
+        }
+        true
+    }
+}
+
+#[async_trait]
+impl Module for RateLimiter {
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(RateLimiter {
+            buckets: Default::default(),
+        })
+    }
+}
@@ -0,0 +1,641 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::{
+    async_trait,
+    builder::{
+        CreateActionRow, CreateAttachment, CreateButton, CreateEmbed, CreateEmbedFooter,
+        CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+    },
+    client::Context,
+    futures::future::BoxFuture,
+    model::{
+        application::{ButtonStyle, CommandInteraction},
+        prelude::{GuildId, UserId},
+    },
+    FutureExt,
+};
+use serenity_command::{BotCommand, CommandBuilder, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{
+    db::Db,
+    modules::{AlbumLookup, Spotify},
+    CommandStore, CompletionStore, Handler, HandlerBuilder, Module, ModuleMap,
+};
+
+use crate::album::{lookup_album, Album};
+use crate::complete::complete_album_link;
+use crate::lp_info::USER_MENTION_RE;
+use crate::spotify_market::SpotifyMarket;
+
+const MIN_SCORE: i64 = 1;
+const MAX_SCORE: i64 = 10;
+/// Longest review kept, to fit in embeds
+const MAX_REVIEW_LEN: usize = 500;
+/// Number of reviews shown with an album's ratings
+const SHOWN_REVIEWS: usize = 5;
+/// Number of ratings listed by /my_ratings
+const HISTORY_LEN: usize = 20;
+/// Number of albums in a year-end chart
+const CHART_LEN: usize = 50;
+const CHART_PAGE_LEN: usize = 10;
+/// How many ratings the server's average counts for when ranking an album
+const PRIOR_WEIGHT: f64 = 2.0;
+/// Points added to the album whose listening parties drew the most listeners
+const MAX_PARTICIPATION_BONUS: f64 = 1.0;
+/// How long the chart's page buttons keep working
+const CHART_BUTTONS_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const PREVIOUS_PAGE_ID: &str = "chart_previous";
+const NEXT_PAGE_ID: &str = "chart_next";
+
+// finds the album a rated link points to
+async fn rated_album(handler: &Handler, link: &str) -> anyhow::Result<Album> {
+    lookup_album(handler, link.trim())
+        .await?
+        .ok_or_else(|| anyhow!("Not a supported album link"))
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "rate",
+    desc = "Rate an album, rating it again replaces your previous rating"
+)]
+pub struct Rate {
+    #[cmd(desc = "Link to the album", autocomplete)]
+    link: String,
+    #[cmd(desc = "Your score, from 1 to 10")]
+    score: i64,
+    #[cmd(desc = "A few words about the album")]
+    review: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for Rate {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        if !(MIN_SCORE..=MAX_SCORE).contains(&self.score) {
+            return CommandResponse::private(format!(
+                "The score must be between {MIN_SCORE} and {MAX_SCORE}"
+            ));
+        }
+        let review = self
+            .review
+            .as_deref()
+            .map(str::trim)
+            .filter(|review| !review.is_empty());
+        if review.is_some_and(|review| review.chars().count() > MAX_REVIEW_LEN) {
+            return CommandResponse::private(format!(
+                "Reviews are limited to {MAX_REVIEW_LEN} characters"
+            ));
+        }
+        let album = rated_album(handler, &self.link).await?;
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO album_ratings
+                 (guild_id, user_id, url, artist, name, score, review, rated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT (guild_id, user_id, url) DO UPDATE
+                 SET artist = ?4, name = ?5, score = ?6, review = ?7, rated_at = ?8",
+            params![
+                guild_id.get(),
+                interaction.user.id.get(),
+                &album.url,
+                &album.artist,
+                &album.name,
+                self.score,
+                review,
+                Utc::now().timestamp(),
+            ],
+        )?;
+        CommandResponse::private(format!(
+            "Rated {} {}/{MAX_SCORE}",
+            album.format_name(),
+            self.score
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "album_ratings",
+    desc = "Show how members of this server rated an album"
+)]
+pub struct AlbumRatings {
+    #[cmd(desc = "Link to the album", autocomplete)]
+    link: String,
+}
+
+#[async_trait]
+impl BotCommand for AlbumRatings {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let album = rated_album(handler, &self.link).await?;
+        let ratings: Vec<(u64, i64, Option<String>)> = {
+            let db = handler.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT user_id, score, review FROM album_ratings
+                     WHERE guild_id = ?1 AND url = ?2
+                     ORDER BY rated_at DESC",
+            )?;
+            let ratings = stmt
+                .query(params![guild_id.get(), &album.url])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .collect()?;
+            ratings
+        };
+        if ratings.is_empty() {
+            return CommandResponse::private(format!("Nobody rated {} yet", album.format_name()));
+        }
+        let average = ratings
+            .iter()
+            .map(|(_, score, _)| *score as f64)
+            .sum::<f64>()
+            / ratings.len() as f64;
+        let counts = ratings.iter().counts_by(|(_, score, _)| *score);
+        let most = counts.values().copied().max().unwrap_or(1);
+        let distribution = (MIN_SCORE..=MAX_SCORE)
+            .rev()
+            .map(|score| {
+                let count = counts.get(&score).copied().unwrap_or(0);
+                // bars are scaled to the most given score
+                let bar = "▇".repeat((count * 10).div_ceil(most));
+                format!("`{score:>2}` {bar} {count}")
+            })
+            .join("\n");
+        let mut embed = CreateEmbed::default()
+            .title(album.format_name())
+            .url(&album.url)
+            .field(
+                "Average",
+                format!("{average:.1}/{MAX_SCORE} ({} ratings)", ratings.len()),
+                false,
+            )
+            .field("Distribution", distribution, false);
+        let reviews = ratings
+            .iter()
+            .filter_map(|(user_id, score, review)| {
+                Some(format!("<@{user_id}> ({score}): {}", review.as_ref()?))
+            })
+            .take(SHOWN_REVIEWS)
+            .join("\n");
+        if !reviews.is_empty() {
+            embed = embed.field("Latest reviews", reviews, false);
+        }
+        CommandResponse::public(embed)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "my_ratings",
+    desc = "List the albums you or another member rated"
+)]
+pub struct MyRatings {
+    #[cmd(desc = "Member whose ratings to list (mention), defaults to you")]
+    user: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for MyRatings {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let user_id = match &self.user {
+            None => interaction.user.id,
+            Some(user) => match USER_MENTION_RE
+                .captures(user.trim())
+                .and_then(|caps| caps[1].parse().ok())
+            {
+                Some(id) => UserId::new(id),
+                None => return CommandResponse::private("Invalid user"),
+            },
+        };
+        let (lines, total) = user_ratings(handler, guild_id, user_id).await?;
+        if lines.is_empty() {
+            return CommandResponse::private(format!("<@{user_id}> has not rated any album yet"));
+        }
+        let mut description = lines.join("\n");
+        if total > lines.len() {
+            description.push_str(&format!("\n…and {} more", total - lines.len()));
+        }
+        let embed = CreateEmbed::default()
+            .title(format!("Album ratings ({total})"))
+            .description(format!("<@{user_id}>\n{description}"));
+        CommandResponse::public(embed)
+    }
+}
+
+// latest ratings of a user formatted for a list, with their total number of ratings
+async fn user_ratings(
+    handler: &Handler,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> anyhow::Result<(Vec<String>, usize)> {
+    let db = handler.db.lock().await;
+    let total: usize = db.conn.query_row(
+        "SELECT COUNT(*) FROM album_ratings WHERE guild_id = ?1 AND user_id = ?2",
+        params![guild_id.get(), user_id.get()],
+        |row| row.get(0),
+    )?;
+    let mut stmt = db.conn.prepare(
+        "SELECT artist, name, url, score, rated_at FROM album_ratings
+             WHERE guild_id = ?1 AND user_id = ?2
+             ORDER BY rated_at DESC LIMIT ?3",
+    )?;
+    let lines = stmt
+        .query(params![guild_id.get(), user_id.get(), HISTORY_LEN])?
+        .map(|row| {
+            let album = Album {
+                artist: row.get(0)?,
+                name: row.get(1)?,
+                url: row.get(2)?,
+                tracks: Vec::new(),
+            };
+            let score: i64 = row.get(3)?;
+            let rated_at: i64 = row.get(4)?;
+            Ok(format!(
+                "· **{score}**/{MAX_SCORE} [{}]({}) <t:{rated_at}:d>",
+                album.format_name(),
+                &album.url
+            ))
+        })
+        .collect()?;
+    Ok((lines, total))
+}
+
+/// An album ranked in a year-end chart
+struct ChartEntry {
+    album: Album,
+    average: f64,
+    ratings: usize,
+    /// Listening parties of the album during the year
+    parties: usize,
+    participants: usize,
+    score: f64,
+}
+
+impl ChartEntry {
+    // single line summary, with markdown if the chart is shown in an embed
+    fn format(&self, rank: usize, markdown: bool) -> String {
+        let mut stats = format!("{:.1}/{MAX_SCORE}, {} ratings", self.average, self.ratings);
+        if self.parties > 0 {
+            stats.push_str(&format!(
+                ", {} LPs, {} listeners",
+                self.parties, self.participants
+            ));
+        }
+        if markdown {
+            format!(
+                "**{rank}.** [{}]({}) ({stats})",
+                self.album.format_name(),
+                &self.album.url
+            )
+        } else {
+            format!(
+                "{rank}. {} ({stats})\n{}",
+                self.album.format_name(),
+                &self.album.url
+            )
+        }
+    }
+}
+
+// start and end of a year as unix timestamps
+fn year_range(year: i32) -> Option<(i64, i64)> {
+    let timestamp = |year| {
+        let start = NaiveDate::from_ymd_opt(year, 1, 1)?.and_hms_opt(0, 0, 0)?;
+        Some(Utc.from_utc_datetime(&start).timestamp())
+    };
+    Some((timestamp(year)?, timestamp(year + 1)?))
+}
+
+/// Albums rated during a year, best first
+///
+/// Averages are pulled towards the server's average for the year, so an album needs
+/// several good ratings to rank above well-liked albums with many ratings. Albums
+/// played in listening parties get up to MAX_PARTICIPATION_BONUS points, in proportion
+/// to how many people took part compared to the most attended album.
+async fn year_chart(
+    handler: &Handler,
+    guild_id: GuildId,
+    year: i32,
+) -> anyhow::Result<Vec<ChartEntry>> {
+    let (start, end) = year_range(year).ok_or_else(|| anyhow!("Invalid year"))?;
+    // listening parties are grouped by (album id, link) with their total listeners
+    type Parties = Vec<(String, Option<String>, usize, usize)>;
+    let (rated, parties): (Vec<(Album, f64, usize)>, Parties) = {
+        let db = handler.db.lock().await;
+        let mut stmt = db.conn.prepare(
+            "SELECT url, artist, name, AVG(score), COUNT(*) FROM album_ratings
+                 WHERE guild_id = ?1 AND rated_at >= ?2 AND rated_at < ?3
+                 GROUP BY url",
+        )?;
+        let rated = stmt
+            .query(params![guild_id.get(), start, end])?
+            .map(|row| {
+                let album = Album {
+                    url: row.get(0)?,
+                    artist: row.get(1)?,
+                    name: row.get(2)?,
+                    tracks: Vec::new(),
+                };
+                Ok((album, row.get(3)?, row.get(4)?))
+            })
+            .collect()?;
+        let mut stmt = db.conn.prepare(
+            "SELECT album_id, link, COUNT(*), SUM(COALESCE(participants, 0)) FROM lp_history
+                 WHERE guild_id = ?1 AND played_at >= ?2 AND played_at < ?3
+                 GROUP BY album_id, link",
+        )?;
+        let parties = stmt
+            .query(params![guild_id.get(), start, end])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .collect()?;
+        (rated, parties)
+    };
+    let total_ratings: usize = rated.iter().map(|(_, _, count)| count).sum();
+    if total_ratings == 0 {
+        return Ok(Vec::new());
+    }
+    let server_average = rated
+        .iter()
+        .map(|(_, average, count)| average * *count as f64)
+        .sum::<f64>()
+        / total_ratings as f64;
+    let mut entries = rated
+        .into_iter()
+        .map(|(album, average, ratings)| {
+            // listening parties store the provider's id and link rather than the rated link
+            let (parties, participants) = parties
+                .iter()
+                .filter(|(album_id, link, _, _)| {
+                    link.as_deref() == Some(album.url.as_str())
+                        || (!album_id.is_empty() && album.url.contains(album_id.as_str()))
+                })
+                .fold(
+                    (0, 0),
+                    |(parties, participants), (_, _, count, listeners)| {
+                        (parties + count, participants + listeners)
+                    },
+                );
+            let score = (average * ratings as f64 + server_average * PRIOR_WEIGHT)
+                / (ratings as f64 + PRIOR_WEIGHT);
+            ChartEntry {
+                album,
+                average,
+                ratings,
+                parties,
+                participants,
+                score,
+            }
+        })
+        .collect_vec();
+    let most_participants = entries
+        .iter()
+        .map(|entry| entry.participants)
+        .max()
+        .unwrap_or(0);
+    if most_participants > 0 {
+        for entry in &mut entries {
+            entry.score +=
+                MAX_PARTICIPATION_BONUS * entry.participants as f64 / most_participants as f64;
+        }
+    }
+    entries.sort_by(|a, b| b.score.total_cmp(&a.score));
+    entries.truncate(CHART_LEN);
+    Ok(entries)
+}
+
+// embed showing one page of a chart
+fn chart_page(entries: &[ChartEntry], year: i32, page: usize) -> CreateEmbed {
+    let pages = entries.len().div_ceil(CHART_PAGE_LEN);
+    let description = entries
+        .iter()
+        .enumerate()
+        .skip(page * CHART_PAGE_LEN)
+        .take(CHART_PAGE_LEN)
+        .map(|(i, entry)| entry.format(i + 1, true))
+        .join("\n");
+    CreateEmbed::default()
+        .title(format!("Top albums of {year}"))
+        .description(description)
+        .footer(CreateEmbedFooter::new(format!("Page {}/{pages}", page + 1)))
+}
+
+// previous and next buttons of a chart, none if it fits in a single page
+fn chart_buttons(entries: &[ChartEntry], page: usize) -> Vec<CreateActionRow> {
+    let pages = entries.len().div_ceil(CHART_PAGE_LEN);
+    if pages <= 1 {
+        return Vec::new();
+    }
+    let buttons = vec![
+        CreateButton::new(PREVIOUS_PAGE_ID)
+            .label("Previous")
+            .style(ButtonStyle::Secondary)
+            .disabled(page == 0),
+        CreateButton::new(NEXT_PAGE_ID)
+            .label("Next")
+            .style(ButtonStyle::Secondary)
+            .disabled(page + 1 >= pages),
+    ];
+    vec![CreateActionRow::Buttons(buttons)]
+}
+
+// turns the pages of a chart when its buttons are clicked, until they time out
+async fn paginate_chart(
+    ctx: Context,
+    interaction: CommandInteraction,
+    entries: Vec<ChartEntry>,
+    year: i32,
+) -> anyhow::Result<()> {
+    let msg = interaction.get_response(&ctx.http).await?;
+    let mut page = 0;
+    while let Some(click) = msg
+        .await_component_interaction(&ctx)
+        .timeout(CHART_BUTTONS_TIMEOUT)
+        .await
+    {
+        page = match click.data.custom_id.as_str() {
+            PREVIOUS_PAGE_ID => page.saturating_sub(1),
+            NEXT_PAGE_ID => (page + 1).min(entries.len().div_ceil(CHART_PAGE_LEN) - 1),
+            _ => page,
+        };
+        let update = CreateInteractionResponseMessage::new()
+            .embed(chart_page(&entries, year, page))
+            .components(chart_buttons(&entries, page));
+        click
+            .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(update))
+            .await?;
+    }
+    interaction
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new().components(Vec::new()),
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "year_end_chart",
+    desc = "Rank the albums this server liked most over a year"
+)]
+pub struct YearEndChart {
+    #[cmd(desc = "Year of the chart, defaults to the current year")]
+    year: Option<i64>,
+    #[cmd(desc = "Also attach the chart as a text list, ready to be posted")]
+    export: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for YearEndChart {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let year = match self.year {
+            None => Utc::now().year(),
+            Some(year) => match i32::try_from(year)
+                .ok()
+                .filter(|year| year_range(*year).is_some())
+            {
+                Some(year) => year,
+                None => return CommandResponse::private("Invalid year"),
+            },
+        };
+        let entries = year_chart(handler, guild_id, year).await?;
+        if entries.is_empty() {
+            return CommandResponse::private(format!("No albums were rated in {year}"));
+        }
+        let mut resp = CreateInteractionResponseMessage::new()
+            .embed(chart_page(&entries, year, 0))
+            .components(chart_buttons(&entries, 0));
+        if self.export.unwrap_or(false) {
+            let list = entries
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| entry.format(i + 1, false))
+                .join("\n\n");
+            let list = format!("Top albums of {year}\n\n{list}\n");
+            resp = resp.add_file(CreateAttachment::bytes(
+                list.into_bytes(),
+                format!("year_end_chart_{year}.txt"),
+            ));
+        }
+        interaction
+            .create_response(&ctx.http, CreateInteractionResponse::Message(resp))
+            .await?;
+        if entries.len() > CHART_PAGE_LEN {
+            let ctx = ctx.clone();
+            let interaction = interaction.clone();
+            tokio::spawn(async move {
+                if let Err(e) = paginate_chart(ctx, interaction, entries, year).await {
+                    eprintln!("Error paginating year-end chart: {e:?}");
+                }
+            });
+        }
+        Ok(CommandResponse::None)
+    }
+}
+
+/// Album ratings of the members of each guild
+pub struct Ratings;
+
+impl Ratings {
+    fn complete_links<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        _key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if ![Rate::NAME, AlbumRatings::NAME].contains(&ac.data.name.as_str()) {
+                return Ok(false);
+            }
+            complete_album_link(handler, ctx, ac).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+#[async_trait]
+impl Module for Ratings {
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder
+            .module::<Spotify>()
+            .await?
+            .module::<AlbumLookup>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS album_ratings (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                url STRING NOT NULL,
+                artist STRING NOT NULL,
+                name STRING NOT NULL,
+                score INTEGER NOT NULL,
+                review STRING,
+                rated_at INTEGER NOT NULL,
+
+                PRIMARY KEY (guild_id, user_id, url)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(Ratings)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<Rate>();
+        store.register::<AlbumRatings>();
+        store.register::<MyRatings>();
+        store.register::<YearEndChart>();
+        completions.push(Ratings::complete_links);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use fallible_iterator::FallibleIterator;
+use rand::{thread_rng, Rng};
+use rusqlite::params;
+use serenity::{
+    async_trait,
+    model::{
+        prelude::{CommandInteraction, GuildId, Message, ReactionType, UserId},
+        Permissions,
+    },
+    prelude::{Context, RwLock},
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::lp_info::USER_MENTION_RE;
+
+/// Reaction added to some of the messages of a user
+pub struct ReactionRule {
+    user_id: UserId,
+    emoji: String,
+    /// The reaction is added to one message in this many
+    odds: u32,
+}
+
+/// Reacts to messages of some users at random, kept in memory as it is checked on every message
+pub struct RandomReactions {
+    rules: RwLock<HashMap<GuildId, Vec<ReactionRule>>>,
+}
+
+fn parse_user(user: &str) -> Option<UserId> {
+    USER_MENTION_RE
+        .captures(user.trim())
+        .and_then(|caps| caps[1].parse().ok())
+        .map(UserId::new)
+}
+
+impl RandomReactions {
+    /// Adds at most one of the reactions configured for the author of a message
+    pub async fn handle_message(&self, ctx: &Context, message: &Message) {
+        let Some(guild_id) = message.guild_id else {
+            return;
+        };
+        let emoji = {
+            let rules = self.rules.read().await;
+            let Some(rules) = rules.get(&guild_id) else {
+                return;
+            };
+            let mut rng = thread_rng();
+            rules
+                .iter()
+                .filter(|rule| rule.user_id == message.author.id)
+                .find(|rule| rng.gen_ratio(1, rule.odds))
+                .map(|rule| rule.emoji.clone())
+        };
+        let Some(emoji) = emoji else {
+            return;
+        };
+        let reaction = match ReactionType::try_from(emoji.as_str()) {
+            Ok(reaction) => reaction,
+            Err(e) => {
+                eprintln!("Invalid random reaction {emoji}: {e}");
+                return;
+            }
+        };
+        if let Err(e) = message.react(&ctx.http, reaction).await {
+            eprintln!("Error adding random reaction {emoji}: {e}");
+        }
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "random_reaction_add",
+    desc = "React to some of the messages of a user at random"
+)]
+pub struct AddRandomReaction {
+    #[cmd(desc = "User whose messages get reactions (mention)")]
+    user: String,
+    #[cmd(desc = "Emoji to react with")]
+    emoji: String,
+    #[cmd(desc = "React to one message in this many")]
+    odds: i64,
+}
+
+#[async_trait]
+impl BotCommand for AddRandomReaction {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let Some(user_id) = parse_user(&self.user) else {
+            return CommandResponse::private("Invalid user");
+        };
+        let emoji = self.emoji.trim().to_string();
+        if emoji.is_empty() || ReactionType::try_from(emoji.as_str()).is_err() {
+            return CommandResponse::private("Invalid emoji");
+        }
+        let Some(odds) = u32::try_from(self.odds).ok().filter(|&odds| odds > 0) else {
+            return CommandResponse::private("Odds must be a positive number");
+        };
+        data.db.lock().await.conn.execute(
+            "INSERT INTO random_reactions (guild_id, user_id, emoji, odds) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (guild_id, user_id, emoji) DO UPDATE SET odds = ?4",
+            params![guild_id.get(), user_id.get(), &emoji, odds],
+        )?;
+        let reactions: &RandomReactions = data.module()?;
+        let mut rules = reactions.rules.write().await;
+        let rules = rules.entry(guild_id).or_default();
+        rules.retain(|rule| rule.user_id != user_id || rule.emoji != emoji);
+        rules.push(ReactionRule {
+            user_id,
+            emoji: emoji.clone(),
+            odds,
+        });
+        CommandResponse::private(format!(
+            "Reacting with {emoji} to one in {odds} messages of <@{user_id}>"
+        ))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "random_reaction_remove",
+    desc = "Stop reacting to the messages of a user"
+)]
+pub struct RemoveRandomReaction {
+    #[cmd(desc = "User whose messages get reactions (mention)")]
+    user: String,
+    #[cmd(desc = "Only remove this emoji")]
+    emoji: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for RemoveRandomReaction {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let Some(user_id) = parse_user(&self.user) else {
+            return CommandResponse::private("Invalid user");
+        };
+        let emoji = self.emoji.as_deref().map(str::trim);
+        let removed = data.db.lock().await.conn.execute(
+            "DELETE FROM random_reactions
+                 WHERE guild_id = ?1 AND user_id = ?2 AND (?3 IS NULL OR emoji = ?3)",
+            params![guild_id.get(), user_id.get(), emoji],
+        )?;
+        let reactions: &RandomReactions = data.module()?;
+        if let Some(rules) = reactions.rules.write().await.get_mut(&guild_id) {
+            rules.retain(|rule| {
+                rule.user_id != user_id || emoji.is_some_and(|emoji| rule.emoji != emoji)
+            });
+        }
+        if removed == 0 {
+            return CommandResponse::private("No matching reactions");
+        }
+        CommandResponse::private(format!("Removed {removed} reaction(s) for <@{user_id}>"))
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "random_reactions",
+    desc = "List the reactions added to messages at random"
+)]
+pub struct ListRandomReactions {}
+
+#[async_trait]
+impl BotCommand for ListRandomReactions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let reactions: &RandomReactions = data.module()?;
+        let rules = reactions.rules.read().await;
+        let lines = rules
+            .get(&guild_id)
+            .map(|rules| {
+                rules
+                    .iter()
+                    .map(|rule| {
+                        format!("<@{}>: {} (one in {})", rule.user_id, rule.emoji, rule.odds)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if lines.is_empty() {
+            return CommandResponse::private("No random reactions configured");
+        }
+        CommandResponse::private(lines.join("\n"))
+    }
+}
+
+#[async_trait]
+impl Module for RandomReactions {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS random_reactions (
+                guild_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                emoji STRING NOT NULL,
+                odds INTEGER NOT NULL,
+
+                PRIMARY KEY (guild_id, user_id, emoji)
+            )",
+            [],
+        )?;
+        let mut stmt = db
+            .conn
+            .prepare("SELECT guild_id, user_id, emoji, odds FROM random_reactions")?;
+        let rows: Vec<(u64, u64, String, u32)> = stmt
+            .query([])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .collect()?;
+        let mut rules = self.rules.write().await;
+        for (guild_id, user_id, emoji, odds) in rows {
+            rules
+                .entry(GuildId::new(guild_id))
+                .or_default()
+                .push(ReactionRule {
+                    user_id: UserId::new(user_id),
+                    emoji,
+                    odds,
+                });
+        }
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(RandomReactions {
+            rules: Default::default(),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<AddRandomReaction>();
+        store.register::<RemoveRandomReaction>();
+        store.register::<ListRandomReactions>();
+    }
+}
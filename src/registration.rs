@@ -0,0 +1,390 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, bail, Context as _};
+use fallible_iterator::FallibleIterator;
+use rusqlite::params;
+use serenity::{
+    async_trait,
+    builder::{CreateCommand, CreateCommandOption},
+    model::{
+        application::Command,
+        prelude::{CommandInteraction, GuildId},
+        Permissions,
+    },
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandBuilder, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::album_club::SubmitAlbum;
+use crate::features;
+
+/// Keeps track of the commands registered with Discord, so that only the ones that changed are
+/// registered again at startup, and of the guilds each feature's commands are deployed to
+pub struct CommandRegistry;
+
+/// Commands their module registers in each guild itself, as their options depend on the guild
+const GUILD_MANAGED: &[&str] = &[SubmitAlbum::NAME];
+
+// guild the commands are registered in, None for global commands
+type Scope = Option<GuildId>;
+
+fn scope_id(scope: Scope) -> u64 {
+    scope.map(GuildId::get).unwrap_or(0)
+}
+
+async fn existing_commands(ctx: &Context, scope: Scope) -> anyhow::Result<Vec<Command>> {
+    Ok(match scope {
+        Some(guild) => guild.get_commands(&ctx.http).await?,
+        None => Command::get_global_commands(&ctx.http).await?,
+    })
+}
+
+async fn create_command(
+    ctx: &Context,
+    scope: Scope,
+    builder: CreateCommand,
+) -> anyhow::Result<Command> {
+    Ok(match scope {
+        Some(guild) => guild.create_command(&ctx.http, builder).await?,
+        None => Command::create_global_command(&ctx.http, builder).await?,
+    })
+}
+
+async fn delete_command(ctx: &Context, scope: Scope, command: &Command) -> anyhow::Result<()> {
+    match scope {
+        Some(guild) => guild.delete_command(&ctx.http, command.id).await?,
+        None => Command::delete_global_command(&ctx.http, command.id).await?,
+    }
+    Ok(())
+}
+
+// guilds the commands of each feature are deployed to, features missing from the map are
+// registered globally
+async fn load_deployments(handler: &Handler) -> anyhow::Result<HashMap<String, Vec<GuildId>>> {
+    let db = handler.db.lock().await;
+    let mut stmt = db
+        .conn
+        .prepare("SELECT feature, guild_id, enabled FROM command_deployments")?;
+    let rows: Vec<(String, u64, bool)> = stmt
+        .query([])?
+        .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .collect()?;
+    let mut deployments: HashMap<String, Vec<GuildId>> = HashMap::new();
+    for (feature, guild_id, enabled) in rows {
+        let guilds = deployments.entry(feature).or_default();
+        if enabled {
+            guilds.push(GuildId::new(guild_id));
+        }
+    }
+    Ok(deployments)
+}
+
+/// Fails unless the command was used by the owner of the bot, for commands affecting every guild
+pub async fn check_owner(ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<()> {
+    let info = ctx.http.get_current_application_info().await?;
+    if info.owner.as_ref().map(|owner| owner.id) != Some(interaction.user.id) {
+        bail!("Only the owner of the bot can use this command");
+    }
+    Ok(())
+}
+
+fn parse_guild(server: Option<&str>, interaction: &CommandInteraction) -> anyhow::Result<GuildId> {
+    match server {
+        Some(server) => Ok(GuildId::new(
+            server.trim().parse().context("Invalid server ID")?,
+        )),
+        None => interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server or given a server ID")),
+    }
+}
+
+fn module_choices(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+    if opt_name != "module" {
+        return opt;
+    }
+    features::FEATURES
+        .iter()
+        .filter(|feature| !feature.commands.is_empty())
+        .fold(opt, |opt, feature| {
+            opt.add_string_choice(feature.desc, feature.key)
+        })
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "deploy_commands",
+    desc = "Register the commands of a module in a server, or in every server"
+)]
+pub struct DeployCommands {
+    #[cmd(desc = "Module whose commands are registered")]
+    module: String,
+    #[cmd(desc = "ID of the server (default: this one)")]
+    server: Option<String>,
+    #[cmd(desc = "Register the commands in every server instead")]
+    everywhere: Option<bool>,
+}
+
+#[async_trait]
+impl BotCommand for DeployCommands {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        check_owner(ctx, interaction).await?;
+        let feature = features::find(&self.module).context("Unknown module")?;
+        let resp = if self.everywhere == Some(true) {
+            data.db.lock().await.conn.execute(
+                "DELETE FROM command_deployments WHERE feature = ?1",
+                [feature.key],
+            )?;
+            format!(
+                "The {} commands are now registered in every server",
+                feature.key
+            )
+        } else {
+            let guild_id = parse_guild(self.server.as_deref(), interaction)?;
+            data.db.lock().await.conn.execute(
+                "INSERT INTO command_deployments (feature, guild_id, enabled) VALUES (?1, ?2, 1)
+                     ON CONFLICT (feature, guild_id) DO UPDATE SET enabled = 1",
+                params![feature.key, guild_id.get()],
+            )?;
+            format!(
+                "The {} commands are now registered in server {guild_id}",
+                feature.key
+            )
+        };
+        CommandRegistry::sync(data, ctx).await?;
+        CommandResponse::private(resp)
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        module_choices(opt_name, opt)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "remove_commands",
+    desc = "Remove the commands of a module from a server"
+)]
+pub struct RemoveCommands {
+    #[cmd(desc = "Module whose commands are removed")]
+    module: String,
+    #[cmd(desc = "ID of the server (default: this one)")]
+    server: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for RemoveCommands {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        check_owner(ctx, interaction).await?;
+        let feature = features::find(&self.module).context("Unknown module")?;
+        let guild_id = parse_guild(self.server.as_deref(), interaction)?;
+        {
+            let db = data.db.lock().await;
+            let deployed: bool = db.conn.query_row(
+                "SELECT COUNT(*) > 0 FROM command_deployments WHERE feature = ?1",
+                [feature.key],
+                |row| row.get(0),
+            )?;
+            // global commands can't be hidden from a single server, register them in every
+            // other server the bot is in instead
+            if !deployed {
+                for guild in ctx.cache.guilds() {
+                    db.conn.execute(
+                        "INSERT INTO command_deployments (feature, guild_id, enabled)
+                             VALUES (?1, ?2, 1)",
+                        params![feature.key, guild.get()],
+                    )?;
+                }
+            }
+            db.conn.execute(
+                "INSERT INTO command_deployments (feature, guild_id, enabled) VALUES (?1, ?2, 0)
+                     ON CONFLICT (feature, guild_id) DO UPDATE SET enabled = 0",
+                params![feature.key, guild_id.get()],
+            )?;
+        }
+        CommandRegistry::sync(data, ctx).await?;
+        CommandResponse::private(format!(
+            "The {} commands were removed from server {guild_id}",
+            feature.key
+        ))
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        module_choices(opt_name, opt)
+    }
+}
+
+impl CommandRegistry {
+    /// Creates the commands that are missing or changed since they were last registered and
+    /// deletes the ones that no longer exist, returning the registered commands
+    pub async fn sync(handler: &Handler, ctx: &Context) -> anyhow::Result<Vec<Command>> {
+        // definitions of the commands as they are sent to Discord, by scope and name
+        let mut wanted: HashMap<Scope, Vec<(String, String, CreateCommand)>> = HashMap::new();
+        let deployments = load_deployments(handler).await?;
+        for runner in handler.commands.read().await.0.values() {
+            let builder = runner.register();
+            let definition = serde_json::to_value(&builder)?;
+            let name = definition["name"]
+                .as_str()
+                .context("command has no name")?
+                .to_string();
+            if GUILD_MANAGED.contains(&name.as_str()) {
+                continue;
+            }
+            let feature = features::FEATURES
+                .iter()
+                .find(|feature| feature.commands.contains(&name.as_str()));
+            // commands of features deployed to specific guilds are registered in each of them
+            let scopes = match (runner.guild(), feature) {
+                (None, Some(feature)) => match deployments.get(feature.key) {
+                    Some(guilds) => guilds.iter().copied().map(Some).collect(),
+                    None => vec![None],
+                },
+                (scope, _) => vec![scope],
+            };
+            for scope in scopes {
+                wanted.entry(scope).or_default().push((
+                    name.clone(),
+                    definition.to_string(),
+                    builder.clone(),
+                ));
+            }
+        }
+        let previous: HashMap<(u64, String), String> = {
+            let db = handler.db.lock().await;
+            let mut stmt = db
+                .conn
+                .prepare("SELECT guild_id, name, definition FROM registered_commands")?;
+            let rows = stmt
+                .query([])?
+                .map(|row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))
+                .collect()?;
+            rows
+        };
+        // global commands are always checked so that removed ones get deleted
+        let mut scopes: HashSet<Scope> = HashSet::from([None]);
+        scopes.extend(wanted.keys().copied());
+        scopes.extend(
+            previous
+                .keys()
+                .map(|(guild_id, _)| Some(*guild_id).filter(|&id| id != 0).map(GuildId::new)),
+        );
+
+        let mut registered = Vec::new();
+        let mut changed = Vec::new();
+        let mut removed = Vec::new();
+        for scope in scopes {
+            let existing = existing_commands(ctx, scope).await?;
+            let wanted = wanted.remove(&scope).unwrap_or_default();
+            for (name, definition, builder) in &wanted {
+                let current = existing.iter().find(|cmd| &cmd.name == name);
+                let unchanged = previous.get(&(scope_id(scope), name.clone())) == Some(definition);
+                match current {
+                    Some(current) if unchanged => registered.push(current.clone()),
+                    _ => {
+                        let command = create_command(ctx, scope, builder.clone())
+                            .await
+                            .with_context(|| format!("failed to register /{name}"))?;
+                        eprintln!("Registered /{name}");
+                        registered.push(command);
+                        changed.push((scope_id(scope), name.clone(), definition.clone()));
+                    }
+                }
+            }
+            for command in existing {
+                if wanted.iter().any(|(name, _, _)| name == &command.name) {
+                    continue;
+                }
+                // guilds also have commands created for forms, only remove the ones registered
+                // here
+                let registered_here =
+                    previous.contains_key(&(scope_id(scope), command.name.clone()));
+                if scope.is_none() || registered_here {
+                    delete_command(ctx, scope, &command)
+                        .await
+                        .with_context(|| format!("failed to delete /{}", &command.name))?;
+                    eprintln!("Deleted /{}", &command.name);
+                }
+            }
+            removed.extend(
+                previous
+                    .keys()
+                    .filter(|(guild_id, name)| {
+                        *guild_id == scope_id(scope)
+                            && !wanted.iter().any(|(wanted, _, _)| wanted == name)
+                    })
+                    .cloned(),
+            );
+        }
+
+        let db = handler.db.lock().await;
+        for (guild_id, name, definition) in changed {
+            db.conn.execute(
+                "INSERT INTO registered_commands (guild_id, name, definition) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (guild_id, name) DO UPDATE SET definition = ?3",
+                params![guild_id, &name, &definition],
+            )?;
+        }
+        for (guild_id, name) in removed {
+            db.conn.execute(
+                "DELETE FROM registered_commands WHERE guild_id = ?1 AND name = ?2",
+                params![guild_id, &name],
+            )?;
+        }
+        Ok(registered)
+    }
+}
+
+#[async_trait]
+impl Module for CommandRegistry {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS registered_commands (
+                guild_id INTEGER NOT NULL,
+                name STRING NOT NULL,
+                definition TEXT NOT NULL,
+
+                PRIMARY KEY (guild_id, name)
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS command_deployments (
+                feature STRING NOT NULL,
+                guild_id INTEGER NOT NULL,
+                enabled BOOLEAN NOT NULL,
+
+                PRIMARY KEY (feature, guild_id)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(CommandRegistry)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<DeployCommands>();
+        store.register::<RemoveCommands>();
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, bail, Context as _};
+use reqwest::{header::USER_AGENT, Url};
+use rspotify::{
+    clients::BaseClient,
+    model::{Market, SearchResult, SearchType},
+    prelude::Id,
+};
+use scraper::{Html, Selector};
+use serenity_command_handler::{modules::Spotify, Handler};
+
+/// RYM refuses requests that don't look like they come from a browser
+const BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+/// Whether a link points to a release on RateYourMusic
+pub fn url_matches(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    matches!(
+        url.domain(),
+        Some("rateyourmusic.com" | "www.rateyourmusic.com")
+    ) && url.path().starts_with("/release/")
+}
+
+// gets the artist and title of a release from its RYM page
+async fn release(url: &str) -> anyhow::Result<(String, String)> {
+    let page = reqwest::Client::new()
+        .get(url)
+        .header(USER_AGENT, BROWSER_USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()
+        .context("failed to get RateYourMusic page")?
+        .text()
+        .await?;
+    let html = Html::parse_document(&page);
+    let title_selector = Selector::parse(".album_title").unwrap();
+    let artist_selector = Selector::parse(".album_info a.artist").unwrap();
+    let title = html
+        .select(&title_selector)
+        .next()
+        // the title element also holds the release's shortcut
+        .and_then(|title| title.text().map(str::trim).find(|text| !text.is_empty()))
+        .ok_or_else(|| anyhow!("No title found on RateYourMusic page"))?
+        .to_string();
+    let artist = html
+        .select(&artist_selector)
+        .map(|artist| artist.text().collect::<String>().trim().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if artist.is_empty() {
+        bail!("No artist found on RateYourMusic page");
+    }
+    Ok((artist, title))
+}
+
+/// Finds the Spotify version of a release linked on RateYourMusic
+pub async fn spotify_url(
+    handler: &Handler,
+    url: &str,
+    market: Option<Market>,
+) -> anyhow::Result<String> {
+    let (artist, title) = release(url).await?;
+    let spotify: &Spotify = handler.module()?;
+    let query = format!("album:{title} artist:{artist}");
+    let SearchResult::Albums(results) = spotify
+        .client
+        .search(&query, SearchType::Album, market, None, Some(5), None)
+        .await?
+    else {
+        bail!("Unexpected search result");
+    };
+    // prefer an exact title match over deluxe editions and the like
+    let album = results
+        .items
+        .iter()
+        .find(|album| album.name.eq_ignore_ascii_case(&title))
+        .or_else(|| results.items.first())
+        .ok_or_else(|| anyhow!("Could not find {artist} - {title} on Spotify"))?;
+    album
+        .id
+        .as_ref()
+        .map(|id| id.url())
+        .ok_or_else(|| anyhow!("Could not find {artist} - {title} on Spotify"))
+}
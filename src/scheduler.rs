@@ -0,0 +1,51 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use serenity::{futures::future::BoxFuture, prelude::Context};
+use serenity_command_handler::Handler;
+
+use crate::error_report;
+
+pub type Task = for<'a> fn(&'a Handler, &'a Context) -> BoxFuture<'a, anyhow::Result<()>>;
+
+/// Runs periodic background tasks once the bot is connected
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<(&'static str, Duration, Task)>,
+    started: AtomicBool,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Runs `task` every `period`, starting right after the bot connects
+    pub fn every(mut self, name: &'static str, period: Duration, task: Task) -> Self {
+        self.tasks.push((name, period, task));
+        self
+    }
+
+    /// Spawns the tasks, does nothing if they are already running (e.g. after a reconnect)
+    pub fn start(&self, handler: &Arc<Handler>, ctx: &Context) {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for &(name, period, task) in &self.tasks {
+            let handler = Arc::clone(handler);
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = task(&handler, &ctx).await {
+                        error_report::report(&ctx, &format!("task {name}"), &e).await;
+                    }
+                }
+            });
+        }
+    }
+}
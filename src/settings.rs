@@ -0,0 +1,552 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::{params, Connection};
+use serenity::{
+    async_trait,
+    builder::CreateCommandOption,
+    model::{
+        prelude::{ChannelId, ChannelType, CommandInteraction, GuildId, ReactionType, RoleId},
+        Permissions,
+    },
+    prelude::{Context, RwLock},
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::features::{self, FEATURES};
+use crate::lp_info::{format_roles, parse_role_mentions};
+
+/// Regex to extract a channel ID from a channel mention or a raw ID
+static CHANNEL_RE: Lazy<Regex> = Lazy::new(|| Regex::new("^(?:<#)?([0-9]+)>?$").unwrap());
+
+/// Parses a channel mention or ID
+pub fn parse_channel(string: &str) -> Option<ChannelId> {
+    CHANNEL_RE
+        .captures(string.trim())
+        .and_then(|caps| caps.get(1).unwrap().as_str().parse().ok())
+        .map(ChannelId::new)
+}
+
+/// Whether two reactions use the same emoji, custom emojis being compared by ID only
+pub fn same_emoji(emoji: &ReactionType, other: &ReactionType) -> bool {
+    match (emoji, other) {
+        (ReactionType::Custom { id, .. }, ReactionType::Custom { id: other, .. }) => id == other,
+        (ReactionType::Unicode(emoji), ReactionType::Unicode(other)) => emoji == other,
+        _ => false,
+    }
+}
+
+/// How the value of a setting is entered and stored
+pub enum SettingKind {
+    /// Role IDs separated by commas
+    Roles,
+    /// Channel ID, restricted to the given channel types if there are any
+    Channel(&'static [ChannelType]),
+    /// Whole number of minutes
+    Minutes { default: i64 },
+    /// Whole number, 0 turning off what it configures
+    Count { default: i64 },
+    /// Keys of features separated by commas
+    Features,
+    /// Unicode emoji or custom emoji mention
+    Emoji,
+    /// Email address
+    Email,
+    /// On or off, off when it is not set
+    Toggle,
+}
+
+/// Setting configured for each guild
+pub struct Setting {
+    pub key: &'static str,
+    pub desc: &'static str,
+    pub kind: SettingKind,
+}
+
+pub const LP_ROLES: Setting = Setting {
+    key: "lp_roles",
+    desc: "Roles used to ping listening parties",
+    kind: SettingKind::Roles,
+};
+
+pub const LP_AUTO_READY_CHECK: Setting = Setting {
+    key: "lp_auto_ready_check",
+    desc: "Whether the bot posts a ready poll when a listening party is pinged",
+    kind: SettingKind::Toggle,
+};
+
+pub const LP_EVENT_CHANNEL: Setting = Setting {
+    key: "lp_event_channel",
+    desc: "Voice or stage channel events are created in when listening parties start",
+    kind: SettingKind::Channel(&[ChannelType::Voice, ChannelType::Stage]),
+};
+
+pub const ATT_ANNOUNCE_CHANNEL: Setting = Setting {
+    key: "att_announce_channel",
+    desc: "Channel new Acquiring the Taste editions are announced in",
+    kind: SettingKind::Channel(&[]),
+};
+
+pub const MAX_SONG_MINUTES: Setting = Setting {
+    key: "max_song_minutes",
+    desc: "Longest song accepted by forms",
+    kind: SettingKind::Minutes { default: 45 },
+};
+
+pub const MAX_EPISODE_MINUTES: Setting = Setting {
+    key: "max_episode_minutes",
+    desc: "Longest podcast episode accepted by forms",
+    kind: SettingKind::Minutes { default: 3 * 60 },
+};
+
+pub const MOD_CHANNEL: Setting = Setting {
+    key: "mod_channel",
+    desc: "Channel moderators are told in when the bot fails to do something, e.g. move a pin",
+    kind: SettingKind::Channel(&[]),
+};
+
+pub const POLL_READY_EMOJI: Setting = Setting {
+    key: "poll_ready_emoji",
+    desc: "Emoji users can react with to ready polls to mark themselves ready",
+    kind: SettingKind::Emoji,
+};
+
+pub const POLL_GO_EMOJI: Setting = Setting {
+    key: "poll_go_emoji",
+    desc: "Emoji hosts can react with to ready polls to start the countdown",
+    kind: SettingKind::Emoji,
+};
+
+pub const POLL_CELEBRATION_EMOJI: Setting = Setting {
+    key: "poll_celebration_emoji",
+    desc: "Emoji posted when the countdown of a ready poll ends, 🎉 if not set",
+    kind: SettingKind::Emoji,
+};
+
+pub const POLL_THRESHOLD: Setting = Setting {
+    key: "poll_threshold",
+    desc: "Number of ready users that starts a ready poll without its host, 0 to turn off",
+    kind: SettingKind::Count { default: 0 },
+};
+
+pub const SHEET_EDITOR: Setting = Setting {
+    key: "sheet_editor",
+    desc: "Email address spreadsheets created by the bot are shared with",
+    kind: SettingKind::Email,
+};
+
+pub const DISABLED_MODULES: Setting = Setting {
+    key: "disabled_modules",
+    desc: "Modules turned off in this server, separated by commas",
+    kind: SettingKind::Features,
+};
+
+/// Settings that can be changed with /config
+pub const SETTINGS: &[Setting] = &[
+    LP_ROLES,
+    LP_AUTO_READY_CHECK,
+    LP_EVENT_CHANNEL,
+    ATT_ANNOUNCE_CHANNEL,
+    MAX_SONG_MINUTES,
+    MAX_EPISODE_MINUTES,
+    MOD_CHANNEL,
+    POLL_READY_EMOJI,
+    POLL_GO_EMOJI,
+    POLL_CELEBRATION_EMOJI,
+    POLL_THRESHOLD,
+    SHEET_EDITOR,
+    DISABLED_MODULES,
+];
+
+impl Setting {
+    // checks a value entered by a user, returning it as it is stored
+    async fn parse(&self, ctx: &Context, input: &str) -> Result<String, String> {
+        match &self.kind {
+            SettingKind::Roles => {
+                let roles = parse_role_mentions(input);
+                if roles.is_empty() {
+                    return Err("No roles mentioned".to_string());
+                }
+                Ok(roles.iter().join(","))
+            }
+            SettingKind::Channel(types) => {
+                let channel = parse_channel(input).ok_or("Invalid channel")?;
+                if types.is_empty() {
+                    return Ok(channel.to_string());
+                }
+                let kind = channel
+                    .to_channel(ctx)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .guild()
+                    .map(|channel| channel.kind);
+                if !kind.is_some_and(|kind| types.contains(&kind)) {
+                    return Err("This kind of channel can't be used for this setting".to_string());
+                }
+                Ok(channel.to_string())
+            }
+            SettingKind::Minutes { .. } => match input.trim().parse::<i64>() {
+                Ok(minutes) if minutes > 0 => Ok(minutes.to_string()),
+                _ => Err("Expected a number of minutes".to_string()),
+            },
+            SettingKind::Count { .. } => match input.trim().parse::<i64>() {
+                Ok(count) if count >= 0 => Ok(count.to_string()),
+                _ => Err("Expected a whole number".to_string()),
+            },
+            SettingKind::Features => {
+                let mut keys = Vec::new();
+                for key in input.split(',').filter(|key| !key.trim().is_empty()) {
+                    let feature = features::find(key).ok_or_else(|| {
+                        format!(
+                            "Unknown module `{}`, expected one of:\n{}",
+                            key.trim(),
+                            FEATURES
+                                .iter()
+                                .map(|feature| format!("`{}`: {}", feature.key, feature.desc))
+                                .join("\n")
+                        )
+                    })?;
+                    keys.push(feature.key);
+                }
+                Ok(keys.into_iter().unique().join(","))
+            }
+            SettingKind::Emoji => {
+                let emoji = input.trim();
+                if emoji.is_empty() || ReactionType::try_from(emoji).is_err() {
+                    return Err("Invalid emoji".to_string());
+                }
+                Ok(emoji.to_string())
+            }
+            SettingKind::Email => {
+                let email = input.trim();
+                match email.split_once('@') {
+                    Some((user, domain)) if !user.is_empty() && domain.contains('.') => {
+                        Ok(email.to_string())
+                    }
+                    _ => Err("Invalid email address".to_string()),
+                }
+            }
+            SettingKind::Toggle => match input.trim().to_lowercase().as_str() {
+                "on" | "true" | "yes" => Ok("on".to_string()),
+                "off" | "false" | "no" => Ok("off".to_string()),
+                _ => Err("Expected on or off".to_string()),
+            },
+        }
+    }
+
+    fn display(&self, value: Option<&str>) -> String {
+        match (&self.kind, value) {
+            (SettingKind::Roles, Some(value)) => format_roles(&parse_roles(value)),
+            (SettingKind::Channel(_), Some(value)) => format!("<#{value}>"),
+            (SettingKind::Minutes { .. }, Some(value)) => format!("{value} minutes"),
+            (SettingKind::Minutes { default }, None) => format!("{default} minutes (default)"),
+            (SettingKind::Count { .. }, Some(value)) => value.to_string(),
+            (SettingKind::Count { default }, None) => format!("{default} (default)"),
+            (SettingKind::Features, Some(value)) => value.replace(',', ", "),
+            (SettingKind::Features, None) => "none".to_string(),
+            (SettingKind::Emoji, Some(value)) => value.to_string(),
+            (SettingKind::Email, Some(value)) => value.to_string(),
+            (SettingKind::Toggle, Some(value)) => value.to_string(),
+            (SettingKind::Toggle, None) => "off".to_string(),
+            (_, None) => "not set".to_string(),
+        }
+    }
+}
+
+fn parse_roles(value: &str) -> Vec<RoleId> {
+    value
+        .split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .map(RoleId::new)
+        .collect()
+}
+
+/// Per-guild settings, kept in memory as they are read on every message
+pub struct GuildSettings {
+    values: RwLock<HashMap<(GuildId, &'static str), String>>,
+}
+
+impl GuildSettings {
+    async fn get(&self, guild_id: GuildId, setting: &Setting) -> Option<String> {
+        self.values
+            .read()
+            .await
+            .get(&(guild_id, setting.key))
+            .cloned()
+    }
+
+    /// Roles configured for a setting, empty if it is not set
+    pub async fn roles(&self, guild_id: GuildId, setting: &Setting) -> Vec<RoleId> {
+        self.get(guild_id, setting)
+            .await
+            .map(|value| parse_roles(&value))
+            .unwrap_or_default()
+    }
+
+    /// Values of a list setting, empty if it is not set
+    pub async fn list(&self, guild_id: GuildId, setting: &Setting) -> Vec<String> {
+        self.get(guild_id, setting)
+            .await
+            .map(|value| value.split(',').map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    pub async fn channel(&self, guild_id: GuildId, setting: &Setting) -> Option<ChannelId> {
+        self.get(guild_id, setting)
+            .await
+            .and_then(|value| value.parse().ok())
+            .map(ChannelId::new)
+    }
+
+    /// Emoji configured for a setting, None if it is not set
+    pub async fn emoji(&self, guild_id: GuildId, setting: &Setting) -> Option<ReactionType> {
+        self.get(guild_id, setting)
+            .await
+            .and_then(|value| ReactionType::try_from(value.as_str()).ok())
+    }
+
+    /// Whether a toggle setting is on
+    pub async fn enabled(&self, guild_id: GuildId, setting: &Setting) -> bool {
+        self.get(guild_id, setting)
+            .await
+            .is_some_and(|value| value == "on")
+    }
+
+    /// Email address configured for a setting, None if it is not set
+    pub async fn email(&self, guild_id: GuildId, setting: &Setting) -> Option<String> {
+        self.get(guild_id, setting).await
+    }
+
+    /// Duration configured for a setting, or its default
+    pub async fn duration(&self, guild_id: Option<GuildId>, setting: &Setting) -> chrono::Duration {
+        let SettingKind::Minutes { default } = setting.kind else {
+            return chrono::Duration::zero();
+        };
+        let minutes = match guild_id {
+            Some(guild_id) => self
+                .get(guild_id, setting)
+                .await
+                .and_then(|value| value.parse().ok()),
+            None => None,
+        };
+        chrono::Duration::minutes(minutes.unwrap_or(default))
+    }
+
+    /// Number configured for a setting, or its default
+    pub async fn count(&self, guild_id: GuildId, setting: &Setting) -> i64 {
+        let SettingKind::Count { default } = setting.kind else {
+            return 0;
+        };
+        self.get(guild_id, setting)
+            .await
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    }
+
+    /// Changes a setting, None resets it
+    pub async fn set(
+        &self,
+        handler: &Handler,
+        guild_id: GuildId,
+        setting: &Setting,
+        value: Option<String>,
+    ) -> anyhow::Result<()> {
+        let db = handler.db.lock().await;
+        let mut values = self.values.write().await;
+        match value {
+            Some(value) => {
+                db.conn.execute(
+                    "INSERT INTO guild_settings (guild_id, key, value) VALUES (?1, ?2, ?3)
+                         ON CONFLICT (guild_id, key) DO UPDATE SET value = ?3",
+                    params![guild_id.get(), setting.key, &value],
+                )?;
+                values.insert((guild_id, setting.key), value);
+            }
+            None => {
+                db.conn.execute(
+                    "DELETE FROM guild_settings WHERE guild_id = ?1 AND key = ?2",
+                    params![guild_id.get(), setting.key],
+                )?;
+                values.remove(&(guild_id, setting.key));
+            }
+        }
+        Ok(())
+    }
+}
+
+// moves settings modules stored in their own tables before guild_settings existed
+fn migrate_legacy_settings(conn: &Connection) -> anyhow::Result<()> {
+    let has_lp_roles: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'lp_roles'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_lp_roles {
+        conn.execute(
+            "INSERT OR IGNORE INTO guild_settings (guild_id, key, value)
+                 SELECT guild_id, 'lp_roles', group_concat(role_id) FROM lp_roles
+                 GROUP BY guild_id",
+            [],
+        )?;
+        conn.execute("DROP TABLE lp_roles", [])?;
+    }
+    let has_event_channels: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('lp_settings') WHERE name = 'event_channel'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_event_channels {
+        conn.execute(
+            "INSERT OR IGNORE INTO guild_settings (guild_id, key, value)
+                 SELECT guild_id, 'lp_event_channel', CAST(event_channel AS TEXT)
+                 FROM lp_settings WHERE event_channel IS NOT NULL",
+            [],
+        )?;
+        conn.execute("UPDATE lp_settings SET event_channel = NULL", [])?;
+    }
+    let has_announce_channels: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('att_config') WHERE name = 'announce_channel'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_announce_channels {
+        conn.execute(
+            "INSERT OR IGNORE INTO guild_settings (guild_id, key, value)
+                 SELECT guild_id, 'att_announce_channel', CAST(announce_channel AS TEXT)
+                 FROM att_config WHERE announce_channel IS NOT NULL",
+            [],
+        )?;
+        conn.execute("UPDATE att_config SET announce_channel = NULL", [])?;
+    }
+    Ok(())
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "config", desc = "View or change this server's settings")]
+pub struct Config {
+    #[cmd(desc = "Whether to get, set or list settings")]
+    action: String,
+    #[cmd(desc = "Setting to get or set")]
+    key: Option<String>,
+    #[cmd(desc = "New value of the setting, leave empty to reset it")]
+    value: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for Config {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let settings: &GuildSettings = data.module()?;
+        if self.action == "list" {
+            let mut lines = Vec::with_capacity(SETTINGS.len());
+            for setting in SETTINGS {
+                let value = settings.get(guild_id, setting).await;
+                lines.push(format!(
+                    "`{}`: {} ({})",
+                    setting.key,
+                    setting.display(value.as_deref()),
+                    setting.desc
+                ));
+            }
+            return CommandResponse::private(lines.join("\n"));
+        }
+        let Some(key) = &self.key else {
+            return CommandResponse::private("Pick the setting to get or set");
+        };
+        let Some(setting) = SETTINGS.iter().find(|setting| setting.key == key) else {
+            return CommandResponse::private(format!("Unknown setting `{key}`"));
+        };
+        match self.action.as_str() {
+            "get" => {
+                let value = settings.get(guild_id, setting).await;
+                CommandResponse::private(format!("`{key}`: {}", setting.display(value.as_deref())))
+            }
+            "set" => {
+                let value = match &self.value {
+                    None => None,
+                    Some(input) => match setting.parse(ctx, input).await {
+                        Ok(value) => Some(value),
+                        Err(e) => return CommandResponse::private(e),
+                    },
+                };
+                settings.set(data, guild_id, setting, value.clone()).await?;
+                CommandResponse::private(format!(
+                    "`{key}` is now {}",
+                    setting.display(value.as_deref())
+                ))
+            }
+            action => CommandResponse::private(format!("Unknown action `{action}`")),
+        }
+    }
+
+    fn setup_options(opt_name: &'static str, opt: CreateCommandOption) -> CreateCommandOption {
+        match opt_name {
+            "action" => opt
+                .add_string_choice("get", "get")
+                .add_string_choice("set", "set")
+                .add_string_choice("list", "list"),
+            "key" => SETTINGS.iter().fold(opt, |opt, setting| {
+                opt.add_string_choice(setting.key, setting.key)
+            }),
+            _ => opt,
+        }
+    }
+}
+
+#[async_trait]
+impl Module for GuildSettings {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS guild_settings (
+                guild_id INTEGER NOT NULL,
+                key STRING NOT NULL,
+                value TEXT NOT NULL,
+
+                PRIMARY KEY (guild_id, key)
+            )",
+            [],
+        )?;
+        migrate_legacy_settings(&db.conn)?;
+        let mut stmt = db
+            .conn
+            .prepare("SELECT guild_id, key, value FROM guild_settings")?;
+        let rows: Vec<(u64, String, String)> = stmt
+            .query([])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .collect()?;
+        let mut values = self.values.write().await;
+        for (guild_id, key, value) in rows {
+            // settings that no longer exist are ignored
+            if let Some(setting) = SETTINGS.iter().find(|setting| setting.key == key) {
+                values.insert((GuildId::new(guild_id), setting.key), value);
+            }
+        }
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(GuildSettings {
+            values: Default::default(),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<Config>();
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Context as _};
+use fallible_iterator::FallibleIterator;
+use rspotify::{prelude::OAuthClient, scopes, AuthCodeSpotify, Config, Credentials, OAuth, Token};
+use rusqlite::{params, OptionalExtension};
+use serenity::{
+    async_trait,
+    model::prelude::{CommandInteraction, UserId},
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::token_cipher::TokenCipher;
+
+/// Base64-encoded 32 bytes key user tokens are encrypted with
+const KEY_VAR: &str = "SPOTIFY_ACCOUNTS_KEY";
+
+/// Scopes users grant to let the bot control their playback and suggest what they played
+/// recently
+fn user_scopes() -> HashSet<String> {
+    scopes!(
+        "user-read-playback-state",
+        "user-modify-playback-state",
+        "user-read-recently-played"
+    )
+}
+
+/// Spotify accounts linked by users, used to act on their behalf
+pub struct SpotifyAccounts {
+    creds: Credentials,
+    oauth: OAuth,
+    // linking accounts is disabled when no key is configured
+    cipher: Option<TokenCipher>,
+}
+
+impl SpotifyAccounts {
+    fn cipher(&self) -> anyhow::Result<&TokenCipher> {
+        self.cipher
+            .as_ref()
+            .ok_or_else(|| anyhow!("Linking Spotify accounts is not configured"))
+    }
+
+    fn client(&self) -> AuthCodeSpotify {
+        AuthCodeSpotify::with_config(
+            self.creds.clone(),
+            self.oauth.clone(),
+            Config {
+                token_refreshing: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Spotify client acting on behalf of a user, if they linked their account
+    pub async fn user_client(
+        &self,
+        handler: &Handler,
+        user: UserId,
+    ) -> anyhow::Result<Option<AuthCodeSpotify>> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(None);
+        };
+        let token: Option<String> = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT token FROM spotify_accounts WHERE user_id = ?1",
+                [user.get()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        let token: Token = serde_json::from_str(&cipher.decrypt(&token)?)?;
+        let client = self.client();
+        *client.token.lock().await.unwrap() = Some(token);
+        Ok(Some(client))
+    }
+
+    /// Store the user's token, should be called after using their client in case the token was
+    /// refreshed
+    pub async fn save_token(
+        &self,
+        handler: &Handler,
+        user: UserId,
+        client: &AuthCodeSpotify,
+    ) -> anyhow::Result<()> {
+        let token = client.token.lock().await.unwrap().clone();
+        let Some(token) = token else {
+            return Ok(());
+        };
+        let encrypted = self.cipher()?.encrypt(&serde_json::to_string(&token)?)?;
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO spotify_accounts (user_id, token) VALUES (?1, ?2)
+                 ON CONFLICT (user_id) DO UPDATE SET token = ?2",
+            params![user.get(), &encrypted],
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "spotify_link",
+    desc = "Link your Spotify account to let the bot control your playback"
+)]
+pub struct LinkSpotify {
+    #[cmd(desc = "URL you were redirected to after authorizing the bot")]
+    url: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for LinkSpotify {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let accounts = data.module::<SpotifyAccounts>()?;
+        accounts.cipher()?;
+        let client = accounts.client();
+        let Some(url) = self.url else {
+            let authorize_url = client.get_authorize_url(false)?;
+            return CommandResponse::private(format!(
+                "[Authorize the bot]({authorize_url}), then run this command again with the URL \
+                 you were redirected to."
+            ));
+        };
+        let code = client
+            .parse_response_code(&url)
+            .ok_or_else(|| anyhow!("No authorization code in this URL"))?;
+        client
+            .request_token(&code)
+            .await
+            .context("requesting spotify token")?;
+        accounts
+            .save_token(data, interaction.user.id, &client)
+            .await?;
+        CommandResponse::private("Spotify account linked!")
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(name = "spotify_unlink", desc = "Unlink your Spotify account")]
+pub struct UnlinkSpotify {}
+
+#[async_trait]
+impl BotCommand for UnlinkSpotify {
+    type Data = Handler;
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let deleted = data.db.lock().await.conn.execute(
+            "DELETE FROM spotify_accounts WHERE user_id = ?1",
+            [interaction.user.id.get()],
+        )?;
+        if deleted == 0 {
+            CommandResponse::private("No Spotify account linked")
+        } else {
+            CommandResponse::private("Spotify account unlinked")
+        }
+    }
+}
+
+#[async_trait]
+impl Module for SpotifyAccounts {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS spotify_accounts (
+                user_id INTEGER PRIMARY KEY,
+                token STRING NOT NULL
+            )",
+            [],
+        )?;
+        // tokens were stored as plain JSON before they were encrypted
+        let Some(cipher) = &self.cipher else {
+            return Ok(());
+        };
+        let mut stmt = db
+            .conn
+            .prepare("SELECT user_id, token FROM spotify_accounts WHERE token LIKE '{%'")?;
+        let plain: Vec<(u64, String)> = stmt
+            .query([])?
+            .map(|row| Ok((row.get(0)?, row.get(1)?)))
+            .collect()?;
+        drop(stmt);
+        for (user_id, token) in plain {
+            db.conn.execute(
+                "UPDATE spotify_accounts SET token = ?2 WHERE user_id = ?1",
+                params![user_id, cipher.encrypt(&token)?],
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        let creds = Credentials::from_env().ok_or_else(|| anyhow!("No spotify credentials"))?;
+        let oauth = OAuth::from_env(user_scopes())
+            .ok_or_else(|| anyhow!("No spotify redirect URI configured"))?;
+        Ok(SpotifyAccounts {
+            creds,
+            oauth,
+            cipher: TokenCipher::from_env(KEY_VAR)?,
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<LinkSpotify>();
+        store.register::<UnlinkSpotify>();
+    }
+}
@@ -1,43 +1,486 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use anyhow::anyhow;
+use chrono::{Duration, Utc};
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
 use rspotify::model::TrackId;
-use serenity::{model::prelude::{UserId, Presence, ActivityType}, async_trait, prelude::RwLock};
-use serenity_command_handler::{Module, ModuleMap};
+use rspotify::prelude::Id;
+use rusqlite::params;
+use serenity::{
+    async_trait,
+    builder::CreateEmbed,
+    futures::{future::BoxFuture, FutureExt},
+    model::prelude::{ActivityType, CommandInteraction, OnlineStatus, Presence, UserId},
+    prelude::{Context, RwLock},
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{
+    db::Db, modules::Spotify, CommandStore, CompletionStore, Handler, HandlerBuilder, Module,
+    ModuleMap,
+};
 
+use crate::add_column;
+use crate::lp_info::{display_duration, USER_MENTION_RE};
+use crate::spotify_cache::SpotifyCache;
+use crate::spotify_market::SpotifyMarket;
 
+#[derive(Clone)]
 pub struct NowPlaying {
     pub track_id: TrackId<'static>,
+    /// Unix timestamps in milliseconds
+    pub start: u64,
     pub end: u64,
+    pub track: Option<String>,
+    /// Artist names separated by semicolons
+    pub artists: Option<String>,
+    pub album: Option<String>,
 }
 
 pub struct SpotifyActivity {
     user_activities: RwLock<HashMap<UserId, NowPlaying>>,
+    /// Users who opted in to having their listening history recorded
+    history_users: RwLock<HashSet<UserId>>,
 }
 
 fn get_now_playing(presence: &Presence) -> Option<NowPlaying> {
-    let act = presence.activities.iter().find(|act| act.kind == ActivityType::Listening && act.name == "Spotify")?;
-    let track_id = TrackId::from_id(act.sync_id.as_deref()?).ok()?.into_static();
-    let end = act.timestamps.as_ref()?.end?;
-    Some(NowPlaying { track_id, end })
+    let act = presence
+        .activities
+        .iter()
+        .find(|act| act.kind == ActivityType::Listening && act.name == "Spotify")?;
+    let track_id = TrackId::from_id(act.sync_id.as_deref()?)
+        .ok()?
+        .into_static();
+    let timestamps = act.timestamps.as_ref()?;
+    let end = timestamps.end?;
+    let start = timestamps.start.unwrap_or(end);
+    Some(NowPlaying {
+        track_id,
+        start,
+        end,
+        track: act.details.clone(),
+        artists: act.state.clone(),
+        album: act
+            .assets
+            .as_ref()
+            .and_then(|assets| assets.large_text.clone()),
+    })
+}
+
+// whether a presence update is about a track that was already playing, as presence events
+// repeat when anything else in the user's status changes, or when they seek or pause
+fn same_play(previous: Option<&NowPlaying>, np: &NowPlaying) -> bool {
+    previous.is_some_and(|previous| previous.track_id == np.track_id && np.start < previous.end)
+}
+
+fn now_millis() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+impl NowPlaying {
+    /// Whether the track finished, presences are not always updated when users stop listening
+    pub fn ended(&self) -> bool {
+        self.end < now_millis()
+    }
 }
 
 impl SpotifyActivity {
-    pub async fn presence_update(&self, presence: &Presence) {
-        if let Some(np) = get_now_playing(presence) {
-            self.user_activities.write().await.insert(presence.user.id, np);
-        } else {
-            self.user_activities.write().await.remove(&presence.user.id);
+    pub async fn presence_update(&self, handler: &Handler, presence: &Presence) {
+        let user_id = presence.user.id;
+        let offline = matches!(
+            presence.status,
+            OnlineStatus::Offline | OnlineStatus::Invisible
+        );
+        let Some(np) = get_now_playing(presence).filter(|_| !offline) else {
+            self.user_activities.write().await.remove(&user_id);
+            return;
+        };
+        let play = np.clone();
+        let new_play = {
+            let mut activities = self.user_activities.write().await;
+            let new_play = !same_play(activities.get(&user_id), &np);
+            activities.insert(user_id, np);
+            new_play
+        };
+        if new_play && self.history_users.read().await.contains(&user_id) {
+            record_play(handler, user_id, &play).await;
         }
     }
 
     pub async fn user_now_playing(&self, user_id: UserId) -> Option<TrackId<'static>> {
-        self.user_activities.read().await.get(&user_id).map(|np| np.track_id.clone_static())
+        self.user_activities
+            .read()
+            .await
+            .get(&user_id)
+            .filter(|np| !np.ended())
+            .map(|np| np.track_id.clone_static())
+    }
+
+    pub async fn user_activity(&self, user_id: UserId) -> Option<NowPlaying> {
+        self.user_activities
+            .read()
+            .await
+            .get(&user_id)
+            .filter(|np| !np.ended())
+            .cloned()
+    }
+
+    /// Background task forgetting tracks that finished playing
+    pub fn prune<'a>(handler: &'a Handler, _ctx: &'a Context) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let activity = handler.module::<SpotifyActivity>()?;
+            activity
+                .user_activities
+                .write()
+                .await
+                .retain(|_, np| !np.ended());
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+async fn record_play(handler: &Handler, user_id: UserId, np: &NowPlaying) {
+    let db = handler.db.lock().await;
+    if let Err(e) = db.conn.execute(
+        "INSERT OR IGNORE INTO listening_history
+             (user_id, track_id, started_at, track, artists, album)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            user_id.get(),
+            np.track_id.id(),
+            (np.start / 1000) as i64,
+            &np.track,
+            &np.artists,
+            &np.album,
+        ],
+    ) {
+        eprintln!("Error recording listening history: {e}");
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "listening_history",
+    desc = "Record what you listen to on Spotify, for server listening stats"
+)]
+pub struct ListeningHistory {
+    #[cmd(desc = "Whether to record your listening history, disabling it deletes it")]
+    enabled: bool,
+}
+
+#[async_trait]
+impl BotCommand for ListeningHistory {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let activity: &SpotifyActivity = data.module()?;
+        let user_id = interaction.user.id;
+        let db = data.db.lock().await;
+        if self.enabled {
+            db.conn.execute(
+                "INSERT OR IGNORE INTO listening_history_users (user_id) VALUES (?1)",
+                [user_id.get()],
+            )?;
+            activity.history_users.write().await.insert(user_id);
+            return CommandResponse::private(
+                "Your listening history will be recorded while your Spotify status is shown",
+            );
+        }
+        db.conn.execute(
+            "DELETE FROM listening_history_users WHERE user_id = ?1",
+            [user_id.get()],
+        )?;
+        db.conn.execute(
+            "DELETE FROM listening_history WHERE user_id = ?1",
+            [user_id.get()],
+        )?;
+        activity.history_users.write().await.remove(&user_id);
+        CommandResponse::private("Your listening history was deleted and will not be recorded")
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "whats_playing",
+    desc = "Show what someone is listening to on Spotify"
+)]
+pub struct WhatsPlaying {
+    #[cmd(desc = "User to check (mention), defaults to you")]
+    user: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for WhatsPlaying {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let user_id = match &self.user {
+            None => interaction.user.id,
+            Some(user) => match USER_MENTION_RE
+                .captures(user.trim())
+                .and_then(|caps| caps.get(1).unwrap().as_str().parse().ok())
+            {
+                Some(id) => UserId::new(id),
+                None => return CommandResponse::private("Invalid user mention"),
+            },
+        };
+        let activity: &SpotifyActivity = data.module()?;
+        let Some(np) = activity.user_activity(user_id).await else {
+            return CommandResponse::private(format!(
+                "<@{user_id}> is not listening to anything on Spotify"
+            ));
+        };
+        let spotify: &Spotify = data.module()?;
+        let cache: &SpotifyCache = data.module()?;
+        let market = SpotifyMarket::for_guild(data, interaction.guild_id).await;
+        let track = cache
+            .track(&spotify.client, np.track_id.clone(), market)
+            .await?;
+        // the presence gives when the track ends, which moves when the user seeks or pauses
+        let remaining = Duration::milliseconds(np.end.saturating_sub(now_millis()) as i64);
+        let mut embed = CreateEmbed::new()
+            .title(&track.name)
+            .url(np.track_id.url())
+            .description(format!(
+                "<@{user_id}> is listening to {} from {}",
+                Spotify::artists_to_string(&track.artists),
+                &track.album.name
+            ))
+            .field(
+                "Remaining",
+                format!(
+                    "{} of {}",
+                    display_duration(remaining.min(track.duration)),
+                    display_duration(track.duration)
+                ),
+                true,
+            );
+        if let Some(image) = track.album.images.first() {
+            embed = embed.thumbnail(&image.url);
+        }
+        CommandResponse::public(embed)
+    }
+}
+
+/// Number of entries in each list of listening_trends
+const TRENDS_ENTRIES: usize = 5;
+
+/// Track played by a member, as recorded in the listening history
+struct Play {
+    user_id: u64,
+    track_id: String,
+    track: Option<String>,
+    artists: Option<String>,
+    album: Option<String>,
+}
+
+fn plays(count: usize) -> String {
+    match count {
+        1 => "1 play".to_string(),
+        n => format!("{n} plays"),
+    }
+}
+
+// sorts counted items by decreasing count, then by name for a stable order
+fn most_common<'a>(counts: HashMap<&'a str, usize>) -> Vec<(&'a str, usize)> {
+    counts
+        .into_iter()
+        .sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)))
+        .take(TRENDS_ENTRIES)
+        .collect()
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "listening_trends",
+    desc = "Show what the server has been listening to this week"
+)]
+pub struct ListeningTrends {}
+
+#[async_trait]
+impl BotCommand for ListeningTrends {
+    type Data = Handler;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let since = (Utc::now() - Duration::days(7)).timestamp();
+        let history: Vec<Play> = {
+            let db = data.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT user_id, track_id, track, artists, album FROM listening_history
+                     WHERE started_at >= ?1",
+            )?;
+            let rows = stmt.query([since])?;
+            rows.map(|row| {
+                Ok(Play {
+                    user_id: row.get(0)?,
+                    track_id: row.get(1)?,
+                    track: row.get(2)?,
+                    artists: row.get(3)?,
+                    album: row.get(4)?,
+                })
+            })
+            .collect()?
+        };
+        // history is recorded for users rather than servers
+        let mut members = HashSet::new();
+        for user_id in history.iter().map(|play| play.user_id).unique() {
+            if guild_id.member(ctx, UserId::new(user_id)).await.is_ok() {
+                members.insert(user_id);
+            }
+        }
+        let history = history
+            .into_iter()
+            .filter(|play| members.contains(&play.user_id))
+            .collect::<Vec<_>>();
+        if history.is_empty() {
+            return CommandResponse::private(
+                "Nothing was recorded here this week, members can opt in with /listening_history",
+            );
+        }
+
+        let artists = history
+            .iter()
+            .filter_map(|play| play.artists.as_deref())
+            .flat_map(|artists| artists.split("; "))
+            .counts();
+        let top_artists = most_common(artists)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (artist, count))| format!("{}. {artist} ({})", i + 1, plays(count)))
+            .join("\n");
+
+        let tracks = history
+            .iter()
+            .map(|play| (play.track_id.as_str(), play))
+            .collect::<HashMap<_, _>>();
+        let top_tracks = most_common(history.iter().map(|play| play.track_id.as_str()).counts())
+            .into_iter()
+            .enumerate()
+            .map(|(i, (id, count))| {
+                let play = tracks[id];
+                let name = play.track.as_deref().unwrap_or("Unknown track");
+                let artists = play
+                    .artists
+                    .as_deref()
+                    .map(|artists| format!(" by {}", artists.replace("; ", ", ")))
+                    .unwrap_or_default();
+                format!(
+                    "{}. [{name}](https://open.spotify.com/track/{id}){artists} ({})",
+                    i + 1,
+                    plays(count)
+                )
+            })
+            .join("\n");
+
+        // albums several members listened to
+        let mut listeners: HashMap<&str, HashSet<u64>> = HashMap::new();
+        for play in &history {
+            if let Some(album) = &play.album {
+                listeners
+                    .entry(album.as_str())
+                    .or_default()
+                    .insert(play.user_id);
+            }
+        }
+        let shared = most_common(
+            listeners
+                .into_iter()
+                .map(|(album, users)| (album, users.len()))
+                .filter(|(_, count)| *count > 1)
+                .collect(),
+        )
+        .into_iter()
+        .map(|(album, count)| format!("{count} people listened to {album}"))
+        .join("\n");
+
+        let mut embed = CreateEmbed::new().title("Listening trends this week");
+        for (name, value) in [
+            ("Top artists", top_artists),
+            ("Top tracks", top_tracks),
+            ("Shared listens", shared),
+        ] {
+            if !value.is_empty() {
+                embed = embed.field(name, value, false);
+            }
+        }
+        CommandResponse::public(embed)
     }
 }
 
 #[async_trait]
 impl Module for SpotifyActivity {
-    async fn init(_: &ModuleMap) ->  anyhow::Result<Self>{
-        Ok(SpotifyActivity { user_activities: Default::default() })
+    async fn add_dependencies(builder: HandlerBuilder) -> anyhow::Result<HandlerBuilder> {
+        builder
+            .module::<Spotify>()
+            .await?
+            .module::<SpotifyCache>()
+            .await?
+            .module::<SpotifyMarket>()
+            .await
+    }
+
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS listening_history_users (
+                user_id INTEGER PRIMARY KEY
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS listening_history (
+                user_id INTEGER NOT NULL,
+                track_id STRING NOT NULL,
+                started_at INTEGER NOT NULL,
+
+                UNIQUE(user_id, track_id, started_at)
+            )",
+            [],
+        )?;
+        // names from the presence, so trends don't need a Spotify lookup for every track
+        add_column(&db.conn, "listening_history", "track", "STRING")?;
+        add_column(&db.conn, "listening_history", "artists", "STRING")?;
+        add_column(&db.conn, "listening_history", "album", "STRING")?;
+        let mut stmt = db
+            .conn
+            .prepare("SELECT user_id FROM listening_history_users")?;
+        let users: Vec<u64> = stmt.query([])?.map(|row| row.get(0)).collect()?;
+        self.history_users
+            .write()
+            .await
+            .extend(users.into_iter().map(UserId::new));
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(SpotifyActivity {
+            user_activities: Default::default(),
+            history_users: Default::default(),
+        })
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<ListeningHistory>();
+        store.register::<WhatsPlaying>();
+        store.register::<ListeningTrends>();
     }
 }
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context as _};
+use futures_util::TryStreamExt;
+use lru::LruCache;
+use reqwest::Url;
+use rspotify::{
+    clients::BaseClient,
+    model::{AlbumId, FullAlbum, FullTrack, Market, SimplifiedTrack, TrackId},
+    prelude::Id,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use serenity::async_trait;
+use serenity_command_handler::{db::Db, Module, ModuleMap};
+
+use crate::{open_db, spotify_retry::with_retry};
+
+/// How long Spotify responses are kept before being fetched again, in seconds
+const CACHE_TTL: i64 = 7 * 24 * 60 * 60;
+/// Number of responses kept in memory
+const MEMORY_CAPACITY: usize = 500;
+
+const TRACK: &str = "track";
+const ALBUM: &str = "album";
+const ALBUM_TRACKS: &str = "album_tracks";
+
+/// Gets the ISRC of a track, which identifies the recording across releases and services
+pub fn isrc(track: &FullTrack) -> Option<&str> {
+    track.external_ids.get("isrc").map(String::as_str)
+}
+
+// availability differs between markets, so responses are cached for each one
+fn cache_key(id: &str, market: Option<Market>) -> String {
+    match market {
+        Some(market) => format!("{id}:{}", <&str>::from(market)),
+        None => id.to_string(),
+    }
+}
+
+/// Spotify tracks and albums looked up recently, kept in memory and in the database
+pub struct SpotifyCache {
+    memory: Mutex<LruCache<(&'static str, String), (i64, String)>>,
+    // separate connection, lookups happen where the handler's database is not available
+    conn: Mutex<Connection>,
+}
+
+impl SpotifyCache {
+    fn get<T: DeserializeOwned>(&self, kind: &'static str, id: &str) -> Option<T> {
+        let key = (kind, id.to_string());
+        let cached = self.memory.lock().unwrap().get(&key).cloned();
+        let (fetched_at, data) = match cached {
+            Some(entry) => entry,
+            None => {
+                let stored = self
+                    .conn
+                    .lock()
+                    .unwrap()
+                    .query_row(
+                        "SELECT fetched_at, data FROM spotify_cache WHERE kind = ?1 AND id = ?2",
+                        params![kind, id],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional();
+                let entry: (i64, String) = match stored {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        eprintln!("Error reading Spotify cache: {e}");
+                        return None;
+                    }
+                };
+                self.memory.lock().unwrap().put(key, entry.clone());
+                entry
+            }
+        };
+        if chrono::Utc::now().timestamp() - fetched_at > CACHE_TTL {
+            return None;
+        }
+        serde_json::from_str(&data).ok()
+    }
+
+    fn put<T: Serialize>(&self, kind: &'static str, id: &str, value: &T) {
+        let Ok(data) = serde_json::to_string(value) else {
+            return;
+        };
+        let now = chrono::Utc::now().timestamp();
+        if let Err(e) = self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO spotify_cache (kind, id, data, fetched_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+            params![kind, id, &data, now],
+        ) {
+            eprintln!("Error caching Spotify {kind}: {e}");
+        }
+        self.memory
+            .lock()
+            .unwrap()
+            .put((kind, id.to_string()), (now, data));
+    }
+
+    // ISRCs never change, so they are kept after the track itself expires
+    fn put_isrc(&self, track: &FullTrack) {
+        let (Some(id), Some(isrc)) = (&track.id, isrc(track)) else {
+            return;
+        };
+        if let Err(e) = self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO spotify_isrcs (track_id, isrc) VALUES (?1, ?2)",
+            params![id.id(), isrc.to_uppercase()],
+        ) {
+            eprintln!("Error caching ISRC: {e}");
+        }
+    }
+
+    /// Fetches a track, unless it was looked up recently
+    pub async fn track<C: BaseClient>(
+        &self,
+        client: &C,
+        id: TrackId<'_>,
+        market: Option<Market>,
+    ) -> anyhow::Result<FullTrack> {
+        let key = cache_key(id.id(), market);
+        if let Some(track) = self.get(TRACK, &key) {
+            return Ok(track);
+        }
+        let track = with_retry(None, || client.track(id.clone_static(), market))
+            .await
+            .context("fetching track")?;
+        self.put(TRACK, &key, &track);
+        self.put_isrc(&track);
+        Ok(track)
+    }
+
+    /// Gets the ISRCs of tracks by ID, leaving out tracks that don't have one
+    pub async fn isrcs<C: BaseClient>(
+        &self,
+        client: &C,
+        ids: &[TrackId<'static>],
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let mut isrcs = HashMap::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT isrc FROM spotify_isrcs WHERE track_id = ?1")?;
+            for id in ids {
+                let stored: Option<String> =
+                    stmt.query_row([id.id()], |row| row.get(0)).optional()?;
+                match stored {
+                    Some(isrc) => {
+                        isrcs.insert(id.id().to_string(), isrc);
+                    }
+                    None => missing.push(id.clone()),
+                }
+            }
+        }
+        // the API accepts up to 50 tracks at once
+        for chunk in missing.chunks(50) {
+            let tracks = with_retry(None, || client.tracks(chunk.iter().cloned(), None))
+                .await
+                .context("fetching tracks")?;
+            for track in tracks {
+                self.put_isrc(&track);
+                if let (Some(id), Some(isrc)) = (&track.id, isrc(&track)) {
+                    isrcs.insert(id.id().to_string(), isrc.to_uppercase());
+                }
+            }
+        }
+        Ok(isrcs)
+    }
+
+    /// Fetches a track from its open.spotify.com URL
+    pub async fn track_from_url<C: BaseClient>(
+        &self,
+        client: &C,
+        url: &str,
+        market: Option<Market>,
+    ) -> anyhow::Result<FullTrack> {
+        let parsed = Url::parse(url).context("Not a valid URL")?;
+        let id = parsed
+            .path()
+            .strip_prefix("/track/")
+            .ok_or_else(|| anyhow!("Not a spotify track URL: {url}"))?;
+        self.track(client, TrackId::from_id(id)?, market).await
+    }
+
+    /// Fetches an album, unless it was looked up recently
+    pub async fn album<C: BaseClient>(
+        &self,
+        client: &C,
+        id: AlbumId<'_>,
+        market: Option<Market>,
+    ) -> anyhow::Result<FullAlbum> {
+        let key = cache_key(id.id(), market);
+        if let Some(album) = self.get(ALBUM, &key) {
+            return Ok(album);
+        }
+        let album = with_retry(None, || client.album(id.clone_static(), market))
+            .await
+            .context("fetching album")?;
+        self.put(ALBUM, &key, &album);
+        Ok(album)
+    }
+
+    /// Fetches every track of an album, unless they were looked up recently
+    pub async fn album_tracks<C: BaseClient>(
+        &self,
+        client: &C,
+        id: AlbumId<'_>,
+        market: Option<Market>,
+    ) -> anyhow::Result<Vec<SimplifiedTrack>> {
+        let key = cache_key(id.id(), market);
+        if let Some(tracks) = self.get(ALBUM_TRACKS, &key) {
+            return Ok(tracks);
+        }
+        let tracks: Vec<SimplifiedTrack> = with_retry(None, || {
+            client.album_track(id.clone_static(), market).try_collect()
+        })
+        .await
+        .context("fetching album tracks")?;
+        self.put(ALBUM_TRACKS, &key, &tracks);
+        Ok(tracks)
+    }
+}
+
+#[async_trait]
+impl Module for SpotifyCache {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS spotify_cache (
+                kind STRING NOT NULL,
+                id STRING NOT NULL,
+                data STRING NOT NULL,
+                fetched_at INTEGER NOT NULL,
+
+                PRIMARY KEY (kind, id)
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS spotify_isrcs (
+                track_id STRING NOT NULL PRIMARY KEY,
+                isrc STRING NOT NULL
+            )",
+            [],
+        )?;
+        let expired = chrono::Utc::now().timestamp() - CACHE_TTL;
+        db.conn
+            .execute("DELETE FROM spotify_cache WHERE fetched_at < ?1", [expired])?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(SpotifyCache {
+            memory: Mutex::new(LruCache::new(NonZeroUsize::new(MEMORY_CAPACITY).unwrap())),
+            conn: Mutex::new(open_db()?),
+        })
+    }
+}
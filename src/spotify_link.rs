@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Context as _};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::{redirect::Policy, Url};
+
+/// Client that does not follow redirects, to read where shortened links point to
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap()
+});
+
+static SHORTENED_LINK_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\bhttps?://spotify\.link/[A-Za-z0-9]+").unwrap());
+
+/// Whether a URL is a spotify.link URL, as given by the share button of Spotify's apps
+pub fn is_shortened(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.domain() == Some("spotify.link"))
+}
+
+/// Finds a shortened link in a message
+pub fn find_shortened(text: &str) -> Option<&str> {
+    SHORTENED_LINK_RE.find(text).map(|m| m.as_str())
+}
+
+/// Follows a shortened link to the open.spotify.com URL it redirects to
+pub async fn resolve(url: &str) -> anyhow::Result<String> {
+    let resp = CLIENT
+        .head(url)
+        .send()
+        .await
+        .context("Failed to resolve shortened spotify URL")?;
+    let location = resp
+        .headers()
+        .get("location")
+        .and_then(|val| val.to_str().ok())
+        .ok_or_else(|| anyhow!("Not a valid spotify URL"))?;
+    let url = Url::parse(location).context("Spotify shortened URL points to invalid URL")?;
+    Ok(url.to_string())
+}
+
+/// Converts a Spotify URI such as spotify:track:<id> to its open.spotify.com URL
+pub fn uri_to_url(uri: &str) -> Option<String> {
+    let (kind, id) = uri.strip_prefix("spotify:")?.split_once(':')?;
+    let valid_id = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric());
+    (matches!(kind, "track" | "album" | "episode") && valid_id)
+        .then(|| format!("https://open.spotify.com/{kind}/{id}"))
+}
+
+/// Converts an open.spotify.com URL to its shorter URI, e.g. spotify:track:<id>
+pub fn url_to_uri(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    if url.domain() != Some("open.spotify.com") {
+        return None;
+    }
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    match segments.as_slice() {
+        [kind, id] => Some(format!("spotify:{kind}:{id}")),
+        _ => None,
+    }
+}
+
+/// Resolves shortened links and Spotify URIs, returning other URLs unchanged
+pub async fn expand(url: &str) -> anyhow::Result<String> {
+    if is_shortened(url) {
+        resolve(url).await
+    } else if let Some(url) = uri_to_url(url.trim()) {
+        Ok(url)
+    } else {
+        Ok(url.to_string())
+    }
+}
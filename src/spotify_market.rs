@@ -0,0 +1,113 @@
+use anyhow::anyhow;
+use rspotify::model::{Country, Market};
+use rusqlite::{params, OptionalExtension};
+use serenity::{
+    async_trait,
+    model::{
+        prelude::{CommandInteraction, GuildId},
+        Permissions,
+    },
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+/// Country Spotify lookups are made for in each guild, so region-locked releases resolve to
+/// versions members can play
+pub struct SpotifyMarket;
+
+/// Parses a two-letter country code
+pub fn parse_country(code: &str) -> Option<Country> {
+    serde_json::from_value(serde_json::Value::String(code.trim().to_uppercase())).ok()
+}
+
+impl SpotifyMarket {
+    /// Market configured for a guild, if any
+    pub async fn for_guild(handler: &Handler, guild_id: Option<GuildId>) -> Option<Market> {
+        let guild_id = guild_id?;
+        let code: Option<String> = handler
+            .db
+            .lock()
+            .await
+            .conn
+            .query_row(
+                "SELECT market FROM spotify_markets WHERE guild_id = ?1",
+                [guild_id.get()],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap_or_else(|e| {
+                eprintln!("Error reading Spotify market: {e}");
+                None
+            });
+        code.as_deref().and_then(parse_country).map(Market::Country)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "spotify_market",
+    desc = "Set the country Spotify lookups are made for in this server"
+)]
+pub struct SetSpotifyMarket {
+    #[cmd(desc = "Two-letter country code such as US or FR, leave empty to use the default")]
+    country: Option<String>,
+}
+
+#[async_trait]
+impl BotCommand for SetSpotifyMarket {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+    async fn run(
+        self,
+        data: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let db = data.db.lock().await;
+        let Some(code) = self.country else {
+            db.conn.execute(
+                "DELETE FROM spotify_markets WHERE guild_id = ?1",
+                [guild_id.get()],
+            )?;
+            return CommandResponse::private("Spotify lookups will use the default market");
+        };
+        if parse_country(&code).is_none() {
+            return CommandResponse::private(format!("{code} is not a valid country code"));
+        }
+        let code = code.trim().to_uppercase();
+        db.conn.execute(
+            "INSERT INTO spotify_markets (guild_id, market) VALUES (?1, ?2)
+                 ON CONFLICT (guild_id) DO UPDATE SET market = ?2",
+            params![guild_id.get(), &code],
+        )?;
+        CommandResponse::private(format!("Spotify lookups will use the {code} market"))
+    }
+}
+
+#[async_trait]
+impl Module for SpotifyMarket {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS spotify_markets (
+                guild_id INTEGER PRIMARY KEY,
+                market STRING NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(SpotifyMarket)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<SetSpotifyMarket>();
+    }
+}
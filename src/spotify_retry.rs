@@ -0,0 +1,75 @@
+use std::future::Future;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use rspotify::{http::HttpError, ClientError, ClientResult};
+use serenity::builder::EditInteractionResponse;
+use serenity::model::prelude::CommandInteraction;
+use serenity::prelude::Context;
+use tokio::{sync::Mutex, time::Instant};
+
+/// Number of times a rate limited request is retried before giving up
+const MAX_RETRIES: usize = 3;
+/// Wait used when Spotify does not say how long to wait
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Requests are held back until then after Spotify rate limited the bot
+static RATE_LIMITED_UNTIL: Lazy<Mutex<Option<Instant>>> = Lazy::new(Default::default);
+
+/// How long Spotify asked to wait, if the request was rate limited
+pub fn retry_after(err: &ClientError) -> Option<Duration> {
+    let ClientError::Http(err) = err else {
+        return None;
+    };
+    let HttpError::StatusCode(resp) = err.as_ref() else {
+        return None;
+    };
+    if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let seconds = resp
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    Some(seconds.map_or(DEFAULT_RETRY_AFTER, Duration::from_secs))
+}
+
+/// Runs a Spotify request, retrying it when rate limited once the wait Spotify asked for is over.
+/// Other requests made through this wait too, and a deferred interaction is told about the delay.
+pub async fn with_retry<T, F, Fut>(
+    deferred: Option<(&Context, &CommandInteraction)>,
+    mut request: F,
+) -> ClientResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ClientResult<T>>,
+{
+    let mut retries = 0;
+    loop {
+        let until = *RATE_LIMITED_UNTIL.lock().await;
+        if let Some(until) = until {
+            tokio::time::sleep_until(until).await;
+        }
+        let err = match request().await {
+            Err(err) => err,
+            res => return res,
+        };
+        let Some(wait) = retry_after(&err).filter(|_| retries < MAX_RETRIES) else {
+            return Err(err);
+        };
+        retries += 1;
+        let until = Instant::now() + wait;
+        let mut limited = RATE_LIMITED_UNTIL.lock().await;
+        *limited = Some(limited.map_or(until, |current| current.max(until)));
+        drop(limited);
+        if let Some((ctx, interaction)) = deferred {
+            let status = format!("Spotify is busy, retrying in {}s…", wait.as_secs().max(1));
+            let edit = EditInteractionResponse::new().content(status);
+            if let Err(e) = interaction.edit_response(&ctx.http, edit).await {
+                eprintln!("failed to report Spotify rate limit: {e:?}");
+            }
+        }
+    }
+}
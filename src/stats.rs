@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use fallible_iterator::FallibleIterator;
+use itertools::Itertools;
+use rusqlite::params;
+use serenity::{
+    async_trait,
+    builder::CreateEmbed,
+    model::{
+        prelude::{CommandInteraction, GuildId},
+        Permissions,
+    },
+    prelude::Context,
+};
+use serenity_command::{BotCommand, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{db::Db, CommandStore, CompletionStore, Handler, Module, ModuleMap};
+
+use crate::registration;
+
+/// Number of days statistics are kept and shown for
+const STATS_DAYS: i64 = 30;
+const TOP_COMMANDS: usize = 10;
+const TOP_GUILDS: usize = 5;
+
+/// Records how often each command is used and how it performs
+pub struct BotStats;
+
+/// Command being processed, recorded once it has been answered
+pub struct CommandUse {
+    guild_id: Option<GuildId>,
+    command: String,
+    /// Token of the interaction, to check whether it was answered
+    token: String,
+    started: tokio::time::Instant,
+}
+
+impl CommandUse {
+    pub fn start(cmd: &CommandInteraction) -> Self {
+        CommandUse {
+            guild_id: cmd.guild_id,
+            command: cmd.data.name.clone(),
+            token: cmd.token.clone(),
+            started: tokio::time::Instant::now(),
+        }
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Whether the command was answered, the framework answers failing commands with their error
+    pub async fn answered(&self, ctx: &Context) -> bool {
+        ctx.http
+            .get_original_interaction_response(&self.token)
+            .await
+            .is_ok()
+    }
+}
+
+impl BotStats {
+    /// Records a processed command, and forgets about the ones older than the statistics shown
+    pub async fn record(&self, handler: &Handler, usage: CommandUse, answered: bool) {
+        let latency = usage.started.elapsed();
+        let now = chrono::Utc::now().timestamp();
+        let db = handler.db.lock().await;
+        let res = db
+            .conn
+            .execute(
+                "INSERT INTO command_stats (guild_id, command, latency_ms, answered, used_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    usage.guild_id.map(GuildId::get),
+                    &usage.command,
+                    latency.as_millis() as u64,
+                    answered,
+                    now,
+                ],
+            )
+            .and_then(|_| {
+                db.conn.execute(
+                    "DELETE FROM command_stats WHERE used_at < ?1",
+                    [now - STATS_DAYS * 24 * 60 * 60],
+                )
+            });
+        if let Err(e) = res {
+            eprintln!("Error recording stats of /{}: {e}", &usage.command);
+        }
+    }
+}
+
+fn format_latency(ms: f64) -> String {
+    let latency = Duration::from_millis(ms as u64);
+    if latency.as_secs() >= 1 {
+        format!("{:.1}s", latency.as_secs_f64())
+    } else {
+        format!("{}ms", latency.as_millis())
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "bot_stats",
+    desc = "Show the most used commands and busiest servers of the last 30 days"
+)]
+pub struct ShowBotStats {}
+
+#[async_trait]
+impl BotCommand for ShowBotStats {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+    async fn run(
+        self,
+        data: &Handler,
+        ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        // statistics cover every guild the bot is in
+        registration::check_owner(ctx, interaction).await?;
+        let since = chrono::Utc::now().timestamp() - STATS_DAYS * 24 * 60 * 60;
+        let (commands, guilds, (total, unanswered)) = {
+            let db = data.db.lock().await;
+            let mut stmt = db.conn.prepare(
+                "SELECT command, COUNT(*), AVG(latency_ms), SUM(NOT answered)
+                     FROM command_stats WHERE used_at >= ?1
+                     GROUP BY command ORDER BY COUNT(*) DESC LIMIT ?2",
+            )?;
+            let commands: Vec<(String, u64, f64, u64)> = stmt
+                .query(params![since, TOP_COMMANDS])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                .collect()?;
+            let mut stmt = db.conn.prepare(
+                "SELECT guild_id, COUNT(*) FROM command_stats
+                     WHERE used_at >= ?1 AND guild_id IS NOT NULL
+                     GROUP BY guild_id ORDER BY COUNT(*) DESC LIMIT ?2",
+            )?;
+            let guilds: Vec<(u64, u64)> = stmt
+                .query(params![since, TOP_GUILDS])?
+                .map(|row| Ok((row.get(0)?, row.get(1)?)))
+                .collect()?;
+            let totals: (u64, u64) = db.conn.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(NOT answered), 0) FROM command_stats
+                     WHERE used_at >= ?1",
+                [since],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            (commands, guilds, totals)
+        };
+        if total == 0 {
+            return CommandResponse::private("No commands were used in the last 30 days");
+        }
+        let commands = commands
+            .into_iter()
+            .map(|(command, uses, latency, unanswered)| {
+                format!(
+                    "`/{command}`: {uses} uses, {} on average, {:.1}% unanswered",
+                    format_latency(latency),
+                    unanswered as f64 * 100.0 / uses as f64
+                )
+            })
+            .join("\n");
+        let guilds = guilds
+            .into_iter()
+            .map(|(guild_id, uses)| {
+                let guild_id = GuildId::new(guild_id);
+                let name = guild_id
+                    .name(&ctx.cache)
+                    .unwrap_or_else(|| guild_id.to_string());
+                format!("{name}: {uses} commands")
+            })
+            .join("\n");
+        let embed = CreateEmbed::new()
+            .title("Bot statistics (last 30 days)")
+            .description(format!(
+                "{total} commands used, {:.1}% unanswered",
+                unanswered as f64 * 100.0 / total as f64
+            ))
+            .field("Most used commands", commands, false)
+            .field("Busiest servers", guilds, false);
+        CommandResponse::private(embed)
+    }
+}
+
+#[async_trait]
+impl Module for BotStats {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS command_stats (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                guild_id INTEGER,
+                command STRING NOT NULL,
+                latency_ms INTEGER NOT NULL,
+                answered BOOLEAN NOT NULL,
+                used_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        db.conn.execute(
+            "CREATE INDEX IF NOT EXISTS command_stats_used_at ON command_stats (used_at)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(BotStats)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, _completions: &mut CompletionStore) {
+        store.register::<ShowBotStats>();
+    }
+}
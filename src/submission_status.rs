@@ -0,0 +1,233 @@
+use anyhow::anyhow;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serenity::{
+    async_trait,
+    futures::future::BoxFuture,
+    model::{
+        prelude::{CommandInteraction, GuildId},
+        Permissions,
+    },
+    prelude::Context,
+    FutureExt,
+};
+use serenity_command::{BotCommand, CommandBuilder, CommandKey, CommandResponse};
+use serenity_command_derive::Command;
+use serenity_command_handler::{
+    command_context::get_str_opt_ac, db::Db, CommandStore, CompletionStore, Handler, Module,
+    ModuleMap,
+};
+
+use crate::complete::respond_choices;
+use crate::forms::Forms;
+use crate::playlist::Playlists;
+
+/// Submission commands of forms and playlists organizers stopped accepting picks for
+pub struct SubmissionStatus;
+
+impl SubmissionStatus {
+    /// Message telling users a submission command is closed, None if it accepts submissions
+    pub async fn closed_message(
+        handler: &Handler,
+        guild_id: GuildId,
+        command_name: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let db = handler.db.lock().await;
+        let closed: Option<Option<i64>> = db
+            .conn
+            .query_row(
+                "SELECT reopens_at FROM closed_submissions
+                     WHERE guild_id = ?1 AND command_name = ?2",
+                params![guild_id.get(), command_name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match closed {
+            None => Ok(None),
+            Some(Some(reopens_at)) if reopens_at <= Utc::now().timestamp() => {
+                db.conn.execute(
+                    "DELETE FROM closed_submissions WHERE guild_id = ?1 AND command_name = ?2",
+                    params![guild_id.get(), command_name],
+                )?;
+                Ok(None)
+            }
+            Some(Some(reopens_at)) => Ok(Some(format!(
+                "Submissions are closed, reopens <t:{reopens_at}:R>"
+            ))),
+            Some(None) => Ok(Some("Submissions are closed".to_string())),
+        }
+    }
+
+    fn complete_commands<'a>(
+        handler: &'a Handler,
+        ctx: &'a Context,
+        _key: CommandKey<'a>,
+        ac: &'a CommandInteraction,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        async move {
+            if ![CloseSubmissions::NAME, OpenSubmissions::NAME].contains(&ac.data.name.as_str()) {
+                return Ok(false);
+            }
+            let Some(guild_id) = ac.guild_id else {
+                return Ok(false);
+            };
+            let opt = get_str_opt_ac(&ac.data.options, "command_name").unwrap_or_default();
+            let choices = submission_commands(handler, guild_id)
+                .await
+                .into_iter()
+                .filter(|name| name.contains(opt))
+                .map(|name| (name.clone(), name))
+                .collect();
+            respond_choices(ctx, ac, choices).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
+
+/// Names of the form and playlist commands of a guild
+pub async fn submission_commands(handler: &Handler, guild_id: GuildId) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(forms) = handler.module::<Forms>() {
+        names.extend(
+            forms
+                .forms
+                .read()
+                .await
+                .iter()
+                .filter(|form| form.guild_id == guild_id.get())
+                .map(|form| form.command_name.clone()),
+        );
+    }
+    if let Ok(playlists) = handler.module::<Playlists>() {
+        names.extend(playlists.command_names(guild_id).await);
+    }
+    names
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "close_submissions",
+    desc = "Stop accepting submissions to a form or playlist"
+)]
+pub struct CloseSubmissions {
+    #[cmd(desc = "The name of the submission command", autocomplete)]
+    command_name: String,
+    #[cmd(desc = "Reopen submissions automatically after this many hours")]
+    reopen_in_hours: Option<i64>,
+}
+
+#[async_trait]
+impl BotCommand for CloseSubmissions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let command_name = self.command_name.trim();
+        if !submission_commands(handler, guild_id)
+            .await
+            .iter()
+            .any(|name| name == command_name)
+        {
+            return CommandResponse::private(format!(
+                "/{command_name} is not a submission command"
+            ));
+        }
+        let reopens_at = match self.reopen_in_hours {
+            Some(hours) if hours <= 0 => {
+                return CommandResponse::private("The delay must be a positive number of hours")
+            }
+            Some(hours) => Some(Utc::now().timestamp() + hours * 60 * 60),
+            None => None,
+        };
+        handler.db.lock().await.conn.execute(
+            "INSERT INTO closed_submissions (guild_id, command_name, reopens_at)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (guild_id, command_name) DO UPDATE SET reopens_at = ?3",
+            params![guild_id.get(), command_name, reopens_at],
+        )?;
+        let resp = match reopens_at {
+            Some(reopens_at) => {
+                format!("Submissions to /{command_name} are closed until <t:{reopens_at}:f>")
+            }
+            None => format!(
+                "Submissions to /{command_name} are closed, use /{} to reopen them",
+                OpenSubmissions::NAME
+            ),
+        };
+        CommandResponse::public(resp)
+    }
+}
+
+#[derive(Command, Debug)]
+#[cmd(
+    name = "open_submissions",
+    desc = "Accept submissions to a closed form or playlist again"
+)]
+pub struct OpenSubmissions {
+    #[cmd(desc = "The name of the submission command", autocomplete)]
+    command_name: String,
+}
+
+#[async_trait]
+impl BotCommand for OpenSubmissions {
+    type Data = Handler;
+    const PERMISSIONS: Permissions = Permissions::MANAGE_EVENTS;
+
+    async fn run(
+        self,
+        handler: &Handler,
+        _ctx: &Context,
+        interaction: &CommandInteraction,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild_id = interaction
+            .guild_id
+            .ok_or_else(|| anyhow!("Must be run in a server"))?;
+        let command_name = self.command_name.trim();
+        let reopened = handler.db.lock().await.conn.execute(
+            "DELETE FROM closed_submissions WHERE guild_id = ?1 AND command_name = ?2",
+            params![guild_id.get(), command_name],
+        )?;
+        if reopened == 0 {
+            return CommandResponse::private(format!(
+                "Submissions to /{command_name} are not closed"
+            ));
+        }
+        CommandResponse::public(format!("Submissions to /{command_name} are open again"))
+    }
+}
+
+#[async_trait]
+impl Module for SubmissionStatus {
+    async fn setup(&mut self, db: &mut Db) -> anyhow::Result<()> {
+        db.conn.execute(
+            "CREATE TABLE IF NOT EXISTS closed_submissions (
+                guild_id INTEGER NOT NULL,
+                command_name STRING NOT NULL,
+                reopens_at INTEGER,
+
+                PRIMARY KEY (guild_id, command_name)
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    async fn init(_: &ModuleMap) -> anyhow::Result<Self> {
+        Ok(SubmissionStatus)
+    }
+
+    fn register_commands(&self, store: &mut CommandStore, completions: &mut CompletionStore) {
+        store.register::<CloseSubmissions>();
+        store.register::<OpenSubmissions>();
+        completions.push(SubmissionStatus::complete_commands);
+    }
+}
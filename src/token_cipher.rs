@@ -0,0 +1,54 @@
+use std::env;
+
+use anyhow::{anyhow, bail, Context as _};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+
+/// Size of the nonce stored in front of each encrypted token
+const NONCE_LEN: usize = 12;
+
+/// Encrypts the tokens of third-party accounts users link before they are stored
+pub struct TokenCipher(ChaCha20Poly1305);
+
+impl TokenCipher {
+    /// Cipher using the base64-encoded 32 bytes key in an environment variable, None if it is not
+    /// set
+    pub fn from_env(var: &str) -> anyhow::Result<Option<Self>> {
+        let Ok(key) = env::var(var) else {
+            return Ok(None);
+        };
+        let key = STANDARD
+            .decode(key.trim())
+            .with_context(|| format!("{var} is not valid base64"))?;
+        let cipher = ChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|_| anyhow!("{var} must be 32 bytes long"))?;
+        Ok(Some(TokenCipher(cipher)))
+    }
+
+    pub fn encrypt(&self, token: &str) -> anyhow::Result<String> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut data = nonce.to_vec();
+        data.extend(
+            self.0
+                .encrypt(&nonce, token.as_bytes())
+                .map_err(|_| anyhow!("failed to encrypt token"))?,
+        );
+        Ok(STANDARD.encode(data))
+    }
+
+    pub fn decrypt(&self, stored: &str) -> anyhow::Result<String> {
+        let data = STANDARD.decode(stored)?;
+        if data.len() < NONCE_LEN {
+            bail!("stored token is too short");
+        }
+        let (nonce, encrypted) = data.split_at(NONCE_LEN);
+        let token = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| anyhow!("failed to decrypt token"))?;
+        Ok(String::from_utf8(token)?)
+    }
+}
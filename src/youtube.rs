@@ -4,10 +4,43 @@ use anyhow::{anyhow, Context};
 use google_youtube3::api;
 use hyper::client::HttpConnector;
 use hyper_tls::HttpsConnector;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use reqwest::Url;
 use serenity::async_trait;
 
-use crate::album::{Album, AlbumProvider};
+use crate::album::{Album, AlbumProvider, Track, TrackProvider};
+
+const VIDEO_URL_START: &str = "https://www.youtube.com/watch?v=";
+const PLAYLIST_URL_START: &str = "https://www.youtube.com/playlist?list=";
+
+/// Parts of video titles such as "(Official Video)" or "[HD]"
+static VIDEO_TITLE_NOISE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\s*(\([^)]*\)|\[[^\]]*\])").unwrap());
+/// ISO 8601 durations, as returned by the API
+static DURATION_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^PT(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?$").unwrap());
+
+// guesses the artist and title of a song from the title and channel of its video
+fn split_video_title(title: &str, channel: &str) -> (String, String) {
+    let title = VIDEO_TITLE_NOISE_RE.replace_all(title, "");
+    // music videos are usually titled "Artist - Title", auto-generated ones are uploaded by
+    // "Artist - Topic"
+    match title.split_once(" - ") {
+        Some((artist, title)) => (artist.trim().to_string(), title.trim().to_string()),
+        None => (
+            channel.trim_end_matches(" - Topic").to_string(),
+            title.trim().to_string(),
+        ),
+    }
+}
+
+fn parse_duration(duration: &str) -> Option<chrono::Duration> {
+    let cap = DURATION_RE.captures(duration)?;
+    let part = |i: usize| cap.get(i).and_then(|m| m.as_str().parse::<i64>().ok());
+    let secs = part(1).unwrap_or(0) * 3600 + part(2).unwrap_or(0) * 60 + part(3).unwrap_or(0);
+    Some(chrono::Duration::seconds(secs))
+}
 
 pub struct Youtube {
     client: api::YouTube<HttpsConnector<HttpConnector>>,
@@ -23,6 +56,131 @@ impl Youtube {
         let client = api::YouTube::new(client.clone(), authenticator.clone());
         Youtube { client }
     }
+
+    pub fn video_id(url: &Url) -> Option<Cow<'_, str>> {
+        url.query_pairs()
+            .find_map(|(key, value)| if key == "v" { Some(value) } else { None })
+            .or_else(|| {
+                url.path_segments()
+                    .and_then(|path| path.last())
+                    .map(Cow::Borrowed)
+            })
+    }
+
+    pub fn video_url(id: &str) -> String {
+        format!("{VIDEO_URL_START}{id}")
+    }
+
+    // returns the id and title of the first video found for the query
+    async fn search_video(&self, q: &str) -> anyhow::Result<(String, String)> {
+        let result = self
+            .client
+            .search()
+            .list(&vec!["snippet".to_string()])
+            .q(q)
+            .add_type("video")
+            .max_results(1)
+            .doit()
+            .await?
+            .1
+            .items
+            .and_then(|results| results.into_iter().next())
+            .ok_or_else(|| anyhow!("No video found for {q}"))?;
+        let id = result
+            .id
+            .and_then(|id| id.video_id)
+            .ok_or_else(|| anyhow!("No video found for {q}"))?;
+        let title = result
+            .snippet
+            .and_then(|snippet| snippet.title)
+            .unwrap_or_default();
+        Ok((id, title))
+    }
+
+    async fn video_track(&self, id: &str) -> anyhow::Result<Track> {
+        let video = self
+            .client
+            .videos()
+            .list(&vec!["snippet".to_string(), "contentDetails".to_string()])
+            .add_id(id)
+            .doit()
+            .await?
+            .1
+            .items
+            .and_then(|videos| videos.into_iter().next())
+            .ok_or_else(|| anyhow!("Could not find video"))?;
+        let snippet = video
+            .snippet
+            .ok_or_else(|| anyhow!("Could not find video title"))?;
+        let (artist, title) = split_video_title(
+            snippet.title.as_deref().unwrap_or_default(),
+            snippet.channel_title.as_deref().unwrap_or_default(),
+        );
+        Ok(Track {
+            title,
+            artist,
+            duration: video
+                .content_details
+                .and_then(|details| details.duration)
+                .as_deref()
+                .and_then(parse_duration),
+            url: Youtube::video_url(id),
+        })
+    }
+
+    pub fn playlist_url(id: &str) -> String {
+        format!("{PLAYLIST_URL_START}{id}")
+    }
+
+    /// Creates a public playlist, returning its id
+    pub async fn create_playlist(&self, title: &str, description: &str) -> anyhow::Result<String> {
+        let playlist = api::Playlist {
+            snippet: Some(api::PlaylistSnippet {
+                title: Some(title.to_string()),
+                description: Some(description.to_string()),
+                ..Default::default()
+            }),
+            status: Some(api::PlaylistStatus {
+                privacy_status: Some("public".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.client
+            .playlists()
+            .insert(playlist)
+            .add_part("snippet")
+            .add_part("status")
+            .doit()
+            .await
+            .context("failed to create youtube playlist")?
+            .1
+            .id
+            .ok_or_else(|| anyhow!("Created youtube playlist has no id"))
+    }
+
+    pub async fn add_to_playlist(&self, playlist_id: &str, video_id: &str) -> anyhow::Result<()> {
+        let item = api::PlaylistItem {
+            snippet: Some(api::PlaylistItemSnippet {
+                playlist_id: Some(playlist_id.to_string()),
+                resource_id: Some(api::ResourceId {
+                    kind: Some("youtube#video".to_string()),
+                    video_id: Some(video_id.to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.client
+            .playlist_items()
+            .insert(item)
+            .add_part("snippet")
+            .doit()
+            .await
+            .context("failed to add video to youtube playlist")?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -37,15 +195,7 @@ impl AlbumProvider for Youtube {
 
     async fn get_from_url(&self, url: &str) -> anyhow::Result<Album> {
         let url: Url = url.parse().context("Invalid URL")?;
-        let id = url
-            .query_pairs()
-            .find_map(|(key, value)| if key == "v" { Some(value) } else { None })
-            .or_else(|| {
-                url.path_segments()
-                    .and_then(|path| path.last())
-                    .map(Cow::Borrowed)
-            })
-            .ok_or_else(|| anyhow!("Invalid youtube url"))?;
+        let id = Youtube::video_id(&url).ok_or_else(|| anyhow!("Invalid youtube url"))?;
         let title = self
             .client
             .videos()
@@ -63,10 +213,39 @@ impl AlbumProvider for Youtube {
             name: title,
             artist: String::new(),
             url: url.to_string(),
+            tracks: Vec::new(),
         })
     }
 
-    async fn query_album(&self, _q: &str) -> anyhow::Result<crate::album::Album> {
-        todo!()
+    async fn query_album(&self, q: &str) -> anyhow::Result<crate::album::Album> {
+        let (id, title) = self.search_video(q).await?;
+        Ok(Album {
+            name: title,
+            artist: String::new(),
+            url: format!("{VIDEO_URL_START}{id}"),
+            tracks: Vec::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl TrackProvider for Youtube {
+    fn url_matches(&self, url: &str) -> bool {
+        url.contains("youtube.com") || url.contains("youtu.be")
+    }
+
+    fn id(&self) -> &'static str {
+        "youtube"
+    }
+
+    async fn get_track(&self, url: &str) -> anyhow::Result<Track> {
+        let url: Url = url.parse().context("Invalid URL")?;
+        let id = Youtube::video_id(&url).ok_or_else(|| anyhow!("Invalid youtube url"))?;
+        self.video_track(&id).await
+    }
+
+    async fn query_track(&self, q: &str) -> anyhow::Result<Track> {
+        let (id, _) = self.search_video(q).await?;
+        self.video_track(&id).await
     }
 }